@@ -0,0 +1,498 @@
+//! Authorize requests using API keys from a header, the query string or a cookie.
+//!
+//! [`ApiKeyAuth`] reads the key from the configured sources and looks it up, either in a set of
+//! keys or with an async callback such as a database query. The principal of an authorized key
+//! is inserted into the request extensions. Requests without a valid key get a
+//! `401 Unauthorized` response.
+//!
+//! Keys sent in the query string are removed from the request URI, so services further down the
+//! stack don't see or log them. To keep them out of [`Trace`] spans as well, add this middleware
+//! before [`TraceLayer`].
+//!
+//! # Example
+//!
+//! ```
+//! use http::{header::HeaderName, Request, Response, StatusCode};
+//! use http_body_util::Full;
+//! use bytes::Bytes;
+//! use tower::{Service, ServiceExt, ServiceBuilder, BoxError};
+//! use tower_http::auth::{api_key::ApiKeyAuth, AsyncRequireAuthorizationLayer};
+//!
+//! #[derive(Clone)]
+//! struct Tenant(String);
+//!
+//! async fn find_tenant(key: String) -> Option<Tenant> {
+//!     // Look up the key, e.g. by its hash in a database...
+//!     # (key == "secret").then(|| Tenant("acme".to_owned()))
+//! }
+//!
+//! async fn handle(request: Request<Full<Bytes>>) -> Result<Response<Full<Bytes>>, BoxError> {
+//!     // The key was removed from the URI.
+//!     assert_eq!(request.uri(), "/reports?year=2024");
+//!     let tenant = request.extensions().get::<Tenant>().unwrap();
+//!     Ok(Response::new(Full::from(format!("Hello {}", tenant.0))))
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), BoxError> {
+//! let mut service = ServiceBuilder::new()
+//!     .layer(AsyncRequireAuthorizationLayer::api_key(
+//!         ApiKeyAuth::lookup(find_tenant)
+//!             .header(HeaderName::from_static("x-api-key"))
+//!             .query("api_key")
+//!             .cookie("api_key"),
+//!     ))
+//!     .service_fn(handle);
+//!
+//! let request = Request::builder()
+//!     .uri("/reports?api_key=secret&year=2024")
+//!     .body(Full::default())
+//!     .unwrap();
+//! let response = service.ready().await?.call(request).await?;
+//! assert_eq!(StatusCode::OK, response.status());
+//!
+//! let request = Request::builder()
+//!     .uri("/reports?api_key=wrong")
+//!     .body(Full::default())
+//!     .unwrap();
+//! let response = service.ready().await?.call(request).await?;
+//! assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Trace`]: crate::trace::Trace
+//! [`TraceLayer`]: crate::trace::TraceLayer
+
+use crate::auth::{
+    credentials::Identity, AsyncAuthorizeRequest, AsyncRequireAuthorization,
+    AsyncRequireAuthorizationLayer,
+};
+use crate::helpers::{decode_form_component, find_in_constant_time};
+use http::{
+    header::{self, HeaderName},
+    HeaderMap, Request, Response, StatusCode, Uri,
+};
+use std::{fmt, future::Future, marker::PhantomData, pin::Pin, sync::Arc};
+
+impl<S, P, ResBody> AsyncRequireAuthorization<S, RequireApiKey<P, ResBody>> {
+    /// Authorize requests using API keys.
+    ///
+    /// See the [module docs](crate::auth::api_key) for more details.
+    pub fn api_key(inner: S, auth: ApiKeyAuth<P>) -> Self {
+        Self::new(inner, RequireApiKey::new(auth))
+    }
+}
+
+impl<P, ResBody> AsyncRequireAuthorizationLayer<RequireApiKey<P, ResBody>> {
+    /// Authorize requests using API keys.
+    ///
+    /// See the [module docs](crate::auth::api_key) for more details.
+    pub fn api_key(auth: ApiKeyAuth<P>) -> Self {
+        Self::new(RequireApiKey::new(auth))
+    }
+}
+
+type LookupFuture<P> = Pin<Box<dyn Future<Output = Option<P>> + Send>>;
+type LookupFn<P> = dyn Fn(String) -> LookupFuture<P> + Send + Sync + 'static;
+
+/// Configuration of API key authorization.
+///
+/// The key is read from the sources in the order they were added. If no source is added, it is
+/// read from the `X-Api-Key` header.
+///
+/// See the [module docs](crate::auth::api_key) for an example.
+pub struct ApiKeyAuth<P = Identity> {
+    sources: Vec<Source>,
+    lookup: Arc<LookupFn<P>>,
+}
+
+#[derive(Clone, Debug)]
+enum Source {
+    Header(HeaderName),
+    Query(String),
+    Cookie(String),
+}
+
+impl ApiKeyAuth<Identity> {
+    /// Accept the given `(name, key)` pairs. The name becomes the [`Identity`] of clients
+    /// sending the key, and several keys may share a name.
+    pub fn keys<I, N, K>(keys: I) -> Self
+    where
+        I: IntoIterator<Item = (N, K)>,
+        N: Into<String>,
+        K: Into<String>,
+    {
+        let keys: Arc<[(Identity, String)]> = keys
+            .into_iter()
+            .map(|(name, key)| (Identity::new(name), key.into()))
+            .collect();
+        Self::new(Arc::new(move |key: String| {
+            let identity = find_in_constant_time(&keys, &key).cloned();
+            Box::pin(std::future::ready(identity))
+        }))
+    }
+}
+
+impl<P> ApiKeyAuth<P> {
+    /// Look up the principal of clients by their key with `lookup`.
+    ///
+    /// The principal is inserted into the request extensions. Returning `None` rejects the
+    /// request.
+    ///
+    /// Unlike for a set of keys, comparing the key in constant time is up to `lookup`, e.g. by
+    /// looking up a hash of the key.
+    pub fn lookup<F, Fut>(lookup: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<P>> + Send + 'static,
+    {
+        Self::new(Arc::new(move |key| Box::pin(lookup(key))))
+    }
+
+    fn new(lookup: Arc<LookupFn<P>>) -> Self {
+        Self {
+            sources: Vec::new(),
+            lookup,
+        }
+    }
+
+    /// Read the key from the header `name`.
+    pub fn header(mut self, name: HeaderName) -> Self {
+        self.sources.push(Source::Header(name));
+        self
+    }
+
+    /// Read the key from the query parameter `name`.
+    ///
+    /// The parameter is removed from the request URI.
+    pub fn query<N: Into<String>>(mut self, name: N) -> Self {
+        self.sources.push(Source::Query(name.into()));
+        self
+    }
+
+    /// Read the key from the cookie `name`.
+    pub fn cookie<N: Into<String>>(mut self, name: N) -> Self {
+        self.sources.push(Source::Cookie(name.into()));
+        self
+    }
+
+    fn key<B>(&self, request: &Request<B>) -> Option<String> {
+        if self.sources.is_empty() {
+            return header_key(request.headers(), &HeaderName::from_static("x-api-key"));
+        }
+        self.sources.iter().find_map(|source| match source {
+            Source::Header(name) => header_key(request.headers(), name),
+            Source::Query(name) => query_key(request.uri(), name),
+            Source::Cookie(name) => cookie_key(request.headers(), name),
+        })
+    }
+
+    /// Remove the query parameters keys are read from.
+    fn strip_query<B>(&self, request: &mut Request<B>) {
+        let Some(query) = request.uri().query() else {
+            return;
+        };
+        let is_key = |pair: &str| {
+            let name = pair.split('=').next().unwrap_or_default();
+            let Some(name) = decode_form_component(name.as_bytes()) else {
+                return false;
+            };
+            self.sources
+                .iter()
+                .any(|source| matches!(source, Source::Query(key) if *key == name))
+        };
+        if !query.split('&').any(is_key) {
+            return;
+        }
+
+        let query = query
+            .split('&')
+            .filter(|pair| !is_key(pair))
+            .collect::<Vec<_>>()
+            .join("&");
+        let path_and_query = if query.is_empty() {
+            request.uri().path().to_owned()
+        } else {
+            format!("{}?{}", request.uri().path(), query)
+        };
+
+        let mut parts = request.uri().clone().into_parts();
+        parts.path_and_query = path_and_query.parse().ok();
+        if let Ok(uri) = Uri::from_parts(parts) {
+            *request.uri_mut() = uri;
+        }
+    }
+}
+
+fn header_key(headers: &HeaderMap, name: &HeaderName) -> Option<String> {
+    let key = headers.get(name)?.to_str().ok()?.trim();
+    (!key.is_empty()).then(|| key.to_owned())
+}
+
+fn query_key(uri: &Uri, name: &str) -> Option<String> {
+    uri.query()?.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        let key = decode_form_component(key.as_bytes())?;
+        let value = decode_form_component(value.as_bytes())?;
+        (key == name && !value.is_empty()).then_some(value)
+    })
+}
+
+fn cookie_key(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|pair| {
+            let (key, value) = pair.trim().split_once('=')?;
+            let value = value.trim_matches('"');
+            (key == name && !value.is_empty()).then(|| value.to_owned())
+        })
+}
+
+impl<P> Clone for ApiKeyAuth<P> {
+    fn clone(&self) -> Self {
+        Self {
+            sources: self.sources.clone(),
+            lookup: self.lookup.clone(),
+        }
+    }
+}
+
+impl<P> fmt::Debug for ApiKeyAuth<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ApiKeyAuth")
+            .field("sources", &self.sources)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Type that performs API key authorization.
+///
+/// See [`AsyncRequireAuthorizationLayer::api_key`] for more details.
+pub struct RequireApiKey<P, ResBody> {
+    auth: ApiKeyAuth<P>,
+    _ty: PhantomData<fn() -> ResBody>,
+}
+
+impl<P, ResBody> RequireApiKey<P, ResBody> {
+    /// Create a new `RequireApiKey`.
+    pub fn new(auth: ApiKeyAuth<P>) -> Self {
+        Self {
+            auth,
+            _ty: PhantomData,
+        }
+    }
+}
+
+impl<P, ResBody> Clone for RequireApiKey<P, ResBody> {
+    fn clone(&self) -> Self {
+        Self {
+            auth: self.auth.clone(),
+            _ty: PhantomData,
+        }
+    }
+}
+
+impl<P, ResBody> fmt::Debug for RequireApiKey<P, ResBody> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequireApiKey")
+            .field("auth", &self.auth)
+            .finish()
+    }
+}
+
+impl<B, P, ResBody> AsyncAuthorizeRequest<B> for RequireApiKey<P, ResBody>
+where
+    B: Send + 'static,
+    P: Clone + Send + Sync + 'static,
+    ResBody: Default + Send + 'static,
+{
+    type RequestBody = B;
+    type ResponseBody = ResBody;
+    type Future = Pin<Box<dyn Future<Output = Result<Request<B>, Response<ResBody>>> + Send>>;
+
+    fn authorize(&mut self, mut request: Request<B>) -> Self::Future {
+        let key = self.auth.key(&request);
+        self.auth.strip_query(&mut request);
+        let lookup = self.auth.lookup.clone();
+
+        Box::pin(async move {
+            let principal = match key {
+                Some(key) => lookup(key).await,
+                None => None,
+            };
+            match principal {
+                Some(principal) => {
+                    request.extensions_mut().insert(principal);
+                    Ok(request)
+                }
+                None => {
+                    let mut res = Response::new(ResBody::default());
+                    *res.status_mut() = StatusCode::UNAUTHORIZED;
+                    Err(res)
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{status_and_body, unauthorized, Body};
+    use tower::{BoxError, ServiceBuilder, ServiceExt};
+
+    async fn echo(req: Request<Body>) -> Result<Response<Body>, BoxError> {
+        let identity = req.extensions().get::<Identity>().unwrap();
+        let body = format!("{} {}", identity.name(), req.uri());
+        Ok(Response::new(Body::from(body)))
+    }
+
+    async fn call(auth: ApiKeyAuth, request: Request<Body>) -> (StatusCode, String) {
+        let svc = ServiceBuilder::new()
+            .layer(AsyncRequireAuthorizationLayer::api_key(auth))
+            .service_fn(echo);
+        status_and_body(svc, request).await
+    }
+
+    fn keys() -> ApiKeyAuth {
+        ApiKeyAuth::keys([("ci", "ci-key"), ("deploy", "deploy-key")])
+    }
+
+    fn request(uri: &str, header: Option<(&str, &str)>) -> Request<Body> {
+        let mut request = Request::builder().uri(uri);
+        if let Some((name, value)) = header {
+            request = request.header(name, value);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn default_header() {
+        assert_eq!(
+            call(keys(), request("/", Some(("x-api-key", "deploy-key")))).await,
+            (StatusCode::OK, "deploy /".to_owned())
+        );
+        assert_eq!(
+            call(keys(), request("/", Some(("x-api-key", "wrong")))).await,
+            unauthorized()
+        );
+        assert_eq!(
+            call(keys(), request("/?api_key=ci-key", None)).await,
+            unauthorized()
+        );
+    }
+
+    #[tokio::test]
+    async fn query() {
+        let auth = || keys().query("api_key");
+
+        assert_eq!(
+            call(auth(), request("/a?x=1&api_key=ci-key&y=2", None)).await,
+            (StatusCode::OK, "ci /a?x=1&y=2".to_owned())
+        );
+        assert_eq!(
+            call(auth(), request("/a?api_key=ci-key", None)).await,
+            (StatusCode::OK, "ci /a".to_owned())
+        );
+        assert_eq!(
+            call(auth(), request("/a?api%5Fkey=ci%2Dkey", None)).await,
+            (StatusCode::OK, "ci /a".to_owned())
+        );
+        assert_eq!(
+            call(auth(), request("/a?api_key=", None)).await,
+            unauthorized()
+        );
+        assert_eq!(
+            call(auth(), request("/a?api_key=wrong", None)).await,
+            unauthorized()
+        );
+        // Keys are form encoded, so `+` is a space.
+        assert_eq!(
+            call(
+                ApiKeyAuth::keys([("ci", "ci key")]).query("api key"),
+                request("/a?api+key=ci+key&x=1", None)
+            )
+            .await,
+            (StatusCode::OK, "ci /a?x=1".to_owned())
+        );
+        // Only the configured sources are used.
+        assert_eq!(
+            call(auth(), request("/", Some(("x-api-key", "ci-key")))).await,
+            unauthorized()
+        );
+    }
+
+    #[tokio::test]
+    async fn cookie() {
+        let auth = || keys().cookie("api_key");
+
+        assert_eq!(
+            call(
+                auth(),
+                request("/", Some(("cookie", "theme=dark; api_key=ci-key")))
+            )
+            .await,
+            (StatusCode::OK, "ci /".to_owned())
+        );
+        assert_eq!(
+            call(
+                auth(),
+                request("/", Some(("cookie", "other_api_key=ci-key")))
+            )
+            .await,
+            unauthorized()
+        );
+    }
+
+    #[tokio::test]
+    async fn sources_in_order() {
+        let auth = || keys().header(HeaderName::from_static("x-key")).query("key");
+
+        assert_eq!(
+            call(
+                auth(),
+                request("/?key=deploy-key", Some(("x-key", "ci-key")))
+            )
+            .await,
+            (StatusCode::OK, "ci /".to_owned())
+        );
+        assert_eq!(
+            call(auth(), request("/?key=deploy-key", None)).await,
+            (StatusCode::OK, "deploy /".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn async_lookup() {
+        #[derive(Clone)]
+        struct Tenant(&'static str);
+
+        let svc = ServiceBuilder::new()
+            .layer(AsyncRequireAuthorizationLayer::api_key(
+                ApiKeyAuth::lookup(|key: String| async move {
+                    tokio::task::yield_now().await;
+                    (key == "acme-key").then_some(Tenant("acme"))
+                })
+                .query("api_key"),
+            ))
+            .service_fn(|req: Request<Body>| async move {
+                assert_eq!(req.uri(), "/");
+                let tenant = req.extensions().get::<Tenant>().unwrap();
+                Ok::<_, BoxError>(Response::new(Body::from(tenant.0)))
+            });
+
+        let res = svc
+            .clone()
+            .oneshot(request("/?api_key=acme-key", None))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = svc.oneshot(request("/?api_key=other", None)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,591 @@
+//! Add authorization to requests using a token from an asynchronous [`TokenProvider`].
+//!
+//! [`AsyncAddAuthorizationLayer`] is like [`AddAuthorizationLayer::bearer`] for tokens that
+//! expire, such as OAuth 2 access tokens from the client credentials flow. The token is cached
+//! and fetched again shortly before it expires. Concurrent requests that need a new token wait
+//! for a single fetch instead of each calling the provider.
+//!
+//! Optionally, a request that gets a `401 Unauthorized` response is sent once more with a new
+//! token, in case the token was revoked before it expired.
+//!
+//! # Example
+//!
+//! ```
+//! use http::{Request, Response, StatusCode, header::AUTHORIZATION};
+//! use http_body_util::Full;
+//! use bytes::Bytes;
+//! use std::time::Duration;
+//! use tower::{Service, ServiceExt, ServiceBuilder, BoxError};
+//! use tower_http::auth::AsyncAddAuthorizationLayer;
+//! use tower_http::auth::async_add_authorization::Token;
+//!
+//! async fn fetch_token() -> Result<Token, BoxError> {
+//!     // Request a token from the authorization server...
+//!     Ok(Token::new("eyJhbGciOi...").expires_in(Duration::from_secs(3600)))
+//! }
+//!
+//! # async fn api(request: Request<Full<Bytes>>) -> Result<Response<Full<Bytes>>, BoxError> {
+//! #     assert_eq!(request.headers()[AUTHORIZATION], "Bearer eyJhbGciOi...");
+//! #     Ok(Response::new(Full::default()))
+//! # }
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), BoxError> {
+//! let mut client = ServiceBuilder::new()
+//!     .layer(
+//!         AsyncAddAuthorizationLayer::new(fetch_token)
+//!             .retry_unauthorized(true)
+//!             .as_sensitive(true),
+//!     )
+//!     .service_fn(api);
+//!
+//! let response = client
+//!     .ready()
+//!     .await?
+//!     .call(Request::new(Full::default()))
+//!     .await?;
+//! assert_eq!(StatusCode::OK, response.status());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`AddAuthorizationLayer::bearer`]: crate::auth::AddAuthorizationLayer::bearer
+
+use crate::BoxError;
+use http::{header, HeaderValue, Request, Response, StatusCode};
+use http_body::Body;
+use std::{
+    fmt,
+    future::{poll_fn, Future},
+    mem,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// An access token returned by a [`TokenProvider`].
+#[derive(Clone)]
+pub struct Token {
+    value: String,
+    expires_in: Option<Duration>,
+}
+
+impl Token {
+    /// Create a new `Token` that doesn't expire.
+    ///
+    /// The token is sent as `Authorization: Bearer {token}`.
+    pub fn new<T: Into<String>>(token: T) -> Self {
+        Self {
+            value: token.into(),
+            expires_in: None,
+        }
+    }
+
+    /// Set the lifetime of the token, such as the `expires_in` of an OAuth 2 token response.
+    pub fn expires_in(mut self, expires_in: Duration) -> Self {
+        self.expires_in = Some(expires_in);
+        self
+    }
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Token")
+            .field("expires_in", &self.expires_in)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Trait for fetching access tokens.
+///
+/// Implemented for closures returning a future, such as `async fn`s.
+pub trait TokenProvider: Send + Sync + 'static {
+    /// The error returned when a token can't be fetched.
+    type Error: Into<BoxError>;
+
+    /// The future returned by `fetch`.
+    type Future: Future<Output = Result<Token, Self::Error>> + Send;
+
+    /// Fetch a new token.
+    fn fetch(&self) -> Self::Future;
+}
+
+impl<F, Fut, E> TokenProvider for F
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Token, E>> + Send,
+    E: Into<BoxError>,
+{
+    type Error = E;
+    type Future = Fut;
+
+    fn fetch(&self) -> Self::Future {
+        self()
+    }
+}
+
+/// The token shared by all services created from the same layer.
+struct Shared<P> {
+    provider: P,
+    cached: Mutex<Option<Cached>>,
+    // Held while fetching, so only one request fetches a new token.
+    fetch: tokio::sync::Mutex<()>,
+}
+
+#[derive(Clone)]
+struct Cached {
+    value: HeaderValue,
+    expires_at: Option<Instant>,
+}
+
+impl<P: TokenProvider> Shared<P> {
+    /// The cached token, unless it's about to expire or is the `rejected` one.
+    fn cached(&self, config: &Config, rejected: Option<&HeaderValue>) -> Option<HeaderValue> {
+        let cached = self.cached.lock().unwrap();
+        let cached = cached.as_ref()?;
+        let fresh = cached.expires_at.map_or(true, |expires_at| {
+            Instant::now() + config.refresh_before < expires_at
+        });
+        (fresh && Some(&cached.value) != rejected).then(|| cached.value.clone())
+    }
+
+    async fn token(
+        &self,
+        config: &Config,
+        rejected: Option<&HeaderValue>,
+    ) -> Result<HeaderValue, BoxError> {
+        if let Some(value) = self.cached(config, rejected) {
+            return Ok(value);
+        }
+
+        let _fetching = self.fetch.lock().await;
+        // Another request may have fetched a token while we waited.
+        if let Some(value) = self.cached(config, rejected) {
+            return Ok(value);
+        }
+
+        let token = self.provider.fetch().await.map_err(Into::into)?;
+        let mut value = HeaderValue::from_str(&format!("Bearer {}", token.value))?;
+        value.set_sensitive(config.sensitive);
+        *self.cached.lock().unwrap() = Some(Cached {
+            value: value.clone(),
+            expires_at: token
+                .expires_in
+                .map(|expires_in| Instant::now() + expires_in),
+        });
+        Ok(value)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Config {
+    refresh_before: Duration,
+    retry_unauthorized: bool,
+    sensitive: bool,
+}
+
+/// Layer that applies [`AsyncAddAuthorization`] which adds a token from a [`TokenProvider`] to
+/// all requests using the [`Authorization`] header.
+///
+/// Services created from the same layer share the cached token.
+///
+/// See the [module docs](crate::auth::async_add_authorization) for an example.
+///
+/// [`Authorization`]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Authorization
+pub struct AsyncAddAuthorizationLayer<P> {
+    shared: Arc<Shared<P>>,
+    config: Config,
+}
+
+impl<P> AsyncAddAuthorizationLayer<P> {
+    /// Authorize requests using tokens from `provider`.
+    pub fn new(provider: P) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                provider,
+                cached: Mutex::new(None),
+                fetch: tokio::sync::Mutex::new(()),
+            }),
+            config: Config {
+                refresh_before: Duration::from_secs(60),
+                retry_unauthorized: false,
+                sensitive: false,
+            },
+        }
+    }
+
+    /// Set how long before it expires a token is replaced.
+    ///
+    /// Defaults to 60 seconds.
+    pub fn refresh_before(mut self, refresh_before: Duration) -> Self {
+        self.config.refresh_before = refresh_before;
+        self
+    }
+
+    /// Send a request once more with a new token if it gets a `401 Unauthorized` response.
+    ///
+    /// Only requests whose body is empty, according to [`Body::size_hint`], are sent again.
+    /// Their body is recreated with `Default`.
+    ///
+    /// Defaults to `false`.
+    pub fn retry_unauthorized(mut self, retry: bool) -> Self {
+        self.config.retry_unauthorized = retry;
+        self
+    }
+
+    /// Mark the header as [sensitive].
+    ///
+    /// This can for example be used to hide the header value from logs.
+    ///
+    /// [sensitive]: https://docs.rs/http/latest/http/header/struct.HeaderValue.html#method.set_sensitive
+    #[allow(clippy::wrong_self_convention)]
+    pub fn as_sensitive(mut self, sensitive: bool) -> Self {
+        self.config.sensitive = sensitive;
+        self
+    }
+}
+
+impl<P> Clone for AsyncAddAuthorizationLayer<P> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            config: self.config,
+        }
+    }
+}
+
+impl<P> fmt::Debug for AsyncAddAuthorizationLayer<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncAddAuthorizationLayer")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, P> Layer<S> for AsyncAddAuthorizationLayer<P> {
+    type Service = AsyncAddAuthorization<S, P>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AsyncAddAuthorization {
+            inner,
+            shared: self.shared.clone(),
+            config: self.config,
+        }
+    }
+}
+
+/// Middleware that adds a token from a [`TokenProvider`] to all requests using the
+/// [`Authorization`] header.
+///
+/// See the [module docs](crate::auth::async_add_authorization) for an example.
+///
+/// [`Authorization`]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Authorization
+pub struct AsyncAddAuthorization<S, P> {
+    inner: S,
+    shared: Arc<Shared<P>>,
+    config: Config,
+}
+
+impl<S, P> AsyncAddAuthorization<S, P> {
+    /// Authorize requests using tokens from `provider`.
+    pub fn new(inner: S, provider: P) -> Self {
+        AsyncAddAuthorizationLayer::new(provider).layer(inner)
+    }
+
+    define_inner_service_accessors!();
+
+    /// Returns a new [`Layer`] that wraps services with an `AsyncAddAuthorization` middleware.
+    ///
+    /// [`Layer`]: tower_layer::Layer
+    pub fn layer(provider: P) -> AsyncAddAuthorizationLayer<P> {
+        AsyncAddAuthorizationLayer::new(provider)
+    }
+}
+
+impl<S, P> Clone for AsyncAddAuthorization<S, P>
+where
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            shared: self.shared.clone(),
+            config: self.config,
+        }
+    }
+}
+
+impl<S, P> fmt::Debug for AsyncAddAuthorization<S, P>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncAddAuthorization")
+            .field("inner", &self.inner)
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, P, ReqBody, ResBody> Service<Request<ReqBody>> for AsyncAddAuthorization<S, P>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    P: TokenProvider,
+    ReqBody: Body + Default + Send + 'static,
+    ResBody: Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = BoxError;
+    type Future = ResponseFuture<Response<ResBody>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        let mut inner = self.inner.clone();
+        // mem::swap due to https://docs.rs/tower/latest/tower/trait.Service.html#be-careful-when-cloning-inner-services
+        mem::swap(&mut self.inner, &mut inner);
+        let shared = self.shared.clone();
+        let config = self.config;
+
+        ResponseFuture {
+            inner: Box::pin(async move {
+                let value = shared.token(&config, None).await?;
+
+                let retry = (config.retry_unauthorized
+                    && req.body().size_hint().exact() == Some(0))
+                .then(|| {
+                    let mut retry = Request::new(ReqBody::default());
+                    *retry.method_mut() = req.method().clone();
+                    *retry.uri_mut() = req.uri().clone();
+                    *retry.version_mut() = req.version();
+                    *retry.headers_mut() = req.headers().clone();
+                    *retry.extensions_mut() = req.extensions().clone();
+                    retry
+                });
+
+                req.headers_mut()
+                    .insert(header::AUTHORIZATION, value.clone());
+                let res = inner.call(req).await.map_err(Into::into)?;
+
+                match retry {
+                    Some(mut req) if res.status() == StatusCode::UNAUTHORIZED => {
+                        let value = shared.token(&config, Some(&value)).await?;
+                        req.headers_mut().insert(header::AUTHORIZATION, value);
+                        poll_fn(|cx| inner.poll_ready(cx))
+                            .await
+                            .map_err(Into::into)?;
+                        inner.call(req).await.map_err(Into::into)
+                    }
+                    _ => Ok(res),
+                }
+            }),
+        }
+    }
+}
+
+/// Response future for [`AsyncAddAuthorization`].
+pub struct ResponseFuture<T> {
+    inner: Pin<Box<dyn Future<Output = Result<T, BoxError>> + Send>>,
+}
+
+impl<T> Future for ResponseFuture<T> {
+    type Output = Result<T, BoxError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.inner.as_mut().poll(cx)
+    }
+}
+
+impl<T> fmt::Debug for ResponseFuture<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseFuture").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{status_and_body, Body};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::{service_fn, util::BoxCloneService, ServiceBuilder, ServiceExt};
+
+    /// A provider of `token-1`, `token-2`, ... and the number of fetches.
+    fn counting_provider(
+        expires_in: Option<Duration>,
+    ) -> (impl TokenProvider<Error = BoxError>, Arc<AtomicUsize>) {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let provider = {
+            let fetches = fetches.clone();
+            move || {
+                let fetches = fetches.clone();
+                async move {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    let n = fetches.fetch_add(1, Ordering::SeqCst) + 1;
+                    let token = Token::new(format!("token-{}", n));
+                    Ok(match expires_in {
+                        Some(expires_in) => token.expires_in(expires_in),
+                        None => token,
+                    })
+                }
+            }
+        };
+        (provider, fetches)
+    }
+
+    /// Echoes the `Authorization` header, and rejects every token but `accepted`.
+    fn api(accepted: &'static str) -> BoxCloneService<Request<Body>, Response<Body>, BoxError> {
+        BoxCloneService::new(service_fn(move |req: Request<Body>| async move {
+            let authorization = req.headers()[header::AUTHORIZATION]
+                .to_str()
+                .unwrap()
+                .to_owned();
+            let mut res = Response::new(Body::from(authorization.clone()));
+            if authorization != format!("Bearer {}", accepted) {
+                *res.status_mut() = StatusCode::UNAUTHORIZED;
+            }
+            Ok(res)
+        }))
+    }
+
+    async fn call<S>(svc: S, body: Body) -> (StatusCode, String)
+    where
+        S: Service<Request<Body>, Response = Response<Body>, Error = BoxError>,
+    {
+        status_and_body(svc, Request::new(body)).await
+    }
+
+    fn ok(token: &str) -> (StatusCode, String) {
+        (StatusCode::OK, format!("Bearer {}", token))
+    }
+
+    #[tokio::test]
+    async fn caches_token() {
+        let (provider, fetches) = counting_provider(Some(Duration::from_secs(3600)));
+        let svc = ServiceBuilder::new()
+            .layer(AsyncAddAuthorizationLayer::new(provider))
+            .service(api("token-1"));
+
+        assert_eq!(call(svc.clone(), Body::empty()).await, ok("token-1"));
+        assert_eq!(call(svc, Body::empty()).await, ok("token-1"));
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn refreshes_before_expiry() {
+        let (provider, fetches) = counting_provider(Some(Duration::from_secs(30)));
+        let svc = ServiceBuilder::new()
+            .layer(AsyncAddAuthorizationLayer::new(provider))
+            .service(api("token-2"));
+
+        call(svc.clone(), Body::empty()).await;
+        assert_eq!(call(svc, Body::empty()).await, ok("token-2"));
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+        let (provider, fetches) = counting_provider(Some(Duration::from_secs(30)));
+        let svc = ServiceBuilder::new()
+            .layer(
+                AsyncAddAuthorizationLayer::new(provider).refresh_before(Duration::from_secs(10)),
+            )
+            .service(api("token-1"));
+
+        call(svc.clone(), Body::empty()).await;
+        assert_eq!(call(svc, Body::empty()).await, ok("token-1"));
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn single_flight() {
+        let (provider, fetches) = counting_provider(None);
+        let svc = ServiceBuilder::new()
+            .layer(AsyncAddAuthorizationLayer::new(provider))
+            .service(api("token-1"));
+
+        let responses =
+            futures_util::future::join_all((0..10).map(|_| call(svc.clone(), Body::empty()))).await;
+        for response in responses {
+            assert_eq!(response, ok("token-1"));
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn retry_unauthorized() {
+        let (provider, fetches) = counting_provider(None);
+        let svc = ServiceBuilder::new()
+            .layer(AsyncAddAuthorizationLayer::new(provider).retry_unauthorized(true))
+            .service(api("token-2"));
+
+        assert_eq!(call(svc.clone(), Body::empty()).await, ok("token-2"));
+        assert_eq!(call(svc.clone(), Body::empty()).await, ok("token-2"));
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+        // Requests with a body aren't sent again.
+        let (provider, fetches) = counting_provider(None);
+        let svc = ServiceBuilder::new()
+            .layer(AsyncAddAuthorizationLayer::new(provider).retry_unauthorized(true))
+            .service(api("token-2"));
+        assert_eq!(
+            call(svc, Body::from("body")).await,
+            (StatusCode::UNAUTHORIZED, "Bearer token-1".to_owned())
+        );
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        // Only once.
+        let (provider, fetches) = counting_provider(None);
+        let svc = ServiceBuilder::new()
+            .layer(AsyncAddAuthorizationLayer::new(provider).retry_unauthorized(true))
+            .service(api("token-3"));
+        assert_eq!(
+            call(svc, Body::empty()).await,
+            (StatusCode::UNAUTHORIZED, "Bearer token-2".to_owned())
+        );
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn without_retry() {
+        let (provider, fetches) = counting_provider(None);
+        let svc = ServiceBuilder::new()
+            .layer(AsyncAddAuthorizationLayer::new(provider))
+            .service(api("token-2"));
+
+        assert_eq!(
+            call(svc, Body::empty()).await,
+            (StatusCode::UNAUTHORIZED, "Bearer token-1".to_owned())
+        );
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn making_header_sensitive() {
+        let (provider, _) = counting_provider(None);
+        let svc = ServiceBuilder::new()
+            .layer(AsyncAddAuthorizationLayer::new(provider).as_sensitive(true))
+            .service_fn(|request: Request<Body>| async move {
+                let auth = request.headers().get(header::AUTHORIZATION).unwrap();
+                assert!(auth.is_sensitive());
+                Ok::<_, BoxError>(Response::new(Body::empty()))
+            });
+
+        let res = svc.oneshot(Request::new(Body::empty())).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn provider_error() {
+        let svc = ServiceBuilder::new()
+            .layer(AsyncAddAuthorizationLayer::new(|| async {
+                Err::<Token, _>("authorization server unavailable")
+            }))
+            .service(api("token-1"));
+
+        let err = svc.oneshot(Request::new(Body::empty())).await.unwrap_err();
+        assert_eq!(err.to_string(), "authorization server unavailable");
+    }
+}
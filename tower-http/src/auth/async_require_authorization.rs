@@ -136,7 +136,7 @@ use tower_service::Service;
 /// [`Authorization`]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Authorization
 #[derive(Debug, Clone)]
 pub struct AsyncRequireAuthorizationLayer<T> {
-    auth: T,
+    pub(crate) auth: T,
 }
 
 impl<T> AsyncRequireAuthorizationLayer<T> {
@@ -0,0 +1,609 @@
+//! Authorize requests using `Basic` or `Bearer` credentials from a set or a lookup callback.
+//!
+//! [`BasicAuth`] and [`BearerAuth`] accept any number of credentials, so tokens can be rotated
+//! and clients get their own passwords. Credentials are compared in constant time, and the
+//! [`Identity`] of the authorized client is inserted into the request extensions. Requests that
+//! aren't authorized get a `401 Unauthorized` response with a `WWW-Authenticate` challenge for
+//! the configured realm.
+//!
+//! Since credentials are sent in clear text it is recommended to use HTTPS/TLS with this
+//! middleware. However use of HTTPS/TLS is not enforced by this middleware.
+//!
+//! # Example
+//!
+//! ```
+//! use http::{Request, Response, StatusCode, header::{AUTHORIZATION, WWW_AUTHENTICATE}};
+//! use http_body_util::Full;
+//! use bytes::Bytes;
+//! use tower::{Service, ServiceExt, ServiceBuilder, BoxError};
+//! use tower_http::auth::credentials::{BearerAuth, Identity};
+//! use tower_http::validate_request::ValidateRequestHeaderLayer;
+//!
+//! async fn handle(request: Request<Full<Bytes>>) -> Result<Response<Full<Bytes>>, BoxError> {
+//!     let identity = request.extensions().get::<Identity>().unwrap();
+//!     Ok(Response::new(Full::from(format!("Hello {}", identity.name()))))
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), BoxError> {
+//! let mut service = ServiceBuilder::new()
+//!     .layer(ValidateRequestHeaderLayer::bearer_auth(
+//!         BearerAuth::tokens([
+//!             ("deploy", "new-token"),
+//!             // Still accepted until all clients have been rotated.
+//!             ("deploy", "old-token"),
+//!             ("ci", "ci-token"),
+//!         ])
+//!         .realm("api"),
+//!     ))
+//!     .service_fn(handle);
+//!
+//! let request = Request::builder()
+//!     .header(AUTHORIZATION, "Bearer old-token")
+//!     .body(Full::default())
+//!     .unwrap();
+//! let response = service.ready().await?.call(request).await?;
+//! assert_eq!(StatusCode::OK, response.status());
+//!
+//! let response = service.ready().await?.call(Request::new(Full::default())).await?;
+//! assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+//! assert_eq!(response.headers()[WWW_AUTHENTICATE], "Bearer realm=\"api\"");
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::helpers::{constant_time_eq, find_in_constant_time, quote};
+use crate::validate_request::{ValidateRequest, ValidateRequestHeader, ValidateRequestHeaderLayer};
+use base64::Engine as _;
+use http::{
+    header::{self, HeaderValue},
+    Request, Response, StatusCode,
+};
+use std::{fmt, marker::PhantomData, sync::Arc};
+
+const BASE64: base64::engine::GeneralPurpose = base64::engine::general_purpose::STANDARD;
+
+impl<S, ResBody> ValidateRequestHeader<S, RequireBasic<ResBody>> {
+    /// Authorize requests using `Basic` credentials.
+    ///
+    /// See [`BasicAuth`] for more details.
+    pub fn basic_auth(inner: S, auth: BasicAuth) -> Self
+    where
+        ResBody: Default,
+    {
+        Self::custom(inner, RequireBasic::new(auth))
+    }
+}
+
+impl<ResBody> ValidateRequestHeaderLayer<RequireBasic<ResBody>> {
+    /// Authorize requests using `Basic` credentials.
+    ///
+    /// See [`BasicAuth`] for more details.
+    pub fn basic_auth(auth: BasicAuth) -> Self
+    where
+        ResBody: Default,
+    {
+        Self::custom(RequireBasic::new(auth))
+    }
+}
+
+impl<S, ResBody> ValidateRequestHeader<S, RequireBearer<ResBody>> {
+    /// Authorize requests using `Bearer` tokens.
+    ///
+    /// See [`BearerAuth`] for more details.
+    pub fn bearer_auth(inner: S, auth: BearerAuth) -> Self
+    where
+        ResBody: Default,
+    {
+        Self::custom(inner, RequireBearer::new(auth))
+    }
+}
+
+impl<ResBody> ValidateRequestHeaderLayer<RequireBearer<ResBody>> {
+    /// Authorize requests using `Bearer` tokens.
+    ///
+    /// See [`BearerAuth`] for more details.
+    pub fn bearer_auth(auth: BearerAuth) -> Self
+    where
+        ResBody: Default,
+    {
+        Self::custom(RequireBearer::new(auth))
+    }
+}
+
+/// The identity of an authorized client, inserted into the request extensions.
+///
+/// For [`BasicAuth`] it is the username, for [`BearerAuth`] the name of the token.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Identity(String);
+
+impl Identity {
+    /// Create a new `Identity`.
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        Self(name.into())
+    }
+
+    /// The name of the client.
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+type BasicLookupFn = dyn Fn(&str) -> Option<String> + Send + Sync + 'static;
+type BearerLookupFn = dyn Fn(&str) -> Option<Identity> + Send + Sync + 'static;
+
+/// Configuration of `Basic` authorization.
+///
+/// The `Authorization` header is required to be `Basic {credentials}` where `credentials` is
+/// `base64_encode("{username}:{password}")`.
+///
+/// Rejected requests get a `Basic realm="{realm}", charset="UTF-8"` challenge.
+#[derive(Clone)]
+pub struct BasicAuth {
+    users: BasicUsers,
+    realm: String,
+}
+
+#[derive(Clone)]
+enum BasicUsers {
+    Set(Arc<[(String, String)]>),
+    Lookup(Arc<BasicLookupFn>),
+}
+
+impl BasicAuth {
+    /// Accept the given `(username, password)` pairs.
+    pub fn credentials<I, U, P>(credentials: I) -> Self
+    where
+        I: IntoIterator<Item = (U, P)>,
+        U: Into<String>,
+        P: Into<String>,
+    {
+        let credentials = credentials
+            .into_iter()
+            .map(|(username, password)| (username.into(), password.into()))
+            .collect();
+        Self::new(BasicUsers::Set(credentials))
+    }
+
+    /// Look up the password of a user with `lookup`.
+    ///
+    /// The password is compared to the one sent by the client in constant time.
+    pub fn lookup<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        Self::new(BasicUsers::Lookup(Arc::new(lookup)))
+    }
+
+    fn new(users: BasicUsers) -> Self {
+        Self {
+            users,
+            realm: "Restricted".to_owned(),
+        }
+    }
+
+    /// Sets the realm of the challenge.
+    ///
+    /// Defaults to `Restricted`.
+    pub fn realm<R: Into<String>>(mut self, realm: R) -> Self {
+        self.realm = realm.into();
+        self
+    }
+
+    fn authorize(&self, authorization: Option<&HeaderValue>) -> Option<Identity> {
+        let credentials = strip_scheme(authorization?, "Basic")?;
+        let credentials = BASE64.decode(credentials).ok()?;
+        let credentials = String::from_utf8(credentials).ok()?;
+        let (username, password) = credentials.split_once(':')?;
+
+        let authorized = match &self.users {
+            BasicUsers::Set(set) => set.iter().fold(false, |authorized, (u, p)| {
+                // Deliberately not short-circuiting, to compare against every entry.
+                let matches = constant_time_eq(u.as_bytes(), username.as_bytes())
+                    & constant_time_eq(p.as_bytes(), password.as_bytes());
+                authorized | matches
+            }),
+            BasicUsers::Lookup(lookup) => lookup(username).map_or(false, |expected| {
+                constant_time_eq(expected.as_bytes(), password.as_bytes())
+            }),
+        };
+
+        authorized.then(|| Identity::new(username))
+    }
+}
+
+impl fmt::Debug for BasicAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Deliberately leaves out the passwords.
+        let users = match &self.users {
+            BasicUsers::Set(set) => format!("Set({})", set.len()),
+            BasicUsers::Lookup(_) => "Lookup".to_owned(),
+        };
+        f.debug_struct("BasicAuth")
+            .field("users", &format_args!("{}", users))
+            .field("realm", &self.realm)
+            .finish()
+    }
+}
+
+/// Configuration of `Bearer` token authorization. Commonly used for OAuth 2.
+///
+/// The `Authorization` header is required to be `Bearer {token}`.
+///
+/// Rejected requests get a `Bearer realm="{realm}"` challenge, with `error="invalid_token"` if
+/// a token was sent.
+#[derive(Clone)]
+pub struct BearerAuth {
+    tokens: BearerTokens,
+    realm: String,
+}
+
+#[derive(Clone)]
+enum BearerTokens {
+    Set(Arc<[(Identity, String)]>),
+    Lookup(Arc<BearerLookupFn>),
+}
+
+impl BearerAuth {
+    /// Accept the given `(name, token)` pairs. The name becomes the [`Identity`] of clients
+    /// sending the token, and several tokens may share a name.
+    pub fn tokens<I, N, T>(tokens: I) -> Self
+    where
+        I: IntoIterator<Item = (N, T)>,
+        N: Into<String>,
+        T: Into<String>,
+    {
+        let tokens = tokens
+            .into_iter()
+            .map(|(name, token)| (Identity::new(name), token.into()))
+            .collect();
+        Self::new(BearerTokens::Set(tokens))
+    }
+
+    /// Look up the identity of clients by their token with `lookup`.
+    ///
+    /// Unlike for a set of tokens, comparing the token in constant time is up to `lookup`, e.g.
+    /// by looking up a hash of the token.
+    pub fn lookup<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<Identity> + Send + Sync + 'static,
+    {
+        Self::new(BearerTokens::Lookup(Arc::new(lookup)))
+    }
+
+    fn new(tokens: BearerTokens) -> Self {
+        Self {
+            tokens,
+            realm: "Restricted".to_owned(),
+        }
+    }
+
+    /// Sets the realm of the challenge.
+    ///
+    /// Defaults to `Restricted`.
+    pub fn realm<R: Into<String>>(mut self, realm: R) -> Self {
+        self.realm = realm.into();
+        self
+    }
+
+    fn authorize(&self, token: &str) -> Option<Identity> {
+        match &self.tokens {
+            BearerTokens::Set(set) => find_in_constant_time(set, token).cloned(),
+            BearerTokens::Lookup(lookup) => lookup(token),
+        }
+    }
+}
+
+impl fmt::Debug for BearerAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Deliberately leaves out the tokens.
+        let tokens = match &self.tokens {
+            BearerTokens::Set(set) => format!("Set({})", set.len()),
+            BearerTokens::Lookup(_) => "Lookup".to_owned(),
+        };
+        f.debug_struct("BearerAuth")
+            .field("tokens", &format_args!("{}", tokens))
+            .field("realm", &self.realm)
+            .finish()
+    }
+}
+
+/// Type that performs `Basic` authorization.
+///
+/// See [`ValidateRequestHeader::basic_auth`] for more details.
+pub struct RequireBasic<ResBody> {
+    auth: Arc<BasicAuth>,
+    _ty: PhantomData<fn() -> ResBody>,
+}
+
+impl<ResBody> RequireBasic<ResBody> {
+    fn new(auth: BasicAuth) -> Self {
+        Self {
+            auth: Arc::new(auth),
+            _ty: PhantomData,
+        }
+    }
+}
+
+impl<ResBody> Clone for RequireBasic<ResBody> {
+    fn clone(&self) -> Self {
+        Self {
+            auth: self.auth.clone(),
+            _ty: PhantomData,
+        }
+    }
+}
+
+impl<ResBody> fmt::Debug for RequireBasic<ResBody> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequireBasic")
+            .field("auth", &self.auth)
+            .finish()
+    }
+}
+
+impl<B, ResBody> ValidateRequest<B> for RequireBasic<ResBody>
+where
+    ResBody: Default,
+{
+    type ResponseBody = ResBody;
+
+    fn validate(&mut self, request: &mut Request<B>) -> Result<(), Response<Self::ResponseBody>> {
+        match self
+            .auth
+            .authorize(request.headers().get(header::AUTHORIZATION))
+        {
+            Some(identity) => {
+                request.extensions_mut().insert(identity);
+                Ok(())
+            }
+            None => {
+                let challenge =
+                    format!("Basic realm={}, charset=\"UTF-8\"", quote(&self.auth.realm));
+                Err(unauthorized(&challenge))
+            }
+        }
+    }
+}
+
+/// Type that performs `Bearer` token authorization.
+///
+/// See [`ValidateRequestHeader::bearer_auth`] for more details.
+pub struct RequireBearer<ResBody> {
+    auth: Arc<BearerAuth>,
+    _ty: PhantomData<fn() -> ResBody>,
+}
+
+impl<ResBody> RequireBearer<ResBody> {
+    fn new(auth: BearerAuth) -> Self {
+        Self {
+            auth: Arc::new(auth),
+            _ty: PhantomData,
+        }
+    }
+}
+
+impl<ResBody> Clone for RequireBearer<ResBody> {
+    fn clone(&self) -> Self {
+        Self {
+            auth: self.auth.clone(),
+            _ty: PhantomData,
+        }
+    }
+}
+
+impl<ResBody> fmt::Debug for RequireBearer<ResBody> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequireBearer")
+            .field("auth", &self.auth)
+            .finish()
+    }
+}
+
+impl<B, ResBody> ValidateRequest<B> for RequireBearer<ResBody>
+where
+    ResBody: Default,
+{
+    type ResponseBody = ResBody;
+
+    fn validate(&mut self, request: &mut Request<B>) -> Result<(), Response<Self::ResponseBody>> {
+        let token = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| strip_scheme(value, "Bearer"));
+
+        match token.map(|token| self.auth.authorize(token)) {
+            Some(Some(identity)) => {
+                request.extensions_mut().insert(identity);
+                Ok(())
+            }
+            Some(None) => Err(unauthorized(&format!(
+                "Bearer realm={}, error=\"invalid_token\"",
+                quote(&self.auth.realm)
+            ))),
+            None => Err(unauthorized(&format!(
+                "Bearer realm={}",
+                quote(&self.auth.realm)
+            ))),
+        }
+    }
+}
+
+/// The credentials after the case-insensitive auth `scheme`.
+pub(super) fn strip_scheme<'a>(value: &'a HeaderValue, scheme: &str) -> Option<&'a str> {
+    let (actual, credentials) = value.to_str().ok()?.split_once(' ')?;
+    let credentials = credentials.trim();
+    (actual.eq_ignore_ascii_case(scheme) && !credentials.is_empty()).then_some(credentials)
+}
+
+pub(super) fn unauthorized<ResBody: Default>(challenge: &str) -> Response<ResBody> {
+    let mut res = Response::new(ResBody::default());
+    *res.status_mut() = StatusCode::UNAUTHORIZED;
+    if let Ok(challenge) = HeaderValue::from_str(challenge) {
+        res.headers_mut()
+            .insert(header::WWW_AUTHENTICATE, challenge);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{check_authorization, Body};
+    use tower::{BoxError, ServiceBuilder};
+
+    async fn echo(req: Request<Body>) -> Result<Response<Body>, BoxError> {
+        let identity = req.extensions().get::<Identity>().unwrap();
+        Ok(Response::new(Body::from(identity.name().to_owned())))
+    }
+
+    fn basic(username: &str, password: &str) -> String {
+        format!(
+            "Basic {}",
+            BASE64.encode(format!("{}:{}", username, password))
+        )
+    }
+
+    #[tokio::test]
+    async fn basic_credentials() {
+        let svc = ServiceBuilder::new()
+            .layer(ValidateRequestHeaderLayer::basic_auth(
+                BasicAuth::credentials([("alice", "wonderland"), ("bob", "builder")])
+                    .realm("admin \"area\""),
+            ))
+            .service_fn(echo);
+
+        let challenge = "Basic realm=\"admin \\\"area\\\"\", charset=\"UTF-8\"";
+        let cases = [
+            (Some(basic("alice", "wonderland")), StatusCode::OK, "alice"),
+            (Some(basic("bob", "builder")), StatusCode::OK, "bob"),
+            (
+                Some(basic("alice", "builder")),
+                StatusCode::UNAUTHORIZED,
+                challenge,
+            ),
+            (
+                Some(basic("mallory", "wonderland")),
+                StatusCode::UNAUTHORIZED,
+                challenge,
+            ),
+            (
+                Some(basic("alice", "wonderland").replace("Basic", "basic")),
+                StatusCode::OK,
+                "alice",
+            ),
+            (
+                Some("Basic not-base64!".to_owned()),
+                StatusCode::UNAUTHORIZED,
+                challenge,
+            ),
+            (None, StatusCode::UNAUTHORIZED, challenge),
+        ];
+
+        for (authorization, status, expected) in cases {
+            assert_eq!(
+                check_authorization(svc.clone(), authorization.as_deref()).await,
+                (status, expected.to_owned()),
+                "{:?}",
+                authorization
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn basic_lookup() {
+        let svc = ServiceBuilder::new()
+            .layer(ValidateRequestHeaderLayer::basic_auth(BasicAuth::lookup(
+                |username| (username == "alice").then(|| "wonder:land".to_owned()),
+            )))
+            .service_fn(echo);
+
+        assert_eq!(
+            check_authorization(svc.clone(), Some(&basic("alice", "wonder:land"))).await,
+            (StatusCode::OK, "alice".to_owned())
+        );
+        assert_eq!(
+            check_authorization(svc, Some(&basic("alice", "wonder"))).await,
+            (
+                StatusCode::UNAUTHORIZED,
+                "Basic realm=\"Restricted\", charset=\"UTF-8\"".to_owned()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn bearer_tokens() {
+        let svc = ServiceBuilder::new()
+            .layer(ValidateRequestHeaderLayer::bearer_auth(BearerAuth::tokens(
+                [("deploy", "new"), ("deploy", "old"), ("ci", "ci-token")],
+            )))
+            .service_fn(echo);
+
+        let cases = [
+            (Some("Bearer new"), StatusCode::OK, "deploy"),
+            (Some("Bearer old"), StatusCode::OK, "deploy"),
+            (Some("bearer ci-token"), StatusCode::OK, "ci"),
+            (
+                Some("Bearer wrong"),
+                StatusCode::UNAUTHORIZED,
+                "Bearer realm=\"Restricted\", error=\"invalid_token\"",
+            ),
+            (
+                Some("Basic new"),
+                StatusCode::UNAUTHORIZED,
+                "Bearer realm=\"Restricted\"",
+            ),
+            (
+                None,
+                StatusCode::UNAUTHORIZED,
+                "Bearer realm=\"Restricted\"",
+            ),
+        ];
+
+        for (authorization, status, expected) in cases {
+            assert_eq!(
+                check_authorization(svc.clone(), authorization).await,
+                (status, expected.to_owned()),
+                "{:?}",
+                authorization
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn bearer_lookup() {
+        let svc = ServiceBuilder::new()
+            .layer(ValidateRequestHeaderLayer::bearer_auth(
+                BearerAuth::lookup(|token| token.strip_prefix("user-").map(Identity::new))
+                    .realm("api"),
+            ))
+            .service_fn(echo);
+
+        assert_eq!(
+            check_authorization(svc.clone(), Some("Bearer user-42")).await,
+            (StatusCode::OK, "42".to_owned())
+        );
+        assert_eq!(
+            check_authorization(svc, Some("Bearer admin")).await,
+            (
+                StatusCode::UNAUTHORIZED,
+                "Bearer realm=\"api\", error=\"invalid_token\"".to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn debug_leaves_out_secrets() {
+        let auth = BasicAuth::credentials([("alice", "wonderland")]);
+        assert_eq!(
+            format!("{:?}", auth),
+            "BasicAuth { users: Set(1), realm: \"Restricted\" }"
+        );
+
+        let auth = BearerAuth::tokens([("ci", "secret")]);
+        assert_eq!(
+            format!("{:?}", auth),
+            "BearerAuth { tokens: Set(1), realm: \"Restricted\" }"
+        );
+    }
+}
@@ -0,0 +1,853 @@
+//! Authorize requests using HTTP Digest access authentication ([RFC 7616]).
+//!
+//! [`DigestAuth`] is a [`ValidateRequest`] implementation for
+//! [`ValidateRequestHeaderLayer`]. Clients must answer a challenge with a hash of their
+//! password, a server-generated nonce and the request, so the password is never sent. Requests
+//! that aren't authorized get a `401 Unauthorized` response with a `WWW-Authenticate` challenge
+//! for each supported algorithm, `SHA-256` and `MD5` by default.
+//!
+//! Only the `auth` quality of protection is supported, and the `-sess` algorithm variants and
+//! `userhash` aren't.
+//!
+//! Nonces are signed with a key and carry their creation time, so no state is kept between
+//! requests. Expired nonces are answered with a challenge marked `stale=true`, which clients
+//! retry transparently. Since nonce counts aren't tracked, a captured request can be replayed
+//! for as long as its nonce is valid; use HTTPS and keep the [nonce
+//! lifetime](DigestAuth::nonce_lifetime) short.
+//!
+//! Requires the `auth-digest` feature.
+//!
+//! # Example
+//!
+//! ```
+//! use http::{Request, Response, StatusCode, header::WWW_AUTHENTICATE};
+//! use http_body_util::Full;
+//! use bytes::Bytes;
+//! use tower::{Service, ServiceExt, ServiceBuilder, BoxError};
+//! use tower_http::auth::digest::{DigestAuth, DigestSecret, DigestUser};
+//! use tower_http::validate_request::ValidateRequestHeaderLayer;
+//!
+//! async fn handle(request: Request<Full<Bytes>>) -> Result<Response<Full<Bytes>>, BoxError> {
+//!     let user = request.extensions().get::<DigestUser>().unwrap();
+//!     Ok(Response::new(Full::from(format!("Hello {}", user.username()))))
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), BoxError> {
+//! let auth = DigestAuth::new("devices@example.com", |username: &str| match username {
+//!     "sensor-1" => Some(DigestSecret::password("correct horse battery staple")),
+//!     _ => None,
+//! });
+//!
+//! let mut service = ServiceBuilder::new()
+//!     .layer(ValidateRequestHeaderLayer::digest(auth))
+//!     .service_fn(handle);
+//!
+//! // Requests without credentials are challenged.
+//! let response = service
+//!     .ready()
+//!     .await?
+//!     .call(Request::new(Full::default()))
+//!     .await?;
+//!
+//! assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+//! assert_eq!(response.headers().get_all(WWW_AUTHENTICATE).iter().count(), 2);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [RFC 7616]: https://www.rfc-editor.org/rfc/rfc7616
+//! [`ValidateRequest`]: crate::validate_request::ValidateRequest
+//! [`ValidateRequestHeaderLayer`]: crate::validate_request::ValidateRequestHeaderLayer
+
+use crate::helpers::{constant_time_eq, quote};
+use crate::validate_request::{ValidateRequest, ValidateRequestHeader, ValidateRequestHeaderLayer};
+use base64::Engine as _;
+use hmac::{Hmac, Mac};
+use http::{
+    header::{self, HeaderValue},
+    Request, Response, StatusCode,
+};
+use md5::Md5;
+use sha2::{Digest as _, Sha256};
+use std::{
+    collections::HashMap,
+    fmt::{self, Write as _},
+    marker::PhantomData,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const BASE64: base64::engine::GeneralPurpose = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+
+const TIMESTAMP_LEN: usize = 8;
+const NONCE_RANDOM_LEN: usize = 16;
+const MAC_LEN: usize = 32;
+const NONCE_LEN: usize = TIMESTAMP_LEN + NONCE_RANDOM_LEN + MAC_LEN;
+const MIN_KEY_LEN: usize = 32;
+
+impl<S, ResBody> ValidateRequestHeader<S, Digest<ResBody>> {
+    /// Authorize requests using HTTP Digest access authentication.
+    ///
+    /// See the [`digest` module docs](crate::auth::digest) for more details.
+    pub fn digest(inner: S, auth: DigestAuth) -> Self
+    where
+        ResBody: Default,
+    {
+        Self::custom(inner, Digest::new(auth))
+    }
+}
+
+impl<ResBody> ValidateRequestHeaderLayer<Digest<ResBody>> {
+    /// Authorize requests using HTTP Digest access authentication.
+    ///
+    /// See the [`digest` module docs](crate::auth::digest) for more details.
+    pub fn digest(auth: DigestAuth) -> Self
+    where
+        ResBody: Default,
+    {
+        Self::custom(Digest::new(auth))
+    }
+}
+
+/// Hash algorithms for Digest authentication.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum DigestAlgorithm {
+    /// `SHA-256`
+    Sha256,
+    /// `MD5`, for clients that don't support anything better.
+    Md5,
+}
+
+impl DigestAlgorithm {
+    /// The name of the algorithm in the `algorithm` parameter.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DigestAlgorithm::Sha256 => "SHA-256",
+            DigestAlgorithm::Md5 => "MD5",
+        }
+    }
+
+    fn from_param(param: &str) -> Option<Self> {
+        [DigestAlgorithm::Sha256, DigestAlgorithm::Md5]
+            .iter()
+            .copied()
+            .find(|algorithm| algorithm.as_str().eq_ignore_ascii_case(param))
+    }
+
+    /// Lowercase hex of the hash of `data`.
+    fn hash(&self, data: &str) -> String {
+        match self {
+            DigestAlgorithm::Sha256 => hex(&Sha256::digest(data)),
+            DigestAlgorithm::Md5 => hex(&Md5::digest(data)),
+        }
+    }
+}
+
+impl fmt::Display for DigestAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The secret of a user, returned by the lookup callback of [`DigestAuth`].
+#[derive(Clone)]
+pub struct DigestSecret(SecretInner);
+
+#[derive(Clone)]
+enum SecretInner {
+    Password(String),
+    Ha1 {
+        algorithm: DigestAlgorithm,
+        ha1: String,
+    },
+}
+
+impl DigestSecret {
+    /// The password of the user.
+    pub fn password<P: Into<String>>(password: P) -> Self {
+        Self(SecretInner::Password(password.into()))
+    }
+
+    /// The stored hash `H(username:realm:password)` of the user, as hex, so passwords don't
+    /// have to be stored.
+    ///
+    /// The user can only authenticate with `algorithm`.
+    pub fn ha1<H: Into<String>>(algorithm: DigestAlgorithm, ha1: H) -> Self {
+        Self(SecretInner::Ha1 {
+            algorithm,
+            ha1: ha1.into().to_ascii_lowercase(),
+        })
+    }
+
+    fn ha1_for(&self, algorithm: DigestAlgorithm, username: &str, realm: &str) -> Option<String> {
+        match &self.0 {
+            SecretInner::Password(password) => {
+                Some(algorithm.hash(&format!("{}:{}:{}", username, realm, password)))
+            }
+            SecretInner::Ha1 {
+                algorithm: stored,
+                ha1,
+            } => (*stored == algorithm).then(|| ha1.clone()),
+        }
+    }
+}
+
+impl fmt::Debug for DigestSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Deliberately leaves out the secret.
+        match &self.0 {
+            SecretInner::Password(_) => f.debug_tuple("Password").finish(),
+            SecretInner::Ha1 { algorithm, .. } => f.debug_tuple("Ha1").field(algorithm).finish(),
+        }
+    }
+}
+
+/// The user a request was authorized for, inserted into the request extensions by [`Digest`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DigestUser(String);
+
+impl DigestUser {
+    /// The name of the user.
+    pub fn username(&self) -> &str {
+        &self.0
+    }
+}
+
+type LookupFn = dyn Fn(&str) -> Option<DigestSecret> + Send + Sync + 'static;
+
+/// Configuration of HTTP Digest access authentication.
+///
+/// See the [module docs](crate::auth::digest) for more details.
+#[derive(Clone)]
+pub struct DigestAuth {
+    realm: String,
+    algorithms: Vec<DigestAlgorithm>,
+    nonce_lifetime: Duration,
+    nonce_key: Hmac<Sha256>,
+    opaque: String,
+    lookup: Arc<LookupFn>,
+}
+
+impl DigestAuth {
+    /// Authenticate users of `realm`, looking up their secret by username with `lookup`.
+    ///
+    /// Nonces are signed with a random key, so they are only accepted by this instance. Use
+    /// [`nonce_key`](Self::nonce_key) to share them between instances.
+    pub fn new<R, F>(realm: R, lookup: F) -> Self
+    where
+        R: Into<String>,
+        F: Fn(&str) -> Option<DigestSecret> + Send + Sync + 'static,
+    {
+        let mut key = [0; MIN_KEY_LEN];
+        getrandom::getrandom(&mut key).expect("failed to generate a random nonce key");
+
+        Self {
+            realm: realm.into(),
+            algorithms: vec![DigestAlgorithm::Sha256, DigestAlgorithm::Md5],
+            nonce_lifetime: Duration::from_secs(300),
+            opaque: hex(&Sha256::digest(key))[..32].to_owned(),
+            nonce_key: Hmac::new_from_slice(&key).expect("HMAC accepts keys of any length"),
+            lookup: Arc::new(lookup),
+        }
+    }
+
+    /// Sets the accepted algorithms, in order of preference.
+    ///
+    /// Defaults to `SHA-256` and `MD5`.
+    ///
+    /// # Panics
+    ///
+    /// If `algorithms` is empty.
+    pub fn algorithms<I>(mut self, algorithms: I) -> Self
+    where
+        I: IntoIterator<Item = DigestAlgorithm>,
+    {
+        let mut deduped = Vec::new();
+        for algorithm in algorithms {
+            if !deduped.contains(&algorithm) {
+                deduped.push(algorithm);
+            }
+        }
+        assert!(!deduped.is_empty(), "at least one algorithm is required");
+
+        self.algorithms = deduped;
+        self
+    }
+
+    /// Sets how long nonces are valid.
+    ///
+    /// Defaults to 5 minutes.
+    pub fn nonce_lifetime(mut self, lifetime: Duration) -> Self {
+        self.nonce_lifetime = lifetime;
+        self
+    }
+
+    /// Sets the key nonces are signed with.
+    ///
+    /// Instances sharing a key accept each other's nonces, e.g. behind a load balancer.
+    ///
+    /// # Panics
+    ///
+    /// If `key` is shorter than 32 bytes.
+    pub fn nonce_key<K: AsRef<[u8]>>(mut self, key: K) -> Self {
+        let key = key.as_ref();
+        assert!(
+            key.len() >= MIN_KEY_LEN,
+            "nonce key must be at least {} bytes",
+            MIN_KEY_LEN
+        );
+
+        self.opaque = hex(&Sha256::digest(key))[..32].to_owned();
+        self.nonce_key = Hmac::new_from_slice(key).expect("HMAC accepts keys of any length");
+        self
+    }
+
+    fn mint_nonce(&self, now: u64) -> String {
+        let mut nonce = [0; NONCE_LEN];
+        nonce[..TIMESTAMP_LEN].copy_from_slice(&now.to_be_bytes());
+        getrandom::getrandom(&mut nonce[TIMESTAMP_LEN..TIMESTAMP_LEN + NONCE_RANDOM_LEN])
+            .expect("failed to generate a random nonce");
+
+        let mut mac = self.nonce_key.clone();
+        mac.update(&nonce[..TIMESTAMP_LEN + NONCE_RANDOM_LEN]);
+        nonce[TIMESTAMP_LEN + NONCE_RANDOM_LEN..].copy_from_slice(&mac.finalize().into_bytes());
+
+        BASE64.encode(nonce)
+    }
+
+    fn check_nonce(&self, nonce: &str, now: u64) -> NonceStatus {
+        let Ok(nonce) = BASE64.decode(nonce) else {
+            return NonceStatus::Invalid;
+        };
+        if nonce.len() != NONCE_LEN {
+            return NonceStatus::Invalid;
+        }
+
+        let (signed, signature) = nonce.split_at(TIMESTAMP_LEN + NONCE_RANDOM_LEN);
+        let mut mac = self.nonce_key.clone();
+        mac.update(signed);
+        if mac.verify_slice(signature).is_err() {
+            return NonceStatus::Invalid;
+        }
+
+        let mut timestamp = [0; TIMESTAMP_LEN];
+        timestamp.copy_from_slice(&signed[..TIMESTAMP_LEN]);
+        let created = u64::from_be_bytes(timestamp);
+
+        if created > now || now - created > self.nonce_lifetime.as_secs() {
+            NonceStatus::Stale
+        } else {
+            NonceStatus::Valid
+        }
+    }
+
+    fn challenge(&self, stale: bool, now: u64) -> Vec<HeaderValue> {
+        let nonce = self.mint_nonce(now);
+
+        self.algorithms
+            .iter()
+            .filter_map(|algorithm| {
+                let mut challenge = format!(
+                    "Digest realm={}, qop=\"auth\", algorithm={}, nonce=\"{}\", opaque=\"{}\"",
+                    quote(&self.realm),
+                    algorithm,
+                    nonce,
+                    self.opaque
+                );
+                if stale {
+                    challenge.push_str(", stale=true");
+                }
+                HeaderValue::from_str(&challenge).ok()
+            })
+            .collect()
+    }
+
+    fn authenticate<B>(&self, request: &Request<B>, now: u64) -> Result<DigestUser, Failure> {
+        let params = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| {
+                let (scheme, params) = value.split_once(' ')?;
+                scheme.eq_ignore_ascii_case("Digest").then_some(params)
+            })
+            .and_then(parse_params)
+            .ok_or(Failure::Unauthorized)?;
+        let param = |name: &str| params.get(name).map(String::as_str);
+
+        let (Some(username), Some(realm), Some(nonce), Some(uri), Some(response)) = (
+            param("username"),
+            param("realm"),
+            param("nonce"),
+            param("uri"),
+            param("response"),
+        ) else {
+            return Err(Failure::Unauthorized);
+        };
+
+        let algorithm = match param("algorithm") {
+            Some(algorithm) => DigestAlgorithm::from_param(algorithm),
+            None => Some(DigestAlgorithm::Md5),
+        }
+        .filter(|algorithm| self.algorithms.contains(algorithm))
+        .ok_or(Failure::Unauthorized)?;
+
+        let (Some("auth"), Some(cnonce), Some(nc)) = (param("qop"), param("cnonce"), param("nc"))
+        else {
+            return Err(Failure::Unauthorized);
+        };
+
+        let valid = realm == self.realm
+            && param("opaque").map_or(true, |opaque| opaque == self.opaque)
+            && param("userhash").map_or(true, |userhash| userhash.eq_ignore_ascii_case("false"))
+            && nc.len() == 8
+            && nc.bytes().all(|b| b.is_ascii_hexdigit())
+            && matches_request_target(uri, request);
+        if !valid {
+            return Err(Failure::Unauthorized);
+        }
+
+        let ha1 = (self.lookup)(username)
+            .and_then(|secret| secret.ha1_for(algorithm, username, &self.realm))
+            .ok_or(Failure::Unauthorized)?;
+        let ha2 = algorithm.hash(&format!("{}:{}", request.method(), uri));
+        let expected = algorithm.hash(&format!("{}:{}:{}:{}:auth:{}", ha1, nonce, nc, cnonce, ha2));
+
+        if !constant_time_eq(
+            expected.as_bytes(),
+            response.to_ascii_lowercase().as_bytes(),
+        ) {
+            return Err(Failure::Unauthorized);
+        }
+
+        match self.check_nonce(nonce, now) {
+            NonceStatus::Valid => Ok(DigestUser(username.to_owned())),
+            NonceStatus::Stale => Err(Failure::Stale),
+            NonceStatus::Invalid => Err(Failure::Unauthorized),
+        }
+    }
+}
+
+impl fmt::Debug for DigestAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Deliberately leaves out the nonce key.
+        f.debug_struct("DigestAuth")
+            .field("realm", &self.realm)
+            .field("algorithms", &self.algorithms)
+            .field("nonce_lifetime", &self.nonce_lifetime)
+            .field("lookup", &format_args!("<fn>"))
+            .finish()
+    }
+}
+
+enum NonceStatus {
+    Valid,
+    Stale,
+    Invalid,
+}
+
+enum Failure {
+    Unauthorized,
+    Stale,
+}
+
+/// Type that performs Digest authorization.
+///
+/// See [`ValidateRequestHeader::digest`] for more details.
+pub struct Digest<ResBody> {
+    auth: Arc<DigestAuth>,
+    _ty: PhantomData<fn() -> ResBody>,
+}
+
+impl<ResBody> Digest<ResBody> {
+    fn new(auth: DigestAuth) -> Self {
+        Self {
+            auth: Arc::new(auth),
+            _ty: PhantomData,
+        }
+    }
+}
+
+impl<ResBody> Clone for Digest<ResBody> {
+    fn clone(&self) -> Self {
+        Self {
+            auth: self.auth.clone(),
+            _ty: PhantomData,
+        }
+    }
+}
+
+impl<ResBody> fmt::Debug for Digest<ResBody> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Digest").field("auth", &self.auth).finish()
+    }
+}
+
+impl<B, ResBody> ValidateRequest<B> for Digest<ResBody>
+where
+    ResBody: Default,
+{
+    type ResponseBody = ResBody;
+
+    fn validate(&mut self, request: &mut Request<B>) -> Result<(), Response<Self::ResponseBody>> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+
+        match self.auth.authenticate(request, now) {
+            Ok(user) => {
+                request.extensions_mut().insert(user);
+                Ok(())
+            }
+            Err(failure) => {
+                let mut res = Response::new(ResBody::default());
+                *res.status_mut() = StatusCode::UNAUTHORIZED;
+                let stale = matches!(failure, Failure::Stale);
+                for challenge in self.auth.challenge(stale, now) {
+                    res.headers_mut()
+                        .append(header::WWW_AUTHENTICATE, challenge);
+                }
+                Err(res)
+            }
+        }
+    }
+}
+
+/// Does the `uri` parameter name the request target, in origin or absolute form?
+fn matches_request_target<B>(uri: &str, request: &Request<B>) -> bool {
+    let target = request.uri();
+    let path_and_query = target
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str());
+
+    uri == path_and_query || (target.authority().is_some() && *target == *uri)
+}
+
+/// Parse the comma separated `name=value` parameters of a credential. Names are lowercased and
+/// quoted values unescaped. Duplicate names are rejected.
+fn parse_params(s: &str) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    let mut rest = s;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_ascii_whitespace());
+        if rest.is_empty() {
+            return Some(params);
+        }
+
+        let (name, after) = rest.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() || name.contains(|c: char| c == ',' || c.is_ascii_whitespace()) {
+            return None;
+        }
+        let after = after.trim_start();
+
+        let value;
+        if let Some(quoted) = after.strip_prefix('"') {
+            let mut unescaped = String::new();
+            let mut chars = quoted.char_indices();
+            let end = loop {
+                match chars.next()? {
+                    (_, '\\') => unescaped.push(chars.next()?.1),
+                    (i, '"') => break i,
+                    (_, c) => unescaped.push(c),
+                }
+            };
+            value = unescaped;
+            rest = &quoted[end + 1..];
+        } else {
+            let end = after.find(',').unwrap_or(after.len());
+            value = after[..end].trim().to_owned();
+            rest = &after[end..];
+        }
+
+        if params.insert(name.to_ascii_lowercase(), value).is_some() {
+            return None;
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::Body;
+    use tower::{ServiceBuilder, ServiceExt};
+    use tower_service::Service;
+
+    const REALM: &str = "http-auth@example.org";
+
+    fn auth() -> DigestAuth {
+        DigestAuth::new(REALM, |username: &str| match username {
+            "Mufasa" => Some(DigestSecret::password("Circle of Life")),
+            "Hashed" => Some(DigestSecret::ha1(
+                DigestAlgorithm::Sha256,
+                DigestAlgorithm::Sha256.hash(&format!("Hashed:{}:Circle of Life", REALM)),
+            )),
+            _ => None,
+        })
+    }
+
+    async fn echo(req: Request<Body>) -> Result<Response<Body>, tower::BoxError> {
+        let username = req.extensions().get::<DigestUser>().unwrap().username();
+        Ok(Response::new(Body::from(username.to_owned())))
+    }
+
+    /// Parse a challenge and answer it the way a client would.
+    fn answer(
+        challenge: &HeaderValue,
+        username: &str,
+        password: &str,
+        method: &str,
+        uri: &str,
+    ) -> String {
+        let challenge = challenge.to_str().unwrap().strip_prefix("Digest ").unwrap();
+        let params = parse_params(challenge).unwrap();
+        let algorithm = DigestAlgorithm::from_param(&params["algorithm"]).unwrap();
+        let nonce = &params["nonce"];
+
+        let ha1 = algorithm.hash(&format!("{}:{}:{}", username, params["realm"], password));
+        let ha2 = algorithm.hash(&format!("{}:{}", method, uri));
+        let response = algorithm.hash(&format!("{}:{}:00000001:0a4f113b:auth:{}", ha1, nonce, ha2));
+
+        format!(
+            "Digest username=\"{}\", realm={}, uri=\"{}\", algorithm={}, nonce=\"{}\", \
+             nc=00000001, cnonce=\"0a4f113b\", qop=auth, response=\"{}\", opaque=\"{}\"",
+            username,
+            quote(&params["realm"]),
+            uri,
+            algorithm,
+            nonce,
+            response,
+            params["opaque"],
+        )
+    }
+
+    #[test]
+    fn rfc_7616_example() {
+        // https://www.rfc-editor.org/rfc/rfc7616#section-3.9.1
+        let nonce = "7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v";
+        let cnonce = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+
+        for (algorithm, expected) in [
+            (
+                DigestAlgorithm::Sha256,
+                "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1",
+            ),
+            (DigestAlgorithm::Md5, "8ca523f5e9506fed4657c9700eebdbec"),
+        ] {
+            let ha1 = DigestSecret::password("Circle of Life")
+                .ha1_for(algorithm, "Mufasa", REALM)
+                .unwrap();
+            let ha2 = algorithm.hash("GET:/dir/index.html");
+            let response = algorithm.hash(&format!(
+                "{}:{}:00000001:{}:auth:{}",
+                ha1, nonce, cnonce, ha2
+            ));
+            assert_eq!(response, expected, "{}", algorithm);
+        }
+    }
+
+    #[test]
+    fn parses_params() {
+        let params =
+            parse_params(r#"username="Mu\"fasa", Realm="a, b",nc=00000001 ,qop=auth"#).unwrap();
+        assert_eq!(params["username"], "Mu\"fasa");
+        assert_eq!(params["realm"], "a, b");
+        assert_eq!(params["nc"], "00000001");
+        assert_eq!(params["qop"], "auth");
+
+        assert!(parse_params(r#"username="unterminated"#).is_none());
+        assert!(parse_params("nc=1, nc=2").is_none());
+        assert!(parse_params("novalue").is_none());
+    }
+
+    #[tokio::test]
+    async fn challenges_and_authorizes() {
+        let mut service = ServiceBuilder::new()
+            .layer(ValidateRequestHeaderLayer::digest(auth()))
+            .service_fn(echo);
+
+        let res = service
+            .ready()
+            .await
+            .unwrap()
+            .call(Request::get("/dir/index.html").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let challenges: Vec<_> = res
+            .headers()
+            .get_all(header::WWW_AUTHENTICATE)
+            .iter()
+            .cloned()
+            .collect();
+        assert_eq!(challenges.len(), 2);
+        for (challenge, algorithm) in challenges.iter().zip(["SHA-256", "MD5"]) {
+            let challenge = challenge.to_str().unwrap();
+            assert!(
+                challenge.starts_with(&format!(
+                    "Digest realm=\"{}\", qop=\"auth\", algorithm={}, nonce=\"",
+                    REALM, algorithm
+                )),
+                "{}",
+                challenge
+            );
+            assert!(!challenge.contains("stale"));
+        }
+
+        for (challenge, username) in challenges.iter().zip(["Mufasa", "Hashed"]) {
+            let authorization = answer(
+                challenge,
+                username,
+                "Circle of Life",
+                "GET",
+                "/dir/index.html?x=1",
+            );
+            let req = Request::get("/dir/index.html?x=1")
+                .header(header::AUTHORIZATION, &authorization)
+                .body(Body::empty())
+                .unwrap();
+            let res = service.ready().await.unwrap().call(req).await.unwrap();
+
+            if username == "Hashed" {
+                // The stored hash is for SHA-256 only.
+                assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+                continue;
+            }
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = crate::test_helpers::to_bytes(res.into_body())
+                .await
+                .unwrap();
+            assert_eq!(body, "Mufasa");
+        }
+
+        let authorization = answer(&challenges[0], "Hashed", "Circle of Life", "GET", "/");
+        let req = Request::get("/")
+            .header(header::AUTHORIZATION, &authorization)
+            .body(Body::empty())
+            .unwrap();
+        let res = service.ready().await.unwrap().call(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let rejected = [
+            // wrong password
+            answer(&challenges[0], "Mufasa", "wrong", "GET", "/"),
+            // unknown user
+            answer(&challenges[0], "Scar", "Circle of Life", "GET", "/"),
+            // answered for another method
+            answer(&challenges[0], "Mufasa", "Circle of Life", "POST", "/"),
+            // answered for another URI
+            answer(&challenges[0], "Mufasa", "Circle of Life", "GET", "/other"),
+            // forged nonce
+            answer(&challenges[0], "Mufasa", "Circle of Life", "GET", "/").replace(
+                &parse_params(
+                    challenges[0]
+                        .to_str()
+                        .unwrap()
+                        .strip_prefix("Digest ")
+                        .unwrap(),
+                )
+                .unwrap()["nonce"],
+                &BASE64.encode([0; NONCE_LEN]),
+            ),
+        ];
+        for authorization in rejected {
+            let req = Request::get("/")
+                .header(header::AUTHORIZATION, &authorization)
+                .body(Body::empty())
+                .unwrap();
+            let res = service.ready().await.unwrap().call(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED, "{}", authorization);
+        }
+    }
+
+    #[tokio::test]
+    async fn stale_nonce() {
+        let auth = auth().nonce_lifetime(Duration::from_secs(60));
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let challenge = auth.challenge(false, now - 120).remove(0);
+
+        let mut service = ServiceBuilder::new()
+            .layer(ValidateRequestHeaderLayer::digest(auth))
+            .service_fn(echo);
+
+        let authorization = answer(&challenge, "Mufasa", "Circle of Life", "GET", "/");
+        let req = Request::get("/")
+            .header(header::AUTHORIZATION, &authorization)
+            .body(Body::empty())
+            .unwrap();
+        let res = service.ready().await.unwrap().call(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        for challenge in res.headers().get_all(header::WWW_AUTHENTICATE) {
+            assert!(challenge.to_str().unwrap().ends_with(", stale=true"));
+        }
+
+        // A stale nonce with a wrong password isn't reported as stale.
+        let authorization = answer(&challenge, "Mufasa", "wrong", "GET", "/");
+        let req = Request::get("/")
+            .header(header::AUTHORIZATION, &authorization)
+            .body(Body::empty())
+            .unwrap();
+        let res = service.ready().await.unwrap().call(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        for challenge in res.headers().get_all(header::WWW_AUTHENTICATE) {
+            assert!(!challenge.to_str().unwrap().contains("stale"));
+        }
+    }
+
+    #[tokio::test]
+    async fn restricts_algorithms() {
+        let mut service = ServiceBuilder::new()
+            .layer(ValidateRequestHeaderLayer::digest(
+                auth().algorithms([DigestAlgorithm::Sha256]),
+            ))
+            .service_fn(echo);
+
+        let res = service
+            .ready()
+            .await
+            .unwrap()
+            .call(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let challenges: Vec<_> = res
+            .headers()
+            .get_all(header::WWW_AUTHENTICATE)
+            .iter()
+            .cloned()
+            .collect();
+        assert_eq!(challenges.len(), 1);
+
+        let md5 = challenges[0]
+            .to_str()
+            .unwrap()
+            .replace("algorithm=SHA-256", "algorithm=MD5");
+        let authorization = answer(
+            &HeaderValue::from_str(&md5).unwrap(),
+            "Mufasa",
+            "Circle of Life",
+            "GET",
+            "/",
+        );
+        let req = Request::get("/")
+            .header(header::AUTHORIZATION, &authorization)
+            .body(Body::empty())
+            .unwrap();
+        let res = service.ready().await.unwrap().call(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+}
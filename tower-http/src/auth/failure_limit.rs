@@ -0,0 +1,885 @@
+//! Limit repeated authorization failures to slow down brute-force attacks.
+//!
+//! [`LimitFailures`] wraps any [`ValidateRequest`] or [`AsyncAuthorizeRequest`] and counts the
+//! `401 Unauthorized` responses it produces per client, keyed by IP address or username. Once a
+//! client exceeds the allowed number of failures it is locked out, and its requests get a
+//! `429 Too Many Requests` response with a `Retry-After` header without being checked at all.
+//! The lockout either grows exponentially with every further failure, or has a fixed duration.
+//! When clients are keyed by username or a custom key, a successful authorization resets the
+//! count. Clients keyed by IP address only get their count reset by time, as anyone holding a
+//! valid credential could otherwise interleave successful requests with guesses at other accounts.
+//!
+//! Authorizations that are still in flight count as failures until they complete, so a client
+//! can't get more guesses past the limit by sending them concurrently.
+//!
+//! The counts live in a [`FailureStore`]. [`MemoryFailureStore`] keeps a bounded number of
+//! clients in memory, and you can implement the trait to share the counts between instances.
+//!
+//! # Example
+//!
+//! ```
+//! use http::{Request, Response, StatusCode, header::{AUTHORIZATION, RETRY_AFTER}};
+//! use http_body_util::Full;
+//! use bytes::Bytes;
+//! use std::{net::SocketAddr, time::Duration};
+//! use tower::{Service, ServiceExt, ServiceBuilder, BoxError};
+//! use tower_http::auth::credentials::BearerAuth;
+//! use tower_http::auth::failure_limit::FailureLimiter;
+//! use tower_http::validate_request::ValidateRequestHeaderLayer;
+//!
+//! async fn handle(request: Request<Full<Bytes>>) -> Result<Response<Full<Bytes>>, BoxError> {
+//!     Ok(Response::new(Full::default()))
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), BoxError> {
+//! let mut service = ServiceBuilder::new()
+//!     .layer(
+//!         ValidateRequestHeaderLayer::bearer_auth(BearerAuth::tokens([("ci", "ci-token")]))
+//!             .limit_failures(
+//!                 FailureLimiter::new()
+//!                     .max_failures(3)
+//!                     .lockout(Duration::from_secs(60)),
+//!             ),
+//!     )
+//!     .service_fn(handle);
+//!
+//! let request = || {
+//!     let mut request = Request::builder()
+//!         .header(AUTHORIZATION, "Bearer guess")
+//!         .body(Full::default())
+//!         .unwrap();
+//!     // Usually inserted by the server, or use `SetClientIpLayer`.
+//!     request.extensions_mut().insert("203.0.113.7:4321".parse::<SocketAddr>().unwrap());
+//!     request
+//! };
+//!
+//! for _ in 0..3 {
+//!     let response = service.ready().await?.call(request()).await?;
+//!     assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+//! }
+//!
+//! let response = service.ready().await?.call(request()).await?;
+//! assert_eq!(StatusCode::TOO_MANY_REQUESTS, response.status());
+//! assert_eq!(response.headers()[RETRY_AFTER], "60");
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    auth::{AsyncAuthorizeRequest, AsyncRequireAuthorizationLayer},
+    helpers::client_ip,
+    validate_request::{ValidateRequest, ValidateRequestHeaderLayer},
+};
+use base64::Engine as _;
+use http::{header, Extensions, HeaderMap, HeaderValue, Request, Response, StatusCode};
+use pin_project_lite::pin_project;
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+
+const BASE64: base64::engine::GeneralPurpose = base64::engine::general_purpose::STANDARD;
+
+impl<T> ValidateRequestHeaderLayer<T> {
+    /// Limit the authorization failures of each client.
+    ///
+    /// See the [module docs](crate::auth::failure_limit) for more details.
+    pub fn limit_failures(
+        self,
+        limiter: FailureLimiter,
+    ) -> ValidateRequestHeaderLayer<LimitFailures<T>> {
+        ValidateRequestHeaderLayer::custom(LimitFailures::new(self.validate, limiter))
+    }
+}
+
+impl<T> AsyncRequireAuthorizationLayer<T> {
+    /// Limit the authorization failures of each client.
+    ///
+    /// See the [module docs](crate::auth::failure_limit) for more details.
+    pub fn limit_failures(
+        self,
+        limiter: FailureLimiter,
+    ) -> AsyncRequireAuthorizationLayer<LimitFailures<T>> {
+        AsyncRequireAuthorizationLayer::new(LimitFailures::new(self.auth, limiter))
+    }
+}
+
+/// The recorded authorization failures of a client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Failures {
+    count: u32,
+    last: Instant,
+}
+
+impl Failures {
+    /// Create a new `Failures`.
+    pub fn new(count: u32, last: Instant) -> Self {
+        Self { count, last }
+    }
+
+    /// The number of consecutive failures.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// When the last failure happened.
+    pub fn last(&self) -> Instant {
+        self.last
+    }
+}
+
+/// Trait for storing the authorization failures of clients.
+pub trait FailureStore: Send + Sync + 'static {
+    /// The failures recorded for `key`.
+    fn get(&self, key: &str) -> Option<Failures>;
+
+    /// Record a failure for `key` that happened at `now`, returning the updated failures.
+    fn record_failure(&self, key: &str, now: Instant) -> Failures;
+
+    /// Forget the failures of `key`.
+    fn reset(&self, key: &str);
+}
+
+/// A [`FailureStore`] that keeps the failures of a bounded number of clients in memory.
+///
+/// When full, the client whose last failure is the oldest is forgotten.
+pub struct MemoryFailureStore {
+    capacity: usize,
+    failures: Mutex<HashMap<String, Failures>>,
+}
+
+impl MemoryFailureStore {
+    /// Create a new `MemoryFailureStore` holding at most `capacity` clients.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Default for MemoryFailureStore {
+    /// Holds at most 10 000 clients.
+    fn default() -> Self {
+        Self::new(10_000)
+    }
+}
+
+impl FailureStore for MemoryFailureStore {
+    fn get(&self, key: &str) -> Option<Failures> {
+        self.failures.lock().unwrap().get(key).copied()
+    }
+
+    fn record_failure(&self, key: &str, now: Instant) -> Failures {
+        let mut failures = self.failures.lock().unwrap();
+        if !failures.contains_key(key) && failures.len() >= self.capacity {
+            let oldest = failures
+                .iter()
+                .min_by_key(|(_, failures)| failures.last)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                failures.remove(&oldest);
+            }
+        }
+
+        let entry = failures
+            .entry(key.to_owned())
+            .or_insert(Failures::new(0, now));
+        entry.count = entry.count.saturating_add(1);
+        entry.last = now;
+        *entry
+    }
+
+    fn reset(&self, key: &str) {
+        self.failures.lock().unwrap().remove(key);
+    }
+}
+
+impl fmt::Debug for MemoryFailureStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryFailureStore")
+            .field("capacity", &self.capacity)
+            .field("len", &self.failures.lock().unwrap().len())
+            .finish()
+    }
+}
+
+type KeyFn = dyn Fn(&HeaderMap, &Extensions) -> Option<String> + Send + Sync + 'static;
+
+#[derive(Clone)]
+enum Key {
+    Ip,
+    Username,
+    Custom(Arc<KeyFn>),
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Ip => f.debug_tuple("Ip").finish(),
+            Key::Username => f.debug_tuple("Username").finish(),
+            Key::Custom(_) => f.debug_tuple("Custom").finish(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Penalty {
+    Backoff { base: Duration, max: Duration },
+    Lockout(Duration),
+}
+
+/// Configuration of how authorization failures are limited.
+///
+/// By default clients are keyed by IP address, and locked out after 5 failures for 1 second,
+/// doubling with every further failure up to 15 minutes. Failures are forgotten 15 minutes after
+/// the last one, once the client isn't locked out anymore.
+///
+/// Services created from the same layer share the store, and so do clones of the limiter.
+///
+/// See the [module docs](crate::auth::failure_limit) for an example.
+#[derive(Clone)]
+pub struct FailureLimiter {
+    store: Arc<dyn FailureStore>,
+    key: Key,
+    max_failures: u32,
+    penalty: Penalty,
+    forget_after: Duration,
+    pending: Arc<Mutex<HashMap<String, u32>>>,
+}
+
+impl FailureLimiter {
+    /// Create a new `FailureLimiter` using a [`MemoryFailureStore`].
+    pub fn new() -> Self {
+        Self::with_store(MemoryFailureStore::default())
+    }
+
+    /// Create a new `FailureLimiter` using `store`.
+    pub fn with_store<S: FailureStore>(store: S) -> Self {
+        Self {
+            store: Arc::new(store),
+            key: Key::Ip,
+            max_failures: 5,
+            penalty: Penalty::Backoff {
+                base: Duration::from_secs(1),
+                max: Duration::from_secs(15 * 60),
+            },
+            forget_after: Duration::from_secs(15 * 60),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Key clients by IP address.
+    ///
+    /// The address is taken from the [`ClientIp`] extension inserted by [`SetClientIpLayer`] if the
+    /// `client-ip` feature is enabled, or else a [`SocketAddr`] or [`IpAddr`] extension. Requests
+    /// from an unknown address aren't limited.
+    ///
+    /// A successful authorization doesn't reset the count of an address, which is shared by
+    /// everyone using it.
+    ///
+    /// This is the default.
+    ///
+    /// [`ClientIp`]: crate::client_ip::ClientIp
+    /// [`SetClientIpLayer`]: crate::client_ip::SetClientIpLayer
+    /// [`SocketAddr`]: std::net::SocketAddr
+    /// [`IpAddr`]: std::net::IpAddr
+    pub fn key_by_ip(mut self) -> Self {
+        self.key = Key::Ip;
+        self
+    }
+
+    /// Key clients by the username of `Basic` credentials.
+    ///
+    /// This limits guessing the password of one user from many addresses. Requests without
+    /// `Basic` credentials aren't limited.
+    pub fn key_by_username(mut self) -> Self {
+        self.key = Key::Username;
+        self
+    }
+
+    /// Key clients with `f`. Requests for which it returns `None` aren't limited.
+    ///
+    /// A successful authorization resets the count of its key, so `f` should identify the holder
+    /// of the credentials rather than something shared by many clients.
+    pub fn key_by<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&HeaderMap, &Extensions) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = Key::Custom(Arc::new(f));
+        self
+    }
+
+    /// Set how many failures are allowed before a client is locked out.
+    ///
+    /// This also limits how many authorizations of a client can be in flight at once, as they
+    /// count as failures until they complete. Further requests get a `429 Too Many Requests`
+    /// response without locking the client out.
+    ///
+    /// Defaults to 5.
+    pub fn max_failures(mut self, max_failures: u32) -> Self {
+        self.max_failures = max_failures;
+        self
+    }
+
+    /// Lock clients out for `base`, doubling with every further failure up to `max`.
+    ///
+    /// This is the default, with 1 second up to 15 minutes.
+    pub fn backoff(mut self, base: Duration, max: Duration) -> Self {
+        self.penalty = Penalty::Backoff { base, max };
+        self
+    }
+
+    /// Lock clients out for a fixed `duration`.
+    pub fn lockout(mut self, duration: Duration) -> Self {
+        self.penalty = Penalty::Lockout(duration);
+        self
+    }
+
+    /// Set after how long without failures they're forgotten.
+    ///
+    /// Defaults to 15 minutes.
+    pub fn forget_after(mut self, forget_after: Duration) -> Self {
+        self.forget_after = forget_after;
+        self
+    }
+
+    fn key<B>(&self, request: &Request<B>) -> Option<String> {
+        match &self.key {
+            Key::Ip => client_ip(request.extensions()).map(|ip| ip.to_string()),
+            Key::Username => username(request.headers()),
+            Key::Custom(f) => f(request.headers(), request.extensions()),
+        }
+    }
+
+    fn locked_until(&self, failures: Failures) -> Option<Instant> {
+        let over = failures.count.checked_sub(self.max_failures)?;
+        let penalty = match self.penalty {
+            Penalty::Backoff { base, max } => {
+                base.saturating_mul(2u32.saturating_pow(over)).min(max)
+            }
+            Penalty::Lockout(duration) => duration,
+        };
+        Some(failures.last + penalty)
+    }
+
+    /// Failures that aren't forgotten yet.
+    fn current(&self, key: &str, now: Instant) -> Option<Failures> {
+        let failures = self.store.get(key)?;
+        let locked = self
+            .locked_until(failures)
+            .map_or(false, |until| now < until);
+        if !locked && now.saturating_duration_since(failures.last) > self.forget_after {
+            self.store.reset(key);
+            return None;
+        }
+        Some(failures)
+    }
+
+    /// Start an authorization attempt, or reject a client that is locked out or already has as
+    /// many attempts in flight as it has failures left. A client that isn't locked out can
+    /// always make one attempt.
+    fn begin<ResBody: Default>(&self, key: String) -> Result<Attempt, Response<ResBody>> {
+        let now = Instant::now();
+        let failures = self.current(&key, now);
+        let locked_until = failures
+            .and_then(|failures| self.locked_until(failures))
+            .filter(|until| now < *until);
+        if let Some(until) = locked_until {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(key, "rejecting locked out client");
+            return Err(too_many_requests(until - now));
+        }
+
+        let mut pending = self.pending.lock().unwrap();
+        let in_flight = pending.entry(key.clone()).or_insert(0);
+        let count = failures.map_or(0, |failures| failures.count);
+        if *in_flight > 0 && count.saturating_add(*in_flight) >= self.max_failures {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(key, "rejecting client with too many attempts in flight");
+            return Err(too_many_requests(Duration::from_secs(1)));
+        }
+        *in_flight += 1;
+
+        Ok(Attempt {
+            limiter: self.clone(),
+            key,
+        })
+    }
+}
+
+/// An authorization attempt that is in flight, counted as a failure until it completes.
+struct Attempt {
+    limiter: FailureLimiter,
+    key: String,
+}
+
+impl Attempt {
+    fn finish(self, status: Option<StatusCode>) {
+        let store = &self.limiter.store;
+        match status {
+            None => match self.limiter.key {
+                Key::Ip => {}
+                Key::Username | Key::Custom(_) => store.reset(&self.key),
+            },
+            Some(StatusCode::UNAUTHORIZED) => {
+                store.record_failure(&self.key, Instant::now());
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+impl Drop for Attempt {
+    fn drop(&mut self) {
+        let mut pending = self.limiter.pending.lock().unwrap();
+        if let Some(in_flight) = pending.get_mut(&self.key) {
+            *in_flight -= 1;
+            if *in_flight == 0 {
+                pending.remove(&self.key);
+            }
+        }
+    }
+}
+
+impl Default for FailureLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for FailureLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FailureLimiter")
+            .field("key", &self.key)
+            .field("max_failures", &self.max_failures)
+            .field("penalty", &self.penalty)
+            .field("forget_after", &self.forget_after)
+            .finish_non_exhaustive()
+    }
+}
+
+fn username(headers: &HeaderMap) -> Option<String> {
+    let (scheme, credentials) = headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Basic") {
+        return None;
+    }
+    let decoded = BASE64.decode(credentials.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (username, _) = decoded.split_once(':')?;
+    Some(username.to_owned())
+}
+
+fn too_many_requests<ResBody: Default>(retry_after: Duration) -> Response<ResBody> {
+    let mut secs = retry_after.as_secs();
+    if retry_after.subsec_nanos() > 0 {
+        secs += 1;
+    }
+    let mut res = Response::new(ResBody::default());
+    *res.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+    res.headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+    res
+}
+
+/// Type that limits the authorization failures of a [`ValidateRequest`] or
+/// [`AsyncAuthorizeRequest`].
+///
+/// See the [module docs](crate::auth::failure_limit) for more details.
+#[derive(Clone, Debug)]
+pub struct LimitFailures<A> {
+    auth: A,
+    limiter: FailureLimiter,
+}
+
+impl<A> LimitFailures<A> {
+    /// Limit the authorization failures of `auth` using `limiter`.
+    pub fn new(auth: A, limiter: FailureLimiter) -> Self {
+        Self { auth, limiter }
+    }
+}
+
+impl<B, A> ValidateRequest<B> for LimitFailures<A>
+where
+    A: ValidateRequest<B>,
+    A::ResponseBody: Default,
+{
+    type ResponseBody = A::ResponseBody;
+
+    fn validate(&mut self, request: &mut Request<B>) -> Result<(), Response<Self::ResponseBody>> {
+        let Some(key) = self.limiter.key(request) else {
+            return self.auth.validate(request);
+        };
+        let attempt = self.limiter.begin(key)?;
+
+        let result = self.auth.validate(request);
+        attempt.finish(result.as_ref().err().map(Response::status));
+        result
+    }
+}
+
+impl<B, A> AsyncAuthorizeRequest<B> for LimitFailures<A>
+where
+    A: AsyncAuthorizeRequest<B>,
+    A::ResponseBody: Default,
+{
+    type RequestBody = A::RequestBody;
+    type ResponseBody = A::ResponseBody;
+    type Future = LimitFailuresFuture<A::Future, A::ResponseBody>;
+
+    fn authorize(&mut self, request: Request<B>) -> Self::Future {
+        let Some(key) = self.limiter.key(&request) else {
+            return LimitFailuresFuture {
+                kind: Kind::Authorizing {
+                    future: self.auth.authorize(request),
+                    attempt: None,
+                },
+            };
+        };
+
+        let kind = match self.limiter.begin(key) {
+            Ok(attempt) => Kind::Authorizing {
+                future: self.auth.authorize(request),
+                attempt: Some(attempt),
+            },
+            Err(response) => Kind::Locked {
+                response: Some(response),
+            },
+        };
+        LimitFailuresFuture { kind }
+    }
+}
+
+pin_project! {
+    /// Future for [`LimitFailures`] used as an [`AsyncAuthorizeRequest`].
+    pub struct LimitFailuresFuture<F, ResBody> {
+        #[pin]
+        kind: Kind<F, ResBody>,
+    }
+}
+
+pin_project! {
+    #[project = KindProj]
+    enum Kind<F, ResBody> {
+        Authorizing {
+            #[pin]
+            future: F,
+            attempt: Option<Attempt>,
+        },
+        Locked {
+            response: Option<Response<ResBody>>,
+        },
+    }
+}
+
+impl<F, RequestBody, ResBody> Future for LimitFailuresFuture<F, ResBody>
+where
+    F: Future<Output = Result<Request<RequestBody>, Response<ResBody>>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project().kind.project() {
+            KindProj::Authorizing { future, attempt } => {
+                let result = ready!(future.poll(cx));
+                if let Some(attempt) = attempt.take() {
+                    attempt.finish(result.as_ref().err().map(Response::status));
+                }
+                Poll::Ready(result)
+            }
+            KindProj::Locked { response } => Poll::Ready(Err(response
+                .take()
+                .expect("future polled after completion"))),
+        }
+    }
+}
+
+impl<F, ResBody> fmt::Debug for LimitFailuresFuture<F, ResBody> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LimitFailuresFuture")
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::{
+            api_key::ApiKeyAuth,
+            credentials::{BasicAuth, BearerAuth},
+        },
+        test_helpers::Body,
+    };
+    use std::net::IpAddr;
+    use tower::{BoxError, Service, ServiceBuilder, ServiceExt};
+
+    async fn echo(_: Request<Body>) -> Result<Response<Body>, BoxError> {
+        Ok(Response::new(Body::empty()))
+    }
+
+    fn request(ip: &str, authorization: &str) -> Request<Body> {
+        let mut request = Request::builder()
+            .header(header::AUTHORIZATION, authorization)
+            .body(Body::empty())
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ip.parse::<IpAddr>().unwrap());
+        request
+    }
+
+    async fn status<S>(svc: &mut S, request: Request<Body>) -> (StatusCode, Option<String>)
+    where
+        S: Service<Request<Body>, Response = Response<Body>>,
+        S::Error: fmt::Debug,
+    {
+        let res = svc.ready().await.unwrap().call(request).await.unwrap();
+        let retry_after = res
+            .headers()
+            .get(header::RETRY_AFTER)
+            .map(|value| value.to_str().unwrap().to_owned());
+        (res.status(), retry_after)
+    }
+
+    const OK: (StatusCode, Option<String>) = (StatusCode::OK, None);
+    const UNAUTHORIZED: (StatusCode, Option<String>) = (StatusCode::UNAUTHORIZED, None);
+
+    fn too_many(retry_after: &str) -> (StatusCode, Option<String>) {
+        (StatusCode::TOO_MANY_REQUESTS, Some(retry_after.to_owned()))
+    }
+
+    #[tokio::test]
+    async fn locks_out_by_ip() {
+        let mut svc = ServiceBuilder::new()
+            .layer(
+                ValidateRequestHeaderLayer::bearer_auth(BearerAuth::tokens([("ci", "token")]))
+                    .limit_failures(FailureLimiter::new().max_failures(2)),
+            )
+            .service_fn(echo);
+
+        for _ in 0..2 {
+            assert_eq!(
+                status(&mut svc, request("10.0.0.1", "Bearer guess")).await,
+                UNAUTHORIZED
+            );
+        }
+        // Locked out even with the right token.
+        assert_eq!(
+            status(&mut svc, request("10.0.0.1", "Bearer token")).await,
+            too_many("1")
+        );
+        // Other clients aren't affected.
+        assert_eq!(
+            status(&mut svc, request("10.0.0.2", "Bearer token")).await,
+            OK
+        );
+    }
+
+    #[tokio::test]
+    async fn exponential_backoff() {
+        let limiter = FailureLimiter::new()
+            .max_failures(1)
+            .backoff(Duration::from_secs(10), Duration::from_secs(30));
+        let now = Instant::now();
+
+        let locked_for = |count| {
+            limiter
+                .locked_until(Failures::new(count, now))
+                .map(|until| until - now)
+        };
+        assert_eq!(locked_for(0), None);
+        assert_eq!(locked_for(1), Some(Duration::from_secs(10)));
+        assert_eq!(locked_for(2), Some(Duration::from_secs(20)));
+        assert_eq!(locked_for(3), Some(Duration::from_secs(30)));
+        assert_eq!(locked_for(u32::MAX), Some(Duration::from_secs(30)));
+    }
+
+    #[tokio::test]
+    async fn lockout_expires_and_success_does_not_reset_ip() {
+        let mut svc = ServiceBuilder::new()
+            .layer(
+                ValidateRequestHeaderLayer::bearer_auth(BearerAuth::tokens([("ci", "token")]))
+                    .limit_failures(
+                        FailureLimiter::new()
+                            .max_failures(1)
+                            .lockout(Duration::from_millis(50)),
+                    ),
+            )
+            .service_fn(echo);
+
+        assert_eq!(
+            status(&mut svc, request("10.0.0.1", "Bearer guess")).await,
+            UNAUTHORIZED
+        );
+        assert_eq!(
+            status(&mut svc, request("10.0.0.1", "Bearer token")).await,
+            too_many("1")
+        );
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(
+            status(&mut svc, request("10.0.0.1", "Bearer token")).await,
+            OK
+        );
+        // The success didn't reset the count of the address, so the next failure locks it out
+        // again.
+        assert_eq!(
+            status(&mut svc, request("10.0.0.1", "Bearer guess")).await,
+            UNAUTHORIZED
+        );
+        assert_eq!(
+            status(&mut svc, request("10.0.0.1", "Bearer token")).await,
+            too_many("1")
+        );
+    }
+
+    #[tokio::test]
+    async fn success_resets_username() {
+        let basic =
+            |password: &str| format!("Basic {}", BASE64.encode(format!("alice:{}", password)));
+        let mut svc = ServiceBuilder::new()
+            .layer(
+                ValidateRequestHeaderLayer::basic_auth(BasicAuth::credentials([("alice", "pw")]))
+                    .limit_failures(FailureLimiter::new().key_by_username().max_failures(2)),
+            )
+            .service_fn(echo);
+
+        assert_eq!(
+            status(&mut svc, request("10.0.0.1", &basic("guess"))).await,
+            UNAUTHORIZED
+        );
+        assert_eq!(
+            status(&mut svc, request("10.0.0.1", &basic("pw"))).await,
+            OK
+        );
+        for _ in 0..2 {
+            assert_eq!(
+                status(&mut svc, request("10.0.0.1", &basic("guess"))).await,
+                UNAUTHORIZED
+            );
+        }
+        assert_eq!(
+            status(&mut svc, request("10.0.0.1", &basic("pw"))).await,
+            too_many("1")
+        );
+    }
+
+    #[test]
+    fn attempts_in_flight_count_as_failures() {
+        let limiter = FailureLimiter::new().max_failures(2);
+        let begin = || {
+            limiter
+                .begin::<Body>("10.0.0.1".to_owned())
+                .map_err(|response| response.status())
+        };
+
+        let first = begin().ok().unwrap();
+        let second = begin().ok().unwrap();
+        assert_eq!(begin().err(), Some(StatusCode::TOO_MANY_REQUESTS));
+
+        // A dropped attempt frees its slot.
+        drop(second);
+        let second = begin().ok().unwrap();
+
+        first.finish(Some(StatusCode::UNAUTHORIZED));
+        assert_eq!(begin().err(), Some(StatusCode::TOO_MANY_REQUESTS));
+        second.finish(Some(StatusCode::UNAUTHORIZED));
+        assert_eq!(begin().err(), Some(StatusCode::TOO_MANY_REQUESTS));
+        assert!(limiter.pending.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn forgets_old_failures() {
+        let limiter = FailureLimiter::new()
+            .max_failures(2)
+            .lockout(Duration::ZERO)
+            .forget_after(Duration::from_millis(20));
+        let mut svc = ServiceBuilder::new()
+            .layer(
+                ValidateRequestHeaderLayer::bearer_auth(BearerAuth::tokens([("ci", "token")]))
+                    .limit_failures(limiter.clone()),
+            )
+            .service_fn(echo);
+
+        status(&mut svc, request("10.0.0.1", "Bearer guess")).await;
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(limiter.current("10.0.0.1", Instant::now()), None);
+        assert_eq!(limiter.store.get("10.0.0.1"), None);
+    }
+
+    #[tokio::test]
+    async fn keyed_by_username() {
+        let basic = |username: &str, password: &str| {
+            format!(
+                "Basic {}",
+                BASE64.encode(format!("{}:{}", username, password))
+            )
+        };
+        let mut svc = ServiceBuilder::new()
+            .layer(
+                ValidateRequestHeaderLayer::basic_auth(BasicAuth::credentials([("alice", "pw")]))
+                    .limit_failures(FailureLimiter::new().key_by_username().max_failures(1)),
+            )
+            .service_fn(echo);
+
+        assert_eq!(
+            status(&mut svc, request("10.0.0.1", &basic("alice", "guess"))).await,
+            UNAUTHORIZED
+        );
+        // From another address.
+        assert_eq!(
+            status(&mut svc, request("10.0.0.2", &basic("alice", "pw"))).await,
+            too_many("1")
+        );
+    }
+
+    #[tokio::test]
+    async fn async_authorization() {
+        let mut svc = ServiceBuilder::new()
+            .layer(
+                AsyncRequireAuthorizationLayer::api_key(ApiKeyAuth::keys([("ci", "key")]))
+                    .limit_failures(
+                        FailureLimiter::new()
+                            .max_failures(1)
+                            .lockout(Duration::from_secs(30)),
+                    ),
+            )
+            .service_fn(echo);
+
+        let request = |key: &str| {
+            let mut request = request("10.0.0.1", "");
+            request
+                .headers_mut()
+                .insert("x-api-key", HeaderValue::from_str(key).unwrap());
+            request
+        };
+
+        assert_eq!(status(&mut svc, request("guess")).await, UNAUTHORIZED);
+        assert_eq!(status(&mut svc, request("key")).await, too_many("30"));
+    }
+
+    #[test]
+    fn memory_store_is_bounded() {
+        let store = MemoryFailureStore::new(2);
+        let now = Instant::now();
+        store.record_failure("a", now);
+        store.record_failure("b", now + Duration::from_secs(1));
+        store.record_failure("a", now + Duration::from_secs(2));
+        store.record_failure("c", now + Duration::from_secs(3));
+
+        assert_eq!(store.get("a").map(|f| f.count()), Some(2));
+        assert_eq!(store.get("b"), None);
+        assert_eq!(store.get("c").map(|f| f.count()), Some(1));
+    }
+}
@@ -0,0 +1,1014 @@
+//! Authorize requests using JSON Web Tokens sent as `Bearer` tokens.
+//!
+//! [`JwtAuth`] verifies the signature of the token with a key from a [`JwtKeySource`] and checks
+//! the `exp`, `nbf`, `iss` and `aud` claims, allowing for some clock skew. The decoded claims are
+//! inserted into the request extensions as [`JwtClaims`]. Requests without a valid token get a
+//! `401 Unauthorized` response with a `WWW-Authenticate: Bearer` challenge, as described in
+//! [RFC 6750].
+//!
+//! Tokens signed with `HS256`, `RS256`, `ES256` and `EdDSA` (Ed25519) are supported. Keys can be
+//! configured statically or loaded from a [JWKS] document with [`JwtKeySet`], or provided by
+//! your own [`JwtKeySource`], e.g. one that refreshes the keys of an identity provider.
+//!
+//! # Example
+//!
+//! ```
+//! use http::{Request, Response, StatusCode, header::{AUTHORIZATION, WWW_AUTHENTICATE}};
+//! use http_body_util::Full;
+//! use bytes::Bytes;
+//! use serde_json::Value;
+//! use tower::{Service, ServiceExt, ServiceBuilder, BoxError};
+//! use tower_http::auth::jwt::{JwtAuth, JwtClaims, JwtKey, JwtKeySet};
+//! use tower_http::validate_request::ValidateRequestHeaderLayer;
+//!
+//! async fn handle(request: Request<Full<Bytes>>) -> Result<Response<Full<Bytes>>, BoxError> {
+//!     let claims = request.extensions().get::<JwtClaims<Value>>().unwrap();
+//!     Ok(Response::new(Full::from(format!("Hello {}", claims.claims()["sub"]))))
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), BoxError> {
+//! let keys = JwtKeySet::new([
+//!     JwtKey::hmac(b"a-shared-secret-of-at-least-32-bytes"),
+//!     // Or `JwtKeySet::from_jwks_file("jwks.json")?` for the keys of an identity provider.
+//! ]);
+//!
+//! let mut service = ServiceBuilder::new()
+//!     .layer(ValidateRequestHeaderLayer::jwt(
+//!         JwtAuth::new(keys)
+//!             .issuer("https://auth.example.com")
+//!             .audience("api")
+//!             .realm("api"),
+//!     ))
+//!     .service_fn(handle);
+//!
+//! # let exp = std::time::SystemTime::now()
+//! #     .duration_since(std::time::UNIX_EPOCH)?
+//! #     .as_secs() + 300;
+//! # let token = jsonwebtoken::encode(
+//! #     &jsonwebtoken::Header::default(),
+//! #     &serde_json::json!({
+//! #         "sub": "alice",
+//! #         "iss": "https://auth.example.com",
+//! #         "aud": "api",
+//! #         "exp": exp,
+//! #     }),
+//! #     &jsonwebtoken::EncodingKey::from_secret(b"a-shared-secret-of-at-least-32-bytes"),
+//! # )?;
+//! let request = Request::builder()
+//!     .header(AUTHORIZATION, format!("Bearer {}", token))
+//!     .body(Full::default())
+//!     .unwrap();
+//! let response = service.ready().await?.call(request).await?;
+//! assert_eq!(StatusCode::OK, response.status());
+//!
+//! let request = Request::builder()
+//!     .header(AUTHORIZATION, "Bearer not.a.jwt")
+//!     .body(Full::default())
+//!     .unwrap();
+//! let response = service.ready().await?.call(request).await?;
+//! assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+//! assert_eq!(
+//!     response.headers()[WWW_AUTHENTICATE],
+//!     "Bearer realm=\"api\", error=\"invalid_token\", error_description=\"malformed token\"",
+//! );
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [RFC 6750]: https://datatracker.ietf.org/doc/html/rfc6750#section-3
+//! [JWKS]: https://datatracker.ietf.org/doc/html/rfc7517#section-5
+
+use super::credentials::{strip_scheme, unauthorized};
+use crate::helpers::quote;
+use crate::validate_request::{ValidateRequest, ValidateRequestHeader, ValidateRequestHeaderLayer};
+use http::{header, Request, Response};
+use jsonwebtoken::{
+    errors::ErrorKind,
+    jwk::{AlgorithmParameters, EllipticCurve, Jwk, KeyAlgorithm, PublicKeyUse},
+    Algorithm, DecodingKey, Validation,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{fmt, marker::PhantomData, path::Path, sync::Arc, time::Duration};
+
+impl<S, C, ResBody> ValidateRequestHeader<S, RequireJwt<C, ResBody>> {
+    /// Authorize requests using JSON Web Tokens sent as `Bearer` tokens.
+    ///
+    /// See [`JwtAuth`] for more details.
+    pub fn jwt(inner: S, auth: JwtAuth<C>) -> Self
+    where
+        ResBody: Default,
+    {
+        Self::custom(inner, RequireJwt::new(auth))
+    }
+}
+
+impl<C, ResBody> ValidateRequestHeaderLayer<RequireJwt<C, ResBody>> {
+    /// Authorize requests using JSON Web Tokens sent as `Bearer` tokens.
+    ///
+    /// See [`JwtAuth`] for more details.
+    pub fn jwt(auth: JwtAuth<C>) -> Self
+    where
+        ResBody: Default,
+    {
+        Self::custom(RequireJwt::new(auth))
+    }
+}
+
+/// Errors that can occur while configuring JWT keys.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum ConfigError {
+    /// A key that couldn't be parsed.
+    InvalidKey {
+        /// Why the key is invalid.
+        reason: String,
+    },
+    /// A JWKS document that couldn't be read or parsed, or that has no supported keys.
+    InvalidJwks {
+        /// Why the document is invalid.
+        reason: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::InvalidKey { reason } => write!(f, "invalid JWT key: {reason}"),
+            ConfigError::InvalidJwks { reason } => write!(f, "invalid JWKS document: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// The signature algorithms supported by [`JwtAuth`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum JwtAlgorithm {
+    /// HMAC using SHA-256.
+    Hs256,
+    /// RSASSA-PKCS1-v1_5 using SHA-256.
+    Rs256,
+    /// ECDSA using P-256 and SHA-256.
+    Es256,
+    /// EdDSA using Ed25519.
+    EdDsa,
+}
+
+impl JwtAlgorithm {
+    fn from_header(algorithm: Algorithm) -> Option<Self> {
+        match algorithm {
+            Algorithm::HS256 => Some(Self::Hs256),
+            Algorithm::RS256 => Some(Self::Rs256),
+            Algorithm::ES256 => Some(Self::Es256),
+            Algorithm::EdDSA => Some(Self::EdDsa),
+            _ => None,
+        }
+    }
+
+    fn from_jwk(jwk: &Jwk) -> Option<Self> {
+        match (&jwk.common.key_algorithm, &jwk.algorithm) {
+            (Some(KeyAlgorithm::HS256) | None, AlgorithmParameters::OctetKey(_)) => {
+                Some(Self::Hs256)
+            }
+            (Some(KeyAlgorithm::RS256) | None, AlgorithmParameters::RSA(_)) => Some(Self::Rs256),
+            (Some(KeyAlgorithm::ES256) | None, AlgorithmParameters::EllipticCurve(params))
+                if params.curve == EllipticCurve::P256 =>
+            {
+                Some(Self::Es256)
+            }
+            (Some(KeyAlgorithm::EdDSA) | None, AlgorithmParameters::OctetKeyPair(params))
+                if params.curve == EllipticCurve::Ed25519 =>
+            {
+                Some(Self::EdDsa)
+            }
+            _ => None,
+        }
+    }
+
+    fn to_header(self) -> Algorithm {
+        match self {
+            Self::Hs256 => Algorithm::HS256,
+            Self::Rs256 => Algorithm::RS256,
+            Self::Es256 => Algorithm::ES256,
+            Self::EdDsa => Algorithm::EdDSA,
+        }
+    }
+}
+
+/// A key that verifies the signature of tokens signed with one [`JwtAlgorithm`].
+#[derive(Clone)]
+pub struct JwtKey {
+    id: Option<String>,
+    algorithm: JwtAlgorithm,
+    key: Arc<DecodingKey>,
+}
+
+impl JwtKey {
+    /// A shared secret for `HS256`.
+    pub fn hmac<K: AsRef<[u8]>>(secret: K) -> Self {
+        Self::new(
+            JwtAlgorithm::Hs256,
+            DecodingKey::from_secret(secret.as_ref()),
+        )
+    }
+
+    /// A PEM encoded RSA public key for `RS256`.
+    pub fn rsa_pem<K: AsRef<[u8]>>(pem: K) -> Result<Self, ConfigError> {
+        DecodingKey::from_rsa_pem(pem.as_ref())
+            .map(|key| Self::new(JwtAlgorithm::Rs256, key))
+            .map_err(invalid_key)
+    }
+
+    /// A PEM encoded P-256 public key for `ES256`.
+    pub fn ec_pem<K: AsRef<[u8]>>(pem: K) -> Result<Self, ConfigError> {
+        DecodingKey::from_ec_pem(pem.as_ref())
+            .map(|key| Self::new(JwtAlgorithm::Es256, key))
+            .map_err(invalid_key)
+    }
+
+    /// A PEM encoded Ed25519 public key for `EdDSA`.
+    pub fn ed_pem<K: AsRef<[u8]>>(pem: K) -> Result<Self, ConfigError> {
+        DecodingKey::from_ed_pem(pem.as_ref())
+            .map(|key| Self::new(JwtAlgorithm::EdDsa, key))
+            .map_err(invalid_key)
+    }
+
+    fn new(algorithm: JwtAlgorithm, key: DecodingKey) -> Self {
+        Self {
+            id: None,
+            algorithm,
+            key: Arc::new(key),
+        }
+    }
+
+    /// Set the key ID, which is matched against the `kid` header of tokens.
+    ///
+    /// Keys without an ID are tried for all tokens.
+    pub fn id<I: Into<String>>(mut self, id: I) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// The key ID, if any.
+    pub fn key_id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    /// The algorithm the key verifies.
+    pub fn algorithm(&self) -> JwtAlgorithm {
+        self.algorithm
+    }
+
+    fn matches(&self, key_id: Option<&str>, algorithm: JwtAlgorithm) -> bool {
+        self.algorithm == algorithm
+            && match (key_id, &self.id) {
+                (Some(key_id), Some(id)) => key_id == id,
+                _ => true,
+            }
+    }
+}
+
+impl fmt::Debug for JwtKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtKey")
+            .field("id", &self.id)
+            .field("algorithm", &self.algorithm)
+            .finish()
+    }
+}
+
+fn invalid_key(err: jsonwebtoken::errors::Error) -> ConfigError {
+    ConfigError::InvalidKey {
+        reason: err.to_string(),
+    }
+}
+
+/// Trait for providing the keys that verify tokens.
+///
+/// Implemented by [`JwtKeySet`]. Implement it yourself to, for example, share keys that are
+/// refreshed in the background from the JWKS endpoint of an identity provider.
+pub trait JwtKeySource: Send + Sync + 'static {
+    /// The candidate keys for a token with the given `kid` header and algorithm.
+    ///
+    /// Every returned key must be for `algorithm`. Tokens are rejected if no key is returned.
+    fn keys(&self, key_id: Option<&str>, algorithm: JwtAlgorithm) -> Vec<JwtKey>;
+}
+
+/// A fixed set of [`JwtKey`]s.
+#[derive(Clone, Debug, Default)]
+pub struct JwtKeySet {
+    keys: Vec<JwtKey>,
+}
+
+impl JwtKeySet {
+    /// Create a new `JwtKeySet` from the given keys.
+    pub fn new<I>(keys: I) -> Self
+    where
+        I: IntoIterator<Item = JwtKey>,
+    {
+        Self {
+            keys: keys.into_iter().collect(),
+        }
+    }
+
+    /// Parse a [JWKS] document.
+    ///
+    /// Keys for encryption, and keys with types or algorithms that aren't supported, are
+    /// ignored. Keys without an `alg` are used with the algorithm that matches their type.
+    ///
+    /// [JWKS]: https://datatracker.ietf.org/doc/html/rfc7517#section-5
+    pub fn from_jwks(jwks: &str) -> Result<Self, ConfigError> {
+        let jwks: serde_json::Value =
+            serde_json::from_str(jwks).map_err(|err| ConfigError::InvalidJwks {
+                reason: err.to_string(),
+            })?;
+        let Some(jwks) = jwks.get("keys").and_then(serde_json::Value::as_array) else {
+            return Err(ConfigError::InvalidJwks {
+                reason: "missing \"keys\" array".to_owned(),
+            });
+        };
+
+        let mut keys = Vec::new();
+        for jwk in jwks {
+            // Unknown key types and curves fail to deserialize.
+            let Ok(jwk) = Jwk::deserialize(jwk) else {
+                continue;
+            };
+            if let Some(PublicKeyUse::Encryption | PublicKeyUse::Other(_)) =
+                jwk.common.public_key_use
+            {
+                continue;
+            }
+            let Some(algorithm) = JwtAlgorithm::from_jwk(&jwk) else {
+                continue;
+            };
+            let key = DecodingKey::from_jwk(&jwk).map_err(|err| ConfigError::InvalidJwks {
+                reason: format!(
+                    "key {:?}: {}",
+                    jwk.common.key_id.as_deref().unwrap_or_default(),
+                    err
+                ),
+            })?;
+            keys.push(JwtKey {
+                id: jwk.common.key_id,
+                algorithm,
+                key: Arc::new(key),
+            });
+        }
+
+        if keys.is_empty() {
+            return Err(ConfigError::InvalidJwks {
+                reason: "no supported signing keys".to_owned(),
+            });
+        }
+        Ok(Self { keys })
+    }
+
+    /// Read and parse a [JWKS] document from a file.
+    ///
+    /// See [`from_jwks`](Self::from_jwks) for more details.
+    ///
+    /// [JWKS]: https://datatracker.ietf.org/doc/html/rfc7517#section-5
+    pub fn from_jwks_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let jwks = std::fs::read_to_string(path).map_err(|err| ConfigError::InvalidJwks {
+            reason: format!("{}: {}", path.display(), err),
+        })?;
+        Self::from_jwks(&jwks)
+    }
+
+    /// The keys in the set.
+    pub fn keys(&self) -> &[JwtKey] {
+        &self.keys
+    }
+}
+
+impl JwtKeySource for JwtKeySet {
+    fn keys(&self, key_id: Option<&str>, algorithm: JwtAlgorithm) -> Vec<JwtKey> {
+        self.keys
+            .iter()
+            .filter(|key| key.matches(key_id, algorithm))
+            .cloned()
+            .collect()
+    }
+}
+
+/// The claims of a verified token.
+///
+/// Inserted into the request extensions by [`RequireJwt`].
+#[derive(Clone, Debug)]
+pub struct JwtClaims<C> {
+    claims: C,
+    key_id: Option<String>,
+    algorithm: JwtAlgorithm,
+}
+
+impl<C> JwtClaims<C> {
+    /// The decoded claims.
+    pub fn claims(&self) -> &C {
+        &self.claims
+    }
+
+    /// Consume `self`, returning the decoded claims.
+    pub fn into_claims(self) -> C {
+        self.claims
+    }
+
+    /// The `kid` header of the token, if any.
+    pub fn key_id(&self) -> Option<&str> {
+        self.key_id.as_deref()
+    }
+
+    /// The algorithm the token was signed with.
+    pub fn algorithm(&self) -> JwtAlgorithm {
+        self.algorithm
+    }
+}
+
+/// Configuration for verifying JSON Web Tokens.
+///
+/// The claims are decoded as `C`, a [`serde_json::Value`] by default. Use
+/// [`with_claims`](Self::with_claims) to decode them into your own type.
+///
+/// Tokens must be signed with one of the keys of the [`JwtKeySource`] and have an `exp` claim.
+/// `nbf` is checked if present. `iss` and `aud` are required and checked once an
+/// [`issuer`](Self::issuer) or [`audience`](Self::audience) is configured.
+pub struct JwtAuth<C = serde_json::Value> {
+    keys: Arc<dyn JwtKeySource>,
+    issuers: Vec<String>,
+    audiences: Vec<String>,
+    leeway: Duration,
+    realm: String,
+    _claims: PhantomData<fn() -> C>,
+}
+
+impl JwtAuth {
+    /// Create a new `JwtAuth` that verifies tokens with keys from `keys`.
+    pub fn new<K: JwtKeySource>(keys: K) -> Self {
+        Self {
+            keys: Arc::new(keys),
+            issuers: Vec::new(),
+            audiences: Vec::new(),
+            leeway: Duration::from_secs(60),
+            realm: "Restricted".to_owned(),
+            _claims: PhantomData,
+        }
+    }
+}
+
+impl<C> JwtAuth<C> {
+    /// Decode the claims into `T` instead.
+    ///
+    /// Tokens whose claims can't be decoded into `T` are rejected.
+    pub fn with_claims<T>(self) -> JwtAuth<T> {
+        JwtAuth {
+            keys: self.keys,
+            issuers: self.issuers,
+            audiences: self.audiences,
+            leeway: self.leeway,
+            realm: self.realm,
+            _claims: PhantomData,
+        }
+    }
+
+    /// Accept tokens issued by `issuer`.
+    ///
+    /// Can be called multiple times to accept several issuers.
+    pub fn issuer<I: Into<String>>(mut self, issuer: I) -> Self {
+        self.issuers.push(issuer.into());
+        self
+    }
+
+    /// Accept tokens intended for `audience`.
+    ///
+    /// Can be called multiple times to accept several audiences.
+    pub fn audience<A: Into<String>>(mut self, audience: A) -> Self {
+        self.audiences.push(audience.into());
+        self
+    }
+
+    /// Set the allowed clock skew when checking `exp` and `nbf`.
+    ///
+    /// Defaults to 60 seconds.
+    pub fn leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    /// Set the realm sent in the `WWW-Authenticate` challenge.
+    ///
+    /// Defaults to `Restricted`.
+    pub fn realm<R: Into<String>>(mut self, realm: R) -> Self {
+        self.realm = realm.into();
+        self
+    }
+
+    fn validation(&self, algorithm: JwtAlgorithm) -> Validation {
+        let mut validation = Validation::new(algorithm.to_header());
+        validation.leeway = self.leeway.as_secs();
+        validation.validate_nbf = true;
+        let mut required = vec!["exp"];
+        if !self.issuers.is_empty() {
+            validation.set_issuer(&self.issuers);
+            required.push("iss");
+        }
+        if self.audiences.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&self.audiences);
+            required.push("aud");
+        }
+        validation.set_required_spec_claims(&required);
+        validation
+    }
+
+    /// Verify `token`, returning the `error_description` if it's invalid.
+    fn verify(&self, token: &str) -> Result<JwtClaims<C>, &'static str>
+    where
+        C: DeserializeOwned,
+    {
+        let header = jsonwebtoken::decode_header(token).map_err(|_| "malformed token")?;
+        let algorithm =
+            JwtAlgorithm::from_header(header.alg).ok_or("unsupported signature algorithm")?;
+        let keys = self.keys.keys(header.kid.as_deref(), algorithm);
+        if keys.is_empty() {
+            return Err("unknown signing key");
+        }
+
+        let validation = self.validation(algorithm);
+        for key in keys.iter().filter(|key| key.algorithm == algorithm) {
+            match jsonwebtoken::decode::<C>(token, &key.key, &validation) {
+                Ok(data) => {
+                    return Ok(JwtClaims {
+                        claims: data.claims,
+                        key_id: header.kid,
+                        algorithm,
+                    })
+                }
+                // Try the next key.
+                Err(err) if *err.kind() == ErrorKind::InvalidSignature => {}
+                // The claims are only validated once the signature is.
+                Err(err) => return Err(describe(err.kind())),
+            }
+        }
+        Err("invalid signature")
+    }
+}
+
+fn describe(kind: &ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::ExpiredSignature => "token expired",
+        ErrorKind::ImmatureSignature => "token not yet valid",
+        ErrorKind::InvalidIssuer => "invalid issuer",
+        ErrorKind::InvalidAudience => "invalid audience",
+        ErrorKind::MissingRequiredClaim(_) => "missing required claim",
+        ErrorKind::Json(_) => "invalid claims",
+        _ => "malformed token",
+    }
+}
+
+impl<C> Clone for JwtAuth<C> {
+    fn clone(&self) -> Self {
+        Self {
+            keys: self.keys.clone(),
+            issuers: self.issuers.clone(),
+            audiences: self.audiences.clone(),
+            leeway: self.leeway,
+            realm: self.realm.clone(),
+            _claims: PhantomData,
+        }
+    }
+}
+
+impl<C> fmt::Debug for JwtAuth<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtAuth")
+            .field("issuers", &self.issuers)
+            .field("audiences", &self.audiences)
+            .field("leeway", &self.leeway)
+            .field("realm", &self.realm)
+            .finish()
+    }
+}
+
+/// Type that performs JSON Web Token authorization.
+///
+/// See [`ValidateRequestHeader::jwt`] for more details.
+pub struct RequireJwt<C, ResBody> {
+    auth: Arc<JwtAuth<C>>,
+    _ty: PhantomData<fn() -> ResBody>,
+}
+
+impl<C, ResBody> RequireJwt<C, ResBody> {
+    fn new(auth: JwtAuth<C>) -> Self {
+        Self {
+            auth: Arc::new(auth),
+            _ty: PhantomData,
+        }
+    }
+}
+
+impl<C, ResBody> Clone for RequireJwt<C, ResBody> {
+    fn clone(&self) -> Self {
+        Self {
+            auth: self.auth.clone(),
+            _ty: PhantomData,
+        }
+    }
+}
+
+impl<C, ResBody> fmt::Debug for RequireJwt<C, ResBody> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequireJwt")
+            .field("auth", &self.auth)
+            .finish()
+    }
+}
+
+impl<B, C, ResBody> ValidateRequest<B> for RequireJwt<C, ResBody>
+where
+    C: DeserializeOwned + Clone + Send + Sync + 'static,
+    ResBody: Default,
+{
+    type ResponseBody = ResBody;
+
+    fn validate(&mut self, request: &mut Request<B>) -> Result<(), Response<Self::ResponseBody>> {
+        let token = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| strip_scheme(value, "Bearer"));
+
+        let Some(token) = token else {
+            return Err(unauthorized(&format!(
+                "Bearer realm={}",
+                quote(&self.auth.realm)
+            )));
+        };
+
+        match self.auth.verify(token) {
+            Ok(claims) => {
+                request.extensions_mut().insert(claims);
+                Ok(())
+            }
+            Err(description) => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(reason = description, "rejecting JSON Web Token");
+                Err(unauthorized(&format!(
+                    "Bearer realm={}, error=\"invalid_token\", error_description={}",
+                    quote(&self.auth.realm),
+                    quote(description)
+                )))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{check_authorization, Body};
+    use base64::Engine as _;
+    use http::StatusCode;
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::{json, Value};
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use tower::{BoxError, ServiceBuilder};
+
+    const KEYS_DIR: &str = "../test-files/jwt";
+    const SECRET: &[u8] = b"a-shared-secret-of-at-least-32-bytes";
+
+    fn read(file: &str) -> Vec<u8> {
+        std::fs::read(format!("{}/{}", KEYS_DIR, file)).unwrap()
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn sign(algorithm: Algorithm, kid: Option<&str>, claims: Value) -> String {
+        let key = match algorithm {
+            Algorithm::HS256 => EncodingKey::from_secret(SECRET),
+            Algorithm::RS256 => EncodingKey::from_rsa_pem(&read("rsa.pem")).unwrap(),
+            Algorithm::ES256 => EncodingKey::from_ec_pem(&read("ec.pem")).unwrap(),
+            Algorithm::EdDSA => EncodingKey::from_ed_pem(&read("ed.pem")).unwrap(),
+            _ => unreachable!(),
+        };
+        let mut header = Header::new(algorithm);
+        header.kid = kid.map(str::to_owned);
+        jsonwebtoken::encode(&header, &claims, &key).unwrap()
+    }
+
+    async fn echo(req: Request<Body>) -> Result<Response<Body>, BoxError> {
+        let claims = req.extensions().get::<JwtClaims<Value>>().unwrap();
+        Ok(Response::new(Body::from(
+            claims.claims()["sub"].to_string(),
+        )))
+    }
+
+    fn invalid(description: &str) -> (StatusCode, String) {
+        (
+            StatusCode::UNAUTHORIZED,
+            format!(
+                "Bearer realm=\"api\", error=\"invalid_token\", error_description=\"{}\"",
+                description
+            ),
+        )
+    }
+
+    fn ok(sub: &str) -> (StatusCode, String) {
+        (StatusCode::OK, format!("\"{}\"", sub))
+    }
+
+    #[tokio::test]
+    async fn algorithms() {
+        let keys = JwtKeySet::new([
+            JwtKey::hmac(SECRET),
+            JwtKey::rsa_pem(read("rsa.pub.pem")).unwrap(),
+            JwtKey::ec_pem(read("ec.pub.pem")).unwrap(),
+            JwtKey::ed_pem(read("ed.pub.pem")).unwrap(),
+        ]);
+        let svc = ServiceBuilder::new()
+            .layer(ValidateRequestHeaderLayer::jwt(
+                JwtAuth::new(keys).realm("api"),
+            ))
+            .service_fn(echo);
+
+        let claims = json!({ "sub": "alice", "exp": now() + 60 });
+        for algorithm in [
+            Algorithm::HS256,
+            Algorithm::RS256,
+            Algorithm::ES256,
+            Algorithm::EdDSA,
+        ] {
+            let token = sign(algorithm, None, claims.clone());
+            assert_eq!(
+                check_authorization(svc.clone(), Some(&format!("Bearer {}", token))).await,
+                ok("alice"),
+                "{:?}",
+                algorithm
+            );
+        }
+
+        // Signed with a different key.
+        let token = jsonwebtoken::encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(b"another-secret-of-at-least-32-bytes"),
+        )
+        .unwrap();
+        assert_eq!(
+            check_authorization(svc.clone(), Some(&format!("Bearer {}", token))).await,
+            invalid("invalid signature")
+        );
+
+        let token = sign(Algorithm::HS256, None, claims);
+        let (header, rest) = token.split_once('.').unwrap();
+        let header = String::from_utf8(
+            base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .decode(header)
+                .unwrap(),
+        )
+        .unwrap();
+        let none = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(header.replace("HS256", "none"));
+        assert_eq!(
+            check_authorization(svc.clone(), Some(&format!("Bearer {}.{}", none, rest))).await,
+            invalid("malformed token")
+        );
+
+        assert_eq!(
+            check_authorization(svc.clone(), None).await,
+            (StatusCode::UNAUTHORIZED, "Bearer realm=\"api\"".to_owned())
+        );
+        assert_eq!(
+            check_authorization(svc, Some("Bearer garbage")).await,
+            invalid("malformed token")
+        );
+    }
+
+    #[tokio::test]
+    async fn algorithm_must_match_key() {
+        // An HS256 token "signed" with the RSA public key must not verify against it.
+        let keys = JwtKeySet::new([JwtKey::rsa_pem(read("rsa.pub.pem")).unwrap()]);
+        let svc = ServiceBuilder::new()
+            .layer(ValidateRequestHeaderLayer::jwt(
+                JwtAuth::new(keys).realm("api"),
+            ))
+            .service_fn(echo);
+
+        let token = jsonwebtoken::encode(
+            &Header::default(),
+            &json!({ "sub": "mallory", "exp": now() + 60 }),
+            &EncodingKey::from_secret(&read("rsa.pub.pem")),
+        )
+        .unwrap();
+        assert_eq!(
+            check_authorization(svc, Some(&format!("Bearer {}", token))).await,
+            invalid("unknown signing key")
+        );
+    }
+
+    #[tokio::test]
+    async fn claims() {
+        let svc = ServiceBuilder::new()
+            .layer(ValidateRequestHeaderLayer::jwt(
+                JwtAuth::new(JwtKeySet::new([JwtKey::hmac(SECRET)]))
+                    .issuer("https://auth.example.com")
+                    .issuer("https://legacy.example.com")
+                    .audience("api")
+                    .leeway(Duration::from_secs(30))
+                    .realm("api"),
+            ))
+            .service_fn(echo);
+
+        let now = now();
+        let valid = json!({
+            "sub": "alice",
+            "iss": "https://auth.example.com",
+            "aud": ["web", "api"],
+            "exp": now + 60,
+        });
+        let with = |key: &str, value: Value| {
+            let mut claims = valid.clone();
+            claims[key] = value;
+            claims
+        };
+        let without = |key: &str| {
+            let mut claims = valid.clone();
+            claims.as_object_mut().unwrap().remove(key);
+            claims
+        };
+
+        let cases = [
+            (valid.clone(), ok("alice")),
+            (
+                with("iss", json!("https://legacy.example.com")),
+                ok("alice"),
+            ),
+            (with("aud", json!("api")), ok("alice")),
+            // Within the leeway.
+            (with("exp", json!(now - 10)), ok("alice")),
+            (with("nbf", json!(now + 10)), ok("alice")),
+            (with("exp", json!(now - 60)), invalid("token expired")),
+            (with("nbf", json!(now + 60)), invalid("token not yet valid")),
+            (
+                with("iss", json!("https://evil.example.com")),
+                invalid("invalid issuer"),
+            ),
+            (with("aud", json!("web")), invalid("invalid audience")),
+            (without("exp"), invalid("missing required claim")),
+            (without("iss"), invalid("missing required claim")),
+            (without("aud"), invalid("missing required claim")),
+        ];
+
+        for (claims, expected) in cases {
+            let token = sign(Algorithm::HS256, None, claims.clone());
+            assert_eq!(
+                check_authorization(svc.clone(), Some(&format!("Bearer {}", token))).await,
+                expected,
+                "{}",
+                claims
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn typed_claims() {
+        #[derive(Clone, Debug, serde::Deserialize)]
+        struct Claims {
+            sub: String,
+            admin: bool,
+        }
+
+        let svc = ServiceBuilder::new()
+            .layer(ValidateRequestHeaderLayer::jwt(
+                JwtAuth::new(JwtKeySet::new([JwtKey::hmac(SECRET)]))
+                    .with_claims::<Claims>()
+                    .realm("api"),
+            ))
+            .service_fn(|req: Request<Body>| async move {
+                let claims = req.extensions().get::<JwtClaims<Claims>>().unwrap();
+                assert_eq!(claims.algorithm(), JwtAlgorithm::Hs256);
+                let claims = claims.claims();
+                Ok::<_, BoxError>(Response::new(Body::from(format!(
+                    "{} {}",
+                    claims.sub, claims.admin
+                ))))
+            });
+
+        let token = sign(
+            Algorithm::HS256,
+            None,
+            json!({ "sub": "alice", "admin": true, "exp": now() + 60 }),
+        );
+        assert_eq!(
+            check_authorization(svc.clone(), Some(&format!("Bearer {}", token))).await,
+            (StatusCode::OK, "alice true".to_owned())
+        );
+
+        let token = sign(
+            Algorithm::HS256,
+            None,
+            json!({ "sub": "alice", "exp": now() + 60 }),
+        );
+        assert_eq!(
+            check_authorization(svc, Some(&format!("Bearer {}", token))).await,
+            invalid("invalid claims")
+        );
+    }
+
+    #[tokio::test]
+    async fn jwks() {
+        let keys = JwtKeySet::from_jwks_file(format!("{}/jwks.json", KEYS_DIR)).unwrap();
+        let mut ids = keys
+            .keys()
+            .iter()
+            .map(|key| (key.key_id().unwrap(), key.algorithm()))
+            .collect::<Vec<_>>();
+        ids.sort_unstable_by_key(|(id, _)| *id);
+        assert_eq!(
+            ids,
+            [
+                ("ec-1", JwtAlgorithm::Es256),
+                ("ed-1", JwtAlgorithm::EdDsa),
+                ("hmac-1", JwtAlgorithm::Hs256),
+                ("rsa-1", JwtAlgorithm::Rs256),
+            ]
+        );
+
+        let svc = ServiceBuilder::new()
+            .layer(ValidateRequestHeaderLayer::jwt(
+                JwtAuth::new(keys).realm("api"),
+            ))
+            .service_fn(echo);
+
+        let claims = json!({ "sub": "alice", "exp": now() + 60 });
+        let cases = [
+            (Algorithm::HS256, Some("hmac-1"), ok("alice")),
+            (Algorithm::RS256, Some("rsa-1"), ok("alice")),
+            (Algorithm::ES256, Some("ec-1"), ok("alice")),
+            (Algorithm::EdDSA, Some("ed-1"), ok("alice")),
+            (Algorithm::EdDSA, None, ok("alice")),
+            (
+                Algorithm::RS256,
+                Some("rsa-2"),
+                invalid("unknown signing key"),
+            ),
+            (
+                Algorithm::RS256,
+                Some("ec-1"),
+                invalid("unknown signing key"),
+            ),
+        ];
+        for (algorithm, kid, expected) in cases {
+            let token = sign(algorithm, kid, claims.clone());
+            assert_eq!(
+                check_authorization(svc.clone(), Some(&format!("Bearer {}", token))).await,
+                expected,
+                "{:?} {:?}",
+                algorithm,
+                kid
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert!(matches!(
+            JwtKey::rsa_pem("not a key"),
+            Err(ConfigError::InvalidKey { .. })
+        ));
+        assert!(matches!(
+            JwtKeySet::from_jwks("{}"),
+            Err(ConfigError::InvalidJwks { .. })
+        ));
+        assert!(matches!(
+            JwtKeySet::from_jwks(
+                r#"{"keys": [{"kty": "RSA", "use": "enc", "n": "AQAB", "e": "AQAB"}]}"#
+            ),
+            Err(ConfigError::InvalidJwks { .. })
+        ));
+        assert!(matches!(
+            JwtKeySet::from_jwks_file(format!("{}/missing.json", KEYS_DIR)),
+            Err(ConfigError::InvalidJwks { .. })
+        ));
+    }
+
+    #[test]
+    fn debug_leaves_out_keys() {
+        let auth = JwtAuth::new(JwtKeySet::new([JwtKey::hmac(SECRET).id("k1")])).audience("api");
+        assert_eq!(
+            format!("{:?}", auth),
+            "JwtAuth { issuers: [], audiences: [\"api\"], leeway: 60s, realm: \"Restricted\" }"
+        );
+        assert_eq!(
+            format!("{:?}", JwtKey::hmac(SECRET).id("k1")),
+            "JwtKey { id: Some(\"k1\"), algorithm: Hs256 }"
+        );
+    }
+}
@@ -1,8 +1,21 @@
 //! Authorization related middleware.
 
 pub mod add_authorization;
+pub mod api_key;
+#[cfg(feature = "auth-token-provider")]
+pub mod async_add_authorization;
 pub mod async_require_authorization;
+pub mod credentials;
+#[cfg(feature = "auth-digest")]
+pub mod digest;
+pub mod failure_limit;
+#[cfg(feature = "auth-jwt")]
+pub mod jwt;
 pub mod require_authorization;
+#[cfg(feature = "auth-signature")]
+pub mod signature;
+#[cfg(feature = "auth-signed-url")]
+pub mod signed_url;
 
 #[doc(inline)]
 pub use self::{
@@ -11,3 +24,7 @@ pub use self::{
         AsyncAuthorizeRequest, AsyncRequireAuthorization, AsyncRequireAuthorizationLayer,
     },
 };
+
+#[cfg(feature = "auth-token-provider")]
+#[doc(inline)]
+pub use self::async_add_authorization::{AsyncAddAuthorization, AsyncAddAuthorizationLayer};
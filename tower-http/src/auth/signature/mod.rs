@@ -0,0 +1,816 @@
+//! Sign requests with HMAC-SHA256 and verify the signatures.
+//!
+//! [`SignRequestLayer`] signs outgoing requests with a shared secret. The signature covers the
+//! method, authority, path and query, a timestamp, any headers you select, and a SHA-256 digest
+//! of the body sent in the [`Content-Digest`] header. [`SignatureVerifier`] checks those
+//! signatures on the server with [`AsyncRequireAuthorizationLayer`], looking up the secret by
+//! the key ID of the signature and rejecting signatures outside of a replay window.
+//!
+//! # Formats
+//!
+//! The way requests are canonicalized and the signature is transmitted is configured with
+//! [`SignatureFormat`]:
+//!
+//! - [`SignatureFormat::MessageSignatures`] follows [HTTP Message Signatures (RFC 9421)] and
+//!   uses the `Signature-Input` and `Signature` headers. This is the default.
+//! - [`SignatureFormat::Cavage`] follows the earlier [draft-cavage-http-signatures] and sends
+//!   the signature in an `Authorization: Signature ...` header.
+//!
+//! Both sides must use the same format.
+//!
+//! # Body digests
+//!
+//! With [`BodyDigest::Buffer`], the default, the signer buffers the body to compute its digest
+//! before sending the request, and the verifier buffers the body up to a [maximum
+//! size](SignatureVerifier::max_body_size) to check it before calling the inner service.
+//!
+//! With [`BodyDigest::Stream`], the signer streams the body and sends the digest in a
+//! `Content-Digest` trailer, along with a `Body-Signature` trailer that binds the digest to the
+//! signature of the request. This requires a transport that supports trailers, such as HTTP/2
+//! or chunked HTTP/1.1. The verifier checks the digest while the inner service reads the body,
+//! and fails the body with an [`InvalidBody`] error if it doesn't match. The request itself is
+//! still authenticated before the inner service is called.
+//!
+//! # Example
+//!
+//! ```
+//! use http::{Request, Response, StatusCode, header::CONTENT_TYPE};
+//! use http_body_util::Full;
+//! use bytes::Bytes;
+//! use tower::{Service, ServiceExt, ServiceBuilder, BoxError};
+//! use tower_http::auth::AsyncRequireAuthorizationLayer;
+//! use tower_http::auth::credentials::Identity;
+//! use tower_http::auth::signature::{SignRequestLayer, SignatureVerifier, VerifiedBody};
+//!
+//! async fn handle<B>(request: Request<VerifiedBody<B>>) -> Result<Response<Full<Bytes>>, BoxError> {
+//!     // The key ID of the signature.
+//!     let identity = request.extensions().get::<Identity>().unwrap();
+//!     Ok(Response::new(Full::from(format!("Hello {}", identity.name()))))
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), BoxError> {
+//! let server = ServiceBuilder::new()
+//!     .layer(AsyncRequireAuthorizationLayer::verify_signature(
+//!         SignatureVerifier::keys([("billing", "a-shared-secret")]).require_header(CONTENT_TYPE),
+//!     ))
+//!     .service_fn(handle);
+//!
+//! let mut client = ServiceBuilder::new()
+//!     .layer(SignRequestLayer::new("billing", "a-shared-secret").header(CONTENT_TYPE))
+//!     .service(server);
+//!
+//! let request = Request::post("http://orders.internal/invoices?draft=true")
+//!     .header(CONTENT_TYPE, "application/json")
+//!     .body(Full::<Bytes>::from("{\"amount\":42}"))?;
+//! let response = client.ready().await?.call(request).await?;
+//! assert_eq!(StatusCode::OK, response.status());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Content-Digest`]: https://datatracker.ietf.org/doc/html/rfc9530
+//! [`AsyncRequireAuthorizationLayer`]: crate::auth::AsyncRequireAuthorizationLayer
+//! [HTTP Message Signatures (RFC 9421)]: https://datatracker.ietf.org/doc/html/rfc9421
+//! [draft-cavage-http-signatures]: https://datatracker.ietf.org/doc/html/draft-cavage-http-signatures-12
+
+use base64::Engine as _;
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use http::{
+    header::{self, HeaderName},
+    request::Parts,
+    HeaderMap,
+};
+use http_body::{Frame, SizeHint};
+use sha2::Sha256;
+
+mod sign;
+mod verify;
+
+pub use self::{
+    sign::{ResponseFuture, SignRequest, SignRequestLayer, SignedBody},
+    verify::{InvalidBody, SignatureVerifier, VerifiedBody, VerifySignature},
+};
+
+const BASE64: base64::engine::GeneralPurpose = base64::engine::general_purpose::STANDARD;
+
+const ALGORITHM: &str = "hmac-sha256";
+const DEFAULT_LABEL: &str = "sig1";
+
+const CONTENT_DIGEST: HeaderName = HeaderName::from_static("content-digest");
+const BODY_SIGNATURE: HeaderName = HeaderName::from_static("body-signature");
+const SIGNATURE: HeaderName = HeaderName::from_static("signature");
+const SIGNATURE_INPUT: HeaderName = HeaderName::from_static("signature-input");
+
+/// How requests are canonicalized and how the signature is transmitted.
+///
+/// See the [module docs](self) for more details.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SignatureFormat {
+    /// [HTTP Message Signatures (RFC 9421)] using the `hmac-sha256` algorithm.
+    ///
+    /// The signature covers the `@method`, `@authority`, `@path` and `@query` derived
+    /// components, the selected headers and `content-digest`, with `created`, `keyid`, `alg` and
+    /// `nonce` parameters.
+    ///
+    /// [HTTP Message Signatures (RFC 9421)]: https://datatracker.ietf.org/doc/html/rfc9421
+    #[default]
+    MessageSignatures,
+    /// [draft-cavage-http-signatures] using the `hmac-sha256` algorithm.
+    ///
+    /// The signature covers `(request-target)`, `(created)`, `host`, the selected headers and
+    /// `content-digest`.
+    ///
+    /// [draft-cavage-http-signatures]: https://datatracker.ietf.org/doc/html/draft-cavage-http-signatures-12
+    Cavage,
+}
+
+/// How the digest of the request body is computed and checked.
+///
+/// See the [module docs](self) for more details.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BodyDigest {
+    /// Buffer the body and send or check the digest before the request.
+    #[default]
+    Buffer,
+    /// Stream the body and send or check the digest in trailers.
+    Stream,
+}
+
+fn mac(key: &[u8], data: &[&[u8]]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    for data in data {
+        mac.update(data);
+    }
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// The `Content-Digest` value for a SHA-256 `digest`.
+fn content_digest(digest: &[u8]) -> String {
+    format!("sha-256=:{}:", BASE64.encode(digest))
+}
+
+/// The SHA-256 digest of a `Content-Digest` value.
+fn parse_content_digest(value: &str) -> Option<Vec<u8>> {
+    members(value)
+        .filter_map(|member| member.split_once('='))
+        .find(|(algorithm, _)| algorithm.trim() == "sha-256")
+        .and_then(|(_, digest)| byte_sequence(digest))
+}
+
+/// The bytes of a structured field byte sequence such as `:AQID:`.
+fn byte_sequence(value: &str) -> Option<Vec<u8>> {
+    let value = value.trim().strip_prefix(':')?.strip_suffix(':')?;
+    BASE64.decode(value).ok()
+}
+
+/// The bytes of the body signature for the streamed `content_digest`.
+fn body_signature(key: &[u8], signature: &[u8], content_digest: &str) -> Vec<u8> {
+    mac(key, &[signature, b"\n", content_digest.as_bytes()])
+}
+
+/// Split a list at the commas that aren't inside quotes or parentheses.
+fn members(value: &str) -> impl Iterator<Item = &str> {
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut depth = 0usize;
+    let mut start = 0;
+    let mut members = Vec::new();
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth = depth.saturating_sub(1),
+            ',' if !quoted && depth == 0 => {
+                members.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    members.push(&value[start..]);
+    members
+        .into_iter()
+        .map(str::trim)
+        .filter(|member| !member.is_empty())
+}
+
+/// Parse a structured field string at the start of `value`, returning it and the rest.
+fn parse_string(value: &str) -> Option<(String, &str)> {
+    let value = value.strip_prefix('"')?;
+    let mut string = String::new();
+    let mut chars = value.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => string.push(chars.next()?.1),
+            '"' => return Some((string, &value[i + 1..])),
+            _ => string.push(c),
+        }
+    }
+    None
+}
+
+/// Values of the headers named `name`, trimmed and joined with `, `.
+fn header_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    let mut values = headers.get_all(name).iter().peekable();
+    values.peek()?;
+    let values = values
+        .map(|value| value.to_str().map(str::trim))
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    Some(values.join(", "))
+}
+
+/// The authority the request is sent to, from the URI or the `Host` header.
+fn authority(parts: &Parts) -> Option<String> {
+    let authority = match parts.uri.authority() {
+        Some(authority) => authority.as_str(),
+        None => parts.headers.get(header::HOST)?.to_str().ok()?,
+    };
+    Some(authority.to_ascii_lowercase())
+}
+
+fn path(parts: &Parts) -> &str {
+    match parts.uri.path() {
+        "" => "/",
+        path => path,
+    }
+}
+
+/// The value of a covered component, or `None` if the request doesn't have it.
+fn component_value(parts: &Parts, component: &str) -> Option<String> {
+    match component {
+        "@method" => Some(parts.method.as_str().to_owned()),
+        "@authority" => authority(parts),
+        "@path" => Some(path(parts).to_owned()),
+        "@query" => Some(format!("?{}", parts.uri.query().unwrap_or_default())),
+        "(request-target)" => Some(format!(
+            "{} {}{}",
+            parts.method.as_str().to_ascii_lowercase(),
+            path(parts),
+            parts
+                .uri
+                .query()
+                .map(|query| format!("?{}", query))
+                .unwrap_or_default()
+        )),
+        _ if component.starts_with('@') || component.starts_with('(') => None,
+        _ => header_value(&parts.headers, component),
+    }
+}
+
+/// The RFC 9421 signature base for `components` and the serialized signature `params`.
+fn message_signature_base(parts: &Parts, components: &[String], params: &str) -> Option<String> {
+    let mut base = String::new();
+    for component in components {
+        let value = component_value(parts, component)?;
+        base.push_str(&format!("\"{}\": {}\n", component, value));
+    }
+    base.push_str(&format!("\"@signature-params\": {}", params));
+    Some(base)
+}
+
+/// The draft-cavage signing string for `headers`.
+fn cavage_signature_base(parts: &Parts, headers: &[String], created: u64) -> Option<String> {
+    let lines = headers
+        .iter()
+        .map(|header| {
+            let value = match header.as_str() {
+                "(created)" => created.to_string(),
+                "host" => authority(parts)?,
+                _ => component_value(parts, header)?,
+            };
+            Some(format!("{}: {}", header, value))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(lines.join("\n"))
+}
+
+/// A fully buffered body.
+struct Buffered {
+    data: Option<Bytes>,
+    trailers: Option<HeaderMap>,
+}
+
+impl Buffered {
+    fn new(data: Bytes, trailers: Option<HeaderMap>) -> Self {
+        Self {
+            data: Some(data).filter(|data| !data.is_empty()),
+            trailers,
+        }
+    }
+
+    fn frame(&mut self) -> Option<Frame<Bytes>> {
+        if let Some(data) = self.data.take() {
+            return Some(Frame::data(data));
+        }
+        self.trailers.take().map(Frame::trailers)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.data.is_none() && self.trailers.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.data.as_ref().map_or(0, |data| data.len() as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{credentials::Identity, AsyncRequireAuthorizationLayer};
+    use crate::test_helpers::{status_and_body, unauthorized, Body};
+    use http::{header::CONTENT_TYPE, Request, Response, StatusCode};
+    use http_body_util::BodyExt;
+    use sha2::Digest as _;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use tower::{BoxError, Service, ServiceBuilder, ServiceExt};
+
+    struct Captured {
+        parts: Parts,
+        body: Bytes,
+        trailers: Option<HeaderMap>,
+    }
+
+    impl Captured {
+        fn into_request(self) -> Request<Body> {
+            let body = match self.trailers {
+                Some(trailers) => Body::new(Body::from(self.body).with_trailers(trailers)),
+                None => Body::from(self.body),
+            };
+            Request::from_parts(self.parts, body)
+        }
+    }
+
+    /// Sign a request and capture it as it would be sent.
+    async fn sign(layer: SignRequestLayer, request: Request<Body>) -> Captured {
+        ServiceBuilder::new()
+            .layer(layer)
+            .service_fn(|request: Request<SignedBody<Body>>| async move {
+                let (parts, body) = request.into_parts();
+                let collected = body.collect().await?;
+                let trailers = collected.trailers().cloned();
+                Ok::<_, BoxError>(Captured {
+                    parts,
+                    body: collected.to_bytes(),
+                    trailers,
+                })
+            })
+            .oneshot(request)
+            .await
+            .unwrap()
+    }
+
+    fn post() -> Request<Body> {
+        Request::post("http://orders.internal/invoices?draft=true")
+            .header(CONTENT_TYPE, "text/plain")
+            .body(Body::from("hello"))
+            .unwrap()
+    }
+
+    fn server(
+        verifier: SignatureVerifier,
+    ) -> impl Service<Request<Body>, Response = Response<Body>, Error = BoxError> + Clone {
+        ServiceBuilder::new()
+            .layer(AsyncRequireAuthorizationLayer::verify_signature(verifier))
+            .service_fn(|request: Request<VerifiedBody<Body>>| async move {
+                let key_id = request.extensions().get::<Identity>().unwrap().clone();
+                let body = match request.into_body().collect().await {
+                    Ok(body) => String::from_utf8(body.to_bytes().to_vec()).unwrap(),
+                    Err(err) => format!("error: {}", err),
+                };
+                Ok(Response::new(Body::from(format!(
+                    "{}: {}",
+                    key_id.name(),
+                    body
+                ))))
+            })
+    }
+
+    fn ok(body: &str) -> (StatusCode, String) {
+        (StatusCode::OK, format!("billing: {}", body))
+    }
+
+    const FORMATS: [SignatureFormat; 2] =
+        [SignatureFormat::MessageSignatures, SignatureFormat::Cavage];
+    const BODY_DIGESTS: [BodyDigest; 2] = [BodyDigest::Buffer, BodyDigest::Stream];
+
+    #[tokio::test]
+    async fn round_trip() {
+        for format in FORMATS {
+            for body_digest in BODY_DIGESTS {
+                let layer = SignRequestLayer::new("billing", "secret")
+                    .format(format)
+                    .header(CONTENT_TYPE)
+                    .body_digest(body_digest);
+                let verifier = SignatureVerifier::keys([("billing", "secret")])
+                    .format(format)
+                    .require_header(CONTENT_TYPE)
+                    .body_digest(body_digest);
+
+                let captured = sign(layer, post()).await;
+                match body_digest {
+                    BodyDigest::Buffer => {
+                        assert!(captured.parts.headers.contains_key(CONTENT_DIGEST));
+                        assert!(captured.trailers.is_none());
+                    }
+                    BodyDigest::Stream => {
+                        assert!(!captured.parts.headers.contains_key(CONTENT_DIGEST));
+                        let trailers = captured.trailers.as_ref().unwrap();
+                        assert!(trailers.contains_key(CONTENT_DIGEST));
+                        assert!(trailers.contains_key(BODY_SIGNATURE));
+                    }
+                }
+                match format {
+                    SignatureFormat::MessageSignatures => {
+                        let input = captured.parts.headers[SIGNATURE_INPUT].to_str().unwrap();
+                        assert!(
+                            input.starts_with("sig1=(\"@method\" \"@authority\" \"@path\" \"@query\" \"content-type\""),
+                            "{}",
+                            input
+                        );
+                        assert!(input.contains(";keyid=\"billing\";alg=\"hmac-sha256\";nonce="));
+                    }
+                    SignatureFormat::Cavage => {
+                        let authorization = captured.parts.headers[header::AUTHORIZATION]
+                            .to_str()
+                            .unwrap();
+                        assert!(
+                            authorization.starts_with(
+                                "Signature keyId=\"billing\",algorithm=\"hmac-sha256\",created="
+                            ),
+                            "{}",
+                            authorization
+                        );
+                        assert!(authorization
+                            .contains("headers=\"(request-target) (created) host content-type"));
+                    }
+                }
+
+                assert_eq!(
+                    status_and_body(server(verifier), captured.into_request()).await,
+                    ok("hello"),
+                    "{:?} {:?}",
+                    format,
+                    body_digest
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn tampering() {
+        for format in FORMATS {
+            let layer = SignRequestLayer::new("billing", "secret")
+                .format(format)
+                .header(CONTENT_TYPE);
+            let verifier = || SignatureVerifier::keys([("billing", "secret")]).format(format);
+
+            let mut captured = sign(layer.clone(), post()).await;
+            captured.parts.uri = "http://orders.internal/invoices?draft=false"
+                .parse()
+                .unwrap();
+            assert_eq!(
+                status_and_body(server(verifier()), captured.into_request()).await,
+                unauthorized()
+            );
+
+            let mut captured = sign(layer.clone(), post()).await;
+            captured.parts.method = http::Method::PUT;
+            assert_eq!(
+                status_and_body(server(verifier()), captured.into_request()).await,
+                unauthorized()
+            );
+
+            let mut captured = sign(layer.clone(), post()).await;
+            captured
+                .parts
+                .headers
+                .insert(CONTENT_TYPE, "text/html".parse().unwrap());
+            assert_eq!(
+                status_and_body(server(verifier()), captured.into_request()).await,
+                unauthorized()
+            );
+
+            let mut captured = sign(layer.clone(), post()).await;
+            captured.body = Bytes::from("HELLO");
+            assert_eq!(
+                status_and_body(server(verifier()), captured.into_request()).await,
+                unauthorized()
+            );
+
+            let mut captured = sign(layer.clone(), post()).await;
+            captured.body = Bytes::from("HELLO");
+            assert_eq!(
+                status_and_body(
+                    server(verifier().body_digest(BodyDigest::Stream)),
+                    captured.into_request()
+                )
+                .await,
+                ok("error: body doesn't match its digest")
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn tampering_streamed_body() {
+        let layer = SignRequestLayer::new("billing", "secret").body_digest(BodyDigest::Stream);
+        let verifier = || SignatureVerifier::keys([("billing", "secret")]);
+
+        let captured = sign(layer.clone(), post()).await;
+        assert_eq!(
+            status_and_body(
+                server(verifier().body_digest(BodyDigest::Stream)),
+                captured.into_request()
+            )
+            .await,
+            ok("hello")
+        );
+
+        let mut captured = sign(layer.clone(), post()).await;
+        captured.body = Bytes::from("HELLO");
+        assert_eq!(
+            status_and_body(
+                server(verifier().body_digest(BodyDigest::Stream)),
+                captured.into_request()
+            )
+            .await,
+            ok("error: body doesn't match its digest")
+        );
+        let mut captured = sign(layer.clone(), post()).await;
+        captured.body = Bytes::from("HELLO");
+        assert_eq!(
+            status_and_body(server(verifier()), captured.into_request()).await,
+            unauthorized()
+        );
+
+        // A digest of the tampered body without a matching body signature.
+        let mut captured = sign(layer.clone(), post()).await;
+        captured.body = Bytes::from("HELLO");
+        let trailers = captured.trailers.as_mut().unwrap();
+        trailers.insert(
+            CONTENT_DIGEST,
+            content_digest(&Sha256::digest(b"HELLO")).parse().unwrap(),
+        );
+        assert_eq!(
+            status_and_body(
+                server(verifier().body_digest(BodyDigest::Stream)),
+                captured.into_request()
+            )
+            .await,
+            ok("error: invalid body signature")
+        );
+
+        let mut captured = sign(layer, post()).await;
+        captured.trailers = None;
+        assert_eq!(
+            status_and_body(server(verifier()), captured.into_request()).await,
+            unauthorized()
+        );
+    }
+
+    #[tokio::test]
+    async fn rejections() {
+        let layer = SignRequestLayer::new("billing", "secret");
+        let verifier = || SignatureVerifier::keys([("billing", "secret")]);
+
+        let captured = sign(SignRequestLayer::new("billing", "wrong"), post()).await;
+        assert_eq!(
+            status_and_body(server(verifier()), captured.into_request()).await,
+            unauthorized()
+        );
+
+        let captured = sign(SignRequestLayer::new("shipping", "secret"), post()).await;
+        assert_eq!(
+            status_and_body(server(verifier()), captured.into_request()).await,
+            unauthorized()
+        );
+
+        assert_eq!(
+            status_and_body(server(verifier()), post()).await,
+            unauthorized()
+        );
+
+        // `content-type` isn't signed.
+        let captured = sign(layer.clone(), post()).await;
+        assert_eq!(
+            status_and_body(
+                server(verifier().require_header(CONTENT_TYPE)),
+                captured.into_request()
+            )
+            .await,
+            unauthorized()
+        );
+
+        // Signed by another format.
+        let captured = sign(layer.clone(), post()).await;
+        assert_eq!(
+            status_and_body(
+                server(verifier().format(SignatureFormat::Cavage)),
+                captured.into_request()
+            )
+            .await,
+            unauthorized()
+        );
+
+        // Signed under another label.
+        let captured = sign(layer.clone().label("other"), post()).await;
+        assert_eq!(
+            status_and_body(server(verifier()), captured.into_request()).await,
+            unauthorized()
+        );
+        let captured = sign(layer.clone().label("other"), post()).await;
+        assert_eq!(
+            status_and_body(server(verifier().label("other")), captured.into_request()).await,
+            ok("hello")
+        );
+
+        let captured = sign(layer, post()).await;
+        assert_eq!(
+            status_and_body(server(verifier().max_body_size(4)), captured.into_request()).await,
+            (StatusCode::PAYLOAD_TOO_LARGE, String::new())
+        );
+    }
+
+    #[tokio::test]
+    async fn replay_window() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let request = |created: u64| {
+            let mut parts = request_parts(Request::get("http://orders.internal/invoices"));
+            let headers =
+                ["(request-target)", "(created)", "host", "content-digest"].map(str::to_owned);
+            parts.headers.insert(
+                CONTENT_DIGEST,
+                content_digest(&Sha256::digest(b"")).parse().unwrap(),
+            );
+            let base = cavage_signature_base(&parts, &headers, created).unwrap();
+            let signature = BASE64.encode(mac(b"secret", &[base.as_bytes()]));
+            parts.headers.insert(
+                header::AUTHORIZATION,
+                format!(
+                    "Signature keyId=\"billing\",created={},headers=\"{}\",signature=\"{}\"",
+                    created,
+                    headers.join(" "),
+                    signature
+                )
+                .parse()
+                .unwrap(),
+            );
+            Request::from_parts(parts, Body::empty())
+        };
+        let svc = server(
+            SignatureVerifier::keys([("billing", "secret")])
+                .format(SignatureFormat::Cavage)
+                .max_age(Duration::from_secs(60)),
+        );
+
+        assert_eq!(
+            status_and_body(svc.clone(), request(now - 30)).await,
+            ok("")
+        );
+        assert_eq!(
+            status_and_body(svc.clone(), request(now + 30)).await,
+            ok("")
+        );
+        assert_eq!(
+            status_and_body(svc.clone(), request(now - 90)).await,
+            unauthorized()
+        );
+        assert_eq!(
+            status_and_body(svc, request(now + 90)).await,
+            unauthorized()
+        );
+    }
+
+    #[tokio::test]
+    async fn reject_replays() {
+        let svc = server(
+            SignatureVerifier::lookup(|key_id| (key_id == "billing").then(|| b"secret".to_vec()))
+                .reject_replays(),
+        );
+        let layer = SignRequestLayer::new("billing", "secret");
+
+        let captured = sign(layer.clone(), post()).await;
+        let replayed = Captured {
+            parts: {
+                let mut parts = request_parts(Request::post(captured.parts.uri.clone()));
+                parts.headers = captured.parts.headers.clone();
+                parts
+            },
+            body: captured.body.clone(),
+            trailers: None,
+        };
+        assert_eq!(
+            status_and_body(svc.clone(), captured.into_request()).await,
+            ok("hello")
+        );
+        assert_eq!(
+            status_and_body(svc.clone(), replayed.into_request()).await,
+            unauthorized()
+        );
+
+        // Every signature has its own nonce.
+        let captured = sign(layer, post()).await;
+        assert_eq!(
+            status_and_body(svc, captured.into_request()).await,
+            ok("hello")
+        );
+    }
+
+    fn request_parts(request: http::request::Builder) -> Parts {
+        request.body(()).unwrap().into_parts().0
+    }
+
+    // RFC 9421, Appendix B.2.5.
+    #[test]
+    fn message_signature_test_vector() {
+        let parts = request_parts(
+            Request::post("/foo?param=Value&Pet=dog")
+                .header("host", "example.com")
+                .header("date", "Tue, 20 Apr 2021 02:07:55 GMT")
+                .header("content-type", "application/json"),
+        );
+        let components = ["date", "@authority", "content-type"].map(str::to_owned);
+        let params = "(\"date\" \"@authority\" \"content-type\");created=1618884473;keyid=\"test-shared-secret\"";
+        let base = message_signature_base(&parts, &components, params).unwrap();
+        assert_eq!(
+            base,
+            "\"date\": Tue, 20 Apr 2021 02:07:55 GMT\n\
+             \"@authority\": example.com\n\
+             \"content-type\": application/json\n\
+             \"@signature-params\": (\"date\" \"@authority\" \"content-type\");created=1618884473;keyid=\"test-shared-secret\""
+        );
+
+        let key = BASE64
+            .decode("uzvJfB4u3N0Jy4T7NZ75MDVcr8zSTInedJtkgcu46YW4XByzNJjxBdtjUkdJPBtbmHhIDi6pcl8jsasjlTMtDQ==")
+            .unwrap();
+        assert_eq!(
+            BASE64.encode(mac(&key, &[base.as_bytes()])),
+            "pxcQw6G3AjtMBQjwo8XzkZf/bws5LelbaMk5rGIGtE8="
+        );
+    }
+
+    #[test]
+    fn components() {
+        let parts = request_parts(
+            Request::get("http://Example.com:8080")
+                .header("x-list", " a ")
+                .header("x-list", "b"),
+        );
+        assert_eq!(component_value(&parts, "@method").unwrap(), "GET");
+        assert_eq!(
+            component_value(&parts, "@authority").unwrap(),
+            "example.com:8080"
+        );
+        assert_eq!(component_value(&parts, "@path").unwrap(), "/");
+        assert_eq!(component_value(&parts, "@query").unwrap(), "?");
+        assert_eq!(component_value(&parts, "x-list").unwrap(), "a, b");
+        assert_eq!(component_value(&parts, "x-missing"), None);
+        assert_eq!(component_value(&parts, "@unknown"), None);
+
+        let parts = request_parts(Request::post("/foo?a=1"));
+        assert_eq!(
+            component_value(&parts, "(request-target)").unwrap(),
+            "post /foo?a=1"
+        );
+        assert_eq!(component_value(&parts, "@query").unwrap(), "?a=1");
+    }
+
+    // RFC 9530, Section 2.
+    #[test]
+    fn content_digests() {
+        let digest = sha2::Sha256::digest(b"{\"hello\": \"world\"}\n");
+        let value = content_digest(&digest);
+        assert_eq!(
+            value,
+            "sha-256=:RK/0qy18MlBSVnWgjwz6lZEWjP/lF5HF9bvEF8FabDg=:"
+        );
+        assert_eq!(
+            parse_content_digest(&format!("sha-512=:AAAA:, {}", value)).unwrap(),
+            digest.to_vec()
+        );
+        assert_eq!(parse_content_digest("sha-512=:AAAA:"), None);
+    }
+
+    #[test]
+    fn list_members() {
+        assert_eq!(
+            members(r#"a=("x, y" "z");k="v,w", b=:AA==:,,"#).collect::<Vec<_>>(),
+            [r#"a=("x, y" "z");k="v,w""#, "b=:AA==:"]
+        );
+        assert_eq!(
+            parse_string(r#""a\"b\\c";rest"#).unwrap(),
+            ("a\"b\\c".to_owned(), ";rest")
+        );
+        assert_eq!(parse_string(r#""unterminated"#), None);
+    }
+}
@@ -0,0 +1,493 @@
+use super::{
+    authority, body_signature, cavage_signature_base, content_digest, header_value, mac,
+    message_signature_base, BodyDigest, Buffered, SignatureFormat, ALGORITHM, BASE64,
+    BODY_SIGNATURE, CONTENT_DIGEST, DEFAULT_LABEL, SIGNATURE, SIGNATURE_INPUT,
+};
+use crate::{helpers::quote, BoxError};
+use base64::Engine as _;
+use bytes::{Buf, Bytes};
+use http::{
+    header::{self, HeaderName, HeaderValue},
+    request::Parts,
+    HeaderMap, Request,
+};
+use http_body::{Body, Frame, SizeHint};
+use http_body_util::{combinators::Collect, BodyExt};
+use pin_project_lite::pin_project;
+use sha2::{Digest as _, Sha256};
+use std::{
+    convert::TryFrom,
+    fmt,
+    future::Future,
+    mem,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// Layer that applies [`SignRequest`] which signs requests with HMAC-SHA256.
+///
+/// See the [module docs](crate::auth::signature) for more details.
+#[derive(Clone)]
+pub struct SignRequestLayer {
+    signer: Signer,
+}
+
+#[derive(Clone)]
+struct Signer {
+    key_id: String,
+    key: Arc<[u8]>,
+    format: SignatureFormat,
+    label: String,
+    headers: Vec<HeaderName>,
+    body_digest: BodyDigest,
+}
+
+impl SignRequestLayer {
+    /// Sign requests with the shared `secret` identified by `key_id`.
+    pub fn new<I, K>(key_id: I, secret: K) -> Self
+    where
+        I: Into<String>,
+        K: AsRef<[u8]>,
+    {
+        Self {
+            signer: Signer {
+                key_id: key_id.into(),
+                key: secret.as_ref().into(),
+                format: SignatureFormat::default(),
+                label: DEFAULT_LABEL.to_owned(),
+                headers: Vec::new(),
+                body_digest: BodyDigest::default(),
+            },
+        }
+    }
+
+    /// Set the [`SignatureFormat`].
+    ///
+    /// Defaults to [`SignatureFormat::MessageSignatures`].
+    pub fn format(mut self, format: SignatureFormat) -> Self {
+        self.signer.format = format;
+        self
+    }
+
+    /// Set the label of the signature in the `Signature-Input` and `Signature` headers.
+    ///
+    /// Only used by [`SignatureFormat::MessageSignatures`]. Defaults to `sig1`.
+    pub fn label<L: Into<String>>(mut self, label: L) -> Self {
+        self.signer.label = label.into();
+        self
+    }
+
+    /// Also sign the header `name`, if the request has it.
+    pub fn header(mut self, name: HeaderName) -> Self {
+        self.signer.headers.push(name);
+        self
+    }
+
+    /// Set how the digest of the body is computed.
+    ///
+    /// Defaults to [`BodyDigest::Buffer`].
+    pub fn body_digest(mut self, body_digest: BodyDigest) -> Self {
+        self.signer.body_digest = body_digest;
+        self
+    }
+}
+
+impl fmt::Debug for SignRequestLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SignRequestLayer")
+            .field("signer", &self.signer)
+            .finish()
+    }
+}
+
+impl fmt::Debug for Signer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Signer")
+            .field("key_id", &self.key_id)
+            .field("format", &self.format)
+            .field("label", &self.label)
+            .field("headers", &self.headers)
+            .field("body_digest", &self.body_digest)
+            .finish()
+    }
+}
+
+impl<S> Layer<S> for SignRequestLayer {
+    type Service = SignRequest<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SignRequest {
+            inner,
+            signer: Arc::new(self.signer.clone()),
+        }
+    }
+}
+
+impl Signer {
+    /// Add the signature headers to `parts`, returning the signature.
+    fn sign(&self, parts: &mut Parts, digest: Option<&[u8]>) -> Vec<u8> {
+        if let Some(digest) = digest {
+            let value = HeaderValue::from_str(&content_digest(digest)).unwrap();
+            parts.headers.insert(CONTENT_DIGEST, value);
+        } else {
+            parts.headers.insert(
+                header::TRAILER,
+                HeaderValue::from_static("content-digest, body-signature"),
+            );
+        }
+
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs());
+
+        let mut covered = Vec::new();
+        let mut cover = |name: &str| {
+            if !covered.iter().any(|covered| covered == name) {
+                covered.push(name.to_owned());
+            }
+        };
+        match self.format {
+            SignatureFormat::MessageSignatures => {
+                cover("@method");
+                if authority(parts).is_some() {
+                    cover("@authority");
+                }
+                cover("@path");
+                cover("@query");
+            }
+            SignatureFormat::Cavage => {
+                cover("(request-target)");
+                cover("(created)");
+                if authority(parts).is_some() {
+                    cover("host");
+                }
+            }
+        }
+        for name in &self.headers {
+            if header_value(&parts.headers, name.as_str()).is_some() {
+                cover(name.as_str());
+            }
+        }
+        if digest.is_some() {
+            cover(CONTENT_DIGEST.as_str());
+        }
+
+        match self.format {
+            SignatureFormat::MessageSignatures => {
+                let mut nonce = [0; 16];
+                getrandom::getrandom(&mut nonce).expect("failed to generate a random nonce");
+                let params = format!(
+                    "({});created={};keyid={};alg=\"{}\";nonce=\"{}\"",
+                    covered
+                        .iter()
+                        .map(|component| format!("\"{}\"", component))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    created,
+                    quote(&self.key_id),
+                    ALGORITHM,
+                    BASE64.encode(nonce),
+                );
+                let base = message_signature_base(parts, &covered, &params)
+                    .expect("covered components are present");
+                let signature = mac(&self.key, &[base.as_bytes()]);
+
+                let input = format!("{}={}", self.label, params);
+                let value = format!("{}=:{}:", self.label, BASE64.encode(&signature));
+                if let (Ok(input), Ok(value)) =
+                    (HeaderValue::try_from(input), HeaderValue::try_from(value))
+                {
+                    parts.headers.insert(SIGNATURE_INPUT, input);
+                    parts.headers.insert(SIGNATURE, value);
+                }
+                signature
+            }
+            SignatureFormat::Cavage => {
+                let base = cavage_signature_base(parts, &covered, created)
+                    .expect("covered components are present");
+                let signature = mac(&self.key, &[base.as_bytes()]);
+
+                let value = format!(
+                    "Signature keyId={},algorithm=\"{}\",created={},headers=\"{}\",signature=\"{}\"",
+                    quote(&self.key_id),
+                    ALGORITHM,
+                    created,
+                    covered.join(" "),
+                    BASE64.encode(&signature),
+                );
+                if let Ok(value) = HeaderValue::try_from(value) {
+                    parts.headers.insert(header::AUTHORIZATION, value);
+                }
+                signature
+            }
+        }
+    }
+}
+
+/// Middleware that signs requests with HMAC-SHA256.
+///
+/// See the [module docs](crate::auth::signature) for more details.
+#[derive(Clone, Debug)]
+pub struct SignRequest<S> {
+    inner: S,
+    signer: Arc<Signer>,
+}
+
+impl<S> SignRequest<S> {
+    /// Sign requests with the shared `secret` identified by `key_id`.
+    pub fn new<I, K>(inner: S, key_id: I, secret: K) -> Self
+    where
+        I: Into<String>,
+        K: AsRef<[u8]>,
+    {
+        SignRequestLayer::new(key_id, secret).layer(inner)
+    }
+
+    define_inner_service_accessors!();
+
+    /// Returns a new [`Layer`] that wraps services with a `SignRequest` middleware.
+    ///
+    /// [`Layer`]: tower_layer::Layer
+    pub fn layer<I, K>(key_id: I, secret: K) -> SignRequestLayer
+    where
+        I: Into<String>,
+        K: AsRef<[u8]>,
+    {
+        SignRequestLayer::new(key_id, secret)
+    }
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for SignRequest<S>
+where
+    S: Service<Request<SignedBody<ReqBody>>> + Clone,
+    S::Error: Into<BoxError>,
+    ReqBody: Body,
+    ReqBody::Error: Into<BoxError>,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = ResponseFuture<S, ReqBody>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let mut inner = self.inner.clone();
+        // mem::swap due to https://docs.rs/tower/latest/tower/trait.Service.html#be-careful-when-cloning-inner-services
+        mem::swap(&mut self.inner, &mut inner);
+
+        let (mut parts, body) = req.into_parts();
+        let state = match self.signer.body_digest {
+            BodyDigest::Stream if body.size_hint().exact() != Some(0) => {
+                let signature = self.signer.sign(&mut parts, None);
+                let body = SignedBody {
+                    kind: Kind::Streaming {
+                        inner: body,
+                        trailer: Some(TrailerSigner {
+                            hasher: Sha256::new(),
+                            key: self.signer.key.clone(),
+                            signature,
+                        }),
+                    },
+                };
+                State::Call {
+                    fut: inner.call(Request::from_parts(parts, body)),
+                }
+            }
+            _ => State::Collect {
+                collect: body.collect(),
+                parts: Some(parts),
+            },
+        };
+
+        ResponseFuture {
+            state,
+            service: inner,
+            signer: self.signer.clone(),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`SignRequest`].
+    pub struct ResponseFuture<S, ReqBody>
+    where
+        S: Service<Request<SignedBody<ReqBody>>>,
+        ReqBody: Body,
+    {
+        #[pin]
+        state: State<Collect<ReqBody>, S::Future>,
+        service: S,
+        signer: Arc<Signer>,
+    }
+}
+
+pin_project! {
+    #[project = StateProj]
+    enum State<C, F> {
+        Collect {
+            #[pin]
+            collect: C,
+            parts: Option<Parts>,
+        },
+        Call {
+            #[pin]
+            fut: F,
+        },
+    }
+}
+
+impl<S, ReqBody> Future for ResponseFuture<S, ReqBody>
+where
+    S: Service<Request<SignedBody<ReqBody>>>,
+    S::Error: Into<BoxError>,
+    ReqBody: Body,
+    ReqBody::Error: Into<BoxError>,
+{
+    type Output = Result<S::Response, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+
+        loop {
+            match this.state.as_mut().project() {
+                StateProj::Collect { collect, parts } => {
+                    let collected = ready!(collect.poll(cx)).map_err(Into::into)?;
+                    let mut parts = parts.take().expect("future polled after completion");
+                    let trailers = collected.trailers().cloned();
+                    let data = collected.to_bytes();
+
+                    this.signer.sign(&mut parts, Some(&Sha256::digest(&data)));
+                    let body = SignedBody {
+                        kind: Kind::Buffered {
+                            body: Buffered::new(data, trailers),
+                        },
+                    };
+                    let fut = this.service.call(Request::from_parts(parts, body));
+                    this.state.set(State::Call { fut });
+                }
+                StateProj::Call { fut } => return fut.poll(cx).map_err(Into::into),
+            }
+        }
+    }
+}
+
+pin_project! {
+    /// Request body for [`SignRequest`].
+    ///
+    /// Either the buffered body, or the streamed body followed by the `Content-Digest` and
+    /// `Body-Signature` trailers.
+    pub struct SignedBody<B> {
+        #[pin]
+        kind: Kind<B>,
+    }
+}
+
+pin_project! {
+    #[project = KindProj]
+    enum Kind<B> {
+        Buffered {
+            body: Buffered,
+        },
+        Streaming {
+            #[pin]
+            inner: B,
+            trailer: Option<TrailerSigner>,
+        },
+    }
+}
+
+struct TrailerSigner {
+    hasher: Sha256,
+    key: Arc<[u8]>,
+    signature: Vec<u8>,
+}
+
+impl TrailerSigner {
+    fn sign(self, trailers: &mut HeaderMap) {
+        let digest = content_digest(&self.hasher.finalize());
+        let signature = body_signature(&self.key, &self.signature, &digest);
+        let signature = format!(":{}:", BASE64.encode(signature));
+        trailers.insert(CONTENT_DIGEST, HeaderValue::try_from(digest).unwrap());
+        trailers.insert(BODY_SIGNATURE, HeaderValue::try_from(signature).unwrap());
+    }
+}
+
+impl<B> Body for SignedBody<B>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match self.project().kind.project() {
+            KindProj::Buffered { body } => Poll::Ready(body.frame().map(Ok)),
+            KindProj::Streaming { inner, trailer } => {
+                let Some(signer) = trailer else {
+                    return inner.poll_frame(cx).map(|frame| {
+                        frame.map(|frame| {
+                            frame
+                                .map(|frame| {
+                                    frame.map_data(|mut data| data.copy_to_bytes(data.remaining()))
+                                })
+                                .map_err(Into::into)
+                        })
+                    });
+                };
+                match ready!(inner.poll_frame(cx)) {
+                    Some(Ok(frame)) => {
+                        let frame = frame.map_data(|mut data| data.copy_to_bytes(data.remaining()));
+                        match frame.into_trailers() {
+                            Ok(mut trailers) => {
+                                trailer.take().unwrap().sign(&mut trailers);
+                                Poll::Ready(Some(Ok(Frame::trailers(trailers))))
+                            }
+                            Err(frame) => {
+                                if let Some(data) = frame.data_ref() {
+                                    signer.hasher.update(data);
+                                }
+                                Poll::Ready(Some(Ok(frame)))
+                            }
+                        }
+                    }
+                    Some(Err(err)) => Poll::Ready(Some(Err(err.into()))),
+                    None => {
+                        let mut trailers = HeaderMap::new();
+                        trailer.take().unwrap().sign(&mut trailers);
+                        Poll::Ready(Some(Ok(Frame::trailers(trailers))))
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match &self.kind {
+            Kind::Buffered { body } => body.is_end_stream(),
+            Kind::Streaming { inner, trailer } => trailer.is_none() && inner.is_end_stream(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match &self.kind {
+            Kind::Buffered { body } => body.size_hint(),
+            Kind::Streaming { inner, .. } => inner.size_hint(),
+        }
+    }
+}
+
+impl<B> fmt::Debug for SignedBody<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SignedBody").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,675 @@
+use super::{
+    body_signature, byte_sequence, cavage_signature_base, mac, members, message_signature_base,
+    parse_content_digest, parse_string, BodyDigest, Buffered, SignatureFormat, ALGORITHM, BASE64,
+    BODY_SIGNATURE, CONTENT_DIGEST, DEFAULT_LABEL, SIGNATURE, SIGNATURE_INPUT,
+};
+use crate::{
+    auth::{
+        credentials::{strip_scheme, Identity},
+        AsyncAuthorizeRequest, AsyncRequireAuthorization, AsyncRequireAuthorizationLayer,
+    },
+    helpers::constant_time_eq,
+    BoxError,
+};
+use base64::Engine as _;
+use bytes::{Buf, Bytes};
+use http::{
+    header::{self, HeaderName},
+    request::Parts,
+    HeaderMap, Request, Response, StatusCode,
+};
+use http_body::{Body, Frame, SizeHint};
+use http_body_util::{BodyExt, LengthLimitError, Limited};
+use pin_project_lite::pin_project;
+use sha2::{Digest as _, Sha256};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+impl<S, ResBody> AsyncRequireAuthorization<S, VerifySignature<ResBody>> {
+    /// Authorize requests signed with HMAC-SHA256.
+    ///
+    /// See the [module docs](crate::auth::signature) for more details.
+    pub fn verify_signature(inner: S, verifier: SignatureVerifier) -> Self {
+        Self::new(inner, VerifySignature::new(verifier))
+    }
+}
+
+impl<ResBody> AsyncRequireAuthorizationLayer<VerifySignature<ResBody>> {
+    /// Authorize requests signed with HMAC-SHA256.
+    ///
+    /// See the [module docs](crate::auth::signature) for more details.
+    pub fn verify_signature(verifier: SignatureVerifier) -> Self {
+        Self::new(VerifySignature::new(verifier))
+    }
+}
+
+type LookupFn = dyn Fn(&str) -> Option<Vec<u8>> + Send + Sync + 'static;
+
+/// Configuration for verifying signed requests.
+///
+/// The shared secret is looked up by the key ID of the signature. The signature must cover the
+/// method, authority, path and query of the request, every [required
+/// header](Self::require_header) and the digest of the body, and must have been created within
+/// the [maximum age](Self::max_age). The key ID of authorized requests is inserted into the
+/// request extensions as an [`Identity`].
+///
+/// Requests with a missing or invalid signature get a `401 Unauthorized` response. When the
+/// body is buffered, bodies larger than the [maximum size](Self::max_body_size) get a
+/// `413 Payload Too Large` response.
+///
+/// See the [module docs](crate::auth::signature) for an example.
+pub struct SignatureVerifier {
+    keys: Keys,
+    format: SignatureFormat,
+    label: String,
+    required_headers: Vec<HeaderName>,
+    max_age: Duration,
+    replays: Option<Mutex<HashMap<Vec<u8>, u64>>>,
+    body_digest: BodyDigest,
+    max_body_size: usize,
+}
+
+enum Keys {
+    Set(HashMap<String, Arc<[u8]>>),
+    Lookup(Arc<LookupFn>),
+}
+
+impl SignatureVerifier {
+    /// Verify signatures with the given `(key_id, secret)` pairs.
+    pub fn keys<I, K, S>(keys: I) -> Self
+    where
+        I: IntoIterator<Item = (K, S)>,
+        K: Into<String>,
+        S: AsRef<[u8]>,
+    {
+        Self::new(Keys::Set(
+            keys.into_iter()
+                .map(|(key_id, secret)| (key_id.into(), secret.as_ref().into()))
+                .collect(),
+        ))
+    }
+
+    /// Verify signatures with the secret returned by `lookup` for the key ID of the signature.
+    ///
+    /// Returning `None` rejects the request.
+    pub fn lookup<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<Vec<u8>> + Send + Sync + 'static,
+    {
+        Self::new(Keys::Lookup(Arc::new(lookup)))
+    }
+
+    fn new(keys: Keys) -> Self {
+        Self {
+            keys,
+            format: SignatureFormat::default(),
+            label: DEFAULT_LABEL.to_owned(),
+            required_headers: Vec::new(),
+            max_age: Duration::from_secs(5 * 60),
+            replays: None,
+            body_digest: BodyDigest::default(),
+            max_body_size: 1024 * 1024,
+        }
+    }
+
+    /// Set the [`SignatureFormat`].
+    ///
+    /// Defaults to [`SignatureFormat::MessageSignatures`].
+    pub fn format(mut self, format: SignatureFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Set the label of the signature that is verified.
+    ///
+    /// Only used by [`SignatureFormat::MessageSignatures`]. Defaults to `sig1`.
+    pub fn label<L: Into<String>>(mut self, label: L) -> Self {
+        self.label = label.into();
+        self
+    }
+
+    /// Require the signature to cover the header `name`.
+    pub fn require_header(mut self, name: HeaderName) -> Self {
+        self.required_headers.push(name);
+        self
+    }
+
+    /// Set how far the creation time of a signature may be from now.
+    ///
+    /// This is the replay window, and also bounds the allowed clock skew. Defaults to 5
+    /// minutes.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Reject signatures that were already used within the [maximum age](Self::max_age).
+    ///
+    /// Seen signatures are remembered in memory, so this only detects replays to the same
+    /// instance of the service.
+    pub fn reject_replays(mut self) -> Self {
+        self.replays = Some(Mutex::default());
+        self
+    }
+
+    /// Set how the digest of the body is checked.
+    ///
+    /// Defaults to [`BodyDigest::Buffer`].
+    pub fn body_digest(mut self, body_digest: BodyDigest) -> Self {
+        self.body_digest = body_digest;
+        self
+    }
+
+    /// Set the maximum size of a buffered body, in bytes.
+    ///
+    /// Defaults to 1 MiB.
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Verify the signature of the request head.
+    fn verify(&self, parts: &Parts) -> Result<Verified, &'static str> {
+        let signature = match self.format {
+            SignatureFormat::MessageSignatures => self.parse_message_signature(parts)?,
+            SignatureFormat::Cavage => parse_cavage(parts)?,
+        };
+
+        if signature
+            .algorithm
+            .as_deref()
+            .map_or(false, |algorithm| algorithm != ALGORITHM)
+        {
+            return Err("unsupported signature algorithm");
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs());
+        let created = signature.created.ok_or("missing creation time")?;
+        let max_age = self.max_age.as_secs();
+        if created > now.saturating_add(max_age) {
+            return Err("signature created in the future");
+        }
+        if created < now.saturating_sub(max_age) || signature.expires.map_or(false, |e| e < now) {
+            return Err("signature expired");
+        }
+
+        let required: &[&str] = match self.format {
+            SignatureFormat::MessageSignatures => &["@method", "@authority", "@path", "@query"],
+            SignatureFormat::Cavage => &["(request-target)", "(created)", "host"],
+        };
+        let covers = |name: &str| signature.covered.iter().any(|covered| covered == name);
+        let missing = required
+            .iter()
+            .copied()
+            .chain(self.required_headers.iter().map(HeaderName::as_str))
+            .any(|name| !covers(name));
+        if missing {
+            return Err("signature doesn't cover required components");
+        }
+        let digest_covered = covers(CONTENT_DIGEST.as_str());
+
+        let key_id = signature.key_id.ok_or("missing key id")?;
+        let key: Arc<[u8]> = match &self.keys {
+            Keys::Set(keys) => keys.get(&key_id).cloned(),
+            Keys::Lookup(lookup) => lookup(&key_id).map(Into::into),
+        }
+        .ok_or("unknown key id")?;
+
+        let expected = mac(&key, &[signature.base.as_bytes()]);
+        if !constant_time_eq(&expected, &signature.signature) {
+            return Err("invalid signature");
+        }
+
+        let digest = if digest_covered {
+            let digest = parts
+                .headers
+                .get(CONTENT_DIGEST)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_content_digest)
+                .ok_or("unsupported content digest")?;
+            ExpectedDigest::Header(digest)
+        } else {
+            ExpectedDigest::Trailer {
+                key,
+                signature: signature.signature.clone(),
+            }
+        };
+
+        if let Some(replays) = &self.replays {
+            let mut replays = replays.lock().unwrap();
+            replays.retain(|_, created| *created >= now.saturating_sub(max_age));
+            if replays.insert(signature.signature, created).is_some() {
+                return Err("replayed signature");
+            }
+        }
+
+        Ok(Verified {
+            key_id,
+            check: BodyCheck {
+                hasher: Sha256::new(),
+                digest,
+            },
+        })
+    }
+
+    fn parse_message_signature(&self, parts: &Parts) -> Result<Signature, &'static str> {
+        let member = |name: HeaderName| {
+            let values = parts
+                .headers
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .collect::<Vec<_>>()
+                .join(", ");
+            let member = members(&values).find_map(|member| {
+                let (label, value) = member.split_once('=')?;
+                (label.trim() == self.label).then(|| value.trim().to_owned())
+            });
+            member
+        };
+        let input = member(SIGNATURE_INPUT).ok_or("missing signature")?;
+        let signature = member(SIGNATURE)
+            .and_then(|signature| byte_sequence(&signature))
+            .ok_or("missing signature")?;
+
+        let mut rest = input.strip_prefix('(').ok_or("malformed signature input")?;
+        let mut covered = Vec::new();
+        loop {
+            rest = rest.trim_start_matches(' ');
+            if let Some(params) = rest.strip_prefix(')') {
+                rest = params;
+                break;
+            }
+            let (component, after) = parse_string(rest).ok_or("malformed signature input")?;
+            if !after.starts_with([' ', ')']) {
+                // Component parameters such as `;sf` or `;req` aren't supported.
+                return Err("unsupported signature component");
+            }
+            covered.push(component);
+            rest = after;
+        }
+
+        let mut signature = Signature {
+            covered,
+            created: None,
+            expires: None,
+            key_id: None,
+            algorithm: None,
+            signature,
+            base: String::new(),
+        };
+        while let Some(param) = rest.strip_prefix(';') {
+            let (key, value) = param.split_once('=').ok_or("malformed signature input")?;
+            let (value, after) = match parse_string(value) {
+                Some((value, after)) => (value, after),
+                None => {
+                    let end = value.find(';').unwrap_or(value.len());
+                    (value[..end].to_owned(), &value[end..])
+                }
+            };
+            match key {
+                "created" => signature.created = value.parse().ok(),
+                "expires" => signature.expires = value.parse().ok(),
+                "keyid" => signature.key_id = Some(value),
+                "alg" => signature.algorithm = Some(value),
+                _ => {}
+            }
+            rest = after;
+        }
+        if !rest.is_empty() {
+            return Err("malformed signature input");
+        }
+
+        let params = &input[input.find('(').unwrap_or_default()..];
+        signature.base = message_signature_base(parts, &signature.covered, params)
+            .ok_or("signed component is missing")?;
+        Ok(signature)
+    }
+}
+
+fn parse_cavage(parts: &Parts) -> Result<Signature, &'static str> {
+    let value = parts
+        .headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| strip_scheme(value, "Signature"))
+        .ok_or("missing signature")?;
+
+    let mut signature = Signature {
+        covered: Vec::new(),
+        created: None,
+        expires: None,
+        key_id: None,
+        algorithm: None,
+        signature: Vec::new(),
+        base: String::new(),
+    };
+    for member in members(value) {
+        let (key, value) = member.split_once('=').ok_or("malformed signature")?;
+        let value = match parse_string(value.trim()) {
+            Some((value, "")) => value,
+            Some(_) => return Err("malformed signature"),
+            None => value.trim().to_owned(),
+        };
+        match key.trim() {
+            "keyId" => signature.key_id = Some(value),
+            "algorithm" => signature.algorithm = Some(value),
+            "created" => signature.created = value.parse().ok(),
+            "expires" => signature.expires = value.parse().ok(),
+            "headers" => {
+                signature.covered = value.split_ascii_whitespace().map(str::to_owned).collect()
+            }
+            "signature" => {
+                signature.signature = BASE64.decode(value).map_err(|_| "malformed signature")?
+            }
+            _ => {}
+        }
+    }
+    if signature.signature.is_empty() {
+        return Err("missing signature");
+    }
+
+    let created = signature.created.ok_or("missing creation time")?;
+    signature.base = cavage_signature_base(parts, &signature.covered, created)
+        .ok_or("signed component is missing")?;
+    Ok(signature)
+}
+
+/// A parsed signature of either format.
+struct Signature {
+    covered: Vec<String>,
+    created: Option<u64>,
+    expires: Option<u64>,
+    key_id: Option<String>,
+    algorithm: Option<String>,
+    signature: Vec<u8>,
+    base: String,
+}
+
+struct Verified {
+    key_id: String,
+    check: BodyCheck,
+}
+
+impl fmt::Debug for SignatureVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keys = match &self.keys {
+            Keys::Set(keys) => format!("Set({})", keys.len()),
+            Keys::Lookup(_) => "Lookup".to_owned(),
+        };
+        f.debug_struct("SignatureVerifier")
+            .field("keys", &format_args!("{}", keys))
+            .field("format", &self.format)
+            .field("label", &self.label)
+            .field("required_headers", &self.required_headers)
+            .field("max_age", &self.max_age)
+            .field("reject_replays", &self.replays.is_some())
+            .field("body_digest", &self.body_digest)
+            .field("max_body_size", &self.max_body_size)
+            .finish()
+    }
+}
+
+/// Type that verifies signed requests.
+///
+/// See [`AsyncRequireAuthorizationLayer::verify_signature`] for more details.
+pub struct VerifySignature<ResBody> {
+    verifier: Arc<SignatureVerifier>,
+    _ty: PhantomData<fn() -> ResBody>,
+}
+
+impl<ResBody> VerifySignature<ResBody> {
+    /// Create a new `VerifySignature`.
+    pub fn new(verifier: SignatureVerifier) -> Self {
+        Self {
+            verifier: Arc::new(verifier),
+            _ty: PhantomData,
+        }
+    }
+}
+
+impl<ResBody> Clone for VerifySignature<ResBody> {
+    fn clone(&self) -> Self {
+        Self {
+            verifier: self.verifier.clone(),
+            _ty: PhantomData,
+        }
+    }
+}
+
+impl<ResBody> fmt::Debug for VerifySignature<ResBody> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VerifySignature")
+            .field("verifier", &self.verifier)
+            .finish()
+    }
+}
+
+impl<B, ResBody> AsyncAuthorizeRequest<B> for VerifySignature<ResBody>
+where
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    ResBody: Default + Send + 'static,
+{
+    type RequestBody = VerifiedBody<B>;
+    type ResponseBody = ResBody;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Request<VerifiedBody<B>>, Response<ResBody>>> + Send>>;
+
+    fn authorize(&mut self, request: Request<B>) -> Self::Future {
+        let verifier = self.verifier.clone();
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let Verified { key_id, check } = verifier
+                .verify(&parts)
+                .map_err(|reason| reject(StatusCode::UNAUTHORIZED, reason))?;
+
+            let body = match verifier.body_digest {
+                BodyDigest::Stream => VerifiedBody {
+                    kind: Kind::Streaming {
+                        inner: body,
+                        check: Some(check),
+                    },
+                },
+                BodyDigest::Buffer => {
+                    let collected = Limited::new(body, verifier.max_body_size)
+                        .collect()
+                        .await
+                        .map_err(|err| {
+                            if err.is::<LengthLimitError>() {
+                                reject(StatusCode::PAYLOAD_TOO_LARGE, "body too large")
+                            } else {
+                                reject(StatusCode::BAD_REQUEST, "failed to read body")
+                            }
+                        })?;
+                    let trailers = collected.trailers().cloned();
+                    let data = collected.to_bytes();
+
+                    let mut check = check;
+                    check.hasher.update(&data);
+                    check
+                        .finish(trailers.as_ref())
+                        .map_err(|err| reject(StatusCode::UNAUTHORIZED, err.0))?;
+
+                    VerifiedBody {
+                        kind: Kind::Buffered {
+                            body: Buffered::new(data, trailers),
+                        },
+                    }
+                }
+            };
+
+            let mut request = Request::from_parts(parts, body);
+            request.extensions_mut().insert(Identity::new(key_id));
+            Ok(request)
+        })
+    }
+}
+
+fn reject<ResBody: Default>(status: StatusCode, reason: &'static str) -> Response<ResBody> {
+    #[cfg(feature = "tracing")]
+    tracing::debug!(reason, "rejecting signed request");
+    #[cfg(not(feature = "tracing"))]
+    let _ = reason;
+
+    let mut res = Response::new(ResBody::default());
+    *res.status_mut() = status;
+    res
+}
+
+/// The digest the body is checked against.
+enum ExpectedDigest {
+    /// From the signed `Content-Digest` header.
+    Header(Vec<u8>),
+    /// From the `Content-Digest` trailer, authenticated by the `Body-Signature` trailer.
+    Trailer { key: Arc<[u8]>, signature: Vec<u8> },
+}
+
+struct BodyCheck {
+    hasher: Sha256,
+    digest: ExpectedDigest,
+}
+
+impl BodyCheck {
+    fn finish(self, trailers: Option<&HeaderMap>) -> Result<(), InvalidBody> {
+        let digest = self.hasher.finalize();
+        match self.digest {
+            ExpectedDigest::Header(expected) => {
+                if !constant_time_eq(&digest, &expected) {
+                    return Err(InvalidBody("body doesn't match its digest"));
+                }
+            }
+            ExpectedDigest::Trailer { key, signature } => {
+                let trailer = |name| {
+                    trailers
+                        .and_then(|trailers| trailers.get(name))
+                        .and_then(|value| value.to_str().ok())
+                };
+                let (Some(content_digest_value), Some(body_signature_value)) =
+                    (trailer(CONTENT_DIGEST), trailer(BODY_SIGNATURE))
+                else {
+                    return Err(InvalidBody("missing body digest trailers"));
+                };
+                let expected = body_signature(&key, &signature, content_digest_value);
+                if !byte_sequence(body_signature_value)
+                    .map_or(false, |actual| constant_time_eq(&actual, &expected))
+                {
+                    return Err(InvalidBody("invalid body signature"));
+                }
+                if !parse_content_digest(content_digest_value)
+                    .map_or(false, |expected| constant_time_eq(&digest, &expected))
+                {
+                    return Err(InvalidBody("body doesn't match its digest"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Error returned by [`VerifiedBody`] when the streamed body doesn't match its signed digest.
+#[derive(Debug)]
+pub struct InvalidBody(&'static str);
+
+impl fmt::Display for InvalidBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl std::error::Error for InvalidBody {}
+
+pin_project! {
+    /// Request body for [`VerifySignature`].
+    ///
+    /// Either the buffered and verified body, or the streamed body that fails with an
+    /// [`InvalidBody`] error at its end if it doesn't match its signed digest.
+    pub struct VerifiedBody<B> {
+        #[pin]
+        kind: Kind<B>,
+    }
+}
+
+pin_project! {
+    #[project = KindProj]
+    enum Kind<B> {
+        Buffered {
+            body: Buffered,
+        },
+        Streaming {
+            #[pin]
+            inner: B,
+            check: Option<BodyCheck>,
+        },
+    }
+}
+
+impl<B> Body for VerifiedBody<B>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match self.project().kind.project() {
+            KindProj::Buffered { body } => Poll::Ready(body.frame().map(Ok)),
+            KindProj::Streaming { inner, check } => match ready!(inner.poll_frame(cx)) {
+                Some(Ok(frame)) => {
+                    let frame = frame.map_data(|mut data| data.copy_to_bytes(data.remaining()));
+                    if let Some(data) = frame.data_ref() {
+                        if let Some(check) = check {
+                            check.hasher.update(data);
+                        }
+                    } else if let Some(trailers) = frame.trailers_ref() {
+                        if let Some(Err(err)) =
+                            check.take().map(|check| check.finish(Some(trailers)))
+                        {
+                            return Poll::Ready(Some(Err(err.into())));
+                        }
+                    }
+                    Poll::Ready(Some(Ok(frame)))
+                }
+                Some(Err(err)) => Poll::Ready(Some(Err(err.into()))),
+                None => match check.take() {
+                    Some(check) => Poll::Ready(check.finish(None).err().map(|err| Err(err.into()))),
+                    None => Poll::Ready(None),
+                },
+            },
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match &self.kind {
+            Kind::Buffered { body } => body.is_end_stream(),
+            Kind::Streaming { inner, check } => check.is_none() && inner.is_end_stream(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match &self.kind {
+            Kind::Buffered { body } => body.size_hint(),
+            Kind::Streaming { inner, .. } => inner.size_hint(),
+        }
+    }
+}
+
+impl<B> fmt::Debug for VerifiedBody<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VerifiedBody").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,531 @@
+//! Pre-signed URLs that grant temporary access to a resource.
+//!
+//! [`UrlSigner::sign`] appends an expiry and an HMAC-SHA256 signature over the path and query to
+//! a URI, optionally binding it to the IP address of a client.
+//! [`ValidateRequestHeaderLayer::signed_url`] verifies such URIs with the same secret, rejecting
+//! expired or tampered ones with `403 Forbidden`, and removes the signature parameters before
+//! passing the request on. That makes it a good fit in front of [`ServeDir`], which then only
+//! sees the clean path.
+//!
+//! The parameters are `expires`, the expiry as seconds since the Unix epoch, `ip` for URIs bound
+//! to a client and `signature`, the URL-safe base64 encoded HMAC. They are appended after the
+//! query of the URI, and only those trailing parameters are read and removed, so the URI may
+//! have its own parameters with the same names. Other query parameters are covered by the
+//! signature, so they can't be added or changed either.
+//!
+//! The address of the client is taken from the [`ClientIp`] extension inserted by
+//! [`SetClientIpLayer`], or a [`SocketAddr`] or [`IpAddr`] extension. Use
+//! [`UrlSigner::client_ip`] to read it from another extension.
+//!
+//! # Example
+//!
+//! ```
+//! use http::{Request, Response, StatusCode, Uri};
+//! use http_body_util::Full;
+//! use bytes::Bytes;
+//! use std::time::{Duration, SystemTime};
+//! use tower::{Service, ServiceExt, ServiceBuilder, BoxError};
+//! use tower_http::auth::signed_url::UrlSigner;
+//! use tower_http::validate_request::ValidateRequestHeaderLayer;
+//!
+//! async fn handle(request: Request<Full<Bytes>>) -> Result<Response<Full<Bytes>>, BoxError> {
+//!     // The signature parameters were removed.
+//!     assert_eq!(request.uri(), "/downloads/report.pdf");
+//!     Ok(Response::new(Full::default()))
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), BoxError> {
+//! let signer = UrlSigner::new("a-long-random-secret");
+//!
+//! let mut service = ServiceBuilder::new()
+//!     .layer(ValidateRequestHeaderLayer::signed_url(signer.clone()))
+//!     // Usually `ServeDir::new("assets")`.
+//!     .service_fn(handle);
+//!
+//! // Hand out a link that is valid for 10 minutes.
+//! let uri = signer.sign(
+//!     &Uri::from_static("/downloads/report.pdf"),
+//!     SystemTime::now() + Duration::from_secs(10 * 60),
+//! );
+//!
+//! let request = Request::builder().uri(uri).body(Full::default()).unwrap();
+//! let response = service.ready().await?.call(request).await?;
+//! assert_eq!(StatusCode::OK, response.status());
+//!
+//! let request = Request::builder()
+//!     .uri("/downloads/secrets.pdf")
+//!     .body(Full::default())
+//!     .unwrap();
+//! let response = service.ready().await?.call(request).await?;
+//! assert_eq!(StatusCode::FORBIDDEN, response.status());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`ServeDir`]: crate::services::ServeDir
+//! [`ClientIp`]: crate::client_ip::ClientIp
+//! [`SetClientIpLayer`]: crate::client_ip::SetClientIpLayer
+//! [`SocketAddr`]: std::net::SocketAddr
+
+use crate::{
+    helpers::client_ip,
+    validate_request::{ValidateRequest, ValidateRequestHeader, ValidateRequestHeaderLayer},
+};
+use base64::Engine as _;
+use hmac::{Hmac, Mac};
+use http::{Extensions, Request, Response, StatusCode, Uri};
+use sha2::Sha256;
+use std::{
+    fmt,
+    marker::PhantomData,
+    net::IpAddr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const BASE64: base64::engine::GeneralPurpose = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+
+const EXPIRES: &str = "expires";
+const IP: &str = "ip";
+const SIGNATURE: &str = "signature";
+
+impl<S, ResBody> ValidateRequestHeader<S, RequireSignedUrl<ResBody>> {
+    /// Authorize requests to URIs signed by a [`UrlSigner`].
+    ///
+    /// See the [module docs](crate::auth::signed_url) for more details.
+    pub fn signed_url(inner: S, signer: UrlSigner) -> Self
+    where
+        ResBody: Default,
+    {
+        Self::custom(inner, RequireSignedUrl::new(signer))
+    }
+}
+
+impl<ResBody> ValidateRequestHeaderLayer<RequireSignedUrl<ResBody>> {
+    /// Authorize requests to URIs signed by a [`UrlSigner`].
+    ///
+    /// See the [module docs](crate::auth::signed_url) for more details.
+    pub fn signed_url(signer: UrlSigner) -> Self
+    where
+        ResBody: Default,
+    {
+        Self::custom(RequireSignedUrl::new(signer))
+    }
+}
+
+type ClientIpFn = dyn Fn(&Extensions) -> Option<IpAddr> + Send + Sync + 'static;
+
+/// Signs URIs and verifies signed URIs.
+///
+/// See the [module docs](crate::auth::signed_url) for an example.
+#[derive(Clone)]
+pub struct UrlSigner {
+    mac: Hmac<Sha256>,
+    client_ip: Option<Arc<ClientIpFn>>,
+}
+
+impl UrlSigner {
+    /// Create a new `UrlSigner` using `secret` as the HMAC key.
+    pub fn new<K: AsRef<[u8]>>(secret: K) -> Self {
+        Self {
+            mac: Hmac::new_from_slice(secret.as_ref()).expect("HMAC accepts keys of any length"),
+            client_ip: None,
+        }
+    }
+
+    /// Read the client address of requests to URIs bound to an IP from the request extensions
+    /// with `f`.
+    pub fn client_ip<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Extensions) -> Option<IpAddr> + Send + Sync + 'static,
+    {
+        self.client_ip = Some(Arc::new(f));
+        self
+    }
+
+    /// Sign `uri` so it's valid until `expires`.
+    pub fn sign(&self, uri: &Uri, expires: SystemTime) -> Uri {
+        self.sign_uri(uri, expires, None)
+    }
+
+    /// Sign `uri` so it's valid until `expires` for requests from `ip`.
+    pub fn sign_for_ip(&self, uri: &Uri, expires: SystemTime, ip: IpAddr) -> Uri {
+        self.sign_uri(uri, expires, Some(ip))
+    }
+
+    fn sign_uri(&self, uri: &Uri, expires: SystemTime, ip: Option<IpAddr>) -> Uri {
+        let expires = expires
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |expires| expires.as_secs());
+        let mut query = uri.query().unwrap_or_default().to_owned();
+        for (name, value) in [
+            (EXPIRES, Some(expires.to_string())),
+            (IP, ip.map(|ip| ip.to_string())),
+        ] {
+            if let Some(value) = value {
+                if !query.is_empty() {
+                    query.push('&');
+                }
+                query.push_str(&format!("{}={}", name, value));
+            }
+        }
+
+        let signature = BASE64.encode(self.signature(uri.path(), &query));
+        let path_and_query = format!("{}?{}&{}={}", uri.path(), query, SIGNATURE, signature);
+
+        let mut parts = uri.clone().into_parts();
+        parts.path_and_query = Some(path_and_query.parse().expect("signed URI is valid"));
+        Uri::from_parts(parts).expect("signed URI is valid")
+    }
+
+    fn signature(&self, path: &str, query: &str) -> Vec<u8> {
+        let mut mac = self.mac.clone();
+        mac.update(path.as_bytes());
+        mac.update(b"?");
+        mac.update(query.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn verify<B>(&self, request: &Request<B>) -> Result<(), &'static str> {
+        let uri = request.uri();
+        let params = SignerParams::split(uri.query().ok_or("missing signature")?)?;
+        let signature = BASE64
+            .decode(params.signature)
+            .map_err(|_| "malformed signature")?;
+
+        let mut mac = self.mac.clone();
+        mac.update(uri.path().as_bytes());
+        mac.update(b"?");
+        mac.update(params.signed.as_bytes());
+        mac.verify_slice(&signature)
+            .map_err(|_| "signature mismatch")?;
+
+        let expires = params
+            .expires
+            .parse::<u64>()
+            .map_err(|_| "malformed expiry")?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs());
+        if expires <= now {
+            return Err("expired");
+        }
+
+        if let Some(ip) = params.ip {
+            let ip = ip.parse::<IpAddr>().map_err(|_| "malformed IP")?;
+            if self.request_ip(request.extensions()) != Some(ip) {
+                return Err("IP mismatch");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn request_ip(&self, extensions: &Extensions) -> Option<IpAddr> {
+        match &self.client_ip {
+            Some(f) => f(extensions),
+            None => client_ip(extensions),
+        }
+    }
+}
+
+impl fmt::Debug for UrlSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Deliberately leaves out the secret.
+        f.debug_struct("UrlSigner")
+            .field("client_ip", &self.client_ip.as_ref().map(|_| "Custom"))
+            .finish_non_exhaustive()
+    }
+}
+
+/// The parameters appended by [`UrlSigner::sign`], in the order it appends them.
+///
+/// They are found by their position at the end of the query rather than by name, so
+/// application parameters that happen to be called `expires` or `ip` are left alone. The names
+/// are compared exactly as the signer writes them, which is also what the signature covers.
+struct SignerParams<'a> {
+    /// The query before the parameters.
+    query: &'a str,
+    /// The query covered by the signature.
+    signed: &'a str,
+    expires: &'a str,
+    ip: Option<&'a str>,
+    signature: &'a str,
+}
+
+impl<'a> SignerParams<'a> {
+    fn split(query: &'a str) -> Result<Self, &'static str> {
+        fn param<'a>(pair: &'a str, name: &str) -> Option<&'a str> {
+            pair.strip_prefix(name)?.strip_prefix('=')
+        }
+        fn split_last(query: &str) -> (&str, &str) {
+            query.rsplit_once('&').unwrap_or(("", query))
+        }
+
+        let (signed, last) = split_last(query);
+        let signature = param(last, SIGNATURE).ok_or("missing signature")?;
+
+        let (rest, last) = split_last(signed);
+        let (query, expires, ip) = match param(last, IP) {
+            Some(ip) => {
+                let (query, last) = split_last(rest);
+                (query, param(last, EXPIRES), Some(ip))
+            }
+            None => (rest, param(last, EXPIRES), None),
+        };
+
+        Ok(Self {
+            query,
+            signed,
+            expires: expires.ok_or("missing expiry")?,
+            ip,
+            signature,
+        })
+    }
+}
+
+/// Remove the signature parameters from the URI of a verified request.
+fn strip_params(uri: &mut Uri) {
+    let Some(Ok(params)) = uri.query().map(SignerParams::split) else {
+        return;
+    };
+    let path_and_query = if params.query.is_empty() {
+        uri.path().to_owned()
+    } else {
+        format!("{}?{}", uri.path(), params.query)
+    };
+
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = path_and_query.parse().ok();
+    if let Ok(stripped) = Uri::from_parts(parts) {
+        *uri = stripped;
+    }
+}
+
+/// Type that verifies signed URIs.
+///
+/// See [`ValidateRequestHeader::signed_url`] for more details.
+pub struct RequireSignedUrl<ResBody> {
+    signer: UrlSigner,
+    _ty: PhantomData<fn() -> ResBody>,
+}
+
+impl<ResBody> RequireSignedUrl<ResBody> {
+    fn new(signer: UrlSigner) -> Self {
+        Self {
+            signer,
+            _ty: PhantomData,
+        }
+    }
+}
+
+impl<ResBody> Clone for RequireSignedUrl<ResBody> {
+    fn clone(&self) -> Self {
+        Self {
+            signer: self.signer.clone(),
+            _ty: PhantomData,
+        }
+    }
+}
+
+impl<ResBody> fmt::Debug for RequireSignedUrl<ResBody> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequireSignedUrl")
+            .field("signer", &self.signer)
+            .finish()
+    }
+}
+
+impl<B, ResBody> ValidateRequest<B> for RequireSignedUrl<ResBody>
+where
+    ResBody: Default,
+{
+    type ResponseBody = ResBody;
+
+    fn validate(&mut self, request: &mut Request<B>) -> Result<(), Response<Self::ResponseBody>> {
+        match self.signer.verify(request) {
+            Ok(()) => {
+                strip_params(request.uri_mut());
+                Ok(())
+            }
+            Err(reason) => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(reason, "rejecting signed URL");
+                #[cfg(not(feature = "tracing"))]
+                let _ = reason;
+
+                let mut res = Response::new(ResBody::default());
+                *res.status_mut() = StatusCode::FORBIDDEN;
+                Err(res)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{forbidden, status_and_body, Body};
+    use std::{net::SocketAddr, time::Duration};
+    use tower::{BoxError, ServiceBuilder};
+
+    async fn echo(req: Request<Body>) -> Result<Response<Body>, BoxError> {
+        Ok(Response::new(Body::from(req.uri().to_string())))
+    }
+
+    async fn call(signer: &UrlSigner, request: Request<Body>) -> (StatusCode, String) {
+        let svc = ServiceBuilder::new()
+            .layer(ValidateRequestHeaderLayer::signed_url(signer.clone()))
+            .service_fn(echo);
+        status_and_body(svc, request).await
+    }
+
+    fn request<U: AsRef<str>>(uri: U) -> Request<Body> {
+        Request::builder()
+            .uri(uri.as_ref())
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn signer() -> UrlSigner {
+        UrlSigner::new("secret")
+    }
+
+    fn in_a_minute() -> SystemTime {
+        SystemTime::now() + Duration::from_secs(60)
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let uri = signer().sign(&Uri::from_static("/files/a%20b.txt"), in_a_minute());
+        assert_eq!(
+            call(&signer(), request(uri.to_string())).await,
+            (StatusCode::OK, "/files/a%20b.txt".to_owned())
+        );
+
+        let uri = signer().sign(
+            &Uri::from_static("https://example.com/files/a.txt?download=1"),
+            in_a_minute(),
+        );
+        assert_eq!(uri.authority().unwrap(), "example.com");
+        assert_eq!(
+            call(&signer(), request(uri.path_and_query().unwrap().as_str())).await,
+            (StatusCode::OK, "/files/a.txt?download=1".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn known_signature() {
+        let expires = UNIX_EPOCH + Duration::from_secs(4_102_444_800);
+        let uri = signer().sign(&Uri::from_static("/a.txt"), expires);
+        let signature = BASE64.encode(
+            Hmac::<Sha256>::new_from_slice(b"secret")
+                .unwrap()
+                .chain_update(b"/a.txt?expires=4102444800")
+                .finalize()
+                .into_bytes(),
+        );
+        assert_eq!(
+            uri,
+            format!("/a.txt?expires=4102444800&signature={}", signature).as_str()
+        );
+    }
+
+    #[tokio::test]
+    async fn tampering() {
+        let uri = signer()
+            .sign(&Uri::from_static("/files/a.txt?v=1"), in_a_minute())
+            .to_string();
+
+        for tampered in [
+            uri.replace("/a.txt", "/b.txt"),
+            uri.replace("v=1", "v=2"),
+            format!("{}&extra=1", uri),
+            uri.replace("expires=", "expires=9"),
+            uri.replace("signature=", "signature=A"),
+            format!("{}&signature=AAAA", uri),
+            uri.split("&signature").next().unwrap().to_owned(),
+            "/files/a.txt?v=1".to_owned(),
+        ] {
+            assert_eq!(call(&signer(), request(&tampered)).await, forbidden());
+        }
+
+        assert_eq!(
+            call(&UrlSigner::new("other"), request(&uri)).await,
+            forbidden()
+        );
+    }
+
+    #[tokio::test]
+    async fn application_params_with_the_same_names_are_kept() {
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let uri = signer()
+            .sign_for_ip(
+                &Uri::from_static("/a.txt?expires=never&ip=any&signature=x"),
+                in_a_minute(),
+                ip,
+            )
+            .to_string();
+
+        let mut req = request(&uri);
+        req.extensions_mut().insert(ip);
+        assert_eq!(
+            call(&signer(), req).await,
+            (
+                StatusCode::OK,
+                "/a.txt?expires=never&ip=any&signature=x".to_owned()
+            )
+        );
+
+        // The application's `ip` isn't mistaken for the signer's.
+        let uri = signer()
+            .sign(&Uri::from_static("/a.txt?ip=192.0.2.2"), in_a_minute())
+            .to_string();
+        assert_eq!(
+            call(&signer(), request(&uri)).await,
+            (StatusCode::OK, "/a.txt?ip=192.0.2.2".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn expired() {
+        let uri = signer().sign(
+            &Uri::from_static("/a.txt"),
+            SystemTime::now() - Duration::from_secs(1),
+        );
+        assert_eq!(call(&signer(), request(uri.to_string())).await, forbidden());
+    }
+
+    #[tokio::test]
+    async fn bound_to_ip() {
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        let uri = signer()
+            .sign_for_ip(&Uri::from_static("/a.txt"), in_a_minute(), ip)
+            .to_string();
+
+        let mut req = request(&uri);
+        req.extensions_mut().insert(SocketAddr::new(ip, 4321));
+        assert_eq!(
+            call(&signer(), req).await,
+            (StatusCode::OK, "/a.txt".to_owned())
+        );
+
+        let mut req = request(&uri);
+        req.extensions_mut()
+            .insert("2001:db8::2".parse::<IpAddr>().unwrap());
+        assert_eq!(call(&signer(), req).await, forbidden());
+
+        assert_eq!(call(&signer(), request(&uri)).await, forbidden());
+
+        #[derive(Clone)]
+        struct Peer(IpAddr);
+        let custom = signer().client_ip(|extensions| extensions.get::<Peer>().map(|peer| peer.0));
+        let mut req = request(&uri);
+        req.extensions_mut().insert(Peer(ip));
+        assert_eq!(
+            call(&custom, req).await,
+            (StatusCode::OK, "/a.txt".to_owned())
+        );
+    }
+}
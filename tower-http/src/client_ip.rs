@@ -0,0 +1,292 @@
+//! Middleware that resolves the IP address of the client behind reverse proxies.
+//!
+//! [`SetClientIpLayer`] inserts a [`ClientIp`] into the request extensions, so other middleware
+//! and handlers can consume the client address uniformly, e.g. for rate limiting, allow-lists or
+//! tracing spans.
+//!
+//! The address of the peer the request was received from is read from the request extensions,
+//! where servers usually store it. If the peer is one of the [`TrustedProxies`], the client
+//! address is taken from the `Forwarded`, `X-Forwarded-For` or `X-Real-IP` headers, skipping
+//! other trusted proxies. See [`TrustedProxies::client_ip`] for the details.
+//!
+//! # Example
+//!
+//! ```
+//! use http::{Request, Response};
+//! use std::convert::Infallible;
+//! use std::net::SocketAddr;
+//! use tower::{Service, ServiceExt, ServiceBuilder, service_fn};
+//! use tower_http::client_ip::{ClientIp, SetClientIpLayer};
+//! use tower_http::forwarded::TrustedProxies;
+//!
+//! async fn handle(req: Request<()>) -> Result<Response<String>, Infallible> {
+//!     let client_ip = req.extensions().get::<ClientIp>().unwrap();
+//!     Ok(Response::new(client_ip.to_string()))
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut service = ServiceBuilder::new()
+//!     .layer(SetClientIpLayer::new(TrustedProxies::new(["10.0.0.0/8"])?))
+//!     .service_fn(handle);
+//!
+//! let mut request = Request::builder()
+//!     .header("x-forwarded-for", "203.0.113.7, 10.0.0.2")
+//!     .body(())?;
+//! // Usually added by the server.
+//! request
+//!     .extensions_mut()
+//!     .insert("10.0.0.1:40000".parse::<SocketAddr>()?);
+//!
+//! let response = service.ready().await?.call(request).await?;
+//! assert_eq!(response.body(), "203.0.113.7");
+//! # Ok(())
+//! # }
+//! ```
+
+use http::Request;
+use std::fmt;
+use std::net::IpAddr;
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::forwarded::TrustedProxies;
+
+/// The IP address of the client that sent a request.
+///
+/// Inserted into the request extensions by [`SetClientIp`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ClientIp(IpAddr);
+
+impl ClientIp {
+    /// Create a new `ClientIp`.
+    pub fn new(ip: IpAddr) -> Self {
+        Self(ip)
+    }
+
+    /// The IP address of the client.
+    pub fn ip(&self) -> IpAddr {
+        self.0
+    }
+}
+
+impl From<IpAddr> for ClientIp {
+    fn from(ip: IpAddr) -> Self {
+        Self(ip)
+    }
+}
+
+impl From<ClientIp> for IpAddr {
+    fn from(client_ip: ClientIp) -> Self {
+        client_ip.0
+    }
+}
+
+impl fmt::Display for ClientIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Layer that applies [`SetClientIp`] which inserts the [`ClientIp`] into request extensions.
+///
+/// See the [module docs](crate::client_ip) for more details.
+#[derive(Clone, Debug)]
+pub struct SetClientIpLayer {
+    proxies: TrustedProxies,
+}
+
+impl SetClientIpLayer {
+    /// Create a new `SetClientIpLayer` that believes forwarding headers sent by `proxies`.
+    pub fn new(proxies: TrustedProxies) -> Self {
+        Self { proxies }
+    }
+}
+
+impl<S> Layer<S> for SetClientIpLayer {
+    type Service = SetClientIp<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SetClientIp {
+            inner,
+            proxies: self.proxies.clone(),
+        }
+    }
+}
+
+/// Middleware that inserts the [`ClientIp`] into request extensions.
+///
+/// Requests whose peer address isn't in the extensions are passed on without a `ClientIp`.
+///
+/// See the [module docs](crate::client_ip) for more details.
+#[derive(Clone, Debug)]
+pub struct SetClientIp<S> {
+    inner: S,
+    proxies: TrustedProxies,
+}
+
+impl<S> SetClientIp<S> {
+    /// Create a new `SetClientIp`.
+    pub fn new(inner: S, proxies: TrustedProxies) -> Self {
+        Self { inner, proxies }
+    }
+
+    define_inner_service_accessors!();
+
+    /// Returns a new [`Layer`] that wraps services with a `SetClientIp` middleware.
+    ///
+    /// [`Layer`]: tower_layer::Layer
+    pub fn layer(proxies: TrustedProxies) -> SetClientIpLayer {
+        SetClientIpLayer::new(proxies)
+    }
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for SetClientIp<S>
+where
+    S: Service<Request<ReqBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        if let Some(ip) = self.proxies.client_ip(&req) {
+            req.extensions_mut().insert(ClientIp(ip));
+        }
+        self.inner.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::Response;
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use tower::{service_fn, ServiceExt};
+
+    async fn client_ip(peer: Option<&str>, headers: &[(&str, &str)]) -> Option<ClientIp> {
+        let proxies = TrustedProxies::new(["10.0.0.0/8", "fd00::/8"]).unwrap();
+        let svc = SetClientIp::new(
+            service_fn(|req: Request<()>| async move {
+                let client_ip = req.extensions().get::<ClientIp>().copied();
+                Ok::<_, Infallible>(Response::new(client_ip))
+            }),
+            proxies,
+        );
+
+        let mut req = Request::builder();
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        let mut req = req.body(()).unwrap();
+        if let Some(peer) = peer {
+            req.extensions_mut()
+                .insert(peer.parse::<SocketAddr>().unwrap());
+        }
+
+        svc.oneshot(req).await.unwrap().into_body()
+    }
+
+    fn ip(ip: &str) -> Option<ClientIp> {
+        Some(ClientIp::new(ip.parse().unwrap()))
+    }
+
+    #[tokio::test]
+    async fn untrusted_peer() {
+        assert_eq!(
+            client_ip(
+                Some("203.0.113.7:1234"),
+                &[("x-forwarded-for", "198.51.100.1")]
+            )
+            .await,
+            ip("203.0.113.7")
+        );
+        assert_eq!(
+            client_ip(None, &[("x-forwarded-for", "198.51.100.1")]).await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn forwarded() {
+        assert_eq!(
+            client_ip(
+                Some("10.0.0.1:1234"),
+                &[(
+                    "forwarded",
+                    r#"for=198.51.100.1, for="[2001:db8::1]:4711", for=10.0.0.2"#
+                )]
+            )
+            .await,
+            ip("2001:db8::1")
+        );
+
+        // An obfuscated node ends the chain at the last trusted proxy.
+        assert_eq!(
+            client_ip(
+                Some("10.0.0.1:1234"),
+                &[("forwarded", "for=_hidden, for=10.0.0.2")]
+            )
+            .await,
+            ip("10.0.0.2")
+        );
+
+        // `Forwarded` takes precedence over `X-Forwarded-For`.
+        assert_eq!(
+            client_ip(
+                Some("10.0.0.1:1234"),
+                &[
+                    ("forwarded", "for=198.51.100.1"),
+                    ("x-forwarded-for", "198.51.100.2")
+                ]
+            )
+            .await,
+            ip("198.51.100.1")
+        );
+    }
+
+    #[tokio::test]
+    async fn x_forwarded_for() {
+        assert_eq!(
+            client_ip(
+                Some("[fd00::1]:1234"),
+                &[
+                    ("x-forwarded-for", "198.51.100.1, 203.0.113.7"),
+                    ("x-forwarded-for", "10.0.0.2")
+                ]
+            )
+            .await,
+            ip("203.0.113.7")
+        );
+
+        // All hops are trusted.
+        assert_eq!(
+            client_ip(
+                Some("10.0.0.1:1234"),
+                &[("x-forwarded-for", "10.0.0.3, 10.0.0.2")]
+            )
+            .await,
+            ip("10.0.0.3")
+        );
+    }
+
+    #[tokio::test]
+    async fn x_real_ip() {
+        assert_eq!(
+            client_ip(Some("10.0.0.1:1234"), &[("x-real-ip", "198.51.100.1")]).await,
+            ip("198.51.100.1")
+        );
+        assert_eq!(
+            client_ip(Some("10.0.0.1:1234"), &[("x-real-ip", "garbage")]).await,
+            ip("10.0.0.1")
+        );
+        assert_eq!(client_ip(Some("10.0.0.1:1234"), &[]).await, ip("10.0.0.1"));
+    }
+}
@@ -23,25 +23,30 @@ use std::{
     marker::PhantomData,
     pin::Pin,
     task::{ready, Context, Poll},
+    time::Instant,
 };
+use tokio::io::AsyncBufRead;
 use tokio_util::io::StreamReader;
 
 use super::pin_project_cfg::pin_project_cfg;
+use super::sniff::{PeekedBody, SniffBody};
+use super::stats::{OnCompression, Recorder};
 
 pin_project! {
     /// Response body of [`Compression`].
     ///
     /// [`Compression`]: super::Compression
-    pub struct CompressionBody<B>
+    pub struct CompressionBody<B, C = ()>
     where
         B: Body,
     {
         #[pin]
         pub(crate) inner: BodyInner<B>,
+        recorder: Option<Recorder<C>>,
     }
 }
 
-impl<B> Default for CompressionBody<B>
+impl<B, C> Default for CompressionBody<B, C>
 where
     B: Body + Default,
 {
@@ -50,16 +55,25 @@ where
             inner: BodyInner::Identity {
                 inner: B::default(),
             },
+            recorder: None,
         }
     }
 }
 
-impl<B> CompressionBody<B>
+impl<B, C> CompressionBody<B, C>
 where
     B: Body,
 {
     pub(crate) fn new(inner: BodyInner<B>) -> Self {
-        Self { inner }
+        Self {
+            inner,
+            recorder: None,
+        }
+    }
+
+    pub(crate) fn with_recorder(mut self, recorder: Option<Recorder<C>>) -> Self {
+        self.recorder = recorder;
+        self
     }
 
     /// Get a reference to the inner body
@@ -73,22 +87,45 @@ where
             BodyInner::Brotli { inner } => inner.read.get_ref().get_ref().get_ref().get_ref(),
             #[cfg(feature = "compression-zstd")]
             BodyInner::Zstd { inner } => inner.read.get_ref().get_ref().get_ref().get_ref(),
+            BodyInner::Sniff { inner } => inner.get_ref(),
+            BodyInner::Peeked { inner } => inner.get_ref(),
             BodyInner::Identity { inner } => inner,
         }
     }
 
     /// Get a mutable reference to the inner body
+    ///
+    /// # Panics
+    ///
+    /// Panics if the start of the body has been polled to inspect it, as done by
+    /// [`NotAlreadyCompressed`]. Use [`try_get_mut`](Self::try_get_mut) if that can happen.
+    ///
+    /// [`NotAlreadyCompressed`]: super::predicate::NotAlreadyCompressed
     pub fn get_mut(&mut self) -> &mut B {
+        self.try_get_mut()
+            .expect("the body has been polled to inspect it")
+    }
+
+    /// Get a mutable reference to the inner body, unless the start of the body has been polled to
+    /// inspect it, as done by [`NotAlreadyCompressed`].
+    ///
+    /// Such a body is pinned, and the frames polled from it are held by `self`.
+    ///
+    /// [`NotAlreadyCompressed`]: super::predicate::NotAlreadyCompressed
+    pub fn try_get_mut(&mut self) -> Option<&mut B> {
         match &mut self.inner {
             #[cfg(feature = "compression-gzip")]
-            BodyInner::Gzip { inner } => inner.read.get_mut().get_mut().get_mut().get_mut(),
+            BodyInner::Gzip { inner } => Some(inner.read.get_mut().get_mut().get_mut().get_mut()),
             #[cfg(feature = "compression-deflate")]
-            BodyInner::Deflate { inner } => inner.read.get_mut().get_mut().get_mut().get_mut(),
+            BodyInner::Deflate { inner } => {
+                Some(inner.read.get_mut().get_mut().get_mut().get_mut())
+            }
             #[cfg(feature = "compression-br")]
-            BodyInner::Brotli { inner } => inner.read.get_mut().get_mut().get_mut().get_mut(),
+            BodyInner::Brotli { inner } => Some(inner.read.get_mut().get_mut().get_mut().get_mut()),
             #[cfg(feature = "compression-zstd")]
-            BodyInner::Zstd { inner } => inner.read.get_mut().get_mut().get_mut().get_mut(),
-            BodyInner::Identity { inner } => inner,
+            BodyInner::Zstd { inner } => Some(inner.read.get_mut().get_mut().get_mut().get_mut()),
+            BodyInner::Sniff { .. } | BodyInner::Peeked { .. } => None,
+            BodyInner::Identity { inner } => Some(inner),
         }
     }
 
@@ -127,42 +164,68 @@ where
                 .get_pin_mut()
                 .get_pin_mut()
                 .get_pin_mut(),
+            BodyInnerProj::Sniff { inner } => inner.get_mut().get_pin_mut(),
+            BodyInnerProj::Peeked { inner } => inner.get_mut().get_pin_mut(),
             BodyInnerProj::Identity { inner } => inner,
         }
     }
 
     /// Consume `self`, returning the inner body
+    ///
+    /// # Panics
+    ///
+    /// Panics if the start of the body has been polled to inspect it, as done by
+    /// [`NotAlreadyCompressed`]. Use [`try_into_inner`](Self::try_into_inner) if that can happen.
+    ///
+    /// [`NotAlreadyCompressed`]: super::predicate::NotAlreadyCompressed
     pub fn into_inner(self) -> B {
+        self.try_into_inner()
+            .unwrap_or_else(|_| panic!("the body has been polled to inspect it"))
+    }
+
+    /// Consume `self`, returning the inner body, unless the start of the body has been polled to
+    /// inspect it, as done by [`NotAlreadyCompressed`]. `self` is returned in that case.
+    ///
+    /// Such a body is pinned, and the frames polled from it would be lost.
+    ///
+    /// [`NotAlreadyCompressed`]: super::predicate::NotAlreadyCompressed
+    // Handing `self` back is the point, however large it is.
+    #[allow(clippy::result_large_err)]
+    pub fn try_into_inner(self) -> Result<B, Self> {
         match self.inner {
             #[cfg(feature = "compression-gzip")]
-            BodyInner::Gzip { inner } => inner
+            BodyInner::Gzip { inner } => Ok(inner
                 .read
                 .into_inner()
                 .into_inner()
                 .into_inner()
-                .into_inner(),
+                .into_inner()),
             #[cfg(feature = "compression-deflate")]
-            BodyInner::Deflate { inner } => inner
+            BodyInner::Deflate { inner } => Ok(inner
                 .read
                 .into_inner()
                 .into_inner()
                 .into_inner()
-                .into_inner(),
+                .into_inner()),
             #[cfg(feature = "compression-br")]
-            BodyInner::Brotli { inner } => inner
+            BodyInner::Brotli { inner } => Ok(inner
                 .read
                 .into_inner()
                 .into_inner()
                 .into_inner()
-                .into_inner(),
+                .into_inner()),
             #[cfg(feature = "compression-zstd")]
-            BodyInner::Zstd { inner } => inner
+            BodyInner::Zstd { inner } => Ok(inner
                 .read
                 .into_inner()
                 .into_inner()
                 .into_inner()
-                .into_inner(),
-            BodyInner::Identity { inner } => inner,
+                .into_inner()),
+            inner @ (BodyInner::Sniff { .. } | BodyInner::Peeked { .. }) => Err(Self {
+                inner,
+                recorder: self.recorder,
+            }),
+            BodyInner::Identity { inner } => Ok(inner),
         }
     }
 }
@@ -205,6 +268,14 @@ pin_project_cfg! {
             #[pin]
             inner: ZstdBody<B>,
         },
+        Sniff {
+            #[pin]
+            inner: SniffBody<B>,
+        },
+        Peeked {
+            #[pin]
+            inner: PeekedBody<B>,
+        },
         Identity {
             #[pin]
             inner: B,
@@ -233,15 +304,40 @@ impl<B: Body> BodyInner<B> {
         Self::Zstd { inner }
     }
 
+    pub(crate) fn sniff(inner: SniffBody<B>) -> Self {
+        Self::Sniff { inner }
+    }
+
+    pub(crate) fn peeked(inner: PeekedBody<B>) -> Self {
+        Self::Peeked { inner }
+    }
+
     pub(crate) fn identity(inner: B) -> Self {
         Self::Identity { inner }
     }
+
+    /// Number of uncompressed bytes read from the underlying body.
+    fn bytes_read(&self) -> u64 {
+        match self {
+            #[cfg(feature = "compression-gzip")]
+            BodyInner::Gzip { inner } => inner.read.get_ref().get_ref().bytes_read(),
+            #[cfg(feature = "compression-deflate")]
+            BodyInner::Deflate { inner } => inner.read.get_ref().get_ref().bytes_read(),
+            #[cfg(feature = "compression-br")]
+            BodyInner::Brotli { inner } => inner.read.get_ref().get_ref().bytes_read(),
+            #[cfg(feature = "compression-zstd")]
+            BodyInner::Zstd { inner } => inner.read.get_ref().get_ref().bytes_read(),
+            BodyInner::Sniff { inner } => inner.bytes_read(),
+            BodyInner::Peeked { .. } | BodyInner::Identity { .. } => 0,
+        }
+    }
 }
 
-impl<B> Body for CompressionBody<B>
+impl<B, C> Body for CompressionBody<B, C>
 where
     B: Body,
     B::Error: Into<BoxError>,
+    C: OnCompression,
 {
     type Data = Bytes;
     type Error = BoxError;
@@ -250,7 +346,59 @@ where
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Option<Result<http_body::Frame<Self::Data>, Self::Error>>> {
-        match self.project().inner.project() {
+        let mut this = self.project();
+
+        let Some(recorder) = this.recorder.as_mut() else {
+            return this.inner.poll_frame(cx);
+        };
+
+        let start = Instant::now();
+        let poll = this.inner.as_mut().poll_frame(cx);
+        let output_bytes = match &poll {
+            Poll::Ready(Some(Ok(frame))) => frame.data_ref().map_or(0, Bytes::len),
+            _ => 0,
+        };
+        recorder.record(start, output_bytes);
+
+        if let Poll::Ready(None) = poll {
+            if let Some(recorder) = this.recorder.take() {
+                recorder.finish(this.inner.bytes_read());
+            }
+        }
+
+        poll
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        match &self.inner {
+            BodyInner::Identity { inner } => inner.size_hint(),
+            BodyInner::Peeked { inner } => inner.size_hint(),
+            _ => http_body::SizeHint::new(),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match &self.inner {
+            BodyInner::Identity { inner } => inner.is_end_stream(),
+            BodyInner::Peeked { inner } => inner.is_end_stream(),
+            _ => false,
+        }
+    }
+}
+
+impl<B> Body for BodyInner<B>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<http_body::Frame<Self::Data>, Self::Error>>> {
+        match self.project() {
             #[cfg(feature = "compression-gzip")]
             BodyInnerProj::Gzip { inner } => inner.poll_frame(cx),
             #[cfg(feature = "compression-deflate")]
@@ -259,31 +407,27 @@ where
             BodyInnerProj::Brotli { inner } => inner.poll_frame(cx),
             #[cfg(feature = "compression-zstd")]
             BodyInnerProj::Zstd { inner } => inner.poll_frame(cx),
-            BodyInnerProj::Identity { inner } => match ready!(inner.poll_frame(cx)) {
-                Some(Ok(frame)) => {
-                    let frame = frame.map_data(|mut buf| buf.copy_to_bytes(buf.remaining()));
-                    Poll::Ready(Some(Ok(frame)))
-                }
-                Some(Err(err)) => Poll::Ready(Some(Err(err.into()))),
-                None => Poll::Ready(None),
-            },
-        }
-    }
-
-    fn size_hint(&self) -> http_body::SizeHint {
-        if let BodyInner::Identity { inner } = &self.inner {
-            inner.size_hint()
-        } else {
-            http_body::SizeHint::new()
+            BodyInnerProj::Sniff { inner } => inner.poll_frame(cx),
+            BodyInnerProj::Peeked { inner } => identity_frame(ready!(inner.poll_frame(cx))),
+            BodyInnerProj::Identity { inner } => identity_frame(ready!(inner.poll_frame(cx))),
         }
     }
+}
 
-    fn is_end_stream(&self) -> bool {
-        if let BodyInner::Identity { inner } = &self.inner {
-            inner.is_end_stream()
-        } else {
-            false
+fn identity_frame<D, E>(
+    frame: Option<Result<http_body::Frame<D>, E>>,
+) -> Poll<Option<Result<http_body::Frame<Bytes>, BoxError>>>
+where
+    D: Buf,
+    E: Into<BoxError>,
+{
+    match frame {
+        Some(Ok(frame)) => {
+            let frame = frame.map_data(|mut buf| buf.copy_to_bytes(buf.remaining()));
+            Poll::Ready(Some(Ok(frame)))
         }
+        Some(Err(err)) => Poll::Ready(Some(Err(err.into()))),
+        None => Poll::Ready(None),
     }
 }
 
@@ -296,7 +440,7 @@ where
     type Output = GzipEncoder<Self::Input>;
 
     fn apply(input: Self::Input, quality: CompressionLevel) -> Self::Output {
-        GzipEncoder::with_quality(input, quality.into_async_compression())
+        gzip_encoder(input, quality)
     }
 
     fn get_pin_mut(pinned: Pin<&mut Self::Output>) -> Pin<&mut Self::Input> {
@@ -313,7 +457,7 @@ where
     type Output = ZlibEncoder<Self::Input>;
 
     fn apply(input: Self::Input, quality: CompressionLevel) -> Self::Output {
-        ZlibEncoder::with_quality(input, quality.into_async_compression())
+        deflate_encoder(input, quality)
     }
 
     fn get_pin_mut(pinned: Pin<&mut Self::Output>) -> Pin<&mut Self::Input> {
@@ -330,16 +474,7 @@ where
     type Output = BrotliEncoder<Self::Input>;
 
     fn apply(input: Self::Input, quality: CompressionLevel) -> Self::Output {
-        // The brotli crate used under the hood here has a default compression level of 11,
-        // which is the max for brotli. This causes extremely slow compression times, so we
-        // manually set a default of 4 here.
-        //
-        // This is the same default used by NGINX for on-the-fly brotli compression.
-        let level = match quality {
-            CompressionLevel::Default => async_compression::Level::Precise(4),
-            other => other.into_async_compression(),
-        };
-        BrotliEncoder::with_quality(input, level)
+        brotli_encoder(input, quality)
     }
 
     fn get_pin_mut(pinned: Pin<&mut Self::Output>) -> Pin<&mut Self::Input> {
@@ -356,32 +491,67 @@ where
     type Output = ZstdEncoder<Self::Input>;
 
     fn apply(input: Self::Input, quality: CompressionLevel) -> Self::Output {
-        // See https://issues.chromium.org/issues/41493659:
-        //  "For memory usage reasons, Chromium limits the window size to 8MB"
-        // See https://datatracker.ietf.org/doc/html/rfc8878#name-window-descriptor
-        //  "For improved interoperability, it's recommended for decoders to support values
-        //  of Window_Size up to 8 MB and for encoders not to generate frames requiring a
-        //  Window_Size larger than 8 MB."
-        // Level 17 in zstd (as of v1.5.6) is the first level with a window size of 8 MB (2^23):
-        // https://github.com/facebook/zstd/blob/v1.5.6/lib/compress/clevels.h#L25-L51
-        // Set the parameter for all levels >= 17. This will either have no effect (but reduce
-        // the risk of future changes in zstd) or limit the window log to 8MB.
-        let needs_window_limit = match quality {
-            CompressionLevel::Best => true, // level 20
-            CompressionLevel::Precise(level) => level >= 17,
-            _ => false,
-        };
-        // The parameter is not set for levels below 17 as it will increase the window size
-        // for those levels.
-        if needs_window_limit {
-            let params = [async_compression::zstd::CParameter::window_log(23)];
-            ZstdEncoder::with_quality_and_params(input, quality.into_async_compression(), &params)
-        } else {
-            ZstdEncoder::with_quality(input, quality.into_async_compression())
-        }
+        zstd_encoder(input, quality)
     }
 
     fn get_pin_mut(pinned: Pin<&mut Self::Output>) -> Pin<&mut Self::Input> {
         pinned.get_pin_mut()
     }
 }
+
+#[cfg(feature = "compression-gzip")]
+pub(crate) fn gzip_encoder<R: AsyncBufRead>(input: R, quality: CompressionLevel) -> GzipEncoder<R> {
+    GzipEncoder::with_quality(input, quality.into_async_compression())
+}
+
+#[cfg(feature = "compression-deflate")]
+pub(crate) fn deflate_encoder<R: AsyncBufRead>(
+    input: R,
+    quality: CompressionLevel,
+) -> ZlibEncoder<R> {
+    ZlibEncoder::with_quality(input, quality.into_async_compression())
+}
+
+#[cfg(feature = "compression-br")]
+pub(crate) fn brotli_encoder<R: AsyncBufRead>(
+    input: R,
+    quality: CompressionLevel,
+) -> BrotliEncoder<R> {
+    // The brotli crate used under the hood here has a default compression level of 11,
+    // which is the max for brotli. This causes extremely slow compression times, so we
+    // manually set a default of 4 here.
+    //
+    // This is the same default used by NGINX for on-the-fly brotli compression.
+    let level = match quality {
+        CompressionLevel::Default => async_compression::Level::Precise(4),
+        other => other.into_async_compression(),
+    };
+    BrotliEncoder::with_quality(input, level)
+}
+
+#[cfg(feature = "compression-zstd")]
+pub(crate) fn zstd_encoder<R: AsyncBufRead>(input: R, quality: CompressionLevel) -> ZstdEncoder<R> {
+    // See https://issues.chromium.org/issues/41493659:
+    //  "For memory usage reasons, Chromium limits the window size to 8MB"
+    // See https://datatracker.ietf.org/doc/html/rfc8878#name-window-descriptor
+    //  "For improved interoperability, it's recommended for decoders to support values
+    //  of Window_Size up to 8 MB and for encoders not to generate frames requiring a
+    //  Window_Size larger than 8 MB."
+    // Level 17 in zstd (as of v1.5.6) is the first level with a window size of 8 MB (2^23):
+    // https://github.com/facebook/zstd/blob/v1.5.6/lib/compress/clevels.h#L25-L51
+    // Set the parameter for all levels >= 17. This will either have no effect (but reduce
+    // the risk of future changes in zstd) or limit the window log to 8MB.
+    let needs_window_limit = match quality {
+        CompressionLevel::Best => true, // level 20
+        CompressionLevel::Precise(level) => level >= 17,
+        _ => false,
+    };
+    // The parameter is not set for levels below 17 as it will increase the window size
+    // for those levels.
+    if needs_window_limit {
+        let params = [async_compression::zstd::CParameter::window_log(23)];
+        ZstdEncoder::with_quality_and_params(input, quality.into_async_compression(), &params)
+    } else {
+        ZstdEncoder::with_quality(input, quality.into_async_compression())
+    }
+}
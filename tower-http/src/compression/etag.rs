@@ -0,0 +1,170 @@
+use http::{header, HeaderMap, HeaderValue};
+
+/// How [`Compression`] rewrites the `ETag` of responses it compresses.
+///
+/// A strong `ETag` promises byte-for-byte equality, which no longer holds once the body has been
+/// compressed. Caches that rely on it may serve the compressed bytes to clients that didn't ask
+/// for them, or the other way around.
+///
+/// Set with [`Compression::etag_policy`] or [`CompressionLayer::etag_policy`].
+///
+/// [`Compression`]: super::Compression
+/// [`Compression::etag_policy`]: super::Compression::etag_policy
+/// [`CompressionLayer::etag_policy`]: super::CompressionLayer::etag_policy
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ETagPolicy {
+    /// Leave the `ETag` as it is.
+    ///
+    /// This is the default.
+    #[default]
+    Keep,
+    /// Turn a strong `ETag` into a weak one, so `"abc"` becomes `W/"abc"`.
+    Weaken,
+    /// Append the encoding to the `ETag`, so `"abc"` becomes `"abc-gzip"`.
+    ///
+    /// The suffix of the encoding negotiated for a request is stripped from its `If-None-Match`
+    /// and `If-Match` headers before it reaches the inner service, so it keeps seeing its own
+    /// `ETag`s. `304 Not Modified` responses to such requests get the suffix added back. Tags
+    /// with the suffix of another encoding are left alone, so they don't match.
+    AppendEncoding,
+}
+
+impl ETagPolicy {
+    /// Rewrite the `ETag` in `headers` of a response encoded with `encoding`.
+    pub(crate) fn apply(self, headers: &mut HeaderMap, encoding: &str) {
+        let Some(etag) = headers
+            .get(header::ETAG)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return;
+        };
+        let Some((weak, opaque)) = parse_entity_tag(etag.trim()) else {
+            return;
+        };
+
+        let rewritten = match self {
+            ETagPolicy::Keep => return,
+            ETagPolicy::Weaken if weak => return,
+            ETagPolicy::Weaken => format!("W/\"{}\"", opaque),
+            ETagPolicy::AppendEncoding => {
+                format!(
+                    "{}\"{}-{}\"",
+                    if weak { "W/" } else { "" },
+                    opaque,
+                    encoding
+                )
+            }
+        };
+
+        if let Ok(value) = HeaderValue::from_str(&rewritten) {
+            headers.insert(header::ETAG, value);
+        }
+    }
+}
+
+/// Strip the `encoding` suffix added by [`ETagPolicy::AppendEncoding`] from the `If-None-Match`
+/// and `If-Match` headers of a request.
+///
+/// Only the suffix of the encoding negotiated for this request is stripped. Tags with the suffix
+/// of another encoding name a representation the client won't get, so they are left as they are
+/// and never match.
+///
+/// Returns whether any suffix was stripped.
+pub(crate) fn strip_encoding_suffix(headers: &mut HeaderMap, encoding: &str) -> bool {
+    let mut stripped = false;
+
+    for name in [header::IF_NONE_MATCH, header::IF_MATCH] {
+        let header::Entry::Occupied(mut entry) = headers.entry(name) else {
+            continue;
+        };
+
+        // Leave headers we can't parse alone.
+        let Some(values) = entry
+            .iter()
+            .map(|value| {
+                value
+                    .to_str()
+                    .ok()
+                    .and_then(|list| strip_list(list, encoding))
+            })
+            .collect::<Option<Vec<_>>>()
+        else {
+            continue;
+        };
+
+        if !values.iter().any(|(_, stripped)| *stripped) {
+            continue;
+        }
+        stripped = true;
+
+        let mut values = values.into_iter().map(|(value, _)| value);
+        if let Some(first) = values.next() {
+            entry.insert(first);
+        }
+        for value in values {
+            entry.append(value);
+        }
+    }
+
+    stripped
+}
+
+/// Strip the `encoding` suffix from a comma separated list of entity tags.
+fn strip_list(list: &str, encoding: &str) -> Option<(HeaderValue, bool)> {
+    if list.trim() == "*" {
+        return Some((HeaderValue::from_static("*"), false));
+    }
+
+    let mut stripped = false;
+    let mut out = String::with_capacity(list.len());
+    let mut rest = list;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_ascii_whitespace());
+        if rest.is_empty() {
+            break;
+        }
+
+        let (weak, tail) = match rest.strip_prefix("W/") {
+            Some(tail) => (true, tail),
+            None => (false, rest),
+        };
+        let end = tail.strip_prefix('"')?.find('"')? + 2;
+        let (_, mut opaque) = parse_entity_tag(&tail[..end])?;
+        rest = &tail[end..];
+
+        if let Some(base) = opaque
+            .strip_suffix(encoding)
+            .and_then(|base| base.strip_suffix('-'))
+        {
+            opaque = base;
+            stripped = true;
+        }
+
+        if !out.is_empty() {
+            out.push_str(", ");
+        }
+        if weak {
+            out.push_str("W/");
+        }
+        out.push('"');
+        out.push_str(opaque);
+        out.push('"');
+    }
+
+    let value = HeaderValue::from_str(&out).ok()?;
+    Some((value, stripped))
+}
+
+/// Parse a single entity tag into whether it is weak and its opaque part, without the quotes.
+fn parse_entity_tag(tag: &str) -> Option<(bool, &str)> {
+    let (weak, tag) = match tag.strip_prefix("W/") {
+        Some(tag) => (true, tag),
+        None => (false, tag),
+    };
+    let opaque = tag.strip_prefix('"')?.strip_suffix('"')?;
+    if opaque.contains('"') {
+        return None;
+    }
+    Some((weak, opaque))
+}
@@ -1,11 +1,17 @@
 #![allow(unused_imports)]
 
-use super::{body::BodyInner, CompressionBody};
+use super::{
+    body::BodyInner,
+    etag::ETagPolicy,
+    sniff::{Encoder, NewEncoder, PeekedBody, SniffBody},
+    stats::{CompressionOutcome, OnCompression, Recorder, SkipReason},
+    CompressionBody,
+};
 use crate::compression::predicate::Predicate;
 use crate::compression::CompressionLevel;
 use crate::compression_utils::WrapBody;
 use crate::content_encoding::Encoding;
-use http::{header, HeaderMap, HeaderValue, Response};
+use http::{header, HeaderMap, HeaderValue, Response, StatusCode};
 use http_body::Body;
 use pin_project_lite::pin_project;
 use std::{
@@ -19,24 +25,90 @@ pin_project! {
     ///
     /// [`Compression`]: super::Compression
     #[derive(Debug)]
-    pub struct ResponseFuture<F, P> {
+    pub struct ResponseFuture<F, P, OnCompression = ()>
+    where
+        F: Future,
+        F::Output: sealed::ServiceOutput,
+    {
         #[pin]
         pub(crate) inner: F,
         pub(crate) encoding: Option<Encoding>,
         pub(crate) predicate: P,
         pub(crate) quality: CompressionLevel,
+        // Taken when the outcome is reported or handed to the body.
+        pub(crate) on_compression: Option<OnCompression>,
+        pub(crate) etag_policy: ETagPolicy,
+        // The encoding suffix stripped from the conditional headers of the request, if any.
+        pub(crate) etag_suffix: Option<&'static str>,
+        // The response whose first frames are being polled, if the predicate inspects bodies.
+        pub(crate) peeking: Option<Peeking<<F::Output as sealed::ServiceOutput>::Body>>,
+    }
+}
+
+mod sealed {
+    use http::Response;
+    use http_body::Body;
+
+    /// Names the response body type of the inner service.
+    pub trait ServiceOutput {
+        type Body: Body;
+    }
+
+    impl<B: Body, E> ServiceOutput for Result<Response<B>, E> {
+        type Body = B;
+    }
+}
+
+pub(crate) struct Peeking<B: Body> {
+    parts: http::response::Parts,
+    body: PeekedBody<B>,
+    encoding: Encoding,
+    new_encoder: NewEncoder,
+}
+
+impl<B: Body> std::fmt::Debug for Peeking<B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Peeking")
+            .field("parts", &self.parts)
+            .field("encoding", &self.encoding)
+            .finish_non_exhaustive()
     }
 }
 
-impl<F, B, E, P> Future for ResponseFuture<F, P>
+impl<F, B, E, P, OnCompressionT> Future for ResponseFuture<F, P, OnCompressionT>
 where
     F: Future<Output = Result<Response<B>, E>>,
     B: Body,
     P: Predicate,
+    OnCompressionT: OnCompression,
 {
-    type Output = Result<Response<CompressionBody<B>>, E>;
+    type Output = Result<Response<CompressionBody<B, OnCompressionT>>, E>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.as_mut().project();
+        if let Some(peeking) = this.peeking {
+            ready!(peeking.body.poll_peek(cx));
+            let Peeking {
+                parts,
+                body,
+                encoding,
+                new_encoder,
+            } = this.peeking.take().expect("future polled after completion");
+
+            if body.is_compressed() {
+                // Pass the body through as is, it would only get bigger.
+                self.skipped(SkipReason::AlreadyEncoded);
+                return Poll::Ready(Ok(Response::from_parts(
+                    parts,
+                    CompressionBody::new(BodyInner::peeked(body)),
+                )));
+            }
+
+            let body = SniffBody::new(body, new_encoder, *this.quality);
+            let body = CompressionBody::new(BodyInner::sniff(body));
+            return Poll::Ready(Ok(self.compressed(parts, body, encoding)));
+        }
+
         let res = ready!(self.as_mut().project().inner.poll(cx)?);
 
         let encoding = match self.encoding {
@@ -47,17 +119,11 @@ where
                 //
                 // Note: the inner service has already been called, so its response body and
                 // headers are passed through. Only the status code is overwritten.
+                self.as_mut().skipped(SkipReason::NotAccepted);
+
                 let mut res = res;
                 *res.status_mut() = http::StatusCode::NOT_ACCEPTABLE;
-                if !res.headers().get_all(header::VARY).iter().any(|value| {
-                    contains_ignore_ascii_case(
-                        value.as_bytes(),
-                        header::ACCEPT_ENCODING.as_str().as_bytes(),
-                    )
-                }) {
-                    res.headers_mut()
-                        .append(header::VARY, header::ACCEPT_ENCODING.into());
-                }
+                add_vary(res.headers_mut());
                 let (parts, body) = res.into_parts();
                 return Poll::Ready(Ok(Response::from_parts(
                     parts,
@@ -66,55 +132,75 @@ where
             }
         };
 
-        // never recompress responses that are already compressed
-        let should_compress = !res.headers().contains_key(header::CONTENT_ENCODING)
+        let skip_reason = if res.headers().contains_key(header::CONTENT_ENCODING) {
+            // never recompress responses that are already compressed
+            Some(SkipReason::AlreadyEncoded)
+        } else if res.headers().contains_key(header::CONTENT_RANGE) {
             // never compress responses that are ranges
-            && !res.headers().contains_key(header::CONTENT_RANGE)
-            && self.predicate.should_compress(&res);
+            Some(SkipReason::Range)
+        } else if !self.predicate.should_compress(&res) {
+            Some(SkipReason::Predicate)
+        } else {
+            None
+        };
+        let should_compress = skip_reason.is_none();
 
         let (mut parts, body) = res.into_parts();
 
-        if should_compress
-            && !parts.headers.get_all(header::VARY).iter().any(|value| {
-                contains_ignore_ascii_case(
-                    value.as_bytes(),
-                    header::ACCEPT_ENCODING.as_str().as_bytes(),
-                )
-            })
-        {
-            parts
-                .headers
-                .append(header::VARY, header::ACCEPT_ENCODING.into());
-        }
+        let new_encoder =
+            Encoder::for_encoding(encoding).filter(|_| self.predicate.inspects_body());
 
-        let body = match (should_compress, encoding) {
+        let body = match (should_compress, encoding, new_encoder) {
             // if compression is _not_ supported or the client doesn't accept it
-            (false, _) | (_, Encoding::Identity) => {
+            (false, _, _) | (_, Encoding::Identity, _) => {
+                self.as_mut()
+                    .skipped(skip_reason.unwrap_or(SkipReason::NotAccepted));
+
+                if should_compress {
+                    add_vary(&mut parts.headers);
+                }
+
+                // The request's `ETag` carried a suffix, so the one sent back must too.
+                if let (StatusCode::NOT_MODIFIED, Some(encoding)) = (parts.status, self.etag_suffix)
+                {
+                    self.etag_policy.apply(&mut parts.headers, encoding);
+                }
+
                 return Poll::Ready(Ok(Response::from_parts(
                     parts,
                     CompressionBody::new(BodyInner::identity(body)),
-                )))
+                )));
             }
 
+            (_, _, Some(new_encoder)) => {
+                // Look at the start of the body before deciding on the headers.
+                *self.as_mut().project().peeking = Some(Peeking {
+                    parts,
+                    body: PeekedBody::new(body),
+                    encoding,
+                    new_encoder,
+                });
+                return self.poll(cx);
+            }
             #[cfg(feature = "compression-gzip")]
-            (_, Encoding::Gzip) => {
+            (_, Encoding::Gzip, _) => {
                 CompressionBody::new(BodyInner::gzip(WrapBody::new(body, self.quality)))
             }
             #[cfg(feature = "compression-deflate")]
-            (_, Encoding::Deflate) => {
+            (_, Encoding::Deflate, _) => {
                 CompressionBody::new(BodyInner::deflate(WrapBody::new(body, self.quality)))
             }
             #[cfg(feature = "compression-br")]
-            (_, Encoding::Brotli) => {
+            (_, Encoding::Brotli, _) => {
                 CompressionBody::new(BodyInner::brotli(WrapBody::new(body, self.quality)))
             }
             #[cfg(feature = "compression-zstd")]
-            (_, Encoding::Zstd) => {
+            (_, Encoding::Zstd, _) => {
                 CompressionBody::new(BodyInner::zstd(WrapBody::new(body, self.quality)))
             }
             #[cfg(feature = "fs")]
             #[allow(unreachable_patterns)]
-            (true, _) => {
+            (true, _, _) => {
                 // This should never happen because the `AcceptEncoding` struct which is used to determine
                 // `self.encoding` will only enable the different compression algorithms if the
                 // corresponding crate feature has been enabled. This means
@@ -129,6 +215,7 @@ where
                 // To safeguard against refactors that changes this relationship or other bugs the
                 // server will return an uncompressed response instead of panicking since that could
                 // become a ddos attack vector.
+                add_vary(&mut parts.headers);
                 return Poll::Ready(Ok(Response::from_parts(
                     parts,
                     CompressionBody::new(BodyInner::identity(body)),
@@ -136,15 +223,63 @@ where
             }
         };
 
+        Poll::Ready(Ok(self.compressed(parts, body, encoding)))
+    }
+}
+
+impl<F, P, OnCompressionT> ResponseFuture<F, P, OnCompressionT>
+where
+    F: Future,
+    F::Output: sealed::ServiceOutput,
+    OnCompressionT: OnCompression,
+{
+    /// Reports that the response wasn't compressed.
+    fn skipped(self: Pin<&mut Self>, reason: SkipReason) {
+        if let Some(on_compression) = self.project().on_compression.take() {
+            on_compression.on_compression(&CompressionOutcome::Skipped(reason));
+        }
+    }
+
+    /// Sets the headers of a response whose body is compressed with `encoding`.
+    fn compressed<B>(
+        self: Pin<&mut Self>,
+        mut parts: http::response::Parts,
+        body: CompressionBody<B, OnCompressionT>,
+        encoding: Encoding,
+    ) -> Response<CompressionBody<B, OnCompressionT>>
+    where
+        B: Body,
+    {
+        let this = self.project();
+        let recorder = this
+            .on_compression
+            .take()
+            .map(|on_compression| Recorder::new(on_compression, encoding.to_str()));
+        let body = body.with_recorder(recorder);
+
+        add_vary(&mut parts.headers);
         parts.headers.remove(header::ACCEPT_RANGES);
         parts.headers.remove(header::CONTENT_LENGTH);
 
         parts
             .headers
             .insert(header::CONTENT_ENCODING, encoding.into_header_value());
+        this.etag_policy
+            .apply(&mut parts.headers, encoding.to_str());
+
+        Response::from_parts(parts, body)
+    }
+}
 
-        let res = Response::from_parts(parts, body);
-        Poll::Ready(Ok(res))
+/// Adds `Accept-Encoding` to `Vary`, unless it's there already.
+fn add_vary(headers: &mut HeaderMap) {
+    if !headers.get_all(header::VARY).iter().any(|value| {
+        contains_ignore_ascii_case(
+            value.as_bytes(),
+            header::ACCEPT_ENCODING.as_str().as_bytes(),
+        )
+    }) {
+        headers.append(header::VARY, header::ACCEPT_ENCODING.into());
     }
 }
 
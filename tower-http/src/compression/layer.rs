@@ -1,3 +1,4 @@
+use super::etag::ETagPolicy;
 use super::{Compression, Predicate};
 use crate::compression::predicate::DefaultPredicate;
 use crate::compression::CompressionLevel;
@@ -11,17 +12,20 @@ use tower_layer::Layer;
 ///
 /// See the [module docs](crate::compression) for more details.
 #[derive(Clone, Debug, Default)]
-pub struct CompressionLayer<P = DefaultPredicate> {
+pub struct CompressionLayer<P = DefaultPredicate, OnCompression = ()> {
     accept: AcceptEncoding,
     predicate: P,
     quality: CompressionLevel,
+    on_compression: OnCompression,
+    etag_policy: ETagPolicy,
 }
 
-impl<S, P> Layer<S> for CompressionLayer<P>
+impl<S, P, OnCompression> Layer<S> for CompressionLayer<P, OnCompression>
 where
     P: Predicate,
+    OnCompression: Clone,
 {
-    type Service = Compression<S, P>;
+    type Service = Compression<S, P, OnCompression>;
 
     fn layer(&self, inner: S) -> Self::Service {
         Compression {
@@ -29,6 +33,8 @@ where
             accept: self.accept,
             predicate: self.predicate.clone(),
             quality: self.quality,
+            on_compression: self.on_compression.clone(),
+            etag_policy: self.etag_policy,
         }
     }
 }
@@ -38,7 +44,9 @@ impl CompressionLayer {
     pub fn new() -> Self {
         Self::default()
     }
+}
 
+impl<P, OnCompression> CompressionLayer<P, OnCompression> {
     /// Sets whether to enable the gzip encoding.
     #[cfg(feature = "compression-gzip")]
     pub fn gzip(mut self, enable: bool) -> Self {
@@ -105,10 +113,37 @@ impl CompressionLayer {
         self
     }
 
+    /// Sets the [`OnCompression`] called with the [`CompressionOutcome`] of every response.
+    ///
+    /// See [`Compression::on_compression`] for more details.
+    ///
+    /// [`OnCompression`]: super::OnCompression
+    /// [`CompressionOutcome`]: super::CompressionOutcome
+    pub fn on_compression<NewOnCompression>(
+        self,
+        new_on_compression: NewOnCompression,
+    ) -> CompressionLayer<P, NewOnCompression> {
+        CompressionLayer {
+            accept: self.accept,
+            predicate: self.predicate,
+            quality: self.quality,
+            on_compression: new_on_compression,
+            etag_policy: self.etag_policy,
+        }
+    }
+
+    /// Sets how the `ETag` of compressed responses is rewritten.
+    ///
+    /// See [`Compression::etag_policy`] for more details.
+    pub fn etag_policy(mut self, policy: ETagPolicy) -> Self {
+        self.etag_policy = policy;
+        self
+    }
+
     /// Replace the current compression predicate.
     ///
     /// See [`Compression::compress_when`] for more details.
-    pub fn compress_when<C>(self, predicate: C) -> CompressionLayer<C>
+    pub fn compress_when<C>(self, predicate: C) -> CompressionLayer<C, OnCompression>
     where
         C: Predicate,
     {
@@ -116,6 +151,8 @@ impl CompressionLayer {
             accept: self.accept,
             predicate,
             quality: self.quality,
+            on_compression: self.on_compression,
+            etag_policy: self.etag_policy,
         }
     }
 }
@@ -72,18 +72,23 @@
 pub mod predicate;
 
 mod body;
+mod etag;
 mod future;
 mod layer;
 mod pin_project_cfg;
 mod service;
+mod sniff;
+mod stats;
 
 #[doc(inline)]
 pub use self::{
     body::CompressionBody,
+    etag::ETagPolicy,
     future::ResponseFuture,
     layer::CompressionLayer,
     predicate::{DefaultPredicate, Predicate},
     service::Compression,
+    stats::{CompressionOutcome, CompressionStats, OnCompression, SkipReason},
 };
 pub use crate::compression_utils::CompressionLevel;
 
@@ -97,13 +102,16 @@ mod tests {
     use bytes::Bytes;
     use flate2::read::GzDecoder;
     use http::header::{
-        ACCEPT_ENCODING, ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_RANGE, CONTENT_TYPE, RANGE,
+        ACCEPT_ENCODING, ACCEPT_RANGES, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_RANGE,
+        CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH, RANGE, VARY,
+    };
+    use http::{
+        Extensions, HeaderMap, HeaderName, HeaderValue, Request, Response, StatusCode, Version,
     };
-    use http::{HeaderMap, HeaderName, HeaderValue, Request, Response};
     use http_body::Body as _;
     use http_body_util::BodyExt;
     use std::convert::Infallible;
-    use std::io::Read;
+    use std::io::{Read, Write};
     use std::sync::{Arc, RwLock};
     use tokio::io::{AsyncReadExt, AsyncWriteExt};
     use tokio_util::io::StreamReader;
@@ -282,6 +290,79 @@ mod tests {
         Ok(Response::builder().body(body).unwrap())
     }
 
+    #[tokio::test]
+    async fn on_compression_reports_stats() {
+        let outcomes = Arc::new(RwLock::new(Vec::new()));
+        let recorded = outcomes.clone();
+        let mut svc = Compression::new(service_fn(handle))
+            .compress_when(Always)
+            .on_compression(move |outcome: &CompressionOutcome| {
+                recorded.write().unwrap().push(outcome.clone())
+            });
+
+        let req = Request::builder()
+            .header("accept-encoding", "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let res = svc.ready().await.unwrap().call(req).await.unwrap();
+        assert!(outcomes.read().unwrap().is_empty());
+
+        let compressed = res.into_body().collect().await.unwrap().to_bytes();
+
+        let outcomes = outcomes.read().unwrap();
+        match &outcomes[..] {
+            [CompressionOutcome::Compressed(stats)] => {
+                assert_eq!(stats.encoding, "gzip");
+                assert_eq!(stats.input_bytes, "Hello, World!".len() as u64);
+                assert_eq!(stats.output_bytes, compressed.len() as u64);
+            }
+            other => panic!("unexpected outcomes: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn compression_is_copy() {
+        fn assert_copy<T: Copy>(_: T) {}
+        assert_copy(Compression::new(service_fn(handle)).compress_when(SizeAbove::new(0)));
+    }
+
+    #[tokio::test]
+    async fn on_compression_reports_skip_reason() {
+        let outcomes = Arc::new(RwLock::new(Vec::new()));
+        let recorded = outcomes.clone();
+        let on_compression =
+            move |outcome: &CompressionOutcome| recorded.write().unwrap().push(outcome.clone());
+
+        let mut svc = Compression::new(service_fn(handle))
+            .compress_when(|_: StatusCode, _: Version, _: &HeaderMap, _: &Extensions| false)
+            .on_compression(on_compression.clone());
+        let req = Request::builder()
+            .header("accept-encoding", "gzip")
+            .body(Body::empty())
+            .unwrap();
+        svc.ready().await.unwrap().call(req).await.unwrap();
+
+        let mut svc = Compression::new(service_fn(handle))
+            .compress_when(Always)
+            .on_compression(on_compression);
+        let req = Request::builder()
+            .header("accept-encoding", "identity")
+            .body(Body::empty())
+            .unwrap();
+        svc.ready().await.unwrap().call(req).await.unwrap();
+
+        let reasons = outcomes
+            .read()
+            .unwrap()
+            .iter()
+            .map(|outcome| match outcome {
+                CompressionOutcome::Skipped(reason) => *reason,
+                other => panic!("unexpected outcome: {:?}", other),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(reasons, [SkipReason::Predicate, SkipReason::NotAccepted]);
+    }
+
     #[tokio::test]
     async fn will_not_compress_if_filtered_out() {
         use predicate::Predicate;
@@ -605,6 +686,257 @@ mod tests {
         assert_eq!(trailers["grpc-status"], "0");
     }
 
+    #[tokio::test]
+    async fn no_transform_is_respected() {
+        let svc = service_fn(|_| async {
+            let mut res = Response::new(Body::from("Hello, World!"));
+            res.headers_mut()
+                .insert(CACHE_CONTROL, "max-age=60, No-Transform".parse().unwrap());
+            Ok::<_, std::io::Error>(res)
+        });
+        let svc = Compression::new(svc).compress_when(predicate::NoTransform);
+
+        let req = Request::builder()
+            .header(ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let res = svc.oneshot(req).await.unwrap();
+
+        assert!(!res.headers().contains_key(CONTENT_ENCODING));
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "Hello, World!");
+    }
+
+    #[tokio::test]
+    async fn already_compressed_payload_is_not_recompressed() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&[b'a'; 4096]).unwrap();
+        let payload = Bytes::from(encoder.finish().unwrap());
+
+        let svc = service_fn({
+            let payload = payload.clone();
+            move |_| {
+                let payload = payload.clone();
+                async move { Ok::<_, std::io::Error>(Response::new(Body::from(payload))) }
+            }
+        });
+        let outcomes = Arc::new(RwLock::new(Vec::new()));
+        let recorded = outcomes.clone();
+        let svc = Compression::new(svc)
+            .compress_when(predicate::NotAlreadyCompressed)
+            .quality(CompressionLevel::Best)
+            .on_compression(move |outcome: &CompressionOutcome| {
+                recorded.write().unwrap().push(outcome.clone())
+            });
+
+        let req = Request::builder()
+            .header(ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let res = svc.oneshot(req).await.unwrap();
+        assert!(!res.headers().contains_key(CONTENT_ENCODING));
+        assert!(!res.headers().contains_key(VARY));
+
+        // The gzip payload isn't wrapped in a second layer of gzip.
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, payload);
+        assert!(matches!(
+            outcomes.read().unwrap()[..],
+            [CompressionOutcome::Skipped(SkipReason::AlreadyEncoded)]
+        ));
+    }
+
+    #[tokio::test]
+    async fn not_already_compressed_finds_magic_number_split_across_frames() {
+        // zstd magic number, after an empty frame and split in two.
+        let svc = service_fn(|_req: Request<Body>| async {
+            let stream = futures_util::stream::iter(vec![
+                Ok::<_, BoxError>(Bytes::new()),
+                Ok(Bytes::from_static(&[0x28])),
+                Ok(Bytes::from_static(&[0xb5, 0x2f, 0xfd, 0x00])),
+            ]);
+            Ok::<_, Infallible>(Response::new(Body::from_stream(stream)))
+        });
+        let svc = Compression::new(svc).compress_when(predicate::NotAlreadyCompressed);
+
+        let req = Request::builder()
+            .header(ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let res = svc.oneshot(req).await.unwrap();
+        assert!(!res.headers().contains_key(CONTENT_ENCODING));
+
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, [0x28, 0xb5, 0x2f, 0xfd, 0x00][..]);
+    }
+
+    #[tokio::test]
+    async fn inspected_body_is_not_handed_back() {
+        let svc = service_fn(|_req: Request<Body>| async {
+            Ok::<_, Infallible>(Response::new(Body::from("Hello, World!")))
+        });
+        let svc = Compression::new(svc).compress_when(predicate::NotAlreadyCompressed);
+
+        let req = Request::builder()
+            .header(ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let mut body = svc.oneshot(req).await.unwrap().into_body();
+        assert!(body.try_get_mut().is_none());
+        let body = body.try_into_inner().unwrap_err();
+
+        // The frames polled to inspect the body are kept.
+        let compressed = body.collect().await.unwrap().to_bytes();
+        let mut decoder = GzDecoder::new(&compressed[..]);
+        let mut decompressed = String::new();
+        decoder.read_to_string(&mut decompressed).unwrap();
+        assert_eq!(decompressed, "Hello, World!");
+    }
+
+    #[tokio::test]
+    async fn inspecting_body_keeps_streamed_data_and_trailers() {
+        let svc = service_fn(|_req: Request<Body>| async {
+            let stream = futures_util::stream::iter(vec![
+                Ok::<_, BoxError>(Bytes::from("chunk1")),
+                Ok(Bytes::from("chunk2")),
+                Ok(Bytes::from("chunk3")),
+            ]);
+            let mut trailers = HeaderMap::new();
+            trailers.insert("grpc-status", "0".parse().unwrap());
+            let body = Body::from_stream(stream).with_trailers(trailers);
+            Ok::<_, Infallible>(Response::builder().body(body).unwrap())
+        });
+        let svc = Compression::new(svc).compress_when(Always.and(predicate::NotAlreadyCompressed));
+
+        let req = Request::builder()
+            .header("accept-encoding", "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let res = svc.oneshot(req).await.unwrap();
+
+        let collected = res.into_body().collect().await.unwrap();
+        let trailers = collected.trailers().cloned().unwrap();
+        let compressed_data = collected.to_bytes();
+
+        let mut decoder = GzDecoder::new(&compressed_data[..]);
+        let mut decompressed = String::new();
+        decoder.read_to_string(&mut decompressed).unwrap();
+
+        assert_eq!(decompressed, "chunk1chunk2chunk3");
+        assert_eq!(trailers["grpc-status"], "0");
+    }
+
+    #[tokio::test]
+    async fn etag_policy_weaken() {
+        let svc = service_fn(|_| async {
+            let mut res = Response::new(Body::from("Hello, World!"));
+            res.headers_mut().insert(ETAG, "\"abc\"".parse().unwrap());
+            Ok::<_, std::io::Error>(res)
+        });
+        let svc = Compression::new(svc)
+            .compress_when(Always)
+            .etag_policy(ETagPolicy::Weaken);
+
+        let req = Request::builder()
+            .header(ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let res = svc.oneshot(req).await.unwrap();
+
+        assert_eq!(res.headers()[CONTENT_ENCODING], "gzip");
+        assert_eq!(res.headers()[ETAG], "W/\"abc\"");
+    }
+
+    #[tokio::test]
+    async fn etag_policy_append_encoding() {
+        let svc = service_fn(|req: Request<Body>| async move {
+            let etag = HeaderValue::from_static("\"abc\"");
+            let mut res = if req.headers().get(IF_NONE_MATCH) == Some(&etag) {
+                Response::builder()
+                    .status(StatusCode::NOT_MODIFIED)
+                    .body(Body::empty())
+                    .unwrap()
+            } else {
+                Response::new(Body::from("Hello, World!"))
+            };
+            res.headers_mut().insert(ETAG, etag);
+            Ok::<_, std::io::Error>(res)
+        });
+        let mut svc = Compression::new(svc)
+            .compress_when(SizeAbove::new(1))
+            .etag_policy(ETagPolicy::AppendEncoding);
+
+        let req = Request::builder()
+            .header(ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let res = svc.ready().await.unwrap().call(req).await.unwrap();
+        assert_eq!(res.headers()[ETAG], "\"abc-gzip\"");
+
+        // the suffix is stripped before the request reaches the inner service
+        let req = Request::builder()
+            .header(ACCEPT_ENCODING, "gzip")
+            .header(IF_NONE_MATCH, "\"abc-gzip\"")
+            .body(Body::empty())
+            .unwrap();
+        let res = svc.ready().await.unwrap().call(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert!(!res.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(res.headers()[ETAG], "\"abc-gzip\"");
+    }
+
+    #[tokio::test]
+    async fn etag_policy_append_encoding_other_encoding() {
+        let svc = service_fn(|req: Request<Body>| async move {
+            let etag = HeaderValue::from_static("\"abc\"");
+            let mut res = if req.headers().get(IF_NONE_MATCH) == Some(&etag) {
+                Response::builder()
+                    .status(StatusCode::NOT_MODIFIED)
+                    .body(Body::empty())
+                    .unwrap()
+            } else {
+                Response::new(Body::from("Hello, World!"))
+            };
+            res.headers_mut().insert(ETAG, etag);
+            Ok::<_, std::io::Error>(res)
+        });
+        let svc = Compression::new(svc)
+            .compress_when(SizeAbove::new(1))
+            .etag_policy(ETagPolicy::AppendEncoding);
+
+        // the client cached the gzip representation but now gets br
+        let req = Request::builder()
+            .header(ACCEPT_ENCODING, "br")
+            .header(IF_NONE_MATCH, "\"abc-gzip\"")
+            .body(Body::empty())
+            .unwrap();
+        let res = svc.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_ENCODING], "br");
+        assert_eq!(res.headers()[ETAG], "\"abc-br\"");
+    }
+
+    #[test]
+    fn etag_suffixes_are_stripped_from_conditional_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, "W/\"a,b-br\", \"c-gzip\"".parse().unwrap());
+        headers.insert(IF_MATCH, "*".parse().unwrap());
+
+        assert!(etag::strip_encoding_suffix(&mut headers, "br"));
+        assert_eq!(headers[IF_NONE_MATCH], "W/\"a,b\", \"c-gzip\"");
+        assert_eq!(headers[IF_MATCH], "*");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, "\"a-gzip\"".parse().unwrap());
+        assert!(!etag::strip_encoding_suffix(&mut headers, "br"));
+        assert_eq!(headers[IF_NONE_MATCH], "\"a-gzip\"");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, "not an etag-gzip".parse().unwrap());
+        assert!(!etag::strip_encoding_suffix(&mut headers, "gzip"));
+        assert_eq!(headers[IF_NONE_MATCH], "not an etag-gzip");
+    }
+
     #[tokio::test]
     async fn size_hint_identity() {
         let msg = "Hello, world!";
@@ -17,6 +17,13 @@ pub trait Predicate: Clone {
     where
         B: Body;
 
+    /// Should the start of the body be checked for data that is already compressed?
+    ///
+    /// See [`NotAlreadyCompressed`] for details. Defaults to `false`.
+    fn inspects_body(&self) -> bool {
+        false
+    }
+
     /// Combine two predicates into one.
     ///
     /// The resulting predicate enables compression if both inner predicates do.
@@ -60,6 +67,10 @@ where
             .map(|inner| inner.should_compress(response))
             .unwrap_or(true)
     }
+
+    fn inspects_body(&self) -> bool {
+        self.as_ref().map_or(false, Predicate::inspects_body)
+    }
 }
 
 /// Two predicates combined into one.
@@ -82,6 +93,10 @@ where
     {
         self.lhs.should_compress(response) && self.rhs.should_compress(response)
     }
+
+    fn inspects_body(&self) -> bool {
+        self.lhs.inspects_body() || self.rhs.inspects_body()
+    }
 }
 
 /// The default predicate used by [`Compression`] and [`CompressionLayer`].
@@ -274,3 +289,70 @@ fn content_type<B>(response: &http::Response<B>) -> &str {
         .and_then(|h| h.to_str().ok())
         .unwrap_or_default()
 }
+
+/// [`Predicate`] that wont allow responses with `Cache-Control: no-transform` to be compressed.
+///
+/// [RFC 9111] forbids intermediaries from transforming the content of such responses.
+///
+/// [RFC 9111]: https://www.rfc-editor.org/rfc/rfc9111#section-5.2.2.6
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoTransform;
+
+impl Predicate for NoTransform {
+    fn should_compress<B>(&self, response: &http::Response<B>) -> bool
+    where
+        B: Body,
+    {
+        !response
+            .headers()
+            .get_all(header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|directive| directive.trim().eq_ignore_ascii_case("no-transform"))
+    }
+}
+
+/// [`Predicate`] that wont allow responses with a `Content-Range` header to be compressed.
+///
+/// [`Compression`] never compresses such responses, but this predicate can be used to apply the
+/// same rule elsewhere.
+///
+/// [`Compression`]: super::Compression
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NotForContentRange;
+
+impl Predicate for NotForContentRange {
+    fn should_compress<B>(&self, response: &http::Response<B>) -> bool
+    where
+        B: Body,
+    {
+        !response.headers().contains_key(header::CONTENT_RANGE)
+    }
+}
+
+/// [`Predicate`] that checks whether the body is already compressed before compressing it.
+///
+/// The start of the body is checked for the magic numbers of gzip, zstd, PNG and zip data before
+/// the response is returned. Frames are polled until the longest magic number fits, so magic
+/// numbers split across frames are found too. Bodies that are already compressed are passed through
+/// unchanged, without `Content-Encoding`, and reported as [`SkipReason::AlreadyEncoded`].
+///
+/// Waiting for the body delays the response headers until it has produced a few bytes of data.
+///
+/// [`SkipReason::AlreadyEncoded`]: super::SkipReason::AlreadyEncoded
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NotAlreadyCompressed;
+
+impl Predicate for NotAlreadyCompressed {
+    fn should_compress<B>(&self, _response: &http::Response<B>) -> bool
+    where
+        B: Body,
+    {
+        true
+    }
+
+    fn inspects_body(&self) -> bool {
+        true
+    }
+}
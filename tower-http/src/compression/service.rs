@@ -1,3 +1,5 @@
+use super::etag::{self, ETagPolicy};
+use super::stats::OnCompression;
 use super::{CompressionBody, CompressionLayer, ResponseFuture};
 use crate::compression::predicate::{DefaultPredicate, Predicate};
 use crate::compression::CompressionLevel;
@@ -14,11 +16,13 @@ use tower_service::Service;
 ///
 /// See the [module docs](crate::compression) for more details.
 #[derive(Clone, Copy)]
-pub struct Compression<S, P = DefaultPredicate> {
+pub struct Compression<S, P = DefaultPredicate, OnCompression = ()> {
     pub(crate) inner: S,
     pub(crate) accept: AcceptEncoding,
     pub(crate) predicate: P,
     pub(crate) quality: CompressionLevel,
+    pub(crate) on_compression: OnCompression,
+    pub(crate) etag_policy: ETagPolicy,
 }
 
 impl<S> Compression<S, DefaultPredicate> {
@@ -29,11 +33,13 @@ impl<S> Compression<S, DefaultPredicate> {
             accept: AcceptEncoding::default(),
             predicate: DefaultPredicate::default(),
             quality: CompressionLevel::default(),
+            on_compression: (),
+            etag_policy: ETagPolicy::default(),
         }
     }
 }
 
-impl<S, P> Compression<S, P> {
+impl<S, P, OnCompression> Compression<S, P, OnCompression> {
     define_inner_service_accessors!();
 
     /// Returns a new [`Layer`] that wraps services with a `Compression` middleware.
@@ -109,6 +115,40 @@ impl<S, P> Compression<S, P> {
         self
     }
 
+    /// Sets the [`OnCompression`] called with the [`CompressionOutcome`] of every response.
+    ///
+    /// The callback is cloned for every response. For compressed responses it is called once the
+    /// body has been read to the end, with the encoding used, the number of bytes read and
+    /// written, and the time spent encoding. Otherwise it is called when the response is
+    /// produced, with the reason compression was skipped.
+    ///
+    /// [`OnCompression`]: super::OnCompression
+    /// [`CompressionOutcome`]: super::CompressionOutcome
+    pub fn on_compression<NewOnCompression>(
+        self,
+        new_on_compression: NewOnCompression,
+    ) -> Compression<S, P, NewOnCompression> {
+        Compression {
+            inner: self.inner,
+            accept: self.accept,
+            predicate: self.predicate,
+            quality: self.quality,
+            on_compression: new_on_compression,
+            etag_policy: self.etag_policy,
+        }
+    }
+
+    /// Sets how the `ETag` of compressed responses is rewritten.
+    ///
+    /// Defaults to [`ETagPolicy::Keep`]. See [`ETagPolicy`] for the alternatives.
+    ///
+    /// [`ETagPolicy`]: super::ETagPolicy
+    /// [`ETagPolicy::Keep`]: super::ETagPolicy::Keep
+    pub fn etag_policy(mut self, policy: ETagPolicy) -> Self {
+        self.etag_policy = policy;
+        self
+    }
+
     /// Replace the current compression predicate.
     ///
     /// Predicates are used to determine whether a response should be compressed or not.
@@ -144,7 +184,7 @@ impl<S, P> Compression<S, P> {
     ///
     /// Responses that are already compressed (ie have a `content-encoding` header) will _never_ be
     /// recompressed, regardless what they predicate says.
-    pub fn compress_when<C>(self, predicate: C) -> Compression<S, C>
+    pub fn compress_when<C>(self, predicate: C) -> Compression<S, C, OnCompression>
     where
         C: Predicate,
     {
@@ -153,33 +193,50 @@ impl<S, P> Compression<S, P> {
             accept: self.accept,
             predicate,
             quality: self.quality,
+            on_compression: self.on_compression,
+            etag_policy: self.etag_policy,
         }
     }
 }
 
-impl<ReqBody, ResBody, S, P> Service<Request<ReqBody>> for Compression<S, P>
+impl<ReqBody, ResBody, S, P, OnCompressionT> Service<Request<ReqBody>>
+    for Compression<S, P, OnCompressionT>
 where
     S: Service<Request<ReqBody>, Response = Response<ResBody>>,
     ResBody: Body,
     P: Predicate,
+    OnCompressionT: OnCompression + Clone,
 {
-    type Response = Response<CompressionBody<ResBody>>;
+    type Response = Response<CompressionBody<ResBody, OnCompressionT>>;
     type Error = S::Error;
-    type Future = ResponseFuture<S::Future, P>;
+    type Future = ResponseFuture<S::Future, P, OnCompressionT>;
 
     #[inline]
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         self.inner.poll_ready(cx)
     }
 
-    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
         let encoding = Encoding::from_headers(req.headers(), self.accept);
+        // Only the suffix of the negotiated encoding is stripped, so tags cached for another
+        // encoding don't match.
+        let etag_suffix = match (self.etag_policy, encoding) {
+            (ETagPolicy::AppendEncoding, Some(encoding)) if encoding != Encoding::Identity => {
+                let suffix = encoding.to_str();
+                etag::strip_encoding_suffix(req.headers_mut(), suffix).then_some(suffix)
+            }
+            _ => None,
+        };
 
         ResponseFuture {
             inner: self.inner.call(req),
             encoding,
             predicate: self.predicate.clone(),
             quality: self.quality,
+            on_compression: Some(self.on_compression.clone()),
+            etag_policy: self.etag_policy,
+            etag_suffix,
+            peeking: None,
         }
     }
 }
@@ -0,0 +1,315 @@
+//! Looking at the start of a body before deciding whether to compress it.
+
+#![allow(unused_imports)]
+
+use crate::{
+    compression_utils::{CompressionLevel, Feed},
+    content_encoding::Encoding,
+    BoxError,
+};
+use bytes::{Buf, Bytes, BytesMut};
+use http::HeaderMap;
+use http_body::{Body, Frame};
+use std::{
+    collections::VecDeque,
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+#[cfg(feature = "compression-br")]
+use {super::body::brotli_encoder, async_compression::tokio::bufread::BrotliEncoder};
+#[cfg(feature = "compression-deflate")]
+use {super::body::deflate_encoder, async_compression::tokio::bufread::ZlibEncoder};
+#[cfg(feature = "compression-gzip")]
+use {super::body::gzip_encoder, async_compression::tokio::bufread::GzipEncoder};
+#[cfg(feature = "compression-zstd")]
+use {super::body::zstd_encoder, async_compression::tokio::bufread::ZstdEncoder};
+
+/// Magic numbers of formats that are already compressed.
+const MAGIC_NUMBERS: &[&[u8]] = &[
+    // gzip
+    &[0x1f, 0x8b],
+    // zstd
+    &[0x28, 0xb5, 0x2f, 0xfd],
+    // png
+    &[0x89, b'P', b'N', b'G'],
+    // zip
+    &[b'P', b'K', 0x03, 0x04],
+];
+
+/// Does `prefix` look like the start of compressed data?
+pub(crate) fn is_compressed(prefix: &[u8]) -> bool {
+    MAGIC_NUMBERS.iter().any(|magic| prefix.starts_with(magic))
+}
+
+pub(crate) type NewEncoder = fn(Feed, CompressionLevel) -> Encoder;
+
+/// Encoder that is handed the body data through a [`Feed`].
+///
+/// Unlike the encoders used by [`WrapBody`], these don't own the body. That allows handing them
+/// a body whose first frames have already been polled.
+///
+/// [`WrapBody`]: crate::compression_utils::WrapBody
+pub(crate) enum Encoder {
+    #[cfg(feature = "compression-gzip")]
+    Gzip(GzipEncoder<Feed>),
+    #[cfg(feature = "compression-deflate")]
+    Deflate(ZlibEncoder<Feed>),
+    #[cfg(feature = "compression-br")]
+    // Much larger than the other encoders.
+    Brotli(Box<BrotliEncoder<Feed>>),
+    #[cfg(feature = "compression-zstd")]
+    Zstd(ZstdEncoder<Feed>),
+}
+
+impl Encoder {
+    /// Returns how to create an encoder for `encoding`, or `None` if it isn't supported.
+    pub(crate) fn for_encoding(encoding: Encoding) -> Option<NewEncoder> {
+        match encoding {
+            #[cfg(feature = "compression-gzip")]
+            Encoding::Gzip => Some(|feed, level| Encoder::Gzip(gzip_encoder(feed, level))),
+            #[cfg(feature = "compression-deflate")]
+            Encoding::Deflate => Some(|feed, level| Encoder::Deflate(deflate_encoder(feed, level))),
+            #[cfg(feature = "compression-br")]
+            Encoding::Brotli => {
+                Some(|feed, level| Encoder::Brotli(Box::new(brotli_encoder(feed, level))))
+            }
+            #[cfg(feature = "compression-zstd")]
+            Encoding::Zstd => Some(|feed, level| Encoder::Zstd(zstd_encoder(feed, level))),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+
+    fn feed(&mut self) -> &mut Feed {
+        match self {
+            #[cfg(feature = "compression-gzip")]
+            Encoder::Gzip(encoder) => encoder.get_mut(),
+            #[cfg(feature = "compression-deflate")]
+            Encoder::Deflate(encoder) => encoder.get_mut(),
+            #[cfg(feature = "compression-br")]
+            Encoder::Brotli(encoder) => encoder.get_mut(),
+            #[cfg(feature = "compression-zstd")]
+            Encoder::Zstd(encoder) => encoder.get_mut(),
+        }
+    }
+
+    fn poll_read_buf(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut BytesMut,
+    ) -> Poll<io::Result<usize>> {
+        match self {
+            #[cfg(feature = "compression-gzip")]
+            Encoder::Gzip(encoder) => tokio_util::io::poll_read_buf(Pin::new(encoder), cx, buf),
+            #[cfg(feature = "compression-deflate")]
+            Encoder::Deflate(encoder) => tokio_util::io::poll_read_buf(Pin::new(encoder), cx, buf),
+            #[cfg(feature = "compression-br")]
+            Encoder::Brotli(encoder) => tokio_util::io::poll_read_buf(Pin::new(encoder), cx, buf),
+            #[cfg(feature = "compression-zstd")]
+            Encoder::Zstd(encoder) => tokio_util::io::poll_read_buf(Pin::new(encoder), cx, buf),
+        }
+    }
+}
+
+/// Result of polling a body for a frame.
+type PolledFrame<B> = Option<Result<Frame<<B as Body>::Data>, <B as Body>::Error>>;
+
+/// Length of the longest of the [`MAGIC_NUMBERS`].
+const MAGIC_NUMBER_LEN: usize = 4;
+
+/// Body whose first frames have already been polled, so they can be inspected before the
+/// response headers are sent.
+///
+/// The body has been polled, so it is boxed to keep it pinned.
+pub(crate) struct PeekedBody<B: Body> {
+    body: Pin<Box<B>>,
+    // The polled frames, ending with `None` or an error if the body ended while peeking.
+    peeked: VecDeque<PolledFrame<B>>,
+    // The start of the data, up to `MAGIC_NUMBER_LEN` bytes.
+    prefix: Vec<u8>,
+}
+
+impl<B: Body> PeekedBody<B> {
+    pub(crate) fn new(body: B) -> Self {
+        Self {
+            body: Box::pin(body),
+            peeked: VecDeque::new(),
+            prefix: Vec::with_capacity(MAGIC_NUMBER_LEN),
+        }
+    }
+
+    /// Polls frames until the longest magic number fits in the data polled so far, or the data
+    /// ends.
+    pub(crate) fn poll_peek(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        while self.prefix.len() < MAGIC_NUMBER_LEN {
+            let frame = ready!(self.body.as_mut().poll_frame(cx));
+            let data_ended = match &frame {
+                Some(Ok(frame)) => match frame.data_ref() {
+                    Some(data) => {
+                        let chunk = data.chunk();
+                        let len = chunk.len().min(MAGIC_NUMBER_LEN - self.prefix.len());
+                        self.prefix.extend_from_slice(&chunk[..len]);
+                        false
+                    }
+                    // Trailers come after the data.
+                    None => true,
+                },
+                Some(Err(_)) | None => true,
+            };
+            self.peeked.push_back(frame);
+            if data_ended {
+                break;
+            }
+        }
+        Poll::Ready(())
+    }
+
+    /// Does the data polled so far look like the start of compressed data?
+    pub(crate) fn is_compressed(&self) -> bool {
+        is_compressed(&self.prefix)
+    }
+
+    pub(crate) fn get_ref(&self) -> &B {
+        &self.body
+    }
+
+    pub(crate) fn get_pin_mut(&mut self) -> Pin<&mut B> {
+        self.body.as_mut()
+    }
+}
+
+// The body is boxed and the peeked frames are never pinned.
+impl<B: Body> Unpin for PeekedBody<B> {}
+
+impl<B: Body> Body for PeekedBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match self.peeked.pop_front() {
+            Some(frame) => Poll::Ready(frame),
+            None => self.body.as_mut().poll_frame(cx),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match self.peeked.front() {
+            Some(None) => true,
+            Some(Some(_)) => false,
+            None => self.body.is_end_stream(),
+        }
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        let peeked = self
+            .peeked
+            .iter()
+            .map(|frame| match frame {
+                Some(Ok(frame)) => frame.data_ref().map_or(0, |data| data.remaining() as u64),
+                _ => 0,
+            })
+            .sum::<u64>();
+        if let Some(None) = self.peeked.back() {
+            return http_body::SizeHint::with_exact(peeked);
+        }
+
+        let hint = self.body.size_hint();
+        let mut size = http_body::SizeHint::new();
+        size.set_lower(hint.lower() + peeked);
+        if let Some(upper) = hint.upper() {
+            size.set_upper(upper + peeked);
+        }
+        size
+    }
+}
+
+/// Body that is compressed after its first frames have been inspected.
+pub(crate) struct SniffBody<B: Body> {
+    body: PeekedBody<B>,
+    encoder: Encoder,
+    trailers: Option<HeaderMap>,
+    bytes_read: u64,
+    done: bool,
+}
+
+impl<B: Body> SniffBody<B> {
+    pub(crate) fn new(
+        body: PeekedBody<B>,
+        new_encoder: NewEncoder,
+        quality: CompressionLevel,
+    ) -> Self {
+        Self {
+            body,
+            encoder: new_encoder(Feed::default(), quality),
+            trailers: None,
+            bytes_read: 0,
+            done: false,
+        }
+    }
+
+    pub(crate) fn get_ref(&self) -> &B {
+        self.body.get_ref()
+    }
+
+    pub(crate) fn get_pin_mut(&mut self) -> Pin<&mut B> {
+        self.body.get_pin_mut()
+    }
+
+    /// Number of uncompressed bytes read from the underlying body.
+    pub(crate) fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+}
+
+impl<B> Body for SniffBody<B>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.done {
+                return Poll::Ready(
+                    this.trailers
+                        .take()
+                        .map(|trailers| Ok(Frame::trailers(trailers))),
+                );
+            }
+
+            let mut buf = BytesMut::with_capacity(4096);
+            match this.encoder.poll_read_buf(cx, &mut buf) {
+                Poll::Ready(Ok(0)) => this.done = true,
+                Poll::Ready(Ok(_)) => return Poll::Ready(Some(Ok(Frame::data(buf.freeze())))),
+                Poll::Ready(Err(err)) => return Poll::Ready(Some(Err(err.into()))),
+                // The feed ran dry, refill it from the body.
+                Poll::Pending => match ready!(Pin::new(&mut this.body).poll_frame(cx)) {
+                    Some(Ok(frame)) => match frame.into_data() {
+                        Ok(mut data) => {
+                            let data = data.copy_to_bytes(data.remaining());
+                            this.bytes_read += data.len() as u64;
+                            this.encoder.feed().buf = data;
+                        }
+                        Err(frame) => {
+                            this.trailers = frame.into_trailers().ok();
+                            this.encoder.feed().eof = true;
+                        }
+                    },
+                    Some(Err(err)) => return Poll::Ready(Some(Err(err.into()))),
+                    None => this.encoder.feed().eof = true,
+                },
+            }
+        }
+    }
+}
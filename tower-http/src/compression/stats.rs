@@ -0,0 +1,116 @@
+use std::time::{Duration, Instant};
+
+/// What [`Compression`] did with a response.
+///
+/// Passed to the callback set with [`Compression::on_compression`].
+///
+/// [`Compression`]: super::Compression
+/// [`Compression::on_compression`]: super::Compression::on_compression
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum CompressionOutcome {
+    /// The response body was compressed and has been read to the end.
+    Compressed(CompressionStats),
+    /// The response was passed through uncompressed.
+    Skipped(SkipReason),
+}
+
+/// Statistics about a compressed response body.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct CompressionStats {
+    /// The encoding used, as it appears in the `Content-Encoding` header.
+    pub encoding: &'static str,
+    /// Number of bytes read from the uncompressed body.
+    pub input_bytes: u64,
+    /// Number of compressed bytes produced.
+    pub output_bytes: u64,
+    /// Time spent polling the encoder.
+    ///
+    /// This includes the time spent polling the uncompressed body, as the encoder polls it for
+    /// data.
+    pub duration: Duration,
+}
+
+/// Why a response wasn't compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SkipReason {
+    /// The response already has a `Content-Encoding`.
+    AlreadyEncoded,
+    /// The response is a range, as indicated by `Content-Range`.
+    Range,
+    /// The [`Predicate`] rejected the response, for example because of its `Content-Type` or
+    /// size.
+    ///
+    /// [`Predicate`]: super::Predicate
+    Predicate,
+    /// The client didn't accept any of the enabled encodings.
+    NotAccepted,
+}
+
+/// Trait used to tell [`Compression`] what to do with the [`CompressionOutcome`] of a response.
+///
+/// It is implemented for closures taking a `&CompressionOutcome`, and for `()`, which does
+/// nothing.
+///
+/// See [`Compression::on_compression`] for details.
+///
+/// [`Compression`]: super::Compression
+/// [`Compression::on_compression`]: super::Compression::on_compression
+pub trait OnCompression {
+    /// Do the thing.
+    fn on_compression(self, outcome: &CompressionOutcome);
+}
+
+impl OnCompression for () {
+    #[inline]
+    fn on_compression(self, _: &CompressionOutcome) {}
+}
+
+impl<F> OnCompression for F
+where
+    F: FnOnce(&CompressionOutcome),
+{
+    fn on_compression(self, outcome: &CompressionOutcome) {
+        self(outcome)
+    }
+}
+
+/// Accumulates [`CompressionStats`] while a compressed body is read.
+pub(crate) struct Recorder<C> {
+    callback: C,
+    encoding: &'static str,
+    output_bytes: u64,
+    duration: Duration,
+}
+
+impl<C> Recorder<C>
+where
+    C: OnCompression,
+{
+    pub(crate) fn new(callback: C, encoding: &'static str) -> Self {
+        Self {
+            callback,
+            encoding,
+            output_bytes: 0,
+            duration: Duration::ZERO,
+        }
+    }
+
+    pub(crate) fn record(&mut self, start: Instant, output_bytes: usize) {
+        self.duration += start.elapsed();
+        self.output_bytes += output_bytes as u64;
+    }
+
+    pub(crate) fn finish(self, input_bytes: u64) {
+        let stats = CompressionStats {
+            encoding: self.encoding,
+            input_bytes,
+            output_bytes: self.output_bytes,
+            duration: self.duration,
+        };
+        self.callback
+            .on_compression(&CompressionOutcome::Compressed(stats));
+    }
+}
@@ -11,7 +11,7 @@ use std::{
     pin::Pin,
     task::{ready, Context, Poll},
 };
-use tokio::io::AsyncRead;
+use tokio::io::{AsyncBufRead, AsyncRead, ReadBuf};
 use tokio_util::io::StreamReader;
 
 #[derive(Debug, Clone, Copy)]
@@ -265,6 +265,48 @@ where
     }
 }
 
+/// An `AsyncBufRead` that is handed data by its owner instead of reading it itself.
+///
+/// Used to run an encoder or decoder over data that is polled from elsewhere, such as the output
+/// of another decoder.
+///
+/// Returns `Pending` without registering a waker when it runs dry. The caller then fetches more
+/// data, which registers the waker if it has to wait itself.
+#[derive(Default)]
+pub(crate) struct Feed {
+    pub(crate) buf: Bytes,
+    pub(crate) eof: bool,
+}
+
+impl AsyncRead for Feed {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let data = std::task::ready!(self.as_mut().poll_fill_buf(cx))?;
+        let len = data.len().min(buf.remaining());
+        buf.put_slice(&data[..len]);
+        self.consume(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncBufRead for Feed {
+    fn poll_fill_buf(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        if this.buf.is_empty() && !this.eof {
+            Poll::Pending
+        } else {
+            Poll::Ready(Ok(&this.buf[..]))
+        }
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().buf.advance(amt);
+    }
+}
+
 pin_project! {
     pub(crate) struct BodyIntoStream<B>
     where
@@ -382,7 +424,8 @@ pin_project! {
         #[pin]
         inner: S,
         error: Option<E>,
-        read_some_data: bool
+        read_some_data: bool,
+        bytes_read: u64,
     }
 }
 
@@ -392,9 +435,16 @@ impl<S, E> StreamErrorIntoIoError<S, E> {
             inner,
             error: None,
             read_some_data: false,
+            bytes_read: 0,
         }
     }
 
+    /// Number of bytes yielded by the inner stream so far
+    #[allow(dead_code)]
+    pub(crate) fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
     /// Get a reference to the inner body
     pub(crate) fn get_ref(&self) -> &S {
         &self.inner
@@ -419,6 +469,7 @@ impl<S, E> StreamErrorIntoIoError<S, E> {
 impl<S, T, E> Stream for StreamErrorIntoIoError<S, E>
 where
     S: Stream<Item = Result<T, E>>,
+    T: Buf,
 {
     type Item = Result<T, io::Error>;
 
@@ -428,6 +479,7 @@ where
             None => Poll::Ready(None),
             Some(Ok(value)) => {
                 *this.read_some_data = true;
+                *this.bytes_read += value.remaining() as u64;
                 Poll::Ready(Some(Ok(value)))
             }
             Some(Err(err)) => {
@@ -0,0 +1,748 @@
+//! Middleware that limits the number of in-flight requests per tenant, client or route.
+//!
+//! Unlike tower's [`ConcurrencyLimit`], which shares one limit between all requests and waits in
+//! `poll_ready` until a slot frees up, [`ConcurrencyLimitLayer`] keeps a limit per key and
+//! rejects requests over it right away, with `503 Service Unavailable` by default. The key is
+//! computed from the request by a closure, and requests without a key aren't limited.
+//!
+//! Like [`InFlightRequests`], a request counts until its response body has been sent or
+//! dropped, so slow streaming responses hold their slot. Optionally, requests over the limit can
+//! wait in a bounded queue for a slot, for up to a timeout.
+//!
+//! Keys without in-flight or waiting requests are forgotten, so memory stays bounded by the
+//! number of keys with in-flight requests.
+//!
+//! # Example
+//!
+//! ```
+//! use http::{Request, Response, StatusCode, request::Parts};
+//! use http_body_util::Full;
+//! use bytes::Bytes;
+//! use tower::{Service, ServiceExt, ServiceBuilder, BoxError};
+//! use tower_http::concurrency_limit::ConcurrencyLimitLayer;
+//!
+//! async fn handle(request: Request<Full<Bytes>>) -> Result<Response<Full<Bytes>>, BoxError> {
+//!     Ok(Response::new(Full::from("report")))
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), BoxError> {
+//! let mut service = ServiceBuilder::new()
+//!     // At most 1 in-flight request per tenant.
+//!     .layer(
+//!         ConcurrencyLimitLayer::new(1, |parts: &Parts| {
+//!             parts.headers.get("x-tenant").map(|tenant| tenant.as_bytes().to_vec())
+//!         })
+//!         .rejection(StatusCode::TOO_MANY_REQUESTS),
+//!     )
+//!     .service_fn(handle);
+//!
+//! let request = || {
+//!     Request::builder()
+//!         .header("x-tenant", "acme")
+//!         .body(Full::default())
+//!         .unwrap()
+//! };
+//!
+//! // The response body hasn't been sent yet, so the request is still in flight.
+//! let response = service.ready().await?.call(request()).await?;
+//! assert_eq!(StatusCode::OK, response.status());
+//!
+//! let rejected = service.ready().await?.call(request()).await?;
+//! assert_eq!(StatusCode::TOO_MANY_REQUESTS, rejected.status());
+//!
+//! drop(response);
+//! let response = service.ready().await?.call(request()).await?;
+//! assert_eq!(StatusCode::OK, response.status());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`ConcurrencyLimit`]: https://docs.rs/tower/latest/tower/limit/concurrency/struct.ConcurrencyLimit.html
+//! [`InFlightRequests`]: crate::metrics::InFlightRequests
+
+use crate::helpers::client_ip;
+use http::{request::Parts, Request, Response, StatusCode};
+use http_body::Body;
+use pin_project_lite::pin_project;
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    hash::Hash,
+    mem,
+    net::IpAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tower_layer::Layer;
+use tower_service::Service;
+
+define_rejection! {
+    /// The response to requests over the limit.
+    ///
+    /// See [`ConcurrencyLimitLayer::rejection`] for more details.
+    pub struct ConcurrencyRejection;
+    default = StatusCode::SERVICE_UNAVAILABLE;
+    /// Build the response with `f`.
+    custom();
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Queue {
+    max_waiting: usize,
+    timeout: Duration,
+}
+
+struct Entry {
+    semaphore: Arc<Semaphore>,
+    waiting: usize,
+}
+
+/// The limits shared by all services created from the same layer.
+struct Shared<K> {
+    max: usize,
+    keys: Mutex<HashMap<K, Entry>>,
+}
+
+impl<K: Hash + Eq> Shared<K> {
+    /// Forget `key` if nothing holds or waits for one of its permits.
+    fn release(keys: &mut HashMap<K, Entry>, key: &K) {
+        let idle = keys.get(key).map_or(false, |entry| {
+            entry.waiting == 0 && Arc::strong_count(&entry.semaphore) == 1
+        });
+        if idle {
+            keys.remove(key);
+        }
+    }
+}
+
+/// Holds a slot of a key until dropped.
+struct PermitGuard<K: Hash + Eq> {
+    permit: Option<OwnedSemaphorePermit>,
+    key: K,
+    shared: Arc<Shared<K>>,
+}
+
+impl<K: Hash + Eq> Drop for PermitGuard<K> {
+    fn drop(&mut self) {
+        let mut keys = self.shared.keys.lock().unwrap();
+        drop(self.permit.take());
+        Shared::release(&mut keys, &self.key);
+    }
+}
+
+/// Counts a request waiting for a slot of a key until dropped.
+struct WaitGuard<K: Hash + Eq> {
+    key: Option<K>,
+    shared: Arc<Shared<K>>,
+}
+
+impl<K: Hash + Eq> WaitGuard<K> {
+    fn into_key(mut self) -> K {
+        self.key.take().expect("key taken twice")
+    }
+}
+
+impl<K: Hash + Eq> Drop for WaitGuard<K> {
+    fn drop(&mut self) {
+        let Some(key) = &self.key else {
+            // Dropped after `into_key`, where `wait` already stopped waiting.
+            return;
+        };
+        let mut keys = self.shared.keys.lock().unwrap();
+        if let Some(entry) = keys.get_mut(key) {
+            entry.waiting -= 1;
+        }
+        Shared::release(&mut keys, key);
+    }
+}
+
+/// A type-erased [`PermitGuard`].
+struct Permit(#[allow(dead_code)] Box<dyn Send + Sync>);
+
+type KeyFn<K> = dyn Fn(&Parts) -> Option<K> + Send + Sync + 'static;
+
+/// Layer that applies the [`ConcurrencyLimit`] middleware.
+///
+/// Services created from the same layer share the limits, and so do clones of the layer.
+///
+/// See the [module docs](crate::concurrency_limit) for more details.
+#[must_use]
+pub struct ConcurrencyLimitLayer<K> {
+    key: Arc<KeyFn<K>>,
+    shared: Arc<Shared<K>>,
+    queue: Option<Queue>,
+    rejection: ConcurrencyRejection,
+}
+
+impl ConcurrencyLimitLayer<IpAddr> {
+    /// Allow at most `max` in-flight requests per client IP address.
+    ///
+    /// The address is taken from the [`ClientIp`] extension inserted by [`SetClientIpLayer`],
+    /// or a [`SocketAddr`] or [`IpAddr`] extension. Requests from an unknown address aren't
+    /// limited.
+    ///
+    /// [`ClientIp`]: crate::client_ip::ClientIp
+    /// [`SetClientIpLayer`]: crate::client_ip::SetClientIpLayer
+    /// [`SocketAddr`]: std::net::SocketAddr
+    pub fn per_ip(max: usize) -> Self {
+        Self::new(max, |parts: &Parts| client_ip(&parts.extensions))
+    }
+}
+
+impl<K> ConcurrencyLimitLayer<K> {
+    /// Allow at most `max` in-flight requests per key computed by `key`.
+    ///
+    /// Requests for which `key` returns `None` aren't limited.
+    pub fn new<F>(max: usize, key: F) -> Self
+    where
+        F: Fn(&Parts) -> Option<K> + Send + Sync + 'static,
+    {
+        Self {
+            key: Arc::new(key),
+            shared: Arc::new(Shared {
+                max,
+                keys: Mutex::new(HashMap::new()),
+            }),
+            queue: None,
+            rejection: ConcurrencyRejection::default(),
+        }
+    }
+
+    /// Let up to `max_waiting` requests per key over the limit wait for a slot, for at most
+    /// `timeout`.
+    ///
+    /// Requests that don't fit in the queue, or time out waiting, are rejected.
+    pub fn queue(mut self, max_waiting: usize, timeout: Duration) -> Self {
+        self.queue = Some(Queue {
+            max_waiting,
+            timeout,
+        });
+        self
+    }
+
+    /// Set the response to requests over the limit.
+    ///
+    /// Defaults to `503 Service Unavailable` with an empty body.
+    ///
+    /// ```
+    /// use http::{header::RETRY_AFTER, Response, StatusCode};
+    /// use tower_http::concurrency_limit::{ConcurrencyLimitLayer, ConcurrencyRejection};
+    ///
+    /// let layer = ConcurrencyLimitLayer::per_ip(4).rejection(ConcurrencyRejection::custom(|| {
+    ///     Response::builder()
+    ///         .status(StatusCode::TOO_MANY_REQUESTS)
+    ///         .header(RETRY_AFTER, "1")
+    ///         .body(())
+    ///         .unwrap()
+    /// }));
+    /// ```
+    pub fn rejection<T>(mut self, rejection: T) -> Self
+    where
+        T: Into<ConcurrencyRejection>,
+    {
+        self.rejection = rejection.into();
+        self
+    }
+}
+
+impl<K> Clone for ConcurrencyLimitLayer<K> {
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key.clone(),
+            shared: self.shared.clone(),
+            queue: self.queue,
+            rejection: self.rejection.clone(),
+        }
+    }
+}
+
+impl<K> fmt::Debug for ConcurrencyLimitLayer<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConcurrencyLimitLayer")
+            .field("max", &self.shared.max)
+            .field("queue", &self.queue)
+            .field("rejection", &self.rejection)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, K> Layer<S> for ConcurrencyLimitLayer<K> {
+    type Service = ConcurrencyLimit<S, K>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ConcurrencyLimit {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Middleware that limits the number of in-flight requests per key.
+///
+/// See the [module docs](crate::concurrency_limit) for more details.
+#[must_use]
+pub struct ConcurrencyLimit<S, K> {
+    inner: S,
+    layer: ConcurrencyLimitLayer<K>,
+}
+
+impl<S, K> ConcurrencyLimit<S, K> {
+    /// Allow at most `max` in-flight requests per key computed by `key`.
+    pub fn new<F>(inner: S, max: usize, key: F) -> Self
+    where
+        F: Fn(&Parts) -> Option<K> + Send + Sync + 'static,
+    {
+        Self::layer(max, key).layer(inner)
+    }
+
+    define_inner_service_accessors!();
+
+    /// Returns a new [`Layer`] that wraps services with a `ConcurrencyLimit` middleware.
+    ///
+    /// [`Layer`]: tower_layer::Layer
+    pub fn layer<F>(max: usize, key: F) -> ConcurrencyLimitLayer<K>
+    where
+        F: Fn(&Parts) -> Option<K> + Send + Sync + 'static,
+    {
+        ConcurrencyLimitLayer::new(max, key)
+    }
+}
+
+impl<S, K> Clone for ConcurrencyLimit<S, K>
+where
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+impl<S, K> fmt::Debug for ConcurrencyLimit<S, K>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConcurrencyLimit")
+            .field("inner", &self.inner)
+            .field("layer", &self.layer)
+            .finish()
+    }
+}
+
+enum Acquired<K: Hash + Eq> {
+    Permit(Permit),
+    Wait(Arc<Semaphore>, WaitGuard<K>),
+    Rejected,
+}
+
+impl<K> ConcurrencyLimitLayer<K>
+where
+    K: Hash + Eq + Clone + Send + Sync + 'static,
+{
+    fn try_acquire(&self, key: K) -> Acquired<K> {
+        let mut keys = self.shared.keys.lock().unwrap();
+        let entry = keys.entry(key.clone()).or_insert_with(|| Entry {
+            semaphore: Arc::new(Semaphore::new(self.shared.max)),
+            waiting: 0,
+        });
+
+        if let Ok(permit) = entry.semaphore.clone().try_acquire_owned() {
+            return Acquired::Permit(self.permit(permit, key));
+        }
+
+        match self.queue {
+            Some(queue) if entry.waiting < queue.max_waiting => {
+                entry.waiting += 1;
+                let semaphore = entry.semaphore.clone();
+                let guard = WaitGuard {
+                    key: Some(key),
+                    shared: self.shared.clone(),
+                };
+                Acquired::Wait(semaphore, guard)
+            }
+            _ => {
+                Shared::release(&mut keys, &key);
+                Acquired::Rejected
+            }
+        }
+    }
+
+    fn permit(&self, permit: OwnedSemaphorePermit, key: K) -> Permit {
+        Permit(Box::new(PermitGuard {
+            permit: Some(permit),
+            key,
+            shared: self.shared.clone(),
+        }))
+    }
+
+    fn wait(
+        &self,
+        semaphore: Arc<Semaphore>,
+        guard: WaitGuard<K>,
+    ) -> Pin<Box<dyn Future<Output = Option<Permit>> + Send>> {
+        let timeout = self.queue.map_or(Duration::ZERO, |queue| queue.timeout);
+        let layer = self.clone();
+        Box::pin(async move {
+            let acquired = tokio::time::timeout(timeout, semaphore.acquire_owned()).await;
+
+            let shared = guard.shared.clone();
+            let key = guard.into_key();
+            let mut keys = shared.keys.lock().unwrap();
+            if let Some(entry) = keys.get_mut(&key) {
+                entry.waiting -= 1;
+            }
+
+            match acquired {
+                Ok(Ok(permit)) => {
+                    drop(keys);
+                    Some(layer.permit(permit, key))
+                }
+                _ => {
+                    Shared::release(&mut keys, &key);
+                    None
+                }
+            }
+        })
+    }
+}
+
+impl<S, K, ReqBody, ResBody> Service<Request<ReqBody>> for ConcurrencyLimit<S, K>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone,
+    K: Hash + Eq + Clone + Send + Sync + 'static,
+    ResBody: Default,
+{
+    type Response = Response<ResponseBody<ResBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S, ReqBody>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let (parts, body) = req.into_parts();
+        let key = (self.layer.key)(&parts);
+        let req = Request::from_parts(parts, body);
+
+        let acquired = match key {
+            Some(key) => self.layer.try_acquire(key),
+            None => {
+                return ResponseFuture::called(self.inner.call(req), None);
+            }
+        };
+
+        match acquired {
+            Acquired::Permit(permit) => ResponseFuture::called(self.inner.call(req), Some(permit)),
+            Acquired::Wait(semaphore, guard) => {
+                let mut inner = self.inner.clone();
+                // mem::swap due to https://docs.rs/tower/latest/tower/trait.Service.html#be-careful-when-cloning-inner-services
+                mem::swap(&mut self.inner, &mut inner);
+                ResponseFuture {
+                    state: State::Waiting {
+                        acquire: self.layer.wait(semaphore, guard),
+                    },
+                    pending: Some((inner, req)),
+                    rejection: self.layer.rejection.clone(),
+                }
+            }
+            Acquired::Rejected => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(uri = %req.uri().path(), "request rejected by concurrency limit");
+
+                ResponseFuture {
+                    state: State::Rejected,
+                    pending: None,
+                    rejection: self.layer.rejection.clone(),
+                }
+            }
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`ConcurrencyLimit`].
+    pub struct ResponseFuture<S, ReqBody>
+    where
+        S: Service<Request<ReqBody>>,
+    {
+        #[pin]
+        state: State<S::Future>,
+        pending: Option<(S, Request<ReqBody>)>,
+        rejection: ConcurrencyRejection,
+    }
+}
+
+pin_project! {
+    #[project = StateProj]
+    enum State<F> {
+        Waiting {
+            acquire: Pin<Box<dyn Future<Output = Option<Permit>> + Send>>,
+        },
+        Called {
+            #[pin]
+            future: F,
+            permit: Option<Permit>,
+        },
+        Rejected,
+    }
+}
+
+impl<S, ReqBody> ResponseFuture<S, ReqBody>
+where
+    S: Service<Request<ReqBody>>,
+{
+    fn called(future: S::Future, permit: Option<Permit>) -> Self {
+        Self {
+            state: State::Called { future, permit },
+            pending: None,
+            rejection: ConcurrencyRejection::default(),
+        }
+    }
+}
+
+impl<S, ReqBody, B> Future for ResponseFuture<S, ReqBody>
+where
+    S: Service<Request<ReqBody>, Response = Response<B>>,
+    B: Default,
+{
+    type Output = Result<Response<ResponseBody<B>>, S::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        loop {
+            match this.state.as_mut().project() {
+                StateProj::Waiting { acquire } => match ready!(acquire.as_mut().poll(cx)) {
+                    Some(permit) => {
+                        let (mut inner, req) =
+                            this.pending.take().expect("future polled after completion");
+                        this.state.set(State::Called {
+                            future: inner.call(req),
+                            permit: Some(permit),
+                        });
+                    }
+                    None => {
+                        #[cfg(feature = "tracing")]
+                        tracing::debug!("request timed out waiting for concurrency limit");
+
+                        this.pending.take();
+                        this.state.set(State::Rejected);
+                    }
+                },
+                StateProj::Called { future, permit } => {
+                    let response = ready!(future.poll(cx))?;
+                    let permit = permit.take();
+                    return Poll::Ready(Ok(response.map(|body| ResponseBody {
+                        inner: body,
+                        permit,
+                    })));
+                }
+                StateProj::Rejected => {
+                    let (parts, ()) = this.rejection.to_response().into_parts();
+                    return Poll::Ready(Ok(Response::from_parts(
+                        parts,
+                        ResponseBody {
+                            inner: B::default(),
+                            permit: None,
+                        },
+                    )));
+                }
+            }
+        }
+    }
+}
+
+impl<S, ReqBody> fmt::Debug for ResponseFuture<S, ReqBody>
+where
+    S: Service<Request<ReqBody>>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseFuture").finish_non_exhaustive()
+    }
+}
+
+pin_project! {
+    /// Response body for [`ConcurrencyLimit`].
+    ///
+    /// Holds the slot of the request until the body has ended or is dropped.
+    pub struct ResponseBody<B> {
+        #[pin]
+        inner: B,
+        permit: Option<Permit>,
+    }
+}
+
+impl<B> Body for ResponseBody<B>
+where
+    B: Body,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<http_body::Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let frame = ready!(this.inner.poll_frame(cx));
+        if frame.is_none() {
+            this.permit.take();
+        }
+        Poll::Ready(frame)
+    }
+
+    #[inline]
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl<B> fmt::Debug for ResponseBody<B>
+where
+    B: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseBody")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::Body;
+    use tower::{BoxError, ServiceBuilder, ServiceExt};
+
+    async fn echo(_: Request<Body>) -> Result<Response<Body>, BoxError> {
+        Ok(Response::new(Body::from("hello")))
+    }
+
+    fn request(tenant: &str) -> Request<Body> {
+        Request::builder()
+            .header("x-tenant", tenant)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn by_tenant(parts: &Parts) -> Option<String> {
+        parts
+            .headers
+            .get("x-tenant")
+            .map(|tenant| tenant.to_str().unwrap().to_owned())
+    }
+
+    fn keys<K>(layer: &ConcurrencyLimitLayer<K>) -> usize {
+        layer.shared.keys.lock().unwrap().len()
+    }
+
+    #[tokio::test]
+    async fn limits_per_key() {
+        let layer = ConcurrencyLimitLayer::new(2, by_tenant);
+        let svc = ServiceBuilder::new().layer(layer.clone()).service_fn(echo);
+
+        let first = svc.clone().oneshot(request("a")).await.unwrap();
+        let second = svc.clone().oneshot(request("a")).await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(second.status(), StatusCode::OK);
+
+        let rejected = svc.clone().oneshot(request("a")).await.unwrap();
+        assert_eq!(rejected.status(), StatusCode::SERVICE_UNAVAILABLE);
+        // Other keys have their own limit.
+        let other = svc.clone().oneshot(request("b")).await.unwrap();
+        assert_eq!(other.status(), StatusCode::OK);
+
+        // Reading the body to the end frees the slot.
+        let body = crate::test_helpers::to_bytes(first.into_body())
+            .await
+            .unwrap();
+        assert_eq!(body, "hello");
+        let third = svc.clone().oneshot(request("a")).await.unwrap();
+        assert_eq!(third.status(), StatusCode::OK);
+
+        // So does dropping it.
+        drop((second, third, other));
+        assert_eq!(keys(&layer), 0);
+    }
+
+    #[tokio::test]
+    async fn without_key() {
+        let svc = ServiceBuilder::new()
+            .layer(ConcurrencyLimitLayer::new(0, by_tenant))
+            .service_fn(echo);
+
+        let res = svc.clone().oneshot(request("a")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let res = svc.oneshot(Request::new(Body::empty())).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn per_ip_and_rejection() {
+        let svc = ServiceBuilder::new()
+            .layer(ConcurrencyLimitLayer::per_ip(1).rejection(StatusCode::TOO_MANY_REQUESTS))
+            .service_fn(echo);
+        let request = |ip: &str| {
+            let mut request = Request::new(Body::empty());
+            request
+                .extensions_mut()
+                .insert(ip.parse::<IpAddr>().unwrap());
+            request
+        };
+
+        let _in_flight = svc.clone().oneshot(request("10.0.0.1")).await.unwrap();
+        let res = svc.clone().oneshot(request("10.0.0.1")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let res = svc.oneshot(request("10.0.0.2")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn queue() {
+        let layer = ConcurrencyLimitLayer::new(1, by_tenant).queue(1, Duration::from_secs(10));
+        let svc = ServiceBuilder::new().layer(layer.clone()).service_fn(echo);
+
+        let first = svc.clone().oneshot(request("a")).await.unwrap();
+        let waiting = tokio::spawn(svc.clone().oneshot(request("a")));
+        tokio::task::yield_now().await;
+
+        // The queue is full.
+        let rejected = svc.clone().oneshot(request("a")).await.unwrap();
+        assert_eq!(rejected.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        drop(first);
+        let second = waiting.await.unwrap().unwrap();
+        assert_eq!(second.status(), StatusCode::OK);
+
+        drop(second);
+        assert_eq!(keys(&layer), 0);
+    }
+
+    #[tokio::test]
+    async fn queue_timeout() {
+        let layer = ConcurrencyLimitLayer::new(1, by_tenant).queue(1, Duration::from_millis(10));
+        let svc = ServiceBuilder::new().layer(layer.clone()).service_fn(echo);
+
+        let first = svc.clone().oneshot(request("a")).await.unwrap();
+        let res = svc.clone().oneshot(request("a")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        drop(first);
+        assert_eq!(keys(&layer), 0);
+    }
+}
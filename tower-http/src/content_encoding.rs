@@ -22,7 +22,7 @@ pub(crate) enum Encoding {
 
 impl Encoding {
     #[allow(dead_code)]
-    fn to_str(self) -> &'static str {
+    pub(crate) fn to_str(self) -> &'static str {
         match self {
             #[cfg(any(feature = "fs", feature = "compression-gzip"))]
             Encoding::Gzip => "gzip",
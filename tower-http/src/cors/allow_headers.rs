@@ -5,7 +5,7 @@ use http::{
     request::Parts as RequestParts,
 };
 
-use super::{separated_by_commas, Any, WILDCARD};
+use super::{comma_separated, separated_by_commas, Any, WILDCARD};
 
 /// Holds configuration for how to set the [`Access-Control-Allow-Headers`][mdn] header.
 ///
@@ -58,6 +58,24 @@ impl AllowHeaders {
         matches!(&self.0, AllowHeadersInner::Const(Some(v)) if v == WILDCARD)
     }
 
+    /// Whether `name`, from the `Access-Control-Request-Headers` header of a preflight request, is
+    /// allowed.
+    #[allow(clippy::borrow_interior_mutable_const)]
+    pub(super) fn allows(&self, name: &[u8]) -> bool {
+        match &self.0 {
+            AllowHeadersInner::Const(None) => false,
+            // The wildcard doesn't cover `Authorization`:
+            // https://fetch.spec.whatwg.org/#cors-non-wildcard-request-header-name
+            AllowHeadersInner::Const(Some(v)) if v == WILDCARD => {
+                !name.eq_ignore_ascii_case(header::AUTHORIZATION.as_str().as_bytes())
+            }
+            AllowHeadersInner::Const(Some(v)) => {
+                comma_separated(v).any(|allowed| allowed.eq_ignore_ascii_case(name))
+            }
+            AllowHeadersInner::MirrorRequest => true,
+        }
+    }
+
     pub(super) fn varies_with_request_headers(&self) -> bool {
         !matches!(&self.0, AllowHeadersInner::Const(_))
     }
@@ -6,7 +6,7 @@ use http::{
     Method,
 };
 
-use super::{separated_by_commas, Any, WILDCARD};
+use super::{comma_separated, separated_by_commas, Any, WILDCARD};
 
 /// Holds configuration for how to set the [`Access-Control-Allow-Methods`][mdn] header.
 ///
@@ -72,6 +72,19 @@ impl AllowMethods {
         matches!(&self.0, AllowMethodsInner::Const(Some(v)) if v == WILDCARD)
     }
 
+    /// Whether `method`, from the `Access-Control-Request-Method` header of a preflight request,
+    /// is allowed.
+    #[allow(clippy::borrow_interior_mutable_const)]
+    pub(super) fn allows(&self, method: &HeaderValue) -> bool {
+        match &self.0 {
+            AllowMethodsInner::Const(None) => false,
+            AllowMethodsInner::Const(Some(v)) => {
+                v == WILDCARD || comma_separated(v).any(|m| m == method.as_bytes())
+            }
+            AllowMethodsInner::MirrorRequest => true,
+        }
+    }
+
     pub(super) fn varies_with_request_method(&self) -> bool {
         !matches!(&self.0, AllowMethodsInner::Const(_))
     }
@@ -11,7 +11,7 @@ use std::{
     task::{Context, Poll},
 };
 
-use super::{Any, WILDCARD};
+use super::{origin_pattern::OriginPattern, Any, ConfigError, WILDCARD};
 
 /// Holds configuration for how to set the [`Access-Control-Allow-Origin`][mdn] header.
 ///
@@ -67,6 +67,43 @@ impl AllowOrigin {
         Self(OriginInner::List(origins))
     }
 
+    /// Set the allowed origins from patterns
+    ///
+    /// Each pattern is an origin, `scheme://host[:port]`, where
+    ///
+    /// - the scheme is `http` or `https`,
+    /// - the host may start with a `*.` label to match any subdomain, at any depth, of the rest of
+    ///   the host, but not the host itself,
+    /// - the port may be a number, an inclusive range such as `3000-3999`, or `*` for any port.
+    ///   Without a port only the default port of the scheme matches.
+    ///
+    /// ```
+    /// use tower_http::cors::{AllowOrigin, CorsLayer};
+    ///
+    /// let layer = CorsLayer::new().allow_origin(AllowOrigin::patterns([
+    ///     "https://*.example.com",
+    ///     "http://localhost:3000-3999",
+    /// ])?);
+    /// # Ok::<_, tower_http::cors::ConfigError>(())
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Patterns that could never match an `Origin` sent by a browser are rejected with a
+    /// [`ConfigError`], so a typo fails at startup rather than denying every request.
+    pub fn patterns<I, S>(patterns: I) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let patterns = patterns
+            .into_iter()
+            .map(|pattern| OriginPattern::parse(pattern.as_ref()))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self(OriginInner::Patterns(patterns)))
+    }
+
     /// Set the allowed origins from a predicate
     ///
     /// See [`CorsLayer::allow_origin`] for more details.
@@ -127,6 +164,11 @@ impl AllowOrigin {
             OriginInner::List(l) => {
                 AllowOriginFuture::ok(origin.filter(|o| l.contains(o)).map(|o| (name, o.clone())))
             }
+            OriginInner::Patterns(patterns) => AllowOriginFuture::ok(
+                origin
+                    .filter(|origin| patterns.iter().any(|pattern| pattern.matches(origin)))
+                    .map(|o| (name, o.clone())),
+            ),
             OriginInner::Predicate(c) => AllowOriginFuture::ok(
                 origin
                     .filter(|origin| c(origin, parts))
@@ -187,6 +229,7 @@ impl fmt::Debug for AllowOrigin {
         match &self.0 {
             OriginInner::Const(inner) => f.debug_tuple("Const").field(inner).finish(),
             OriginInner::List(inner) => f.debug_tuple("List").field(inner).finish(),
+            OriginInner::Patterns(inner) => f.debug_tuple("Patterns").field(inner).finish(),
             OriginInner::Predicate(_) => f.debug_tuple("Predicate").finish(),
             OriginInner::AsyncPredicate(_) => f.debug_tuple("AsyncPredicate").finish(),
         }
@@ -221,6 +264,7 @@ impl From<Vec<HeaderValue>> for AllowOrigin {
 enum OriginInner {
     Const(HeaderValue),
     List(Vec<HeaderValue>),
+    Patterns(Arc<[OriginPattern]>),
     Predicate(
         Arc<dyn for<'a> Fn(&'a HeaderValue, &'a RequestParts) -> bool + Send + Sync + 'static>,
     ),
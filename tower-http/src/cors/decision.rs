@@ -0,0 +1,214 @@
+use std::fmt;
+
+use http::{
+    header::{self, HeaderName, HeaderValue},
+    request::Parts as RequestParts,
+    HeaderMap,
+};
+
+use super::{comma_separated, CorsLayer, WILDCARD};
+
+/// Why [`Cors`] did or didn't allow a request.
+///
+/// [`Cors`] attaches it to the extensions of every response, so surrounding layers can log or
+/// report why a browser refused to hand a response to a page. With the `tracing` feature enabled
+/// it is also emitted as an event.
+///
+/// Outside of [strict preflight] mode `Cors` doesn't reject requests itself. It only leaves out,
+/// or sends, the headers a browser uses to decide, so a denied decision means the browser will
+/// block the response.
+///
+/// Match on [`kind`] for what was decided; [`origin`] and [`disallowed`] say which origin, method
+/// or header it was about.
+///
+/// [`Cors`]: super::Cors
+/// [`kind`]: CorsDecision::kind
+/// [`origin`]: CorsDecision::origin
+/// [`disallowed`]: CorsDecision::disallowed
+/// [strict preflight]: super::CorsLayer::strict_preflight
+#[derive(Clone, Debug)]
+pub struct CorsDecision {
+    kind: CorsDecisionKind,
+    origin: Option<HeaderValue>,
+    disallowed: Option<HeaderValue>,
+}
+
+impl CorsDecision {
+    /// The category of the decision.
+    pub fn kind(&self) -> CorsDecisionKind {
+        self.kind
+    }
+
+    /// Whether the browser will let the page read the response.
+    pub fn is_allowed(&self) -> bool {
+        matches!(
+            self.kind,
+            CorsDecisionKind::NotCors | CorsDecisionKind::OriginAllowed
+        )
+    }
+
+    /// The `Origin` of the request, if it had one.
+    pub fn origin(&self) -> Option<&HeaderValue> {
+        self.origin.as_ref()
+    }
+
+    /// The requested method or header that isn't allowed.
+    ///
+    /// Only set for [`CorsDecisionKind::MethodNotAllowed`] and
+    /// [`CorsDecisionKind::HeaderNotAllowed`].
+    pub fn disallowed(&self) -> Option<&HeaderValue> {
+        self.disallowed.as_ref()
+    }
+}
+
+impl fmt::Display for CorsDecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            CorsDecisionKind::NotCors => f.write_str("not a CORS request"),
+            CorsDecisionKind::OriginAllowed => f.write_str("origin allowed"),
+            CorsDecisionKind::OriginDenied => f.write_str("origin not allowed"),
+            CorsDecisionKind::MethodNotAllowed => f.write_str("preflight method not allowed"),
+            CorsDecisionKind::HeaderNotAllowed => f.write_str("preflight header not allowed"),
+            CorsDecisionKind::CredentialsConflict => {
+                f.write_str("credentials allowed together with a wildcard")
+            }
+        }
+    }
+}
+
+/// The category of a [`CorsDecision`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CorsDecisionKind {
+    /// The request had no `Origin` header, so it isn't a CORS request.
+    NotCors,
+
+    /// The origin is allowed.
+    OriginAllowed,
+
+    /// The origin isn't allowed, so no matching `Access-Control-Allow-Origin` header was sent.
+    OriginDenied,
+
+    /// The method in the `Access-Control-Request-Method` header of a preflight request isn't
+    /// allowed.
+    MethodNotAllowed,
+
+    /// A header in the `Access-Control-Request-Headers` header of a preflight request isn't
+    /// allowed.
+    HeaderNotAllowed,
+
+    /// `Access-Control-Allow-Credentials: true` was sent together with a wildcard (`*`), which
+    /// browsers refuse for requests with credentials.
+    ///
+    /// Configurations where this always happens are rejected when the middleware is built, but
+    /// it can still happen with [`AllowCredentials::predicate`].
+    ///
+    /// [`AllowCredentials::predicate`]: super::AllowCredentials::predicate
+    CredentialsConflict,
+}
+
+/// The parts of a [`CorsDecision`] known before the allowed origin has been resolved.
+#[derive(Debug)]
+pub(super) struct PendingDecision {
+    origin: Option<HeaderValue>,
+    preflight: Option<(CorsDecisionKind, HeaderValue)>,
+}
+
+impl PendingDecision {
+    pub(super) fn new(layer: &CorsLayer, parts: &RequestParts, is_preflight: bool) -> Self {
+        let origin = parts.headers.get(header::ORIGIN).cloned();
+        let preflight = if is_preflight {
+            check_preflight(layer, &parts.headers)
+        } else {
+            None
+        };
+
+        Self { origin, preflight }
+    }
+
+    /// Decide based on the CORS headers about to be added to the response.
+    pub(super) fn finish(self, headers: &HeaderMap) -> CorsDecision {
+        let kind = match &self.origin {
+            None => CorsDecisionKind::NotCors,
+            Some(origin) if !origin_allowed(origin, headers) => CorsDecisionKind::OriginDenied,
+            Some(_) if credentials_conflict(headers) => CorsDecisionKind::CredentialsConflict,
+            Some(_) => match &self.preflight {
+                Some((kind, _)) => *kind,
+                None => CorsDecisionKind::OriginAllowed,
+            },
+        };
+
+        let disallowed = match (kind, self.preflight) {
+            (
+                CorsDecisionKind::MethodNotAllowed | CorsDecisionKind::HeaderNotAllowed,
+                Some((_, value)),
+            ) => Some(value),
+            _ => None,
+        };
+
+        let decision = CorsDecision {
+            kind,
+            origin: self.origin,
+            disallowed,
+        };
+
+        #[cfg(feature = "tracing")]
+        if decision.is_allowed() {
+            tracing::trace!(origin = ?decision.origin, "CORS: {}", decision);
+        } else {
+            tracing::debug!(
+                origin = ?decision.origin,
+                disallowed = ?decision.disallowed,
+                "CORS: {}",
+                decision
+            );
+        }
+
+        decision
+    }
+}
+
+fn check_preflight(
+    layer: &CorsLayer,
+    headers: &HeaderMap,
+) -> Option<(CorsDecisionKind, HeaderValue)> {
+    if let Some(method) = headers.get(header::ACCESS_CONTROL_REQUEST_METHOD) {
+        // https://fetch.spec.whatwg.org/#cors-safelisted-method
+        let safelisted = matches!(method.as_bytes(), b"GET" | b"HEAD" | b"POST");
+        if !safelisted && !layer.allow_methods.allows(method) {
+            return Some((CorsDecisionKind::MethodNotAllowed, method.clone()));
+        }
+    }
+
+    headers
+        .get_all(header::ACCESS_CONTROL_REQUEST_HEADERS)
+        .iter()
+        .flat_map(comma_separated)
+        .find(|name| !layer.allow_headers.allows(name))
+        .map(|name| {
+            let name = HeaderValue::from_bytes(name).expect("slice of a header value");
+            (CorsDecisionKind::HeaderNotAllowed, name)
+        })
+}
+
+#[allow(clippy::borrow_interior_mutable_const)]
+fn origin_allowed(origin: &HeaderValue, headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        .map_or(false, |allowed| allowed == WILDCARD || allowed == origin)
+}
+
+#[allow(clippy::borrow_interior_mutable_const)]
+fn credentials_conflict(headers: &HeaderMap) -> bool {
+    const WILDCARD_HEADERS: [HeaderName; 4] = [
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        header::ACCESS_CONTROL_ALLOW_HEADERS,
+        header::ACCESS_CONTROL_ALLOW_METHODS,
+        header::ACCESS_CONTROL_EXPOSE_HEADERS,
+    ];
+
+    headers.contains_key(header::ACCESS_CONTROL_ALLOW_CREDENTIALS)
+        && WILDCARD_HEADERS
+            .iter()
+            .any(|name| headers.get(name) == Some(&WILDCARD))
+}
@@ -45,15 +45,29 @@
 //! # }
 //! ```
 //!
+//! # Different rules for different routes
+//!
+//! Use [`SelectCorsLayer`] to pick a configuration per request, by path prefix, host or a
+//! predicate over the request.
+//!
+//! # Debugging
+//!
+//! Browsers don't tell the page why they blocked a response. [`Cors`] attaches a [`CorsDecision`]
+//! to the extensions of every response, recording whether the origin was allowed and, if not, why
+//! the browser will refuse it. With the `tracing` feature enabled the decision is also emitted as
+//! an event.
+//!
 //! [mdn]: https://developer.mozilla.org/en-US/docs/Web/HTTP/CORS
 
 #![allow(clippy::enum_variant_names)]
 
 use allow_origin::AllowOriginFuture;
 use bytes::{BufMut, BytesMut};
+use decision::PendingDecision;
 use http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, Response};
 use pin_project_lite::pin_project;
 use std::{
+    fmt,
     future::Future,
     mem,
     pin::Pin,
@@ -67,17 +81,29 @@ mod allow_headers;
 mod allow_methods;
 mod allow_origin;
 mod allow_private_network;
+mod decision;
 mod expose_headers;
 mod max_age;
+mod origin_pattern;
+mod preflight_rejection;
+mod select;
 mod vary;
 
 #[cfg(test)]
 mod tests;
 
 pub use self::{
-    allow_credentials::AllowCredentials, allow_headers::AllowHeaders, allow_methods::AllowMethods,
-    allow_origin::AllowOrigin, allow_private_network::AllowPrivateNetwork,
-    expose_headers::ExposeHeaders, max_age::MaxAge, vary::Vary,
+    allow_credentials::AllowCredentials,
+    allow_headers::AllowHeaders,
+    allow_methods::AllowMethods,
+    allow_origin::AllowOrigin,
+    allow_private_network::AllowPrivateNetwork,
+    decision::{CorsDecision, CorsDecisionKind},
+    expose_headers::ExposeHeaders,
+    max_age::MaxAge,
+    preflight_rejection::PreflightRejection,
+    select::{SelectCors, SelectCorsLayer},
+    vary::Vary,
 };
 
 /// Layer that applies the [`Cors`] middleware which adds headers for [CORS][mdn].
@@ -97,8 +123,88 @@ pub struct CorsLayer {
     max_age: MaxAge,
     vary: Vary,
     is_vary_custom: bool,
+    strict_preflight: bool,
+    preflight_rejection: PreflightRejection,
+    forward_non_cors_options: bool,
+}
+
+/// Errors that can occur while configuring [`CorsLayer`].
+///
+/// Returned by [`AllowOrigin::patterns`] for patterns that could never match an `Origin` sent by a
+/// browser.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum ConfigError {
+    /// A pattern with a scheme other than `http` or `https`, or without a scheme at all.
+    OpaqueOrigin {
+        /// The offending pattern.
+        pattern: String,
+    },
+
+    /// A pattern containing a path, query, fragment or user info.
+    InvalidOriginUrlComponents {
+        /// The offending pattern.
+        pattern: String,
+    },
+
+    /// A pattern containing non-ASCII characters. Browsers send IDN hostnames in punycode form,
+    /// so the pattern must use it too.
+    NonAsciiHostname {
+        /// The offending pattern.
+        pattern: String,
+    },
+
+    /// A pattern whose host is empty or contains characters not allowed in a hostname.
+    InvalidHost {
+        /// The offending pattern.
+        pattern: String,
+    },
+
+    /// A pattern with a wildcard anywhere but as the leading label of the host (`*.example.com`).
+    InvalidWildcard {
+        /// The offending pattern.
+        pattern: String,
+    },
+
+    /// A pattern with a port that isn't a number, a range such as `3000-3999`, or `*`.
+    InvalidPort {
+        /// The offending pattern.
+        pattern: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::OpaqueOrigin { pattern } => write!(
+                f,
+                "invalid origin pattern {pattern:?}: scheme must be http or https"
+            ),
+            ConfigError::InvalidOriginUrlComponents { pattern } => write!(
+                f,
+                "invalid origin pattern {pattern:?}: path, query, fragment and user info are not allowed"
+            ),
+            ConfigError::NonAsciiHostname { pattern } => write!(
+                f,
+                "invalid origin pattern {pattern:?}: non-ASCII hostnames must be supplied in punycode (xn--…)"
+            ),
+            ConfigError::InvalidHost { pattern } => {
+                write!(f, "invalid origin pattern {pattern:?}: invalid host")
+            }
+            ConfigError::InvalidWildcard { pattern } => write!(
+                f,
+                "invalid origin pattern {pattern:?}: `*` is only allowed as the first label of the host"
+            ),
+            ConfigError::InvalidPort { pattern } => write!(
+                f,
+                "invalid origin pattern {pattern:?}: port must be a number, a range or `*`"
+            ),
+        }
+    }
 }
 
+impl std::error::Error for ConfigError {}
+
 #[allow(clippy::declare_interior_mutable_const)]
 const WILDCARD: HeaderValue = HeaderValue::from_static("*");
 
@@ -121,6 +227,9 @@ impl CorsLayer {
             max_age: Default::default(),
             vary: Default::default(),
             is_vary_custom: false,
+            strict_preflight: false,
+            preflight_rejection: Default::default(),
+            forward_non_cors_options: false,
         }
     }
 
@@ -456,6 +565,142 @@ impl CorsLayer {
         self
     }
 
+    /// Reject preflight requests the configuration doesn't allow.
+    ///
+    /// By default preflight requests are answered with the configured headers whatever they ask
+    /// for, and the browser decides whether the actual request may be sent. In strict mode the
+    /// `Origin`, [`Access-Control-Request-Method`][acrm] and
+    /// [`Access-Control-Request-Headers`][acrh] headers of preflight requests are checked
+    /// against the configuration, and preflight requests that ask for something that isn't
+    /// allowed get the [`preflight_rejection`] response instead.
+    ///
+    /// The [`CorsDecision`] attached to the response says why it was rejected.
+    ///
+    /// ```
+    /// use http::Method;
+    /// use tower_http::cors::CorsLayer;
+    ///
+    /// let layer = CorsLayer::new()
+    ///     .allow_methods([Method::GET, Method::PUT])
+    ///     .strict_preflight(true);
+    /// ```
+    ///
+    /// [acrm]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Access-Control-Request-Method
+    /// [acrh]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Access-Control-Request-Headers
+    /// [`preflight_rejection`]: Self::preflight_rejection
+    pub fn strict_preflight(mut self, strict: bool) -> Self {
+        self.strict_preflight = strict;
+        self
+    }
+
+    /// Set the response to preflight requests rejected by [strict mode].
+    ///
+    /// Defaults to an empty `403 Forbidden` response. Rejection responses carry no CORS headers
+    /// other than `Vary`.
+    ///
+    /// ```
+    /// use http::StatusCode;
+    /// use tower_http::cors::CorsLayer;
+    ///
+    /// let layer = CorsLayer::new()
+    ///     .strict_preflight(true)
+    ///     .preflight_rejection(StatusCode::NO_CONTENT);
+    /// ```
+    ///
+    /// [strict mode]: Self::strict_preflight
+    pub fn preflight_rejection<T>(mut self, rejection: T) -> Self
+    where
+        T: Into<PreflightRejection>,
+    {
+        self.preflight_rejection = rejection.into();
+        self
+    }
+
+    /// Forward `OPTIONS` requests that aren't CORS preflight requests to the inner service.
+    ///
+    /// By default every `OPTIONS` request is answered as a preflight request. With this enabled
+    /// only `OPTIONS` requests with both an `Origin` and an `Access-Control-Request-Method`
+    /// header are, and other `OPTIONS` requests are handled like any other request.
+    pub fn forward_non_cors_options(mut self, forward: bool) -> Self {
+        self.forward_non_cors_options = forward;
+        self
+    }
+
+    /// Handle `req` according to this configuration, calling `inner` unless it is a preflight
+    /// request.
+    fn call<S, ReqBody>(&self, inner: &mut S, req: Request<ReqBody>) -> ResponseFuture<S::Future>
+    where
+        S: Service<Request<ReqBody>>,
+    {
+        let (parts, body) = req.into_parts();
+        let origin = parts.headers.get(&header::ORIGIN);
+
+        let mut headers = HeaderMap::new();
+
+        // These headers are applied to both preflight and subsequent regular CORS requests:
+        // https://fetch.spec.whatwg.org/#http-responses
+
+        headers.extend(self.allow_credentials.to_header(origin, &parts));
+        headers.extend(self.allow_private_network.to_header(origin, &parts));
+        headers.extend(self.vary.to_header());
+
+        let allow_origin_future = self.allow_origin.to_future(origin, &parts);
+
+        let is_preflight = parts.method == Method::OPTIONS
+            && !(self.forward_non_cors_options
+                && (origin.is_none()
+                    || !parts
+                        .headers
+                        .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)));
+
+        let decision = Some(PendingDecision::new(self, &parts, is_preflight));
+
+        // Return results immediately upon preflight request
+        if is_preflight {
+            // These headers are applied only to preflight requests
+            headers.extend(self.allow_methods.to_header(&parts));
+            headers.extend(self.allow_headers.to_header(&parts));
+            headers.extend(self.max_age.to_header(origin, &parts));
+
+            ResponseFuture {
+                inner: Kind::PreflightCall {
+                    allow_origin_future,
+                    decision,
+                    rejection: self
+                        .strict_preflight
+                        .then(|| self.preflight_rejection.clone()),
+                    headers,
+                },
+            }
+        } else {
+            // This header is applied only to non-preflight requests
+            headers.extend(self.expose_headers.to_header(&parts));
+
+            let req = Request::from_parts(parts, body);
+            ResponseFuture {
+                inner: Kind::CorsCall {
+                    allow_origin_future,
+                    allow_origin_complete: false,
+                    decision,
+                    future: inner.call(req),
+                    headers,
+                },
+            }
+        }
+    }
+
+    /// Checks the configuration and returns a copy ready to handle requests.
+    fn prepare(&self) -> Self {
+        ensure_usable_cors_rules(self);
+
+        // Clone the layer to modify Vary header logic
+        let mut layer = self.clone();
+
+        layer.update_vary_header();
+
+        layer
+    }
+
     /// Recomputes the `Vary` header, if it hasn't been set explicitly.
     fn update_vary_header(&mut self) {
         if !self.is_vary_custom {
@@ -514,6 +759,18 @@ where
     }
 }
 
+/// Split a comma separated header value into its non-empty elements, with whitespace trimmed.
+fn comma_separated(value: &HeaderValue) -> impl Iterator<Item = &[u8]> {
+    value
+        .as_bytes()
+        .split(|&b| b == b',')
+        .filter_map(|element| {
+            let start = element.iter().position(|b| !b.is_ascii_whitespace())?;
+            let end = element.iter().rposition(|b| !b.is_ascii_whitespace())?;
+            Some(&element[start..=end])
+        })
+}
+
 impl Default for CorsLayer {
     fn default() -> Self {
         Self::new()
@@ -524,14 +781,10 @@ impl<S> Layer<S> for CorsLayer {
     type Service = Cors<S>;
 
     fn layer(&self, inner: S) -> Self::Service {
-        ensure_usable_cors_rules(self);
-
-        // Clone the layer to modify Vary header logic
-        let mut layer = self.clone();
-
-        layer.update_vary_header();
-
-        Cors { inner, layer }
+        Cors {
+            inner,
+            layer: self.prepare(),
+        }
     }
 }
 
@@ -671,6 +924,30 @@ impl<S> Cors<S> {
         self.map_layer(|layer| layer.allow_private_network(allow_private_network))
     }
 
+    /// Reject preflight requests the configuration doesn't allow.
+    ///
+    /// See [`CorsLayer::strict_preflight`] for more details.
+    pub fn strict_preflight(self, strict: bool) -> Self {
+        self.map_layer(|layer| layer.strict_preflight(strict))
+    }
+
+    /// Set the response to preflight requests rejected by strict mode.
+    ///
+    /// See [`CorsLayer::preflight_rejection`] for more details.
+    pub fn preflight_rejection<T>(self, rejection: T) -> Self
+    where
+        T: Into<PreflightRejection>,
+    {
+        self.map_layer(|layer| layer.preflight_rejection(rejection))
+    }
+
+    /// Forward `OPTIONS` requests that aren't CORS preflight requests to the inner service.
+    ///
+    /// See [`CorsLayer::forward_non_cors_options`] for more details.
+    pub fn forward_non_cors_options(self, forward: bool) -> Self {
+        self.map_layer(|layer| layer.forward_non_cors_options(forward))
+    }
+
     fn map_layer<F>(mut self, f: F) -> Self
     where
         F: FnOnce(CorsLayer) -> CorsLayer,
@@ -697,52 +974,12 @@ where
     }
 
     fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
-        let (parts, body) = req.into_parts();
-        let origin = parts.headers.get(&header::ORIGIN);
-
-        let mut headers = HeaderMap::new();
-
-        // These headers are applied to both preflight and subsequent regular CORS requests:
-        // https://fetch.spec.whatwg.org/#http-responses
-
-        headers.extend(self.layer.allow_credentials.to_header(origin, &parts));
-        headers.extend(self.layer.allow_private_network.to_header(origin, &parts));
-        headers.extend(self.layer.vary.to_header());
-
-        let allow_origin_future = self.layer.allow_origin.to_future(origin, &parts);
-
-        // Return results immediately upon preflight request
-        if parts.method == Method::OPTIONS {
-            // These headers are applied only to preflight requests
-            headers.extend(self.layer.allow_methods.to_header(&parts));
-            headers.extend(self.layer.allow_headers.to_header(&parts));
-            headers.extend(self.layer.max_age.to_header(origin, &parts));
-
-            ResponseFuture {
-                inner: Kind::PreflightCall {
-                    allow_origin_future,
-                    headers,
-                },
-            }
-        } else {
-            // This header is applied only to non-preflight requests
-            headers.extend(self.layer.expose_headers.to_header(&parts));
-
-            let req = Request::from_parts(parts, body);
-            ResponseFuture {
-                inner: Kind::CorsCall {
-                    allow_origin_future,
-                    allow_origin_complete: false,
-                    future: self.inner.call(req),
-                    headers,
-                },
-            }
-        }
+        self.layer.call(&mut self.inner, req)
     }
 }
 
 pin_project! {
-    /// Response future for [`Cors`].
+    /// Response future for [`Cors`] and [`SelectCors`].
     pub struct ResponseFuture<F> {
         #[pin]
         inner: Kind<F>,
@@ -756,6 +993,7 @@ pin_project! {
             #[pin]
             allow_origin_future: AllowOriginFuture,
             allow_origin_complete: bool,
+            decision: Option<PendingDecision>,
             #[pin]
             future: F,
             headers: HeaderMap,
@@ -763,6 +1001,8 @@ pin_project! {
         PreflightCall {
             #[pin]
             allow_origin_future: AllowOriginFuture,
+            decision: Option<PendingDecision>,
+            rejection: Option<PreflightRejection>,
             headers: HeaderMap,
         },
     }
@@ -780,6 +1020,7 @@ where
             KindProj::CorsCall {
                 allow_origin_future,
                 allow_origin_complete,
+                decision,
                 future,
                 headers,
             } => {
@@ -790,6 +1031,10 @@ where
 
                 let mut response: Response<B> = ready!(future.poll(cx))?;
 
+                if let Some(decision) = decision.take() {
+                    response.extensions_mut().insert(decision.finish(headers));
+                }
+
                 let response_headers = response.headers_mut();
 
                 // vary header can have multiple values, don't overwrite
@@ -804,12 +1049,32 @@ where
             }
             KindProj::PreflightCall {
                 allow_origin_future,
+                decision,
+                rejection,
                 headers,
             } => {
                 headers.extend(ready!(allow_origin_future.poll(cx)));
 
-                let mut response = Response::new(B::default());
-                mem::swap(response.headers_mut(), headers);
+                let decision = decision.take().map(|decision| decision.finish(headers));
+
+                let mut response = match (rejection, &decision) {
+                    (Some(rejection), Some(decision)) if !decision.is_allowed() => {
+                        let mut response = rejection.to_response(decision).map(|()| B::default());
+                        // Caches still need to know what the rejection depends on.
+                        if let Some(vary) = headers.remove(header::VARY) {
+                            response.headers_mut().append(header::VARY, vary);
+                        }
+                        response
+                    }
+                    _ => {
+                        let mut response = Response::new(B::default());
+                        mem::swap(response.headers_mut(), headers);
+                        response
+                    }
+                };
+                if let Some(decision) = decision {
+                    response.extensions_mut().insert(decision);
+                }
 
                 Poll::Ready(Ok(response))
             }
@@ -0,0 +1,187 @@
+use super::ConfigError;
+use http::HeaderValue;
+use std::ops::RangeInclusive;
+
+/// An origin pattern such as `https://*.example.com` or `http://localhost:3000-3999`.
+///
+/// See [`AllowOrigin::patterns`] for the syntax.
+///
+/// [`AllowOrigin::patterns`]: super::AllowOrigin::patterns
+#[derive(Clone, Debug)]
+pub(super) struct OriginPattern {
+    scheme: &'static str,
+    host: Host,
+    ports: RangeInclusive<u16>,
+}
+
+#[derive(Clone, Debug)]
+enum Host {
+    Exact(String),
+    /// Any subdomain of the domain, stored with its leading dot.
+    Subdomains(String),
+}
+
+impl OriginPattern {
+    pub(super) fn parse(pattern: &str) -> Result<Self, ConfigError> {
+        if !pattern.is_ascii() {
+            return Err(ConfigError::NonAsciiHostname {
+                pattern: pattern.to_owned(),
+            });
+        }
+
+        let (scheme, rest) = pattern
+            .split_once("://")
+            .and_then(|(scheme, rest)| Some((parse_scheme(scheme)?, rest)))
+            .ok_or_else(|| ConfigError::OpaqueOrigin {
+                pattern: pattern.to_owned(),
+            })?;
+
+        if rest.contains(['/', '?', '#', '@']) {
+            return Err(ConfigError::InvalidOriginUrlComponents {
+                pattern: pattern.to_owned(),
+            });
+        }
+
+        let (host, port) = split_host_port(rest).ok_or_else(|| ConfigError::InvalidHost {
+            pattern: pattern.to_owned(),
+        })?;
+
+        let host = if let Some(domain) = host.strip_prefix("*.") {
+            Host::Subdomains(format!(".{}", domain.to_ascii_lowercase()))
+        } else {
+            Host::Exact(host.to_ascii_lowercase())
+        };
+        let name = match &host {
+            Host::Exact(name) => name.as_str(),
+            Host::Subdomains(name) => &name[1..],
+        };
+        if name.contains('*') {
+            return Err(ConfigError::InvalidWildcard {
+                pattern: pattern.to_owned(),
+            });
+        }
+        if !is_valid_host(name) {
+            return Err(ConfigError::InvalidHost {
+                pattern: pattern.to_owned(),
+            });
+        }
+
+        let ports = match port {
+            None => {
+                let port = default_port(scheme);
+                port..=port
+            }
+            Some("*") => 0..=u16::MAX,
+            Some(port) => parse_ports(port).ok_or_else(|| ConfigError::InvalidPort {
+                pattern: pattern.to_owned(),
+            })?,
+        };
+
+        Ok(Self {
+            scheme,
+            host,
+            ports,
+        })
+    }
+
+    pub(super) fn matches(&self, origin: &HeaderValue) -> bool {
+        let Some((scheme, rest)) = origin.to_str().ok().and_then(|o| o.split_once("://")) else {
+            return false;
+        };
+        if !scheme.eq_ignore_ascii_case(self.scheme) {
+            return false;
+        }
+        // An origin is only a scheme, host and port. Anything else, such as a path or userinfo,
+        // would let the host be read loosely, as in `https://evil.com/.example.com`.
+        if rest.contains(['/', '?', '#', '@']) {
+            return false;
+        }
+
+        let Some((host, port)) = split_host_port(rest) else {
+            return false;
+        };
+        if !is_valid_host(host) {
+            return false;
+        }
+        let port = match port {
+            None => default_port(self.scheme),
+            Some(port) => match port.parse() {
+                Ok(port) => port,
+                Err(_) => return false,
+            },
+        };
+        if !self.ports.contains(&port) {
+            return false;
+        }
+
+        match &self.host {
+            Host::Exact(name) => host.eq_ignore_ascii_case(name),
+            Host::Subdomains(suffix) => {
+                host.len() > suffix.len()
+                    && host.is_char_boundary(host.len() - suffix.len())
+                    && host[host.len() - suffix.len()..].eq_ignore_ascii_case(suffix)
+            }
+        }
+    }
+}
+
+fn parse_scheme(scheme: &str) -> Option<&'static str> {
+    if scheme.eq_ignore_ascii_case("http") {
+        Some("http")
+    } else if scheme.eq_ignore_ascii_case("https") {
+        Some("https")
+    } else {
+        None
+    }
+}
+
+fn default_port(scheme: &str) -> u16 {
+    if scheme == "https" {
+        443
+    } else {
+        80
+    }
+}
+
+/// Split `host[:port]`, where the host may be a bracketed IPv6 address.
+fn split_host_port(authority: &str) -> Option<(&str, Option<&str>)> {
+    let port_start = if authority.starts_with('[') {
+        authority.find(']')? + 1
+    } else {
+        authority.find(':').unwrap_or(authority.len())
+    };
+
+    let (host, port) = authority.split_at(port_start);
+    if port.is_empty() {
+        Some((host, None))
+    } else {
+        Some((host, Some(port.strip_prefix(':')?)))
+    }
+}
+
+fn is_valid_host(host: &str) -> bool {
+    if let Some(ipv6) = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+        return ipv6.parse::<std::net::Ipv6Addr>().is_ok();
+    }
+
+    !host.is_empty()
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+}
+
+fn parse_ports(ports: &str) -> Option<RangeInclusive<u16>> {
+    match ports.split_once('-') {
+        Some((start, end)) => {
+            let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+            (start <= end).then_some(start..=end)
+        }
+        None => {
+            let port = ports.parse().ok()?;
+            Some(port..=port)
+        }
+    }
+}
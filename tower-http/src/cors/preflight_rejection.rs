@@ -0,0 +1,15 @@
+use http::StatusCode;
+
+use super::CorsDecision;
+
+define_rejection! {
+    /// Holds configuration for the response to preflight requests rejected in strict mode.
+    ///
+    /// See [`CorsLayer::preflight_rejection`] for more details.
+    ///
+    /// [`CorsLayer::preflight_rejection`]: super::CorsLayer::preflight_rejection
+    pub struct PreflightRejection;
+    default = StatusCode::FORBIDDEN;
+    /// Build the response from the reason the preflight request was rejected.
+    custom(decision: &CorsDecision);
+}
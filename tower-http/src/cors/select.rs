@@ -0,0 +1,219 @@
+use std::{
+    fmt,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use http::{header, request::Parts as RequestParts, Request, Response};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use super::{CorsLayer, ResponseFuture};
+use crate::helpers::PathPrefix;
+
+/// Layer that applies the [`SelectCors`] middleware, which picks the CORS configuration for each
+/// request.
+///
+/// Each policy is a [`CorsLayer`] paired with a rule saying which requests it applies to. Rules
+/// are tried in the order they were added and the first one that matches wins. Requests no rule
+/// matches use the default policy.
+///
+/// Preflight requests are sent to the URL of the actual request, so they are matched against the
+/// same policy.
+///
+/// # Example
+///
+/// ```
+/// use http::Method;
+/// use tower_http::cors::{AllowOrigin, Any, CorsLayer, SelectCorsLayer};
+///
+/// let layer = SelectCorsLayer::new(CorsLayer::new())
+///     // Anyone may read the public API, without credentials.
+///     .path_prefix("/api/public", CorsLayer::permissive())
+///     // Only the admin frontend may call the admin API.
+///     .host(
+///         "admin.example.com",
+///         CorsLayer::new()
+///             .allow_origin(AllowOrigin::exact("https://admin.example.com".parse().unwrap()))
+///             .allow_methods([Method::GET, Method::POST, Method::DELETE])
+///             .allow_credentials(true),
+///     )
+///     .matching(
+///         |parts| parts.uri.path().ends_with(".json"),
+///         CorsLayer::new().allow_origin(Any),
+///     );
+/// ```
+///
+/// # Panics
+///
+/// The policies are checked when they are added, and adding one that [`CorsLayer`] would panic
+/// on panics too.
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct SelectCorsLayer {
+    policies: Arc<Policies>,
+}
+
+impl SelectCorsLayer {
+    /// Create a new `SelectCorsLayer` using `default` for requests no rule matches.
+    ///
+    /// Use [`CorsLayer::new`] as the default to send no CORS headers for those requests.
+    pub fn new(default: CorsLayer) -> Self {
+        Self {
+            policies: Arc::new(Policies {
+                rules: Vec::new(),
+                default: default.prepare(),
+            }),
+        }
+    }
+
+    /// Use `policy` for requests whose path starts with `prefix`.
+    ///
+    /// The prefix is matched by whole path segments, so `/api` matches `/api` and `/api/users` but
+    /// not `/apis`.
+    ///
+    /// # Panics
+    ///
+    /// If `prefix` doesn't start with a `/`.
+    pub fn path_prefix(self, prefix: &str, policy: CorsLayer) -> Self {
+        self.push(Rule::PathPrefix(PathPrefix::new(prefix)), policy)
+    }
+
+    /// Use `policy` for requests to `host`.
+    ///
+    /// The host is taken from the request URI, or the `Host` header if the URI has none. It is
+    /// compared case-insensitively and without the port.
+    pub fn host(self, host: &str, policy: CorsLayer) -> Self {
+        self.push(Rule::Host(host.to_ascii_lowercase()), policy)
+    }
+
+    /// Use `policy` for requests `f` returns `true` for.
+    ///
+    /// If `f` looks at request headers, make sure the `Vary` header of the policies mentions them,
+    /// see [`CorsLayer::vary`].
+    pub fn matching<F>(self, f: F, policy: CorsLayer) -> Self
+    where
+        F: Fn(&RequestParts) -> bool + Send + Sync + 'static,
+    {
+        self.push(Rule::Predicate(Arc::new(f)), policy)
+    }
+
+    fn push(mut self, rule: Rule, policy: CorsLayer) -> Self {
+        let policy = policy.prepare();
+        Arc::make_mut(&mut self.policies).rules.push((rule, policy));
+        self
+    }
+}
+
+impl<S> Layer<S> for SelectCorsLayer {
+    type Service = SelectCors<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SelectCors {
+            inner,
+            policies: self.policies.clone(),
+        }
+    }
+}
+
+/// Middleware which adds headers for [CORS][mdn], picking the configuration for each request.
+///
+/// See [`SelectCorsLayer`] for more details.
+///
+/// [mdn]: https://developer.mozilla.org/en-US/docs/Web/HTTP/CORS
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct SelectCors<S> {
+    inner: S,
+    policies: Arc<Policies>,
+}
+
+impl<S> SelectCors<S> {
+    define_inner_service_accessors!();
+
+    /// Returns a new [`Layer`] that wraps services with a [`SelectCors`] middleware.
+    ///
+    /// [`Layer`]: tower_layer::Layer
+    pub fn layer(default: CorsLayer) -> SelectCorsLayer {
+        SelectCorsLayer::new(default)
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for SelectCors<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    ResBody: Default,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let (parts, body) = req.into_parts();
+        let policy = self.policies.select(&parts);
+
+        policy.call(&mut self.inner, Request::from_parts(parts, body))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Policies {
+    rules: Vec<(Rule, CorsLayer)>,
+    default: CorsLayer,
+}
+
+impl Policies {
+    fn select(&self, parts: &RequestParts) -> &CorsLayer {
+        self.rules
+            .iter()
+            .find(|(rule, _)| rule.matches(parts))
+            .map_or(&self.default, |(_, policy)| policy)
+    }
+}
+
+#[derive(Clone)]
+enum Rule {
+    PathPrefix(PathPrefix),
+    /// Stored in lowercase.
+    Host(String),
+    Predicate(Arc<dyn for<'a> Fn(&'a RequestParts) -> bool + Send + Sync + 'static>),
+}
+
+impl Rule {
+    fn matches(&self, parts: &RequestParts) -> bool {
+        match self {
+            Rule::PathPrefix(prefix) => prefix.matches(parts.uri.path()),
+            Rule::Host(host) => request_host(parts).map_or(false, |h| h.eq_ignore_ascii_case(host)),
+            Rule::Predicate(f) => f(parts),
+        }
+    }
+}
+
+impl fmt::Debug for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rule::PathPrefix(prefix) => f.debug_tuple("PathPrefix").field(prefix).finish(),
+            Rule::Host(host) => f.debug_tuple("Host").field(host).finish(),
+            Rule::Predicate(_) => f.debug_tuple("Predicate").finish(),
+        }
+    }
+}
+
+/// The host the request was sent to, without the port.
+fn request_host(parts: &RequestParts) -> Option<&str> {
+    if let Some(host) = parts.uri.host() {
+        return Some(host);
+    }
+
+    let host = parts.headers.get(header::HOST)?.to_str().ok()?;
+    if host.starts_with('[') {
+        // IPv6 address, keep the brackets like `Uri::host` does.
+        host.find(']').map(|end| &host[..=end])
+    } else {
+        Some(host.split(':').next().unwrap_or(host))
+    }
+}
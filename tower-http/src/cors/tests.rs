@@ -244,3 +244,418 @@ async fn exact_origin_does_not_emit_origin_vary_header() {
 
     assert!(res.headers().get(header::VARY).is_none());
 }
+
+#[tokio::test]
+async fn origin_patterns() {
+    let svc = CorsLayer::new()
+        .allow_origin(
+            AllowOrigin::patterns([
+                "https://*.example.com",
+                "http://localhost:3000-3999",
+                "http://[::1]:*",
+            ])
+            .unwrap(),
+        )
+        .layer(service_fn(|_: Request<Body>| async {
+            Ok::<_, Infallible>(Response::new(Body::empty()))
+        }));
+
+    for (origin, allowed) in [
+        ("https://app.example.com", true),
+        ("https://a.b.example.com", true),
+        ("https://APP.Example.com:443", true),
+        ("https://example.com", false),
+        ("https://evilexample.com", false),
+        ("http://app.example.com", false),
+        ("https://app.example.com:8443", false),
+        ("http://localhost:3000", true),
+        ("http://localhost:3999", true),
+        ("http://localhost:4000", false),
+        ("http://localhost", false),
+        ("http://[::1]:8080", true),
+        ("null", false),
+        ("https://evil.com/.example.com", false),
+        ("https://evil.com?.example.com", false),
+        ("https://evil.com#.example.com", false),
+        ("https://a.example.com@evil.com", false),
+        ("https://evil.com@a.example.com", false),
+        ("https://app.example.com/", false),
+        ("https://evil.com\\.example.com", false),
+    ] {
+        let req = Request::builder()
+            .header(header::ORIGIN, origin)
+            .body(Body::empty())
+            .unwrap();
+        let res = svc.clone().oneshot(req).await.unwrap();
+
+        assert_eq!(
+            res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            allowed
+                .then(|| HeaderValue::from_str(origin).unwrap())
+                .as_ref(),
+            "{}",
+            origin
+        );
+    }
+}
+
+#[test]
+fn invalid_origin_patterns() {
+    use crate::cors::ConfigError;
+
+    type Check = fn(&ConfigError) -> bool;
+    let cases: [(&str, Check); 9] = [
+        ("example.com", |e| {
+            matches!(e, ConfigError::OpaqueOrigin { .. })
+        }),
+        ("ftp://example.com", |e| {
+            matches!(e, ConfigError::OpaqueOrigin { .. })
+        }),
+        ("https://example.com/", |e| {
+            matches!(e, ConfigError::InvalidOriginUrlComponents { .. })
+        }),
+        ("https://exämple.com", |e| {
+            matches!(e, ConfigError::NonAsciiHostname { .. })
+        }),
+        ("https://", |e| matches!(e, ConfigError::InvalidHost { .. })),
+        ("https://exa mple.com", |e| {
+            matches!(e, ConfigError::InvalidHost { .. })
+        }),
+        ("https://api.*.example.com", |e| {
+            matches!(e, ConfigError::InvalidWildcard { .. })
+        }),
+        ("http://localhost:4000-3000", |e| {
+            matches!(e, ConfigError::InvalidPort { .. })
+        }),
+        ("http://localhost:", |e| {
+            matches!(e, ConfigError::InvalidPort { .. })
+        }),
+    ];
+
+    for (pattern, check) in cases {
+        let err = AllowOrigin::patterns([pattern]).unwrap_err();
+        assert!(check(&err), "{}: {:?}", pattern, err);
+    }
+}
+
+#[tokio::test]
+async fn decision_is_attached_to_responses() {
+    use crate::cors::{AllowCredentials, CorsDecision, CorsDecisionKind};
+
+    let svc = CorsLayer::new()
+        .allow_origin(AllowOrigin::list([HeaderValue::from_static(
+            "https://example.com",
+        )]))
+        .allow_methods([Method::GET, Method::PUT])
+        .allow_headers([header::CONTENT_TYPE])
+        .layer(service_fn(|_: Request<Body>| async {
+            Ok::<_, Infallible>(Response::new(Body::empty()))
+        }));
+
+    let cases = [
+        (Method::GET, None, None, None, CorsDecisionKind::NotCors),
+        (
+            Method::GET,
+            Some("https://example.com"),
+            None,
+            None,
+            CorsDecisionKind::OriginAllowed,
+        ),
+        (
+            Method::GET,
+            Some("https://evil.com"),
+            None,
+            None,
+            CorsDecisionKind::OriginDenied,
+        ),
+        (
+            Method::OPTIONS,
+            Some("https://example.com"),
+            Some("PUT"),
+            Some("Content-Type"),
+            CorsDecisionKind::OriginAllowed,
+        ),
+        (
+            Method::OPTIONS,
+            Some("https://example.com"),
+            Some("DELETE"),
+            None,
+            CorsDecisionKind::MethodNotAllowed,
+        ),
+        (
+            Method::OPTIONS,
+            Some("https://example.com"),
+            Some("PUT"),
+            Some("content-type, x-custom"),
+            CorsDecisionKind::HeaderNotAllowed,
+        ),
+    ];
+
+    for (method, origin, request_method, request_headers, kind) in cases {
+        let mut req = Request::builder().method(method);
+        if let Some(origin) = origin {
+            req = req.header(header::ORIGIN, origin);
+        }
+        if let Some(request_method) = request_method {
+            req = req.header(header::ACCESS_CONTROL_REQUEST_METHOD, request_method);
+        }
+        if let Some(request_headers) = request_headers {
+            req = req.header(header::ACCESS_CONTROL_REQUEST_HEADERS, request_headers);
+        }
+        let res = svc
+            .clone()
+            .oneshot(req.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let decision = res.extensions().get::<CorsDecision>().unwrap();
+        assert_eq!(decision.kind(), kind, "{:?}", decision);
+        assert_eq!(decision.origin().map(|o| o.to_str().unwrap()), origin);
+    }
+
+    let req = Request::builder()
+        .method(Method::OPTIONS)
+        .header(header::ORIGIN, "https://example.com")
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PUT")
+        .header(
+            header::ACCESS_CONTROL_REQUEST_HEADERS,
+            "content-type, x-custom",
+        )
+        .body(Body::empty())
+        .unwrap();
+    let res = svc.oneshot(req).await.unwrap();
+    let decision = res.extensions().get::<CorsDecision>().unwrap();
+    assert!(!decision.is_allowed());
+    assert_eq!(decision.disallowed().unwrap(), "x-custom");
+
+    // Credentials for some origins only can't be rejected up front.
+    let svc = CorsLayer::new()
+        .allow_origin(Any)
+        .allow_credentials(AllowCredentials::predicate(|_, _| true))
+        .layer(service_fn(|_: Request<Body>| async {
+            Ok::<_, Infallible>(Response::new(Body::empty()))
+        }));
+    let req = Request::builder()
+        .header(header::ORIGIN, "https://example.com")
+        .body(Body::empty())
+        .unwrap();
+    let res = svc.oneshot(req).await.unwrap();
+    assert_eq!(
+        res.extensions().get::<CorsDecision>().unwrap().kind(),
+        CorsDecisionKind::CredentialsConflict
+    );
+}
+
+#[tokio::test]
+async fn strict_preflight() {
+    use crate::cors::{CorsDecision, CorsDecisionKind};
+    use http::StatusCode;
+
+    let svc = CorsLayer::new()
+        .allow_origin(AllowOrigin::list([HeaderValue::from_static(
+            "https://example.com",
+        )]))
+        .allow_methods([Method::GET, Method::PUT])
+        .allow_headers([header::CONTENT_TYPE])
+        .strict_preflight(true)
+        .layer(service_fn(|_: Request<Body>| async {
+            Ok::<_, Infallible>(Response::new(Body::empty()))
+        }));
+
+    let preflight = |origin: &str, method: &str, headers: &str| {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, method)
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, headers)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let res = svc
+        .clone()
+        .oneshot(preflight("https://example.com", "PUT", "content-type"))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .unwrap(),
+        "https://example.com"
+    );
+
+    for (req, kind) in [
+        (
+            preflight("https://example.com", "DELETE", "content-type"),
+            CorsDecisionKind::MethodNotAllowed,
+        ),
+        (
+            preflight("https://example.com", "PUT", "x-custom"),
+            CorsDecisionKind::HeaderNotAllowed,
+        ),
+        (
+            preflight("https://evil.com", "PUT", "content-type"),
+            CorsDecisionKind::OriginDenied,
+        ),
+    ] {
+        let res = svc.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert!(res
+            .headers()
+            .keys()
+            .all(|name| !name.as_str().starts_with("access-control-")));
+        assert_eq!(res.headers().get(header::VARY).unwrap(), "origin");
+        assert_eq!(res.extensions().get::<CorsDecision>().unwrap().kind(), kind);
+    }
+
+    let svc = CorsLayer::new()
+        .allow_origin(Any)
+        .strict_preflight(true)
+        .preflight_rejection(StatusCode::NO_CONTENT)
+        .layer(service_fn(|_: Request<Body>| async {
+            Ok::<_, Infallible>(Response::new(Body::empty()))
+        }));
+    let res = svc
+        .oneshot(preflight("https://example.com", "DELETE", ""))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn forward_non_cors_options() {
+    use http::StatusCode;
+
+    let svc = CorsLayer::new()
+        .allow_origin(Any)
+        .allow_methods(Any)
+        .forward_non_cors_options(true)
+        .layer(service_fn(|_: Request<Body>| async {
+            Ok::<_, Infallible>(
+                Response::builder()
+                    .status(StatusCode::NO_CONTENT)
+                    .header(header::ALLOW, "GET, OPTIONS")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        }));
+
+    // No `Access-Control-Request-Method`, so not a preflight request.
+    let req = Request::builder()
+        .method(Method::OPTIONS)
+        .header(header::ORIGIN, "https://example.com")
+        .body(Body::empty())
+        .unwrap();
+    let res = svc.clone().oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    assert_eq!(res.headers().get(header::ALLOW).unwrap(), "GET, OPTIONS");
+    assert_eq!(
+        res.headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .unwrap(),
+        "*"
+    );
+
+    let req = Request::builder()
+        .method(Method::OPTIONS)
+        .header(header::ORIGIN, "https://example.com")
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PUT")
+        .body(Body::empty())
+        .unwrap();
+    let res = svc.oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers().get(header::ALLOW).is_none());
+    assert_eq!(
+        res.headers()
+            .get(header::ACCESS_CONTROL_ALLOW_METHODS)
+            .unwrap(),
+        "*"
+    );
+}
+
+#[tokio::test]
+async fn select_policy_per_request() {
+    use crate::cors::SelectCorsLayer;
+
+    let svc = SelectCorsLayer::new(CorsLayer::new())
+        .path_prefix("/api/public/", CorsLayer::permissive())
+        .host(
+            "admin.example.com",
+            CorsLayer::new()
+                .allow_origin(AllowOrigin::exact(HeaderValue::from_static(
+                    "https://admin.example.com",
+                )))
+                .allow_methods([Method::DELETE])
+                .allow_credentials(true),
+        )
+        .matching(
+            |parts| parts.uri.path().ends_with(".json"),
+            CorsLayer::new().allow_origin(Any),
+        )
+        .layer(service_fn(|_: Request<Body>| async {
+            Ok::<_, Infallible>(Response::new(Body::empty()))
+        }));
+
+    let get = |host: &str, path: &str| {
+        Request::builder()
+            .uri(path)
+            .header(header::HOST, host)
+            .header(header::ORIGIN, "https://admin.example.com")
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    for (req, allow_origin, allow_credentials) in [
+        (get("example.com", "/api/public"), Some("*"), false),
+        (get("example.com", "/api/public/users"), Some("*"), false),
+        (get("example.com", "/api/publicity"), None, false),
+        (
+            get("Admin.Example.com:8443", "/api/public/users"),
+            Some("*"),
+            false,
+        ),
+        (
+            get("Admin.Example.com:8443", "/users"),
+            Some("https://admin.example.com"),
+            true,
+        ),
+        (get("example.com", "/data.json"), Some("*"), false),
+        (get("example.com", "/users"), None, false),
+    ] {
+        let uri = req.uri().clone();
+        let res = svc.clone().oneshot(req).await.unwrap();
+        assert_eq!(
+            res.headers()
+                .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .map(|v| v.to_str().unwrap()),
+            allow_origin,
+            "{}",
+            uri
+        );
+        assert_eq!(
+            res.headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_CREDENTIALS),
+            allow_credentials,
+            "{}",
+            uri
+        );
+    }
+
+    // Preflight requests use the policy of the actual request.
+    let req = Request::builder()
+        .method(Method::OPTIONS)
+        .uri("/users")
+        .header(header::HOST, "admin.example.com")
+        .header(header::ORIGIN, "https://admin.example.com")
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "DELETE")
+        .body(Body::empty())
+        .unwrap();
+    let res = svc.oneshot(req).await.unwrap();
+    assert_eq!(
+        res.headers()
+            .get(header::ACCESS_CONTROL_ALLOW_METHODS)
+            .unwrap(),
+        "DELETE"
+    );
+}
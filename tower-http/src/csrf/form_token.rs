@@ -0,0 +1,332 @@
+use std::fmt::{self, Debug, Formatter};
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use bytes::{Buf, Bytes};
+use http::{header, request::Parts, HeaderMap, Method, Request, Response, StatusCode};
+use http_body::{Body, Frame, SizeHint};
+use http_body_util::{combinators::Collect, BodyExt, LengthLimitError, Limited};
+use pin_project_lite::pin_project;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::helpers::decode_form_component;
+use crate::BoxError;
+
+/// Token submitted in a form field, inserted into the request extensions by [`FormToken`].
+#[derive(Clone)]
+pub(super) struct SubmittedToken(pub(super) String);
+
+/// Layer that applies the [`FormToken`] middleware.
+///
+/// Created with [`TokenFallback::form_layer`].
+///
+/// [`TokenFallback::form_layer`]: super::TokenFallback::form_layer
+#[derive(Clone, Debug)]
+#[must_use]
+pub struct FormTokenLayer {
+    field: String,
+    max_size: usize,
+}
+
+impl FormTokenLayer {
+    pub(super) fn new(field: String, max_size: usize) -> Self {
+        Self { field, max_size }
+    }
+}
+
+impl<S> Layer<S> for FormTokenLayer {
+    type Service = FormToken<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        FormToken {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Middleware that reads the [`TokenFallback`] token from the fields of form submissions.
+///
+/// Bodies of `application/x-www-form-urlencoded` requests with a method other than `GET`,
+/// `HEAD` and `OPTIONS` are buffered, and the token field is handed to [`Csrf`], which must be
+/// applied inside this middleware. Bodies larger than the [limit] are rejected with
+/// `413 Payload Too Large`. The inner service receives the buffered body unchanged.
+///
+/// [`TokenFallback`]: super::TokenFallback
+/// [`Csrf`]: super::Csrf
+/// [limit]: super::TokenFallback::max_form_size
+#[derive(Clone, Debug)]
+#[must_use]
+pub struct FormToken<S> {
+    inner: S,
+    layer: FormTokenLayer,
+}
+
+impl<S> FormToken<S> {
+    define_inner_service_accessors!();
+}
+
+fn is_form(method: &Method, headers: &HeaderMap) -> bool {
+    if matches!(method, &Method::GET | &Method::HEAD | &Method::OPTIONS) {
+        return false;
+    }
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map_or(false, |mime| {
+            mime.trim()
+                .eq_ignore_ascii_case("application/x-www-form-urlencoded")
+        })
+}
+
+/// Finds the value of `field` in a `application/x-www-form-urlencoded` body.
+fn find_field(form: &[u8], field: &str) -> Option<String> {
+    form.split(|&b| b == b'&').find_map(|pair| {
+        let mut pair = pair.splitn(2, |&b| b == b'=');
+        let name = pair.next()?;
+        let value = pair.next().unwrap_or_default();
+        if decode_form_component(name)? == field {
+            decode_form_component(value)
+        } else {
+            None
+        }
+    })
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for FormToken<S>
+where
+    S: Service<Request<FormTokenBody<ReqBody>>, Response = Response<ResBody>> + Clone,
+    ReqBody: Body,
+    ReqBody::Error: Into<BoxError>,
+    ResBody: Default,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = FormTokenFuture<S, ReqBody>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        if !is_form(req.method(), req.headers()) {
+            let req = req.map(|inner| FormTokenBody {
+                kind: Kind::Streaming { inner },
+            });
+            return FormTokenFuture {
+                state: State::Called {
+                    future: self.inner.call(req),
+                },
+                pending: None,
+                field: String::new(),
+            };
+        }
+
+        let mut inner = self.inner.clone();
+        // mem::swap due to https://docs.rs/tower/latest/tower/trait.Service.html#be-careful-when-cloning-inner-services
+        mem::swap(&mut self.inner, &mut inner);
+        let (parts, body) = req.into_parts();
+        FormTokenFuture {
+            state: State::Collecting {
+                collect: Limited::new(body, self.layer.max_size).collect(),
+            },
+            pending: Some((inner, parts)),
+            field: self.layer.field.clone(),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`FormToken`].
+    pub struct FormTokenFuture<S, ReqBody>
+    where
+        S: Service<Request<FormTokenBody<ReqBody>>>,
+        ReqBody: Body,
+        ReqBody::Error: Into<BoxError>,
+    {
+        #[pin]
+        state: State<Collect<Limited<ReqBody>>, S::Future>,
+        pending: Option<(S, Parts)>,
+        field: String,
+    }
+}
+
+pin_project! {
+    #[project = StateProj]
+    enum State<C, F> {
+        Collecting {
+            #[pin]
+            collect: C,
+        },
+        Called {
+            #[pin]
+            future: F,
+        },
+    }
+}
+
+impl<S, ReqBody, ResBody> Future for FormTokenFuture<S, ReqBody>
+where
+    S: Service<Request<FormTokenBody<ReqBody>>, Response = Response<ResBody>>,
+    ReqBody: Body,
+    ReqBody::Error: Into<BoxError>,
+    ResBody: Default,
+{
+    type Output = Result<S::Response, S::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        loop {
+            match this.state.as_mut().project() {
+                StateProj::Collecting { collect } => {
+                    let collected = match ready!(collect.poll(cx)) {
+                        Ok(collected) => collected,
+                        Err(err) => {
+                            let status = if err.is::<LengthLimitError>() {
+                                StatusCode::PAYLOAD_TOO_LARGE
+                            } else {
+                                StatusCode::BAD_REQUEST
+                            };
+                            let mut res = Response::new(ResBody::default());
+                            *res.status_mut() = status;
+                            return Poll::Ready(Ok(res));
+                        }
+                    };
+
+                    let (mut inner, mut parts) =
+                        this.pending.take().expect("future polled after completion");
+                    let trailers = collected.trailers().cloned();
+                    let data = collected.to_bytes();
+                    if let Some(token) = find_field(&data, this.field) {
+                        parts.extensions.insert(SubmittedToken(token));
+                    }
+
+                    let body = FormTokenBody {
+                        kind: Kind::Buffered {
+                            data: Some(data).filter(|data| !data.is_empty()),
+                            trailers,
+                        },
+                    };
+                    let future = inner.call(Request::from_parts(parts, body));
+                    this.state.set(State::Called { future });
+                }
+                StateProj::Called { future } => return future.poll(cx),
+            }
+        }
+    }
+}
+
+impl<S, ReqBody> Debug for FormTokenFuture<S, ReqBody>
+where
+    S: Service<Request<FormTokenBody<ReqBody>>>,
+    ReqBody: Body,
+    ReqBody::Error: Into<BoxError>,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FormTokenFuture").finish_non_exhaustive()
+    }
+}
+
+pin_project! {
+    /// Request body for services wrapped in [`FormToken`].
+    ///
+    /// Forms whose fields have been read are buffered, other bodies are streamed.
+    pub struct FormTokenBody<B> {
+        #[pin]
+        kind: Kind<B>,
+    }
+}
+
+pin_project! {
+    #[project = KindProj]
+    enum Kind<B> {
+        Buffered {
+            data: Option<Bytes>,
+            trailers: Option<HeaderMap>,
+        },
+        Streaming {
+            #[pin]
+            inner: B,
+        },
+    }
+}
+
+impl<B> Body for FormTokenBody<B>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match self.project().kind.project() {
+            KindProj::Buffered { data, trailers } => Poll::Ready(
+                data.take()
+                    .map(Frame::data)
+                    .or_else(|| trailers.take().map(Frame::trailers))
+                    .map(Ok),
+            ),
+            KindProj::Streaming { inner } => match ready!(inner.poll_frame(cx)) {
+                Some(Ok(frame)) => Poll::Ready(Some(Ok(
+                    frame.map_data(|mut data| data.copy_to_bytes(data.remaining()))
+                ))),
+                Some(Err(err)) => Poll::Ready(Some(Err(err.into()))),
+                None => Poll::Ready(None),
+            },
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match &self.kind {
+            Kind::Buffered { data, trailers } => data.is_none() && trailers.is_none(),
+            Kind::Streaming { inner } => inner.is_end_stream(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match &self.kind {
+            Kind::Buffered { data, .. } => {
+                SizeHint::with_exact(data.as_ref().map_or(0, |data| data.len() as u64))
+            }
+            Kind::Streaming { inner } => inner.size_hint(),
+        }
+    }
+}
+
+impl<B> Debug for FormTokenBody<B>
+where
+    B: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            Kind::Buffered { data, .. } => f.debug_tuple("Buffered").field(data).finish(),
+            Kind::Streaming { inner } => f.debug_tuple("Streaming").field(inner).finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_form_fields() {
+        let form = b"name=J%C3%BCrgen+M&csrf_token=abc-_1&empty";
+        assert_eq!(find_field(form, "csrf_token").as_deref(), Some("abc-_1"));
+        assert_eq!(find_field(form, "name").as_deref(), Some("Jürgen M"));
+        assert_eq!(find_field(form, "empty").as_deref(), Some(""));
+        assert_eq!(find_field(form, "missing"), None);
+        assert_eq!(
+            find_field(b"csrf%5Ftoken=x", "csrf_token").as_deref(),
+            Some("x")
+        );
+    }
+}
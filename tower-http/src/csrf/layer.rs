@@ -6,7 +6,11 @@ use tower_layer::Layer;
 
 use super::service::Csrf;
 use super::url::UriExt;
-use super::{BypassFn, ConfigError, DebugFn, DefaultResponseForProtectionError, Origins};
+use super::{
+    BypassFn, ConfigError, DebugFn, DefaultResponseForProtectionError, Origins, ProtectionError,
+    ReportFn,
+};
+use crate::forwarded::TrustedProxies;
 
 /// Layer that applies the [`Csrf`] middleware.
 ///
@@ -15,30 +19,41 @@ use super::{BypassFn, ConfigError, DebugFn, DefaultResponseForProtectionError, O
 #[must_use]
 pub struct CsrfLayer<T = DefaultResponseForProtectionError> {
     insecure_bypass: Option<Arc<BypassFn>>,
+    report: Option<Arc<ReportFn>>,
     rejection_response: T,
     trusted_origins: Origins,
+    trusted_proxies: Option<TrustedProxies>,
+    #[cfg(feature = "csrf-token")]
+    token_fallback: Option<super::TokenFallback>,
 }
 
 impl Default for CsrfLayer {
     fn default() -> Self {
         Self {
             insecure_bypass: None,
+            report: None,
             rejection_response: DefaultResponseForProtectionError,
             trusted_origins: Origins::default(),
+            trusted_proxies: None,
+            #[cfg(feature = "csrf-token")]
+            token_fallback: None,
         }
     }
 }
 
 impl<T> Debug for CsrfLayer<T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        f.debug_struct("CsrfLayer")
-            .field(
-                "insecure_bypass",
-                &self.insecure_bypass.as_ref().map(|_| DebugFn),
-            )
-            .field("trusted_origins", &self.trusted_origins)
-            .field("rejection_response", &DebugFn)
-            .finish()
+        let mut f = f.debug_struct("CsrfLayer");
+        f.field(
+            "insecure_bypass",
+            &self.insecure_bypass.as_ref().map(|_| DebugFn),
+        )
+        .field("report", &self.report.as_ref().map(|_| DebugFn))
+        .field("trusted_origins", &self.trusted_origins)
+        .field("trusted_proxies", &self.trusted_proxies);
+        #[cfg(feature = "csrf-token")]
+        f.field("token_fallback", &self.token_fallback);
+        f.field("rejection_response", &DebugFn).finish()
     }
 }
 
@@ -110,6 +125,69 @@ impl<T> CsrfLayer<T> {
         self
     }
 
+    /// Switches to report-only mode: requests that would be rejected are passed
+    /// to the inner service instead, after calling `report` with the
+    /// [`ProtectionError`] they would have been rejected with.
+    ///
+    /// The error is also attached to the request's extensions, so handlers can
+    /// tell such requests apart. This lets you measure which clients would be
+    /// blocked, e.g. legacy clients that send neither `Sec-Fetch-Site` nor
+    /// `Origin`, before enforcing the protection.
+    ///
+    /// ```
+    /// # use tower_http::csrf::CsrfLayer;
+    /// let layer = CsrfLayer::new().with_report_only(|error, method, uri| {
+    ///     eprintln!("CSRF: {error}: {method} {uri}");
+    /// });
+    /// ```
+    pub fn with_report_only<F>(mut self, report: F) -> Self
+    where
+        F: Fn(&ProtectionError, &Method, &Uri) + Send + Sync + 'static,
+    {
+        #[cfg(feature = "tracing")]
+        tracing::debug!("enabled report-only mode");
+
+        self.report = Some(Arc::new(report));
+        self
+    }
+
+    /// Compares the `Origin` against the host and scheme forwarded by trusted
+    /// reverse proxies, instead of the `Host` the proxy sent.
+    ///
+    /// Forwarding headers are only believed if the request was received from one
+    /// of the `proxies`; see [`TrustedProxies::effective_authority`]. When the
+    /// scheme is forwarded, an `Origin` with a different scheme no longer
+    /// matches.
+    ///
+    /// ```
+    /// # use tower_http::csrf::CsrfLayer;
+    /// use tower_http::forwarded::TrustedProxies;
+    ///
+    /// let layer = CsrfLayer::new().with_trusted_proxies(TrustedProxies::new(["10.0.0.0/8"])?);
+    /// # Ok::<_, tower_http::forwarded::ConfigError>(())
+    /// ```
+    pub fn with_trusted_proxies(mut self, proxies: TrustedProxies) -> Self {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(?proxies, "added trusted proxies");
+
+        self.trusted_proxies = Some(proxies);
+        self
+    }
+
+    /// Allows requests that would be rejected with
+    /// [`CrossOriginRequestFromOldBrowser`] if they carry a valid signed
+    /// double-submit token.
+    ///
+    /// See [`TokenFallback`](super::TokenFallback) for details. Requires the
+    /// `csrf-token` feature.
+    ///
+    /// [`CrossOriginRequestFromOldBrowser`]: super::ProtectionErrorKind::CrossOriginRequestFromOldBrowser
+    #[cfg(feature = "csrf-token")]
+    pub fn with_token_fallback(mut self, tokens: super::TokenFallback) -> Self {
+        self.token_fallback = Some(tokens);
+        self
+    }
+
     /// Replaces the response builder used when a request is rejected.
     ///
     /// Accepts any type that implements [`ResponseForProtectionError`](super::ResponseForProtectionError),
@@ -124,7 +202,11 @@ impl<T> CsrfLayer<T> {
     {
         CsrfLayer {
             insecure_bypass: self.insecure_bypass,
+            report: self.report,
             trusted_origins: self.trusted_origins,
+            trusted_proxies: self.trusted_proxies,
+            #[cfg(feature = "csrf-token")]
+            token_fallback: self.token_fallback,
             rejection_response,
         }
     }
@@ -140,8 +222,12 @@ where
         Csrf::new(
             inner,
             self.insecure_bypass.clone(),
+            self.report.clone(),
             self.rejection_response.clone(),
             self.trusted_origins.clone(),
+            self.trusted_proxies.clone(),
+            #[cfg(feature = "csrf-token")]
+            self.token_fallback.clone(),
         )
     }
 }
@@ -12,7 +12,8 @@
 //! 3. `Sec-Fetch-Site` is `same-origin` or `none`.
 //! 4. Neither `Sec-Fetch-Site` nor `Origin` is present.
 //! 5. The `Origin`'s authority (host and any port) matches the request's effective
-//!    host byte-for-byte (the request-target authority if present, else `Host`).
+//!    host byte-for-byte (the request-target authority if present, else `Host`,
+//!    or the host forwarded by [trusted proxies](CsrfLayer::with_trusted_proxies)).
 //!
 //! Rejected requests receive a `403 Forbidden` response. The originating
 //! [`ProtectionError`] is attached to the response's extensions — on every
@@ -22,6 +23,15 @@
 //! [`CsrfLayer::with_rejection_response`](CsrfLayer::with_rejection_response)
 //! to replace the rejection response with a custom builder.
 //!
+//! Old browsers and embedded webviews that send neither `Sec-Fetch-Site` nor a
+//! matching `Origin` can be let through with a signed double-submit token,
+//! see [`CsrfLayer::with_token_fallback`] (requires the `csrf-token` feature).
+//!
+//! To roll the protection out safely, start in report-only mode with
+//! [`CsrfLayer::with_report_only`]: requests that would be rejected reach the
+//! inner service with the [`ProtectionError`] in their extensions, and are
+//! reported to a callback.
+//!
 //! # Deployment caveat
 //!
 //! The middleware trusts whatever `Origin` and `Host` reach it. Reverse proxies
@@ -29,7 +39,9 @@
 //! strip `Origin` silently degrade the protection: the `Origin`/`Host`
 //! fallback can no longer match, and `Sec-Fetch-Site` becomes the only
 //! remaining line of defense. Configure intermediaries to forward both headers
-//! unchanged.
+//! unchanged, or to report the original host in `Forwarded` or
+//! `X-Forwarded-Host` and register them with
+//! [`CsrfLayer::with_trusted_proxies`].
 //!
 //! # Example
 //!
@@ -81,7 +93,49 @@
 //! # }
 //! ```
 //!
+//! # Resource isolation
+//!
+//! [`Csrf`] only protects state-changing requests. [`ResourceIsolationLayer`]
+//! implements the stricter [Fetch Metadata resource isolation policy][isolation],
+//! which also stops other sites from loading your resources, e.g. to probe
+//! them with timing or XS-Leak attacks. A request is allowed if any of the
+//! following hold:
+//!
+//! 1. `Sec-Fetch-Site` is missing (old browsers and non-browser clients).
+//! 2. `Sec-Fetch-Site` is `same-origin`, `same-site` or `none`.
+//! 3. It is a top-level navigation: a `GET` or `HEAD` with `Sec-Fetch-Mode: navigate`
+//!    and `Sec-Fetch-Dest: document`.
+//! 4. It is a CORS request (`Sec-Fetch-Mode: cors`) to a path allow-listed with
+//!    [`allow_cors`](ResourceIsolationLayer::allow_cors).
+//! 5. It is any other `GET` or `HEAD` to a path allow-listed with
+//!    [`allow_embedding`](ResourceIsolationLayer::allow_embedding).
+//!
+//! Rejections work like those of [`Csrf`], with
+//! [`ProtectionErrorKind::CrossSiteResourceRequest`]. With
+//! [`report_only`](ResourceIsolationLayer::report_only) violations are logged
+//! instead, so a policy can be tried out before it is enforced.
+//!
+//! ```
+//! use tower_http::csrf::{CsrfLayer, ResourceIsolationLayer};
+//! use tower::ServiceBuilder;
+//! # use tower::service_fn;
+//! # use http::{Request, Response};
+//! # let handle = service_fn(|_: Request<()>| async {
+//! #     Ok::<_, std::convert::Infallible>(Response::new(()))
+//! # });
+//!
+//! let service = ServiceBuilder::new()
+//!     .layer(CsrfLayer::new())
+//!     .layer(
+//!         ResourceIsolationLayer::new()
+//!             .allow_embedding("/static")
+//!             .allow_cors("/api/public"),
+//!     )
+//!     .service(handle);
+//! ```
+//!
 //! [cross-site request forgery]: https://developer.mozilla.org/en-US/docs/Glossary/CSRF
+//! [isolation]: https://web.dev/articles/fetch-metadata
 //! [filippo]: https://words.filippo.io/csrf/
 //! [go]: https://pkg.go.dev/net/http#CrossOriginProtection
 //! [`Sec-Fetch-Site`]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Sec-Fetch-Site
@@ -93,16 +147,27 @@ use std::sync::Arc;
 
 use http::{Method, Uri};
 
+#[cfg(feature = "csrf-token")]
+mod form_token;
 mod future;
 mod layer;
+mod resource_isolation;
 mod response;
 mod service;
+#[cfg(feature = "csrf-token")]
+mod token;
 mod url;
 
 pub use self::future::ResponseFuture;
 pub use self::layer::CsrfLayer;
+pub use self::resource_isolation::{ResourceIsolation, ResourceIsolationLayer};
 pub use self::response::{DefaultResponseForProtectionError, ResponseForProtectionError};
 pub use self::service::Csrf;
+#[cfg(feature = "csrf-token")]
+pub use self::{
+    form_token::{FormToken, FormTokenBody, FormTokenFuture, FormTokenLayer},
+    token::TokenFallback,
+};
 
 /// Errors that can occur while configuring [`CsrfLayer`].
 #[derive(Clone, Debug, PartialEq)]
@@ -139,6 +204,14 @@ pub enum ConfigError {
         /// The offending origin string.
         origin: String,
     },
+
+    /// A [`TokenFallback`] key shorter than 32 bytes was given. Short keys
+    /// make the token signatures guessable.
+    #[cfg(feature = "csrf-token")]
+    TokenKeyTooShort {
+        /// The length of the key, in bytes.
+        len: usize,
+    },
 }
 
 impl fmt::Display for ConfigError {
@@ -159,6 +232,11 @@ impl fmt::Display for ConfigError {
                 f,
                 "invalid origin {origin:?}: non-ASCII hostnames must be supplied in punycode (xn--…)"
             ),
+            #[cfg(feature = "csrf-token")]
+            ConfigError::TokenKeyTooShort { len } => write!(
+                f,
+                "CSRF token key is {len} bytes long, it must be at least 32 bytes"
+            ),
         }
     }
 }
@@ -199,6 +277,9 @@ impl fmt::Display for ProtectionError {
             ProtectionErrorKind::CrossOriginRequestFromOldBrowser => {
                 f.write_str("Cross-Origin request from old browser detected")
             }
+            ProtectionErrorKind::CrossSiteResourceRequest => {
+                f.write_str("Cross-Site resource request detected")
+            }
         }
     }
 }
@@ -216,10 +297,16 @@ pub enum ProtectionErrorKind {
     /// check. Modern browsers always send `Sec-Fetch-Site`, so this typically
     /// means the request came from an old browser or non-browser client.
     CrossOriginRequestFromOldBrowser,
+
+    /// A cross-site request that is neither a top-level navigation nor to a path
+    /// allow-listed for embedding or CORS was rejected by [`ResourceIsolation`].
+    CrossSiteResourceRequest,
 }
 
 type BypassFn = dyn Fn(&Method, &Uri) -> bool + Send + Sync + 'static;
 
+type ReportFn = dyn Fn(&ProtectionError, &Method, &Uri) + Send + Sync + 'static;
+
 struct DebugFn;
 
 impl Debug for DebugFn {
@@ -520,16 +607,22 @@ mod tests {
             .with_insecure_bypass(|method, uri| method == Method::POST && uri.path() == "/bypass")
             .layer(());
 
+        let token_fallback = if cfg!(feature = "csrf-token") {
+            "token_fallback: None, "
+        } else {
+            ""
+        };
+
         assert_eq!(
             format!("{:?}", middleware),
-            "Csrf { inner: (), insecure_bypass: Some(<fn>), trusted_origins: Origins({}), rejection_response: <fn> }"
+            format!("Csrf {{ inner: (), insecure_bypass: Some(<fn>), report: None, trusted_origins: Origins({{}}), trusted_proxies: None, {}rejection_response: <fn> }}", token_fallback)
         );
 
         let middleware = layer.layer(());
 
         assert_eq!(
             format!("{:?}", middleware),
-            "Csrf { inner: (), insecure_bypass: None, trusted_origins: Origins({}), rejection_response: <fn> }"
+            format!("Csrf {{ inner: (), insecure_bypass: None, report: None, trusted_origins: Origins({{}}), trusted_proxies: None, {}rejection_response: <fn> }}", token_fallback)
         );
     }
 
@@ -1005,4 +1098,460 @@ mod tests {
             assert_eq!(middleware.verify(&req), test.result, "{}", test.name);
         }
     }
+
+    #[test]
+    fn test_resource_isolation_policy() {
+        struct Test {
+            name: &'static str,
+            method: &'static str,
+            path: &'static str,
+            site: Option<&'static str>,
+            mode: Option<&'static str>,
+            dest: Option<&'static str>,
+            allowed: bool,
+        }
+
+        let tests = [
+            Test {
+                name: "no fetch metadata",
+                method: "GET",
+                path: "/private",
+                site: None,
+                mode: None,
+                dest: None,
+                allowed: true,
+            },
+            Test {
+                name: "same-site subresource",
+                method: "GET",
+                path: "/private",
+                site: Some("same-site"),
+                mode: Some("no-cors"),
+                dest: Some("image"),
+                allowed: true,
+            },
+            Test {
+                name: "user initiated",
+                method: "GET",
+                path: "/private",
+                site: Some("none"),
+                mode: Some("navigate"),
+                dest: Some("document"),
+                allowed: true,
+            },
+            Test {
+                name: "cross-site top-level navigation",
+                method: "GET",
+                path: "/private",
+                site: Some("cross-site"),
+                mode: Some("navigate"),
+                dest: Some("document"),
+                allowed: true,
+            },
+            Test {
+                name: "cross-site form post navigation",
+                method: "POST",
+                path: "/private",
+                site: Some("cross-site"),
+                mode: Some("navigate"),
+                dest: Some("document"),
+                allowed: false,
+            },
+            Test {
+                name: "cross-site iframe",
+                method: "GET",
+                path: "/private",
+                site: Some("cross-site"),
+                mode: Some("navigate"),
+                dest: Some("iframe"),
+                allowed: false,
+            },
+            Test {
+                name: "cross-site image",
+                method: "GET",
+                path: "/private",
+                site: Some("cross-site"),
+                mode: Some("no-cors"),
+                dest: Some("image"),
+                allowed: false,
+            },
+            Test {
+                name: "cross-site image from embeddable path",
+                method: "GET",
+                path: "/static/logo.png",
+                site: Some("cross-site"),
+                mode: Some("no-cors"),
+                dest: Some("image"),
+                allowed: true,
+            },
+            Test {
+                name: "embeddable prefix matches whole segments",
+                method: "GET",
+                path: "/statics/logo.png",
+                site: Some("cross-site"),
+                mode: Some("no-cors"),
+                dest: Some("image"),
+                allowed: false,
+            },
+            Test {
+                name: "cross-site fetch from embeddable path",
+                method: "GET",
+                path: "/static/logo.png",
+                site: Some("cross-site"),
+                mode: Some("cors"),
+                dest: Some("empty"),
+                allowed: false,
+            },
+            Test {
+                name: "cross-site fetch from cors path",
+                method: "DELETE",
+                path: "/api/items/1",
+                site: Some("cross-site"),
+                mode: Some("cors"),
+                dest: Some("empty"),
+                allowed: true,
+            },
+            Test {
+                name: "cross-site no-cors request to cors path",
+                method: "GET",
+                path: "/api/items/1",
+                site: Some("cross-site"),
+                mode: Some("no-cors"),
+                dest: Some("script"),
+                allowed: false,
+            },
+        ];
+
+        let middleware = ResourceIsolationLayer::new()
+            .allow_embedding("/static/")
+            .allow_cors("/api")
+            .layer(());
+
+        for test in tests {
+            let mut req = Request::builder().method(test.method).uri(test.path);
+            for (name, value) in [
+                ("sec-fetch-site", test.site),
+                ("sec-fetch-mode", test.mode),
+                ("sec-fetch-dest", test.dest),
+            ] {
+                if let Some(value) = value {
+                    req = req.header(name, value);
+                }
+            }
+            let req = req.body(()).unwrap();
+
+            let expected = if test.allowed {
+                Ok(())
+            } else {
+                Err(ProtectionError::new(
+                    ProtectionErrorKind::CrossSiteResourceRequest,
+                ))
+            };
+            assert_eq!(middleware.verify(&req), expected, "{}", test.name);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resource_isolation_service() {
+        let cross_site_image = || {
+            Request::builder()
+                .uri("/foo")
+                .header("sec-fetch-site", "cross-site")
+                .header("sec-fetch-mode", "no-cors")
+                .header("sec-fetch-dest", "image")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let svc = ResourceIsolationLayer::new().layer(echo_service());
+        let res = svc.oneshot(cross_site_image()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            res.extensions().get::<ProtectionError>(),
+            Some(&ProtectionError::new(
+                ProtectionErrorKind::CrossSiteResourceRequest
+            )),
+        );
+
+        let svc = ResourceIsolationLayer::new()
+            .report_only(true)
+            .layer(echo_service());
+        let res = svc.oneshot(cross_site_image()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"foo");
+    }
+
+    #[tokio::test]
+    async fn test_service_report_only() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let reported = Arc::new(AtomicUsize::new(0));
+        let svc = CsrfLayer::new()
+            .with_report_only({
+                let reported = reported.clone();
+                move |err, method, uri| {
+                    assert_eq!(err.kind(), ProtectionErrorKind::CrossOriginRequest);
+                    assert_eq!(method, Method::POST);
+                    assert_eq!(uri.path(), "/bar");
+                    reported.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+            .layer(service_fn(|req: Request<Body>| async move {
+                let err = req.extensions().get::<ProtectionError>().cloned();
+                let body = match err {
+                    Some(err) => err.to_string().into(),
+                    None => Body::empty(),
+                };
+                Ok::<_, Infallible>(Response::new(body))
+            }));
+
+        let req = Request::builder()
+            .method("POST")
+            .uri("/bar")
+            .header("sec-fetch-site", "cross-site")
+            .body(Body::empty())
+            .unwrap();
+        let res = svc.clone().oneshot(req).await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.extensions().get::<ProtectionError>().is_none());
+        let body = to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"Cross-Origin request detected");
+        assert_eq!(reported.load(Ordering::SeqCst), 1);
+
+        // Allowed requests aren't reported.
+        let req = Request::builder()
+            .method("POST")
+            .uri("/bar")
+            .header("sec-fetch-site", "same-origin")
+            .body(Body::empty())
+            .unwrap();
+        let res = svc.oneshot(req).await.unwrap();
+
+        let body = to_bytes(res.into_body()).await.unwrap();
+        assert!(body.is_empty());
+        assert_eq!(reported.load(Ordering::SeqCst), 1);
+    }
+
+    #[cfg(feature = "csrf-token")]
+    #[tokio::test]
+    async fn test_service_token_fallback() {
+        const KEY: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+        let tokens = TokenFallback::new(KEY).unwrap();
+        let svc = CsrfLayer::new()
+            .with_token_fallback(tokens.clone())
+            .layer(echo_service());
+
+        let mut response = Response::new(());
+        let token = tokens.set_cookie(&mut response);
+        let set_cookie = response.headers()[http::header::SET_COOKIE]
+            .to_str()
+            .unwrap();
+        assert_eq!(
+            set_cookie,
+            format!("csrf_token={}; Path=/; Secure; SameSite=Strict", token)
+        );
+
+        let forged = TokenFallback::new([b'x'; 32]).unwrap().mint();
+        let other = tokens.mint();
+
+        let cases = [
+            (Some(token.as_str()), Some(token.as_str()), StatusCode::OK),
+            (Some(token.as_str()), None, StatusCode::FORBIDDEN),
+            (None, Some(token.as_str()), StatusCode::FORBIDDEN),
+            (
+                Some(token.as_str()),
+                Some(other.as_str()),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                Some(forged.as_str()),
+                Some(forged.as_str()),
+                StatusCode::FORBIDDEN,
+            ),
+            (Some("garbage"), Some("garbage"), StatusCode::FORBIDDEN),
+        ];
+
+        for (cookie, header, status) in cases {
+            let mut req = Request::builder()
+                .method("POST")
+                .uri("/bar")
+                .header("host", "example.com")
+                .header("origin", "https://other.example");
+            if let Some(cookie) = cookie {
+                req = req.header("cookie", format!("theme=dark; csrf_token={}", cookie));
+            }
+            if let Some(header) = header {
+                req = req.header("x-csrf-token", header);
+            }
+
+            let res = svc
+                .clone()
+                .oneshot(req.body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(
+                res.status(),
+                status,
+                "cookie: {:?}, header: {:?}",
+                cookie,
+                header
+            );
+        }
+
+        // The token doesn't override an explicit cross-site signal.
+        let req = Request::builder()
+            .method("POST")
+            .uri("/bar")
+            .header("sec-fetch-site", "cross-site")
+            .header("cookie", format!("csrf_token={}", token))
+            .header("x-csrf-token", &token)
+            .body(Body::empty())
+            .unwrap();
+        let res = svc.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        assert_eq!(
+            TokenFallback::new(b"too short").unwrap_err(),
+            ConfigError::TokenKeyTooShort { len: 9 }
+        );
+    }
+
+    #[cfg(feature = "csrf-token")]
+    #[tokio::test]
+    async fn test_service_token_fallback_form_field() {
+        use tower::ServiceBuilder;
+
+        let tokens = TokenFallback::new(b"0123456789abcdef0123456789abcdef")
+            .unwrap()
+            .max_form_size(128);
+        let svc = ServiceBuilder::new()
+            .layer(tokens.form_layer())
+            .layer(CsrfLayer::new().with_token_fallback(tokens.clone()))
+            .service_fn(|req: Request<FormTokenBody<Body>>| async move {
+                // The form is still readable by the handler.
+                let body = to_bytes(req.into_body()).await.unwrap();
+                Ok::<_, Infallible>(Response::new(Body::from(body)))
+            });
+        let token = tokens.mint();
+        let other = tokens.mint();
+
+        let cases = [
+            (format!("name=a+b&csrf_token={}", token), StatusCode::OK),
+            (format!("csrf_token={}", other), StatusCode::FORBIDDEN),
+            ("name=a+b".to_owned(), StatusCode::FORBIDDEN),
+            ("x".repeat(129), StatusCode::PAYLOAD_TOO_LARGE),
+        ];
+        for (form, status) in cases {
+            let req = Request::builder()
+                .method("POST")
+                .uri("/bar")
+                .header("host", "example.com")
+                .header("origin", "https://other.example")
+                .header("content-type", "application/x-www-form-urlencoded")
+                .header("cookie", format!("csrf_token={}", token))
+                .body(Body::from(form.clone()))
+                .unwrap();
+            let res = svc.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), status, "form: {:?}", form);
+            if status == StatusCode::OK {
+                assert_eq!(to_bytes(res.into_body()).await.unwrap(), form);
+            }
+        }
+    }
+
+    #[cfg(feature = "csrf-token")]
+    #[tokio::test]
+    async fn test_service_token_fallback_max_age_and_session() {
+        use std::time::{Duration, SystemTime};
+
+        let tokens = TokenFallback::new(b"0123456789abcdef0123456789abcdef")
+            .unwrap()
+            .max_age(Duration::from_secs(60 * 60))
+            .bind_to_session(|headers, _| {
+                headers
+                    .get("x-session")
+                    .map(|session| session.as_bytes().to_vec())
+            });
+        let svc = CsrfLayer::new()
+            .with_token_fallback(tokens.clone())
+            .layer(echo_service());
+
+        let hour_ago = SystemTime::now() - Duration::from_secs(60 * 60 + 1);
+        let fresh = tokens.mint_for_session(b"alice");
+        let expired = tokens.mint_at(hour_ago, b"alice");
+        let unbound = tokens.mint();
+
+        let cases = [
+            (&fresh, Some("alice"), StatusCode::OK),
+            (&fresh, Some("mallory"), StatusCode::FORBIDDEN),
+            (&fresh, None, StatusCode::FORBIDDEN),
+            (&expired, Some("alice"), StatusCode::FORBIDDEN),
+            (&unbound, Some("alice"), StatusCode::FORBIDDEN),
+        ];
+        for (token, session, status) in cases {
+            let mut req = Request::builder()
+                .method("POST")
+                .uri("/bar")
+                .header("host", "example.com")
+                .header("origin", "https://other.example")
+                .header("cookie", format!("csrf_token={}", token))
+                .header("x-csrf-token", token.as_str());
+            if let Some(session) = session {
+                req = req.header("x-session", session);
+            }
+            let res = svc
+                .clone()
+                .oneshot(req.body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(res.status(), status, "session: {:?}", session);
+        }
+    }
+
+    #[test]
+    fn test_middleware_trusted_proxies() {
+        use crate::forwarded::TrustedProxies;
+        use std::net::SocketAddr;
+
+        let middleware = CsrfLayer::new()
+            .with_trusted_proxies(TrustedProxies::new(["10.0.0.0/8"]).unwrap())
+            .layer(());
+
+        let cross_origin = Err(ProtectionError::new(
+            ProtectionErrorKind::CrossOriginRequestFromOldBrowser,
+        ));
+
+        let cases = [
+            ("10.0.0.1:1234", "https://example.com", Ok(())),
+            // The scheme is forwarded, so it has to match too.
+            ("10.0.0.1:1234", "http://example.com", cross_origin.clone()),
+            (
+                "10.0.0.1:1234",
+                "https://backend.internal",
+                cross_origin.clone(),
+            ),
+            // Forwarding headers from other peers are ignored.
+            ("203.0.113.7:1234", "https://example.com", cross_origin),
+            ("203.0.113.7:1234", "https://backend.internal", Ok(())),
+        ];
+
+        for (peer, origin, result) in cases {
+            let mut req = Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("host", "backend.internal")
+                .header("origin", origin)
+                .header("x-forwarded-host", "example.com")
+                .header("x-forwarded-proto", "https")
+                .body(())
+                .unwrap();
+            req.extensions_mut()
+                .insert(peer.parse::<SocketAddr>().unwrap());
+
+            assert_eq!(middleware.verify(&req), result, "{} {}", peer, origin);
+        }
+    }
 }
@@ -0,0 +1,257 @@
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use http::{Method, Request, Response};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use super::future::ResponseFuture;
+use super::{
+    DebugFn, DefaultResponseForProtectionError, ProtectionError, ProtectionErrorKind,
+    ResponseForProtectionError,
+};
+use crate::helpers::PathPrefix;
+
+/// Layer that applies the [`ResourceIsolation`] middleware.
+///
+/// See the [module docs](crate::csrf#resource-isolation) for more details.
+#[derive(Clone)]
+#[must_use]
+pub struct ResourceIsolationLayer<T = DefaultResponseForProtectionError> {
+    embeddable: Paths,
+    cors_accessible: Paths,
+    report_only: bool,
+    rejection_response: T,
+}
+
+impl Default for ResourceIsolationLayer {
+    fn default() -> Self {
+        Self {
+            embeddable: Paths::default(),
+            cors_accessible: Paths::default(),
+            report_only: false,
+            rejection_response: DefaultResponseForProtectionError,
+        }
+    }
+}
+
+impl<T> Debug for ResourceIsolationLayer<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResourceIsolationLayer")
+            .field("embeddable", &self.embeddable)
+            .field("cors_accessible", &self.cors_accessible)
+            .field("report_only", &self.report_only)
+            .field("rejection_response", &DebugFn)
+            .finish()
+    }
+}
+
+impl ResourceIsolationLayer {
+    /// Creates a new `ResourceIsolationLayer` with no allow-listed paths, enforcing the policy
+    /// with the default rejection response.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<T> ResourceIsolationLayer<T> {
+    /// Allows cross-site pages to embed resources under `prefix`, for example as images, scripts
+    /// or iframes.
+    ///
+    /// Cross-site `GET` and `HEAD` requests to these paths are allowed, except CORS requests (see
+    /// [`allow_cors`](Self::allow_cors) for those).
+    ///
+    /// The prefix is matched by whole path segments, so `/static` matches `/static` and
+    /// `/static/app.js` but not `/statics`.
+    ///
+    /// # Panics
+    ///
+    /// If `prefix` doesn't start with a `/`.
+    pub fn allow_embedding(mut self, prefix: &str) -> Self {
+        self.embeddable.insert(prefix);
+        self
+    }
+
+    /// Allows cross-site CORS requests (`Sec-Fetch-Mode: cors`) to paths under `prefix`, with any
+    /// method.
+    ///
+    /// Which origins may read the responses is still up to CORS, see [`cors`](crate::cors).
+    ///
+    /// The prefix is matched by whole path segments, so `/api` matches `/api` and `/api/users`
+    /// but not `/apis`.
+    ///
+    /// # Panics
+    ///
+    /// If `prefix` doesn't start with a `/`.
+    pub fn allow_cors(mut self, prefix: &str) -> Self {
+        self.cors_accessible.insert(prefix);
+        self
+    }
+
+    /// Reports requests violating the policy instead of rejecting them.
+    ///
+    /// Violations are logged with [`tracing`] at the `WARN` level (when the `tracing` feature is
+    /// enabled) and the request is passed to the inner service. Useful to find out what a policy
+    /// would break before enforcing it.
+    ///
+    /// [`tracing`]: https://crates.io/crates/tracing
+    pub fn report_only(mut self, report_only: bool) -> Self {
+        self.report_only = report_only;
+        self
+    }
+
+    /// Replaces the response builder used when a request is rejected.
+    ///
+    /// Works like [`CsrfLayer::with_rejection_response`](super::CsrfLayer::with_rejection_response):
+    /// the default builder returns a `403 Forbidden` with an empty body, and the
+    /// [`ProtectionError`] is attached to the response's extensions regardless of the builder.
+    pub fn with_rejection_response<R>(self, rejection_response: R) -> ResourceIsolationLayer<R>
+    where
+        R: Clone,
+    {
+        ResourceIsolationLayer {
+            embeddable: self.embeddable,
+            cors_accessible: self.cors_accessible,
+            report_only: self.report_only,
+            rejection_response,
+        }
+    }
+}
+
+impl<S, T> Layer<S> for ResourceIsolationLayer<T>
+where
+    T: Clone,
+{
+    type Service = ResourceIsolation<S, T>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ResourceIsolation {
+            inner,
+            embeddable: self.embeddable.clone(),
+            cors_accessible: self.cors_accessible.clone(),
+            report_only: self.report_only,
+            rejection_response: self.rejection_response.clone(),
+        }
+    }
+}
+
+/// Middleware that enforces a Fetch Metadata resource isolation policy.
+///
+/// See the [module docs](crate::csrf#resource-isolation) for more details.
+#[derive(Clone)]
+#[must_use]
+pub struct ResourceIsolation<S, T = DefaultResponseForProtectionError> {
+    inner: S,
+    embeddable: Paths,
+    cors_accessible: Paths,
+    report_only: bool,
+    rejection_response: T,
+}
+
+impl<S, T> ResourceIsolation<S, T> {
+    define_inner_service_accessors!();
+
+    pub(super) fn verify<Body>(&self, req: &Request<Body>) -> Result<(), ProtectionError> {
+        let header = |name| req.headers().get(name).map(|h| h.as_bytes());
+
+        // Fetch spec mandates lowercase here; exact byte match is intentional.
+        match header("sec-fetch-site") {
+            // Not sent by old browsers and non-browser clients, which this policy can't judge.
+            None | Some(b"") => return Ok(()),
+            Some(b"same-origin" | b"same-site" | b"none") => return Ok(()),
+            Some(_) => {}
+        }
+
+        let mode = header("sec-fetch-mode");
+        let path = req.uri().path();
+        let is_safe = matches!(req.method(), &Method::GET | &Method::HEAD);
+
+        // Top-level navigations, such as following a link from another site.
+        if is_safe && mode == Some(b"navigate") && header("sec-fetch-dest") == Some(b"document") {
+            return Ok(());
+        }
+
+        if mode == Some(b"cors") {
+            if self.cors_accessible.matches(path) {
+                return Ok(());
+            }
+        } else if is_safe && self.embeddable.matches(path) {
+            return Ok(());
+        }
+
+        Err(ProtectionError::new(
+            ProtectionErrorKind::CrossSiteResourceRequest,
+        ))
+    }
+}
+
+impl<S: Debug, T> Debug for ResourceIsolation<S, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResourceIsolation")
+            .field("inner", &self.inner)
+            .field("embeddable", &self.embeddable)
+            .field("cors_accessible", &self.cors_accessible)
+            .field("report_only", &self.report_only)
+            .field("rejection_response", &DebugFn)
+            .finish()
+    }
+}
+
+impl<S, T, ReqBody, ResBody> Service<Request<ReqBody>> for ResourceIsolation<S, T>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    T: ResponseForProtectionError<ResBody>,
+{
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+    type Response = Response<ResBody>;
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        match self.verify(&req) {
+            Ok(_) => ResponseFuture::future(self.inner.call(req)),
+            Err(_err) if self.report_only => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(uri = %req.uri().path(), error = %_err, "request reported");
+
+                ResponseFuture::future(self.inner.call(req))
+            }
+            Err(err) => {
+                #[cfg(feature = "tracing")]
+                tracing::trace!(uri = %req.uri().path(), error = %err, "request rejected");
+
+                let mut response = self
+                    .rejection_response
+                    .response_for_protection_error(err.clone());
+
+                response.extensions_mut().insert(err);
+
+                ResponseFuture::rejected(Ok(response))
+            }
+        }
+    }
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+}
+
+/// Path prefixes, stored without a trailing `/`, so `/` is the empty string.
+#[derive(Clone, Default)]
+struct Paths(Arc<Vec<PathPrefix>>);
+
+impl Paths {
+    fn insert(&mut self, prefix: &str) {
+        Arc::make_mut(&mut self.0).push(PathPrefix::new(prefix));
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        self.0.iter().any(|prefix| prefix.matches(path))
+    }
+}
+
+impl Debug for Paths {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Paths").field(&self.0).finish()
+    }
+}
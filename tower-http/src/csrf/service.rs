@@ -9,8 +9,9 @@ use tower_service::Service;
 use super::future::ResponseFuture;
 use super::{
     BypassFn, DebugFn, DefaultResponseForProtectionError, Origins, ProtectionError,
-    ProtectionErrorKind, ResponseForProtectionError,
+    ProtectionErrorKind, ReportFn, ResponseForProtectionError,
 };
+use crate::forwarded::TrustedProxies;
 
 /// Middleware that enforces cross-origin request forgery (CSRF) protection.
 ///
@@ -20,22 +21,33 @@ use super::{
 pub struct Csrf<S, T = DefaultResponseForProtectionError> {
     inner: S,
     insecure_bypass: Option<Arc<BypassFn>>,
+    report: Option<Arc<ReportFn>>,
     rejection_response: T,
     trusted_origins: Origins,
+    trusted_proxies: Option<TrustedProxies>,
+    #[cfg(feature = "csrf-token")]
+    token_fallback: Option<super::TokenFallback>,
 }
 
 impl<S, T> Csrf<S, T> {
     pub(super) fn new(
         inner: S,
         insecure_bypass: Option<Arc<BypassFn>>,
+        report: Option<Arc<ReportFn>>,
         rejection_response: T,
         trusted_origins: Origins,
+        trusted_proxies: Option<TrustedProxies>,
+        #[cfg(feature = "csrf-token")] token_fallback: Option<super::TokenFallback>,
     ) -> Self {
         Self {
             inner,
             insecure_bypass,
+            report,
             rejection_response,
             trusted_origins,
+            trusted_proxies,
+            #[cfg(feature = "csrf-token")]
+            token_fallback,
         }
     }
 
@@ -109,18 +121,33 @@ impl<S, T> Csrf<S, T> {
 
         let host = req.headers().get("host").map(|h| h.as_bytes());
 
+        // Behind trusted proxies, the host and scheme the client used are the
+        // forwarded ones.
+        let forwarded = self
+            .trusted_proxies
+            .as_ref()
+            .map(|proxies| proxies.effective_authority(req));
+
         // Mirrors the reference's `url.Parse(origin).Host == req.Host`. Per RFC 7230
         // §5.3, req.Host is the request-target authority (absolute-form URI / HTTP/2
-        // `:authority`) if present, else the Host header. Byte-exact and scheme-blind,
-        // so an http→https mismatch can't be caught here — we fail open (HSTS helps).
-        let effective_host = req
-            .uri()
-            .authority()
-            .map(|a| a.as_str().as_bytes())
-            .or(host);
+        // `:authority`) if present, else the Host header. Byte-exact and scheme-blind
+        // unless a proxy forwarded the scheme, so an http→https mismatch usually
+        // can't be caught here — we fail open (HSTS helps).
+        let effective_host = match &forwarded {
+            Some(forwarded) => forwarded.authority().map(|a| a.as_str().as_bytes()),
+            None => req
+                .uri()
+                .authority()
+                .map(|a| a.as_str().as_bytes())
+                .or(host),
+        };
+        let effective_scheme = forwarded.as_ref().and_then(|f| f.scheme());
 
         if let (Some(uri), Some(effective_host)) = (&origin_uri, effective_host) {
-            if uri.authority().map(|a| a.as_str().as_bytes()) == Some(effective_host) {
+            let same_scheme = effective_scheme.map_or(true, |scheme| uri.scheme() == Some(scheme));
+
+            if same_scheme && uri.authority().map(|a| a.as_str().as_bytes()) == Some(effective_host)
+            {
                 #[cfg(feature = "tracing")]
                 tracing::trace!(uri = %req.uri().path(), "request passed: origin is same as host");
                 return Ok(());
@@ -131,6 +158,15 @@ impl<S, T> Csrf<S, T> {
             return Ok(());
         }
 
+        #[cfg(feature = "csrf-token")]
+        if let Some(tokens) = &self.token_fallback {
+            if tokens.verify(req.headers(), req.extensions()) {
+                #[cfg(feature = "tracing")]
+                tracing::trace!(uri = %req.uri().path(), "request passed: valid CSRF token");
+                return Ok(());
+            }
+        }
+
         Err(ProtectionError::new(
             ProtectionErrorKind::CrossOriginRequestFromOldBrowser,
         ))
@@ -146,23 +182,30 @@ where
         Self {
             inner: S::default(),
             insecure_bypass: None,
+            report: None,
             rejection_response: T::default(),
             trusted_origins: Origins::default(),
+            trusted_proxies: None,
+            #[cfg(feature = "csrf-token")]
+            token_fallback: None,
         }
     }
 }
 
 impl<S: Debug, T> Debug for Csrf<S, T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Csrf")
-            .field("inner", &self.inner)
+        let mut f = f.debug_struct("Csrf");
+        f.field("inner", &self.inner)
             .field(
                 "insecure_bypass",
                 &self.insecure_bypass.as_ref().map(|_| DebugFn),
             )
+            .field("report", &self.report.as_ref().map(|_| DebugFn))
             .field("trusted_origins", &self.trusted_origins)
-            .field("rejection_response", &DebugFn)
-            .finish()
+            .field("trusted_proxies", &self.trusted_proxies);
+        #[cfg(feature = "csrf-token")]
+        f.field("token_fallback", &self.token_fallback);
+        f.field("rejection_response", &DebugFn).finish()
     }
 }
 
@@ -175,9 +218,20 @@ where
     type Future = ResponseFuture<S::Future>;
     type Response = Response<ResBody>;
 
-    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
         match self.verify(&req) {
             Ok(_) => ResponseFuture::future(self.inner.call(req)),
+            Err(err) if self.report.is_some() => {
+                #[cfg(feature = "tracing")]
+                tracing::trace!(uri = %req.uri().path(), error = %err, "request reported");
+
+                if let Some(report) = &self.report {
+                    report(&err, req.method(), req.uri());
+                }
+                req.extensions_mut().insert(err);
+
+                ResponseFuture::future(self.inner.call(req))
+            }
             Err(err) => {
                 #[cfg(feature = "tracing")]
                 tracing::trace!(uri = %req.uri().path(), error = %err, "request rejected");
@@ -0,0 +1,319 @@
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::Engine as _;
+use hmac::{Hmac, Mac};
+use http::{header, Extensions, HeaderMap, HeaderName, HeaderValue, Response};
+use sha2::Sha256;
+
+use super::form_token::{FormTokenLayer, SubmittedToken};
+use super::{ConfigError, DebugFn};
+use crate::helpers::constant_time_eq;
+
+const BASE64: base64::engine::GeneralPurpose = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+
+const NONCE_LEN: usize = 16;
+const ISSUED_AT_LEN: usize = 8;
+const MAC_LEN: usize = 32;
+const TOKEN_LEN: usize = NONCE_LEN + ISSUED_AT_LEN + MAC_LEN;
+const MIN_KEY_LEN: usize = 32;
+
+type SessionFn = dyn Fn(&HeaderMap, &Extensions) -> Option<Vec<u8>> + Send + Sync + 'static;
+
+/// Double-submit token fallback for requests [`Csrf`] can't judge by their headers.
+///
+/// Requests without `Sec-Fetch-Site` whose `Origin` doesn't match the host, which [`Csrf`] would
+/// reject with [`ProtectionErrorKind::CrossOriginRequestFromOldBrowser`], are allowed if they
+/// carry a valid token both in a cookie and in a request header or form field. The token is
+/// signed with HMAC-SHA256, so it can't be forged by a site that manages to plant a cookie, and
+/// another site can't read the cookie to copy it into the request.
+///
+/// Tokens record when they were minted and expire after [`max_age`](Self::max_age). They can
+/// also be [bound to a session](Self::bind_to_session), so a leaked token is only valid for the
+/// session it was minted for.
+///
+/// Mint tokens into responses with [`set_cookie`](Self::set_cookie) and have clients send the
+/// token back in the [header](Self::header_name) or, for plain HTML forms, in the
+/// [form field](Self::form_field). Reading form fields requires buffering the request body,
+/// which is done by the separate [`form_layer`](Self::form_layer).
+///
+/// Requires the `csrf-token` feature.
+///
+/// ```
+/// use http::Response;
+/// use tower::ServiceBuilder;
+/// use tower_http::csrf::{CsrfLayer, TokenFallback};
+/// # use http::Request;
+/// # use tower_http::csrf::FormTokenBody;
+/// # let handler = tower::service_fn(|_: Request<FormTokenBody<String>>| async {
+/// #     Ok::<_, std::convert::Infallible>(Response::new(String::new()))
+/// # });
+///
+/// let tokens = TokenFallback::new(b"a secret key of at least 32 bytes")?;
+/// let service = ServiceBuilder::new()
+///     // Reads the token from form submissions...
+///     .layer(tokens.form_layer())
+///     // ...for the CSRF protection, which must come after.
+///     .layer(CsrfLayer::new().with_token_fallback(tokens.clone()))
+///     .service(handler);
+///
+/// // In a handler serving the page that makes the requests:
+/// let mut response = Response::new(());
+/// let token = tokens.set_cookie(&mut response);
+/// // ...and embed `token` in a `csrf_token` form field or the `X-CSRF-Token` header.
+/// # Ok::<_, tower_http::csrf::ConfigError>(())
+/// ```
+///
+/// [`Csrf`]: super::Csrf
+/// [`ProtectionErrorKind::CrossOriginRequestFromOldBrowser`]: super::ProtectionErrorKind::CrossOriginRequestFromOldBrowser
+#[derive(Clone)]
+pub struct TokenFallback {
+    mac: Hmac<Sha256>,
+    cookie_name: String,
+    header_name: HeaderName,
+    form_field: String,
+    max_form_size: usize,
+    max_age: Duration,
+    session: Option<Arc<SessionFn>>,
+}
+
+impl TokenFallback {
+    /// Creates a token fallback signing tokens with `key`.
+    ///
+    /// The cookie and form field are named `csrf_token` and the header `x-csrf-token` by
+    /// default. Tokens expire after 12 hours.
+    ///
+    /// Keys shorter than 32 bytes are rejected with [`ConfigError::TokenKeyTooShort`].
+    pub fn new<K: AsRef<[u8]>>(key: K) -> Result<Self, ConfigError> {
+        let key = key.as_ref();
+        if key.len() < MIN_KEY_LEN {
+            return Err(ConfigError::TokenKeyTooShort { len: key.len() });
+        }
+
+        Ok(Self {
+            mac: Hmac::new_from_slice(key).expect("HMAC accepts keys of any length"),
+            cookie_name: "csrf_token".to_owned(),
+            header_name: HeaderName::from_static("x-csrf-token"),
+            form_field: "csrf_token".to_owned(),
+            max_form_size: 64 * 1024,
+            max_age: Duration::from_secs(12 * 60 * 60),
+            session: None,
+        })
+    }
+
+    /// Sets the name of the cookie holding the token.
+    ///
+    /// # Panics
+    ///
+    /// If `name` isn't a valid cookie name.
+    pub fn cookie_name<S: Into<String>>(mut self, name: S) -> Self {
+        let name = name.into();
+        assert!(
+            !name.is_empty() && HeaderName::from_bytes(name.as_bytes()).is_ok(),
+            "invalid cookie name `{}`",
+            name
+        );
+
+        self.cookie_name = name;
+        self
+    }
+
+    /// Sets the name of the request header holding the token.
+    pub fn header_name(mut self, name: HeaderName) -> Self {
+        self.header_name = name;
+        self
+    }
+
+    /// Sets the name of the form field holding the token.
+    ///
+    /// Form fields are read by the [`form_layer`](Self::form_layer).
+    pub fn form_field<S: Into<String>>(mut self, name: S) -> Self {
+        self.form_field = name.into();
+        self
+    }
+
+    /// Sets the largest form body the [`form_layer`](Self::form_layer) buffers, in bytes.
+    ///
+    /// Larger forms are rejected with `413 Payload Too Large`. Defaults to 64 KiB.
+    pub fn max_form_size(mut self, bytes: usize) -> Self {
+        self.max_form_size = bytes;
+        self
+    }
+
+    /// Sets how long tokens are valid after being minted.
+    ///
+    /// Defaults to 12 hours.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Binds tokens to the session value returned by `session`.
+    ///
+    /// Tokens must be minted with [`mint_for_session`](Self::mint_for_session) or
+    /// [`set_cookie_for_session`](Self::set_cookie_for_session), and are only valid for requests
+    /// for which `session` returns the same value, such as a session ID taken from a cookie or
+    /// the request extensions. Requests without a session are rejected.
+    pub fn bind_to_session<F>(mut self, session: F) -> Self
+    where
+        F: Fn(&HeaderMap, &Extensions) -> Option<Vec<u8>> + Send + Sync + 'static,
+    {
+        self.session = Some(Arc::new(session));
+        self
+    }
+
+    /// Returns a layer reading the token from the [form field](Self::form_field) of form
+    /// submissions.
+    ///
+    /// It must be applied outside [`Csrf`], so the token is available when the request is
+    /// checked. See [`FormToken`] for details.
+    ///
+    /// [`Csrf`]: super::Csrf
+    /// [`FormToken`]: super::FormToken
+    pub fn form_layer(&self) -> FormTokenLayer {
+        FormTokenLayer::new(self.form_field.clone(), self.max_form_size)
+    }
+
+    /// Mints a new token that isn't bound to a session.
+    pub fn mint(&self) -> String {
+        self.mint_at(SystemTime::now(), b"")
+    }
+
+    /// Mints a new token bound to `session`.
+    ///
+    /// See [`bind_to_session`](Self::bind_to_session).
+    pub fn mint_for_session(&self, session: &[u8]) -> String {
+        self.mint_at(SystemTime::now(), session)
+    }
+
+    pub(super) fn mint_at(&self, issued_at: SystemTime, session: &[u8]) -> String {
+        let issued_at = issued_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let mut token = [0; TOKEN_LEN];
+        let (payload, mac) = token.split_at_mut(NONCE_LEN + ISSUED_AT_LEN);
+        let (nonce, time) = payload.split_at_mut(NONCE_LEN);
+        getrandom::getrandom(nonce).expect("failed to generate a random nonce");
+        time.copy_from_slice(&issued_at.to_be_bytes());
+        mac.copy_from_slice(&self.sign(payload, session));
+
+        BASE64.encode(token)
+    }
+
+    /// Mints a new token and adds a `Set-Cookie` header holding it to `response`.
+    ///
+    /// Returns the token, to be handed to the client so it can send it in the
+    /// [header](Self::header_name) or [form field](Self::form_field). The cookie is `Secure` and
+    /// `SameSite=Strict`, but not `HttpOnly`, so scripts on the page can read it.
+    pub fn set_cookie<B>(&self, response: &mut Response<B>) -> String {
+        let token = self.mint();
+        self.add_cookie(response, &token);
+        token
+    }
+
+    /// Like [`set_cookie`](Self::set_cookie), but mints a token bound to `session`.
+    pub fn set_cookie_for_session<B>(&self, response: &mut Response<B>, session: &[u8]) -> String {
+        let token = self.mint_for_session(session);
+        self.add_cookie(response, &token);
+        token
+    }
+
+    fn add_cookie<B>(&self, response: &mut Response<B>, token: &str) {
+        let cookie = format!(
+            "{}={}; Path=/; Secure; SameSite=Strict",
+            self.cookie_name, token
+        );
+        let cookie = HeaderValue::from_str(&cookie).expect("cookie name and token are valid");
+        response.headers_mut().append(header::SET_COOKIE, cookie);
+    }
+
+    /// Does the request carry the same valid token in the cookie and the header or form field?
+    pub(super) fn verify(&self, headers: &HeaderMap, extensions: &Extensions) -> bool {
+        let Some(cookie) = self.cookie(headers) else {
+            return false;
+        };
+        let submitted = headers
+            .get(&self.header_name)
+            .map(HeaderValue::as_bytes)
+            .or_else(|| {
+                extensions
+                    .get::<SubmittedToken>()
+                    .map(|token| token.0.as_bytes())
+            });
+        let Some(submitted) = submitted else {
+            return false;
+        };
+        if !constant_time_eq(submitted, cookie) {
+            return false;
+        }
+
+        let session = match &self.session {
+            Some(session) => match session(headers, extensions) {
+                Some(session) => session,
+                None => return false,
+            },
+            None => Vec::new(),
+        };
+        self.verify_token(cookie, &session, SystemTime::now())
+    }
+
+    fn verify_token(&self, token: &[u8], session: &[u8], now: SystemTime) -> bool {
+        let Ok(token) = BASE64.decode(token) else {
+            return false;
+        };
+        if token.len() != TOKEN_LEN {
+            return false;
+        }
+        let (payload, mac) = token.split_at(NONCE_LEN + ISSUED_AT_LEN);
+
+        let mut expected = self.mac.clone();
+        expected.update(payload);
+        expected.update(session);
+        if expected.verify_slice(mac).is_err() {
+            return false;
+        }
+
+        let mut issued_at = [0; ISSUED_AT_LEN];
+        issued_at.copy_from_slice(&payload[NONCE_LEN..]);
+        let issued_at = u64::from_be_bytes(issued_at);
+        let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        now.saturating_sub(issued_at) <= self.max_age.as_secs()
+    }
+
+    fn sign(&self, payload: &[u8], session: &[u8]) -> [u8; MAC_LEN] {
+        let mut mac = self.mac.clone();
+        mac.update(payload);
+        mac.update(session);
+        mac.finalize().into_bytes().into()
+    }
+
+    fn cookie<'a>(&self, headers: &'a HeaderMap) -> Option<&'a [u8]> {
+        headers
+            .get_all(header::COOKIE)
+            .iter()
+            .flat_map(|value| value.as_bytes().split(|&b| b == b';'))
+            .find_map(|pair| {
+                let start = pair.iter().position(|b| !b.is_ascii_whitespace())?;
+                let pair = &pair[start..];
+                let value = pair.strip_prefix(self.cookie_name.as_bytes())?;
+                value.strip_prefix(b"=")
+            })
+    }
+}
+
+impl Debug for TokenFallback {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        // Deliberately leaves out the key.
+        f.debug_struct("TokenFallback")
+            .field("cookie_name", &self.cookie_name)
+            .field("header_name", &self.header_name)
+            .field("form_field", &self.form_field)
+            .field("max_form_size", &self.max_form_size)
+            .field("max_age", &self.max_age)
+            .field("session", &self.session.as_ref().map(|_| DebugFn))
+            .finish()
+    }
+}
@@ -1,5 +1,9 @@
 #![allow(unused_imports)]
 
+use super::{
+    coding::{poll_decode, Coding, Decoder},
+    limits::LimitState,
+};
 use crate::compression_utils::CompressionLevel;
 use crate::{
     compression_utils::{AsyncReadBody, BodyIntoStream, DecorateAsyncRead, WrapBody},
@@ -15,7 +19,7 @@ use async_compression::tokio::bufread::ZlibDecoder;
 use async_compression::tokio::bufread::ZstdDecoder;
 use bytes::{Buf, Bytes};
 use http::HeaderMap;
-use http_body::{Body, SizeHint};
+use http_body::{Body, Frame, SizeHint};
 use pin_project_lite::pin_project;
 use std::task::Context;
 use std::{
@@ -37,6 +41,10 @@ pin_project! {
     {
         #[pin]
         pub(crate) inner: BodyInner<B>,
+        // Decoders for the codings applied before the one `inner` decodes, innermost first.
+        decoders: Vec<Decoder>,
+        trailers: Option<HeaderMap>,
+        limits: LimitState,
     }
 }
 
@@ -49,6 +57,9 @@ where
             inner: BodyInner::Identity {
                 inner: B::default(),
             },
+            decoders: Vec::new(),
+            trailers: None,
+            limits: LimitState::default(),
         }
     }
 }
@@ -58,7 +69,26 @@ where
     B: Body,
 {
     pub(crate) fn new(inner: BodyInner<B>) -> Self {
-        Self { inner }
+        Self {
+            inner,
+            decoders: Vec::new(),
+            trailers: None,
+            limits: LimitState::default(),
+        }
+    }
+
+    /// Decode `body` from `codings`, given in the order they were applied.
+    pub(crate) fn decode(body: B, codings: &[Coding]) -> Self {
+        let (outermost, rest) = codings.split_last().expect("at least one coding to decode");
+
+        let mut this = Self::new(BodyInner::decode(body, *outermost));
+        this.decoders = rest.iter().copied().map(Decoder::new).collect();
+        this
+    }
+
+    pub(crate) fn with_limits(mut self, limits: LimitState) -> Self {
+        self.limits = limits;
+        self
     }
 
     /// Get a reference to the inner body
@@ -284,6 +314,44 @@ impl<B: Body> BodyInner<B> {
     pub(crate) fn identity(inner: B) -> Self {
         Self::Identity { inner }
     }
+
+    fn decode(body: B, coding: Coding) -> Self {
+        match coding {
+            #[cfg(feature = "decompression-gzip")]
+            Coding::Gzip => Self::gzip(WrapBody::new(body, CompressionLevel::default())),
+            #[cfg(feature = "decompression-deflate")]
+            Coding::Deflate => Self::deflate(WrapBody::new(body, CompressionLevel::default())),
+            #[cfg(feature = "decompression-br")]
+            Coding::Brotli => Self::brotli(WrapBody::new(body, CompressionLevel::default())),
+            #[cfg(feature = "decompression-zstd")]
+            Coding::Zstd => Self::zstd(WrapBody::new(body, CompressionLevel::default())),
+        }
+    }
+
+    /// Number of compressed bytes read from the underlying body, or `None` if it isn't being
+    /// decoded.
+    fn compressed_bytes_read(&self) -> Option<u64> {
+        match self {
+            #[cfg(feature = "decompression-gzip")]
+            BodyInner::Gzip { inner } => Some(inner.read.get_ref().get_ref().bytes_read()),
+            #[cfg(feature = "decompression-deflate")]
+            BodyInner::Deflate { inner } => Some(inner.read.get_ref().get_ref().bytes_read()),
+            #[cfg(feature = "decompression-br")]
+            BodyInner::Brotli { inner } => Some(inner.read.get_ref().get_ref().bytes_read()),
+            #[cfg(feature = "decompression-zstd")]
+            BodyInner::Zstd { inner } => Some(inner.read.get_ref().get_ref().bytes_read()),
+            BodyInner::Identity { .. } => None,
+
+            #[cfg(not(feature = "decompression-gzip"))]
+            BodyInner::Gzip { inner } => match inner.0 {},
+            #[cfg(not(feature = "decompression-deflate"))]
+            BodyInner::Deflate { inner } => match inner.0 {},
+            #[cfg(not(feature = "decompression-br"))]
+            BodyInner::Brotli { inner } => match inner.0 {},
+            #[cfg(not(feature = "decompression-zstd"))]
+            BodyInner::Zstd { inner } => match inner.0 {},
+        }
+    }
 }
 
 impl<B> Body for DecompressionBody<B>
@@ -298,7 +366,70 @@ where
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Option<Result<http_body::Frame<Self::Data>, Self::Error>>> {
-        match self.project().inner.project() {
+        let mut this = self.project();
+
+        if this.limits.exceeded() {
+            return Poll::Ready(None);
+        }
+
+        let frame = if this.decoders.is_empty() {
+            ready!(this.inner.as_mut().poll_frame(cx))
+        } else {
+            let mut inner = this.inner.as_mut();
+            let trailers = &mut *this.trailers;
+            let mut poll_source = |cx: &mut Context<'_>| loop {
+                match ready!(inner.as_mut().poll_frame(cx)) {
+                    Some(Ok(frame)) => match frame.into_data() {
+                        Ok(data) if data.is_empty() => {}
+                        Ok(data) => return Poll::Ready(Some(Ok(data))),
+                        Err(frame) => *trailers = frame.into_trailers().ok(),
+                    },
+                    Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                    None => return Poll::Ready(None),
+                }
+            };
+
+            match ready!(poll_decode(this.decoders, cx, &mut poll_source)) {
+                Some(result) => Some(result.map(Frame::data)),
+                None => this
+                    .trailers
+                    .take()
+                    .map(|trailers| Ok(Frame::trailers(trailers))),
+            }
+        };
+
+        if let (Some(Ok(frame)), Some(compressed)) = (&frame, this.inner.compressed_bytes_read()) {
+            if let Some(data) = frame.data_ref() {
+                if let Err(err) = this.limits.record(data.len(), compressed) {
+                    return Poll::Ready(Some(Err(err.into())));
+                }
+            }
+        }
+
+        Poll::Ready(frame)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self.inner {
+            BodyInner::Identity { ref inner } => inner.size_hint(),
+            _ => SizeHint::default(),
+        }
+    }
+}
+
+impl<B> Body for BodyInner<B>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<http_body::Frame<Self::Data>, Self::Error>>> {
+        match self.project() {
             #[cfg(feature = "decompression-gzip")]
             BodyInnerProj::Gzip { inner } => inner.poll_frame(cx),
             #[cfg(feature = "decompression-deflate")]
@@ -326,13 +457,6 @@ where
             BodyInnerProj::Zstd { inner } => match inner.0 {},
         }
     }
-
-    fn size_hint(&self) -> SizeHint {
-        match self.inner {
-            BodyInner::Identity { ref inner } => inner.size_hint(),
-            _ => SizeHint::default(),
-        }
-    }
 }
 
 #[cfg(feature = "decompression-gzip")]
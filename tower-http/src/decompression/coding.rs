@@ -0,0 +1,220 @@
+//! Parsing of `Content-Encoding` and decoding of stacked content codings.
+
+#![allow(unused_imports)]
+
+use crate::{
+    compression_utils::{AcceptEncoding, Feed},
+    content_encoding::SupportedEncodings,
+    BoxError,
+};
+#[cfg(feature = "decompression-br")]
+use async_compression::tokio::bufread::BrotliDecoder;
+#[cfg(feature = "decompression-gzip")]
+use async_compression::tokio::bufread::GzipDecoder;
+#[cfg(feature = "decompression-deflate")]
+use async_compression::tokio::bufread::ZlibDecoder;
+#[cfg(feature = "decompression-zstd")]
+use async_compression::tokio::bufread::ZstdDecoder;
+use bytes::{Buf, Bytes, BytesMut};
+use http::{header, HeaderMap};
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// A content coding that can be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Coding {
+    #[cfg(feature = "decompression-gzip")]
+    Gzip,
+    #[cfg(feature = "decompression-deflate")]
+    Deflate,
+    #[cfg(feature = "decompression-br")]
+    Brotli,
+    #[cfg(feature = "decompression-zstd")]
+    Zstd,
+}
+
+impl Coding {
+    fn parse(token: &str, _accept: AcceptEncoding) -> Option<Self> {
+        #[cfg(feature = "decompression-gzip")]
+        if (token.eq_ignore_ascii_case("gzip") || token.eq_ignore_ascii_case("x-gzip"))
+            && _accept.gzip()
+        {
+            return Some(Coding::Gzip);
+        }
+
+        #[cfg(feature = "decompression-deflate")]
+        if token.eq_ignore_ascii_case("deflate") && _accept.deflate() {
+            return Some(Coding::Deflate);
+        }
+
+        #[cfg(feature = "decompression-br")]
+        if token.eq_ignore_ascii_case("br") && _accept.br() {
+            return Some(Coding::Brotli);
+        }
+
+        #[cfg(feature = "decompression-zstd")]
+        if token.eq_ignore_ascii_case("zstd") && _accept.zstd() {
+            return Some(Coding::Zstd);
+        }
+
+        None
+    }
+}
+
+/// The content codings listed by the `Content-Encoding` headers of a message.
+#[derive(Debug)]
+pub(crate) enum Codings {
+    /// No coding, or only `identity`, was applied.
+    Identity,
+    /// The codings in the order they were applied.
+    Supported(Vec<Coding>),
+    /// A coding isn't accepted, or more codings were applied than allowed.
+    Unsupported,
+}
+
+impl Codings {
+    pub(crate) fn from_headers(
+        headers: &HeaderMap,
+        accept: AcceptEncoding,
+        max_depth: usize,
+    ) -> Self {
+        let mut codings = Vec::new();
+
+        for value in headers.get_all(header::CONTENT_ENCODING) {
+            let Ok(value) = value.to_str() else {
+                return Codings::Unsupported;
+            };
+
+            for token in value.split(',').map(str::trim) {
+                if token.is_empty() || token.eq_ignore_ascii_case("identity") {
+                    continue;
+                }
+
+                match Coding::parse(token, accept) {
+                    Some(coding) if codings.len() < max_depth => codings.push(coding),
+                    _ => return Codings::Unsupported,
+                }
+            }
+        }
+
+        if codings.is_empty() {
+            Codings::Identity
+        } else {
+            Codings::Supported(codings)
+        }
+    }
+}
+
+/// Decoder for a coding applied before the outermost one.
+///
+/// The outermost coding is decoded straight from the body. The codings under it are decoded from
+/// the output of the decoder above, which is handed over through a [`Feed`].
+pub(crate) enum Decoder {
+    #[cfg(feature = "decompression-gzip")]
+    Gzip(GzipDecoder<Feed>),
+    #[cfg(feature = "decompression-deflate")]
+    Deflate(ZlibDecoder<Feed>),
+    #[cfg(feature = "decompression-br")]
+    Brotli(BrotliDecoder<Feed>),
+    #[cfg(feature = "decompression-zstd")]
+    Zstd(ZstdDecoder<Feed>),
+}
+
+impl Decoder {
+    pub(crate) fn new(coding: Coding) -> Self {
+        match coding {
+            #[cfg(feature = "decompression-gzip")]
+            Coding::Gzip => Decoder::Gzip(GzipDecoder::new(Feed::default())),
+            #[cfg(feature = "decompression-deflate")]
+            Coding::Deflate => Decoder::Deflate(ZlibDecoder::new(Feed::default())),
+            #[cfg(feature = "decompression-br")]
+            Coding::Brotli => Decoder::Brotli(BrotliDecoder::new(Feed::default())),
+            #[cfg(feature = "decompression-zstd")]
+            Coding::Zstd => {
+                let mut decoder = ZstdDecoder::new(Feed::default());
+                decoder.multiple_members(true);
+                Decoder::Zstd(decoder)
+            }
+        }
+    }
+
+    fn feed(&mut self) -> &mut Feed {
+        match self {
+            #[cfg(feature = "decompression-gzip")]
+            Decoder::Gzip(decoder) => decoder.get_mut(),
+            #[cfg(feature = "decompression-deflate")]
+            Decoder::Deflate(decoder) => decoder.get_mut(),
+            #[cfg(feature = "decompression-br")]
+            Decoder::Brotli(decoder) => decoder.get_mut(),
+            #[cfg(feature = "decompression-zstd")]
+            Decoder::Zstd(decoder) => decoder.get_mut(),
+        }
+    }
+
+    fn poll_read_buf(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut BytesMut,
+    ) -> Poll<io::Result<usize>> {
+        match self {
+            #[cfg(feature = "decompression-gzip")]
+            Decoder::Gzip(decoder) => tokio_util::io::poll_read_buf(Pin::new(decoder), cx, buf),
+            #[cfg(feature = "decompression-deflate")]
+            Decoder::Deflate(decoder) => tokio_util::io::poll_read_buf(Pin::new(decoder), cx, buf),
+            #[cfg(feature = "decompression-br")]
+            Decoder::Brotli(decoder) => tokio_util::io::poll_read_buf(Pin::new(decoder), cx, buf),
+            #[cfg(feature = "decompression-zstd")]
+            Decoder::Zstd(decoder) => tokio_util::io::poll_read_buf(Pin::new(decoder), cx, buf),
+        }
+    }
+}
+
+/// Decode the next chunk of data with the last of `decoders`.
+///
+/// `poll_source` yields the data coming out of the outermost decoder.
+pub(crate) fn poll_decode<F>(
+    decoders: &mut [Decoder],
+    cx: &mut Context<'_>,
+    poll_source: &mut F,
+) -> Poll<Option<Result<Bytes, BoxError>>>
+where
+    F: FnMut(&mut Context<'_>) -> Poll<Option<Result<Bytes, BoxError>>>,
+{
+    let Some((decoder, rest)) = decoders.split_last_mut() else {
+        return poll_source(cx);
+    };
+
+    loop {
+        let mut buf = BytesMut::with_capacity(4096);
+        match decoder.poll_read_buf(cx, &mut buf) {
+            Poll::Ready(Ok(0)) => {
+                let feed = decoder.feed();
+                if !feed.buf.is_empty() {
+                    return Poll::Ready(Some(Err(
+                        "there are extra bytes after body has been decompressed".into(),
+                    )));
+                }
+                if feed.eof {
+                    return Poll::Ready(None);
+                }
+                // The decoder is done, make sure nothing follows its input.
+                match std::task::ready!(poll_decode(rest, cx, poll_source)) {
+                    Some(Ok(data)) => decoder.feed().buf = data,
+                    Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                    None => decoder.feed().eof = true,
+                }
+            }
+            Poll::Ready(Ok(_)) => return Poll::Ready(Some(Ok(buf.freeze()))),
+            Poll::Ready(Err(err)) => return Poll::Ready(Some(Err(err.into()))),
+            // The feed ran dry, refill it from the decoder above.
+            Poll::Pending => match std::task::ready!(poll_decode(rest, cx, poll_source)) {
+                Some(Ok(data)) => decoder.feed().buf = data,
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => decoder.feed().eof = true,
+            },
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use super::Decompression;
+use super::{limits::DecompressionLimits, Decompression};
 use crate::compression_utils::AcceptEncoding;
 use tower_layer::Layer;
 
@@ -11,6 +11,7 @@ use tower_layer::Layer;
 #[derive(Debug, Default, Clone)]
 pub struct DecompressionLayer {
     accept: AcceptEncoding,
+    limits: DecompressionLimits,
 }
 
 impl<S> Layer<S> for DecompressionLayer {
@@ -20,6 +21,7 @@ impl<S> Layer<S> for DecompressionLayer {
         Decompression {
             inner: service,
             accept: self.accept,
+            limits: self.limits,
         }
     }
 }
@@ -89,4 +91,38 @@ impl DecompressionLayer {
         self.accept.set_zstd(false);
         self
     }
+
+    /// Sets the maximum number of bytes a response body may decompress to.
+    ///
+    /// Reading past the limit makes the body return a [`DecompressionLimitError`].
+    ///
+    /// By default there is no limit.
+    ///
+    /// [`DecompressionLimitError`]: super::DecompressionLimitError
+    pub fn max_decompressed_bytes(mut self, limit: usize) -> Self {
+        self.limits.set_max_bytes(limit);
+        self
+    }
+
+    /// Sets the maximum ratio between decompressed and compressed bytes of a response body.
+    ///
+    /// Exceeding the ratio makes the body return a [`DecompressionLimitError`].
+    ///
+    /// By default there is no limit.
+    ///
+    /// [`DecompressionLimitError`]: super::DecompressionLimitError
+    pub fn max_compression_ratio(mut self, ratio: u32) -> Self {
+        self.limits.set_max_ratio(ratio);
+        self
+    }
+
+    /// Sets how many stacked content codings, such as `Content-Encoding: gzip, br`, are decoded.
+    ///
+    /// Bodies with more codings are passed through without being decoded.
+    ///
+    /// Defaults to 2.
+    pub fn max_encoding_depth(mut self, depth: usize) -> Self {
+        self.limits.set_max_depth(depth);
+        self
+    }
 }
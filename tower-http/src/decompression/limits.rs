@@ -0,0 +1,143 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// Bounds applied to the output of a decoder.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DecompressionLimits {
+    pub(crate) max_bytes: Option<u64>,
+    pub(crate) max_ratio: Option<u64>,
+    pub(crate) max_depth: usize,
+}
+
+impl DecompressionLimits {
+    /// How many stacked content codings are decoded by default.
+    pub(crate) const DEFAULT_MAX_DEPTH: usize = 2;
+
+    pub(crate) fn set_max_depth(&mut self, depth: usize) {
+        self.max_depth = depth;
+    }
+
+    pub(crate) fn set_max_bytes(&mut self, limit: usize) {
+        self.max_bytes = Some(limit as u64);
+    }
+
+    pub(crate) fn set_max_ratio(&mut self, ratio: u32) {
+        self.max_ratio = Some(u64::from(ratio));
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.max_bytes.is_some() || self.max_ratio.is_some()
+    }
+}
+
+impl Default for DecompressionLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: None,
+            max_ratio: None,
+            max_depth: Self::DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+/// Running totals kept by a [`DecompressionBody`] to enforce its limits.
+///
+/// [`DecompressionBody`]: super::DecompressionBody
+#[derive(Debug, Default)]
+pub(crate) struct LimitState {
+    limits: DecompressionLimits,
+    decompressed: u64,
+    exceeded: bool,
+    // Set when a limit is hit so `RequestDecompression` can answer with `413`.
+    signal: Option<Arc<AtomicBool>>,
+}
+
+impl LimitState {
+    pub(crate) fn new(limits: DecompressionLimits) -> Self {
+        Self {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn with_signal(mut self, signal: Arc<AtomicBool>) -> Self {
+        self.signal = Some(signal);
+        self
+    }
+
+    pub(crate) fn exceeded(&self) -> bool {
+        self.exceeded
+    }
+
+    /// Account for `len` decompressed bytes, given that `compressed` bytes have been read from the
+    /// underlying body so far.
+    pub(crate) fn record(
+        &mut self,
+        len: usize,
+        compressed: u64,
+    ) -> Result<(), DecompressionLimitError> {
+        self.decompressed = self.decompressed.saturating_add(len as u64);
+
+        let kind = match self.limits {
+            DecompressionLimits {
+                max_bytes: Some(max),
+                ..
+            } if self.decompressed > max => Kind::Size,
+            DecompressionLimits {
+                max_ratio: Some(ratio),
+                ..
+            } if self.decompressed > compressed.saturating_mul(ratio) => Kind::Ratio,
+            _ => return Ok(()),
+        };
+
+        self.exceeded = true;
+        if let Some(signal) = &self.signal {
+            signal.store(true, Ordering::Release);
+        }
+
+        Err(DecompressionLimitError { kind })
+    }
+}
+
+/// Error returned by [`DecompressionBody`] when the decompressed data exceeds one of the
+/// configured limits.
+///
+/// [`DecompressionBody`]: super::DecompressionBody
+#[derive(Debug)]
+pub struct DecompressionLimitError {
+    kind: Kind,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Size,
+    Ratio,
+}
+
+impl DecompressionLimitError {
+    /// Returns `true` if the maximum number of decompressed bytes was exceeded.
+    pub fn is_size_exceeded(&self) -> bool {
+        matches!(self.kind, Kind::Size)
+    }
+
+    /// Returns `true` if the maximum compression ratio was exceeded.
+    pub fn is_ratio_exceeded(&self) -> bool {
+        matches!(self.kind, Kind::Ratio)
+    }
+}
+
+impl fmt::Display for DecompressionLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            Kind::Size => write!(f, "decompressed body exceeded the size limit"),
+            Kind::Ratio => write!(f, "decompressed body exceeded the compression ratio limit"),
+        }
+    }
+}
+
+impl std::error::Error for DecompressionLimitError {}
@@ -94,13 +94,15 @@
 mod request;
 
 mod body;
+mod coding;
 mod future;
 mod layer;
+mod limits;
 mod service;
 
 pub use self::{
     body::DecompressionBody, future::ResponseFuture, layer::DecompressionLayer,
-    service::Decompression,
+    limits::DecompressionLimitError, service::Decompression,
 };
 
 pub use self::request::future::RequestDecompressionFuture;
@@ -186,6 +188,92 @@ mod tests {
         Ok(res)
     }
 
+    #[tokio::test]
+    async fn max_decompressed_bytes() {
+        let mut client = Decompression::new(service_fn(handle_zeros)).max_decompressed_bytes(1024);
+
+        let req = Request::new(Body::empty());
+        let res = client.ready().await.unwrap().call(req).await.unwrap();
+
+        let err = res.into_body().collect().await.unwrap_err();
+        let err = err.downcast_ref::<DecompressionLimitError>().unwrap();
+        assert!(err.is_size_exceeded());
+    }
+
+    #[tokio::test]
+    async fn max_compression_ratio() {
+        let mut client = Decompression::new(service_fn(handle_zeros)).max_compression_ratio(10);
+
+        let req = Request::new(Body::empty());
+        let res = client.ready().await.unwrap().call(req).await.unwrap();
+
+        let err = res.into_body().collect().await.unwrap_err();
+        let err = err.downcast_ref::<DecompressionLimitError>().unwrap();
+        assert!(err.is_ratio_exceeded());
+    }
+
+    #[tokio::test]
+    async fn limits_allow_bodies_within_bounds() {
+        let mut client = Decompression::new(Compression::new(service_fn(handle)))
+            .max_decompressed_bytes(1024)
+            .max_compression_ratio(10);
+
+        let req = Request::builder()
+            .header("accept-encoding", "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let res = client.ready().await.unwrap().call(req).await.unwrap();
+
+        let collected = res.into_body().collect().await.unwrap();
+        assert_eq!(&collected.to_bytes()[..], b"Hello, World!");
+    }
+
+    async fn handle_zeros(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&[0; 1024 * 1024]).unwrap();
+
+        let mut res = Response::new(Body::from(encoder.finish().unwrap()));
+        res.headers_mut()
+            .insert("content-encoding", "gzip".parse().unwrap());
+        Ok(res)
+    }
+
+    #[tokio::test]
+    async fn decompress_stacked_encodings() {
+        let mut client = Decompression::new(service_fn(handle_gzip_then_zstd));
+
+        let req = Request::new(Body::empty());
+        let res = client.ready().await.unwrap().call(req).await.unwrap();
+        assert!(!res.headers().contains_key("content-encoding"));
+
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"Hello, World!");
+    }
+
+    #[tokio::test]
+    async fn stacked_encodings_deeper_than_the_limit_are_passed_through() {
+        let mut client =
+            Decompression::new(service_fn(handle_gzip_then_zstd)).max_encoding_depth(1);
+
+        let req = Request::new(Body::empty());
+        let res = client.ready().await.unwrap().call(req).await.unwrap();
+        assert_eq!(res.headers()["content-encoding"], "gzip, zstd");
+
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_ne!(&body[..], b"Hello, World!");
+    }
+
+    async fn handle_gzip_then_zstd(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(b"Hello, World!").unwrap();
+        let zstd = zstd::encode_all(&gzip.finish().unwrap()[..], 0).unwrap();
+
+        let mut res = Response::new(Body::from(zstd));
+        res.headers_mut()
+            .insert("content-encoding", "gzip, zstd".parse().unwrap());
+        Ok(res)
+    }
+
     #[allow(dead_code)]
     async fn is_compatible_with_hyper() {
         let client =
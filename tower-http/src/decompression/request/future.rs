@@ -9,8 +9,12 @@ use http_body_util::Empty;
 use pin_project_lite::pin_project;
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 use std::task::Context;
-use std::task::Poll;
+use std::task::{ready, Poll};
 
 pin_project! {
     #[derive(Debug)]
@@ -35,7 +39,8 @@ pin_project! {
     {
         Inner {
             #[pin]
-            fut: F
+            fut: F,
+            limit_exceeded: Option<Arc<AtomicBool>>,
         },
         Unsupported {
             #[pin]
@@ -57,9 +62,12 @@ where
     }
 
     #[must_use]
-    pub(super) fn inner(fut: F) -> Self {
+    pub(super) fn inner(fut: F, limit_exceeded: Option<Arc<AtomicBool>>) -> Self {
         Self {
-            kind: Kind::Inner { fut },
+            kind: Kind::Inner {
+                fut,
+                limit_exceeded,
+            },
         }
     }
 }
@@ -75,9 +83,32 @@ where
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         match self.project().kind.project() {
-            StateProj::Inner { fut } => fut.poll(cx).map_ok(|res| {
-                res.map(|body| UnsyncBoxBody::from_inner(body.map_err(Into::into).boxed_unsync()))
-            }),
+            StateProj::Inner {
+                fut,
+                limit_exceeded,
+            } => {
+                let result = ready!(fut.poll(cx));
+
+                // The inner service most likely failed because it couldn't read the body, so
+                // report the real cause whatever it responded with.
+                if let Some(limit_exceeded) = limit_exceeded {
+                    if limit_exceeded.load(Ordering::Acquire) {
+                        let res = Response::builder()
+                            .status(StatusCode::PAYLOAD_TOO_LARGE)
+                            .body(UnsyncBoxBody::from_inner(
+                                Empty::new().map_err(Into::into).boxed_unsync(),
+                            ))
+                            .unwrap();
+                        return Poll::Ready(Ok(res));
+                    }
+                }
+
+                Poll::Ready(result.map(|res| {
+                    res.map(|body| {
+                        UnsyncBoxBody::from_inner(body.map_err(Into::into).boxed_unsync())
+                    })
+                }))
+            }
             StateProj::Unsupported { accept } => {
                 let res = Response::builder()
                     .header(
@@ -1,5 +1,6 @@
 use super::service::RequestDecompression;
 use crate::compression_utils::AcceptEncoding;
+use crate::decompression::limits::DecompressionLimits;
 use tower_layer::Layer;
 
 /// Decompresses request bodies and calls its underlying service.
@@ -17,6 +18,7 @@ use tower_layer::Layer;
 pub struct RequestDecompressionLayer {
     accept: AcceptEncoding,
     pass_through_unaccepted: bool,
+    limits: DecompressionLimits,
 }
 
 impl<S> Layer<S> for RequestDecompressionLayer {
@@ -27,6 +29,7 @@ impl<S> Layer<S> for RequestDecompressionLayer {
             inner: service,
             accept: self.accept,
             pass_through_unaccepted: self.pass_through_unaccepted,
+            limits: self.limits,
         }
     }
 }
@@ -102,4 +105,41 @@ impl RequestDecompressionLayer {
         self.pass_through_unaccepted = enable;
         self
     }
+
+    /// Sets the maximum number of bytes a request body may decompress to.
+    ///
+    /// Reading past the limit makes the body return a [`DecompressionLimitError`] and the
+    /// middleware responds with `413 Payload Too Large`.
+    ///
+    /// By default there is no limit.
+    ///
+    /// [`DecompressionLimitError`]: crate::decompression::DecompressionLimitError
+    pub fn max_decompressed_bytes(mut self, limit: usize) -> Self {
+        self.limits.set_max_bytes(limit);
+        self
+    }
+
+    /// Sets the maximum ratio between decompressed and compressed bytes of a request body.
+    ///
+    /// Exceeding the ratio makes the body return a [`DecompressionLimitError`] and the
+    /// middleware responds with `413 Payload Too Large`.
+    ///
+    /// By default there is no limit.
+    ///
+    /// [`DecompressionLimitError`]: crate::decompression::DecompressionLimitError
+    pub fn max_compression_ratio(mut self, ratio: u32) -> Self {
+        self.limits.set_max_ratio(ratio);
+        self
+    }
+
+    /// Sets how many stacked content codings, such as `Content-Encoding: gzip, br`, are decoded.
+    ///
+    /// Requests with more codings are rejected with `415 Unsupported Media Type`, unless
+    /// [`pass_through_unaccepted`](Self::pass_through_unaccepted) is enabled.
+    ///
+    /// Defaults to 2.
+    pub fn max_encoding_depth(mut self, depth: usize) -> Self {
+        self.limits.set_max_depth(depth);
+        self
+    }
 }
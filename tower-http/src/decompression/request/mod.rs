@@ -7,6 +7,7 @@ mod tests {
     use super::service::RequestDecompression;
     use crate::decompression::DecompressionBody;
     use crate::test_helpers::Body;
+    use crate::BoxError;
     use flate2::{write::GzEncoder, Compression};
     use http::{header, Request, Response, StatusCode};
     use http_body_util::BodyExt;
@@ -44,6 +45,60 @@ mod tests {
         let _ = svc.ready().await.unwrap().call(req).await.unwrap();
     }
 
+    #[tokio::test]
+    async fn exceeding_limits_returns_payload_too_large() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[0; 1024 * 1024]).unwrap();
+        let req = Request::builder()
+            .header(header::CONTENT_ENCODING, "gzip")
+            .body(Body::from(encoder.finish().unwrap()))
+            .unwrap();
+
+        let mut svc =
+            RequestDecompression::new(service_fn(read_body_or_fail)).max_decompressed_bytes(1024);
+        let res = svc.ready().await.unwrap().call(req).await.unwrap();
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, res.status());
+    }
+
+    #[tokio::test]
+    async fn limits_allow_bodies_within_bounds() {
+        let req = request_gzip();
+        let mut svc = RequestDecompression::new(service_fn(assert_request_is_decompressed))
+            .max_decompressed_bytes(1024)
+            .max_compression_ratio(10);
+        let res = svc.ready().await.unwrap().call(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+
+    #[tokio::test]
+    async fn decompress_stacked_encodings() {
+        let mut inner = GzEncoder::new(Vec::new(), Compression::default());
+        inner.write_all(b"Hello?").unwrap();
+        let mut outer = GzEncoder::new(Vec::new(), Compression::default());
+        outer.write_all(&inner.finish().unwrap()).unwrap();
+        let req = Request::builder()
+            .header(header::CONTENT_ENCODING, "gzip")
+            .header(header::CONTENT_ENCODING, "gzip")
+            .body(Body::from(outer.finish().unwrap()))
+            .unwrap();
+
+        let mut svc = RequestDecompression::new(service_fn(assert_request_is_decompressed));
+        let res = svc.ready().await.unwrap().call(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+
+    #[tokio::test]
+    async fn stacked_encodings_deeper_than_the_limit_return_unsupported_media_type() {
+        let req = Request::builder()
+            .header(header::CONTENT_ENCODING, "gzip, gzip, gzip")
+            .body(Body::empty())
+            .unwrap();
+
+        let mut svc = RequestDecompression::new(service_fn(should_not_be_called));
+        let res = svc.ready().await.unwrap().call(req).await.unwrap();
+        assert_eq!(StatusCode::UNSUPPORTED_MEDIA_TYPE, res.status());
+    }
+
     async fn assert_request_is_decompressed(
         req: Request<DecompressionBody<Body>>,
     ) -> Result<Response<Body>, Infallible> {
@@ -68,6 +123,13 @@ mod tests {
         Ok(Response::new(Body::empty()))
     }
 
+    async fn read_body_or_fail(
+        req: Request<DecompressionBody<Body>>,
+    ) -> Result<Response<Body>, BoxError> {
+        req.into_body().collect().await?;
+        Ok(Response::new(Body::empty()))
+    }
+
     async fn should_not_be_called(
         _: Request<DecompressionBody<Body>>,
     ) -> Result<Response<Body>, Infallible> {
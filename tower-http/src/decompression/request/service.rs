@@ -1,25 +1,21 @@
 use super::future::RequestDecompressionFuture as ResponseFuture;
 use super::layer::RequestDecompressionLayer;
 use crate::body::UnsyncBoxBody;
-use crate::compression_utils::CompressionLevel;
 use crate::{
-    compression_utils::AcceptEncoding, decompression::body::BodyInner,
-    decompression::DecompressionBody, BoxError,
+    compression_utils::AcceptEncoding,
+    decompression::body::BodyInner,
+    decompression::coding::Codings,
+    decompression::limits::{DecompressionLimits, LimitState},
+    decompression::DecompressionBody,
+    BoxError,
 };
 use bytes::Buf;
 use http::{header, Request, Response};
 use http_body::Body;
+use std::sync::{atomic::AtomicBool, Arc};
 use std::task::{Context, Poll};
 use tower_service::Service;
 
-#[cfg(any(
-    feature = "decompression-gzip",
-    feature = "decompression-deflate",
-    feature = "decompression-br",
-    feature = "decompression-zstd",
-))]
-use crate::content_encoding::SupportedEncodings;
-
 /// Decompresses request bodies and calls its underlying service.
 ///
 /// Transparently decompresses request bodies based on the `Content-Encoding` header.
@@ -36,6 +32,7 @@ pub struct RequestDecompression<S> {
     pub(super) inner: S,
     pub(super) accept: AcceptEncoding,
     pub(super) pass_through_unaccepted: bool,
+    pub(super) limits: DecompressionLimits,
 }
 
 impl<S, ReqBody, ResBody, D> Service<Request<ReqBody>> for RequestDecompression<S>
@@ -57,55 +54,28 @@ where
     fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
         let (mut parts, body) = req.into_parts();
 
-        let body =
-            if let header::Entry::Occupied(entry) = parts.headers.entry(header::CONTENT_ENCODING) {
-                match entry.get().as_bytes() {
-                    #[cfg(feature = "decompression-gzip")]
-                    b"gzip" if self.accept.gzip() => {
-                        entry.remove();
-                        parts.headers.remove(header::CONTENT_LENGTH);
-                        BodyInner::gzip(crate::compression_utils::WrapBody::new(
-                            body,
-                            CompressionLevel::default(),
-                        ))
-                    }
-                    #[cfg(feature = "decompression-deflate")]
-                    b"deflate" if self.accept.deflate() => {
-                        entry.remove();
-                        parts.headers.remove(header::CONTENT_LENGTH);
-                        BodyInner::deflate(crate::compression_utils::WrapBody::new(
-                            body,
-                            CompressionLevel::default(),
-                        ))
-                    }
-                    #[cfg(feature = "decompression-br")]
-                    b"br" if self.accept.br() => {
-                        entry.remove();
-                        parts.headers.remove(header::CONTENT_LENGTH);
-                        BodyInner::brotli(crate::compression_utils::WrapBody::new(
-                            body,
-                            CompressionLevel::default(),
-                        ))
-                    }
-                    #[cfg(feature = "decompression-zstd")]
-                    b"zstd" if self.accept.zstd() => {
-                        entry.remove();
-                        parts.headers.remove(header::CONTENT_LENGTH);
-                        BodyInner::zstd(crate::compression_utils::WrapBody::new(
-                            body,
-                            CompressionLevel::default(),
-                        ))
-                    }
-                    b"identity" => BodyInner::identity(body),
-                    _ if self.pass_through_unaccepted => BodyInner::identity(body),
-                    _ => return ResponseFuture::unsupported_encoding(self.accept),
+        let mut body =
+            match Codings::from_headers(&parts.headers, self.accept, self.limits.max_depth) {
+                Codings::Supported(codings) => {
+                    parts.headers.remove(header::CONTENT_ENCODING);
+                    parts.headers.remove(header::CONTENT_LENGTH);
+                    DecompressionBody::decode(body, &codings)
+                }
+                Codings::Identity => DecompressionBody::new(BodyInner::identity(body)),
+                Codings::Unsupported if self.pass_through_unaccepted => {
+                    DecompressionBody::new(BodyInner::identity(body))
                 }
-            } else {
-                BodyInner::identity(body)
+                Codings::Unsupported => return ResponseFuture::unsupported_encoding(self.accept),
             };
-        let body = DecompressionBody::new(body);
+        let limit_exceeded = if self.limits.is_enabled() {
+            let signal = Arc::new(AtomicBool::new(false));
+            body = body.with_limits(LimitState::new(self.limits).with_signal(signal.clone()));
+            Some(signal)
+        } else {
+            None
+        };
         let req = Request::from_parts(parts, body);
-        ResponseFuture::inner(self.inner.call(req))
+        ResponseFuture::inner(self.inner.call(req), limit_exceeded)
     }
 }
 
@@ -116,6 +86,7 @@ impl<S> RequestDecompression<S> {
             inner: service,
             accept: AcceptEncoding::default(),
             pass_through_unaccepted: false,
+            limits: DecompressionLimits::default(),
         }
     }
 
@@ -195,4 +166,41 @@ impl<S> RequestDecompression<S> {
         self.accept.set_zstd(false);
         self
     }
+
+    /// Sets the maximum number of bytes a request body may decompress to.
+    ///
+    /// Reading past the limit makes the body return a [`DecompressionLimitError`] and the
+    /// middleware responds with `413 Payload Too Large`.
+    ///
+    /// By default there is no limit.
+    ///
+    /// [`DecompressionLimitError`]: crate::decompression::DecompressionLimitError
+    pub fn max_decompressed_bytes(mut self, limit: usize) -> Self {
+        self.limits.set_max_bytes(limit);
+        self
+    }
+
+    /// Sets the maximum ratio between decompressed and compressed bytes of a request body.
+    ///
+    /// Exceeding the ratio makes the body return a [`DecompressionLimitError`] and the
+    /// middleware responds with `413 Payload Too Large`.
+    ///
+    /// By default there is no limit.
+    ///
+    /// [`DecompressionLimitError`]: crate::decompression::DecompressionLimitError
+    pub fn max_compression_ratio(mut self, ratio: u32) -> Self {
+        self.limits.set_max_ratio(ratio);
+        self
+    }
+
+    /// Sets how many stacked content codings, such as `Content-Encoding: gzip, br`, are decoded.
+    ///
+    /// Requests with more codings are rejected with `415 Unsupported Media Type`, unless
+    /// [`pass_through_unaccepted`](Self::pass_through_unaccepted) is enabled.
+    ///
+    /// Defaults to 2.
+    pub fn max_encoding_depth(mut self, depth: usize) -> Self {
+        self.limits.set_max_depth(depth);
+        self
+    }
 }
@@ -1,4 +1,4 @@
-use super::{DecompressionBody, DecompressionLayer, ResponseFuture};
+use super::{limits::DecompressionLimits, DecompressionBody, DecompressionLayer, ResponseFuture};
 use crate::compression_utils::AcceptEncoding;
 use http::{
     header::{self, ACCEPT_ENCODING},
@@ -18,6 +18,7 @@ use tower_service::Service;
 pub struct Decompression<S> {
     pub(crate) inner: S,
     pub(crate) accept: AcceptEncoding,
+    pub(crate) limits: DecompressionLimits,
 }
 
 impl<S> Decompression<S> {
@@ -26,6 +27,7 @@ impl<S> Decompression<S> {
         Self {
             inner: service,
             accept: AcceptEncoding::default(),
+            limits: DecompressionLimits::default(),
         }
     }
 
@@ -97,6 +99,40 @@ impl<S> Decompression<S> {
         self.accept.set_zstd(false);
         self
     }
+
+    /// Sets the maximum number of bytes a response body may decompress to.
+    ///
+    /// Reading past the limit makes the body return a [`DecompressionLimitError`].
+    ///
+    /// By default there is no limit.
+    ///
+    /// [`DecompressionLimitError`]: super::DecompressionLimitError
+    pub fn max_decompressed_bytes(mut self, limit: usize) -> Self {
+        self.limits.set_max_bytes(limit);
+        self
+    }
+
+    /// Sets the maximum ratio between decompressed and compressed bytes of a response body.
+    ///
+    /// Exceeding the ratio makes the body return a [`DecompressionLimitError`].
+    ///
+    /// By default there is no limit.
+    ///
+    /// [`DecompressionLimitError`]: super::DecompressionLimitError
+    pub fn max_compression_ratio(mut self, ratio: u32) -> Self {
+        self.limits.set_max_ratio(ratio);
+        self
+    }
+
+    /// Sets how many stacked content codings, such as `Content-Encoding: gzip, br`, are decoded.
+    ///
+    /// Bodies with more codings are passed through without being decoded.
+    ///
+    /// Defaults to 2.
+    pub fn max_encoding_depth(mut self, depth: usize) -> Self {
+        self.limits.set_max_depth(depth);
+        self
+    }
 }
 
 impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Decompression<S>
@@ -122,6 +158,7 @@ where
         ResponseFuture {
             inner: self.inner.call(req),
             accept: self.accept,
+            limits: self.limits,
         }
     }
 }
@@ -0,0 +1,638 @@
+//! Resolve where a request was originally sent, behind trusted reverse proxies.
+//!
+//! Reverse proxies and load balancers rewrite the connection: the service sees the proxy as the
+//! peer, and often an internal `Host` and plain `http`. Proxies record the original values in the
+//! [`Forwarded`] header, or the de-facto `X-Forwarded-For`, `X-Forwarded-Host` and
+//! `X-Forwarded-Proto` headers. Any client can send those headers too, so they may only be
+//! believed when the peer is a proxy you trust.
+//!
+//! [`TrustedProxies`] holds the networks of those proxies and computes the
+//! [`EffectiveAuthority`] of requests, i.e. the scheme and host the client used. It is used by
+//! [`CsrfLayer::with_trusted_proxies`] and [`ServeDir::trusted_proxies`].
+//!
+//! # Example
+//!
+//! ```
+//! use http::Request;
+//! use std::net::SocketAddr;
+//! use tower_http::forwarded::TrustedProxies;
+//!
+//! let proxies = TrustedProxies::new(["10.0.0.0/8"])?;
+//!
+//! let mut request = Request::builder()
+//!     .uri("/")
+//!     .header("host", "backend.internal")
+//!     .header("forwarded", "for=203.0.113.7;host=example.com;proto=https")
+//!     .body(())
+//!     .unwrap();
+//! // The peer address is usually added to the extensions by the server.
+//! request
+//!     .extensions_mut()
+//!     .insert("10.1.2.3:40000".parse::<SocketAddr>().unwrap());
+//!
+//! let effective = proxies.effective_authority(&request);
+//! assert_eq!(effective.scheme().map(|s| s.as_str()), Some("https"));
+//! assert_eq!(effective.authority().map(|a| a.as_str()), Some("example.com"));
+//! # Ok::<_, tower_http::forwarded::ConfigError>(())
+//! ```
+//!
+//! [`Forwarded`]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Forwarded
+//! [`CsrfLayer::with_trusted_proxies`]: crate::csrf::CsrfLayer::with_trusted_proxies
+//! [`ServeDir::trusted_proxies`]: crate::services::ServeDir::trusted_proxies
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use http::uri::{Authority, Scheme};
+use http::{header, Extensions, HeaderMap, Request};
+
+/// Errors that can occur while configuring [`TrustedProxies`].
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum ConfigError {
+    /// A network that isn't an IP address or a CIDR block such as `10.0.0.0/8`.
+    InvalidNetwork {
+        /// The offending network.
+        network: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::InvalidNetwork { network } => write!(
+                f,
+                "invalid network {network:?}: expected an IP address or a CIDR block"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+type PeerAddrFn = dyn Fn(&Extensions) -> Option<IpAddr> + Send + Sync + 'static;
+
+/// The reverse proxies whose forwarding headers are believed.
+///
+/// See the [module docs](self) for more details.
+#[derive(Clone)]
+pub struct TrustedProxies {
+    networks: Arc<[IpNetwork]>,
+    peer_addr: Arc<PeerAddrFn>,
+}
+
+impl TrustedProxies {
+    /// Trusts proxies in the given networks, each an IP address or a CIDR block such as
+    /// `10.0.0.0/8` or `fd00::/8`.
+    ///
+    /// The address of the peer is read from a [`SocketAddr`] or [`IpAddr`] in the request
+    /// extensions. Use [`peer_addr`](Self::peer_addr) if your server stores it differently.
+    pub fn new<I, S>(networks: I) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let networks = networks
+            .into_iter()
+            .map(|network| {
+                let network = network.as_ref();
+                IpNetwork::parse(network).ok_or_else(|| ConfigError::InvalidNetwork {
+                    network: network.to_owned(),
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            networks,
+            peer_addr: Arc::new(|extensions| {
+                extensions
+                    .get::<SocketAddr>()
+                    .map(SocketAddr::ip)
+                    .or_else(|| extensions.get::<IpAddr>().copied())
+            }),
+        })
+    }
+
+    /// Sets how the address of the peer is read from the request extensions.
+    ///
+    /// ```
+    /// use std::net::{IpAddr, SocketAddr};
+    /// use tower_http::forwarded::TrustedProxies;
+    ///
+    /// #[derive(Clone)]
+    /// struct ConnectInfo(SocketAddr);
+    ///
+    /// let proxies = TrustedProxies::new(["10.0.0.0/8"])?
+    ///     .peer_addr(|extensions| extensions.get::<ConnectInfo>().map(|info| info.0.ip()));
+    /// # Ok::<_, tower_http::forwarded::ConfigError>(())
+    /// ```
+    pub fn peer_addr<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Extensions) -> Option<IpAddr> + Send + Sync + 'static,
+    {
+        self.peer_addr = Arc::new(f);
+        self
+    }
+
+    /// Is `addr` one of the trusted proxies?
+    pub fn is_trusted(&self, addr: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(addr))
+    }
+
+    /// Is the peer the request was received from one of the trusted proxies?
+    pub fn is_trusted_peer<B>(&self, req: &Request<B>) -> bool {
+        (self.peer_addr)(req.extensions()).map_or(false, |addr| self.is_trusted(addr))
+    }
+
+    /// The scheme and authority the client sent the request to.
+    ///
+    /// Starts from the request URI, or the `Host` header if the URI has no authority. If the
+    /// peer is a trusted proxy, the values it forwarded take precedence:
+    ///
+    /// - From the `Forwarded` header, the `host` and `proto` of the element added by the
+    ///   outermost proxy in an unbroken chain of trusted proxies.
+    /// - Otherwise from the last value of `X-Forwarded-Host` and `X-Forwarded-Proto`, which the
+    ///   trusted peer is expected to set.
+    pub fn effective_authority<B>(&self, req: &Request<B>) -> EffectiveAuthority {
+        let mut effective = EffectiveAuthority::from_request(req);
+
+        if !self.is_trusted_peer(req) {
+            return effective;
+        }
+
+        let headers = req.headers();
+        let (host, proto) = if headers.contains_key(header::FORWARDED) {
+            self.forwarded_values(headers)
+        } else {
+            (
+                last_value(headers, "x-forwarded-host"),
+                last_value(headers, "x-forwarded-proto"),
+            )
+        };
+
+        if let Some(authority) = host.and_then(|host| host.parse().ok()) {
+            effective.authority = Some(authority);
+        }
+        if let Some(scheme) = proto.and_then(|proto| proto.parse().ok()) {
+            effective.scheme = Some(scheme);
+        }
+
+        effective
+    }
+
+    /// The address of the client that sent the request.
+    ///
+    /// If the peer isn't a trusted proxy, it is the client. Otherwise the addresses forwarded by
+    /// the peer are walked from the one it added towards the client, and the first address that
+    /// isn't a trusted proxy is the client. The addresses are taken from:
+    ///
+    /// - the `for` nodes of the `Forwarded` header,
+    /// - otherwise the `X-Forwarded-For` header,
+    /// - otherwise the `X-Real-IP` header.
+    ///
+    /// If the walk reaches an address that can't be parsed, e.g. an obfuscated `Forwarded` node,
+    /// the last trusted proxy before it is returned. If all addresses are trusted, the one
+    /// furthest from the peer is returned.
+    ///
+    /// Returns `None` if the peer address isn't in the request extensions, since forwarded
+    /// addresses can't be trusted without it.
+    pub fn client_ip<B>(&self, req: &Request<B>) -> Option<IpAddr> {
+        let peer = (self.peer_addr)(req.extensions())?;
+        if !self.is_trusted(peer) {
+            return Some(peer);
+        }
+
+        let headers = req.headers();
+        let forwarded_for = if headers.contains_key(header::FORWARDED) {
+            forwarded_elements(headers)
+                .iter()
+                .map(ForwardedElement::for_addr)
+                .collect()
+        } else if headers.contains_key("x-forwarded-for") {
+            let mut addrs = Vec::new();
+            for value in headers.get_all("x-forwarded-for") {
+                match value.to_str() {
+                    Ok(value) => addrs.extend(value.split(',').map(parse_node)),
+                    Err(_) => addrs.push(None),
+                }
+            }
+            addrs
+        } else {
+            let real_ip = headers.get("x-real-ip");
+            vec![real_ip.and_then(|value| parse_node(value.to_str().ok()?))]
+        };
+
+        let mut client = peer;
+        for addr in forwarded_for.into_iter().rev() {
+            let Some(addr) = addr else {
+                break;
+            };
+            client = addr;
+            if !self.is_trusted(addr) {
+                break;
+            }
+        }
+
+        Some(client)
+    }
+
+    /// Walks the `Forwarded` elements from the one added by the peer towards the client, as long
+    /// as each element was added by a trusted proxy.
+    fn forwarded_values<'a>(&self, headers: &'a HeaderMap) -> (Option<&'a str>, Option<&'a str>) {
+        let elements = forwarded_elements(headers);
+
+        let mut host = None;
+        let mut proto = None;
+        for element in elements.iter().rev() {
+            host = element.host.or(host);
+            proto = element.proto.or(proto);
+
+            // The element before this one was added by the node this one was received from.
+            if !element
+                .for_addr()
+                .map_or(false, |addr| self.is_trusted(addr))
+            {
+                break;
+            }
+        }
+
+        (host, proto)
+    }
+}
+
+impl fmt::Debug for TrustedProxies {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TrustedProxies")
+            .field("networks", &self.networks)
+            .finish()
+    }
+}
+
+/// The scheme and authority a client sent a request to.
+///
+/// Computed by [`TrustedProxies::effective_authority`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EffectiveAuthority {
+    scheme: Option<Scheme>,
+    authority: Option<Authority>,
+}
+
+impl EffectiveAuthority {
+    /// The authority from the request URI or the `Host` header, without looking at forwarding
+    /// headers.
+    pub fn from_request<B>(req: &Request<B>) -> Self {
+        let authority = req.uri().authority().cloned().or_else(|| {
+            req.headers()
+                .get(header::HOST)
+                .and_then(|host| Authority::try_from(host.as_bytes()).ok())
+        });
+
+        Self {
+            scheme: req.uri().scheme().cloned(),
+            authority,
+        }
+    }
+
+    /// The scheme, if known.
+    ///
+    /// Requests received directly usually don't carry their scheme.
+    pub fn scheme(&self) -> Option<&Scheme> {
+        self.scheme.as_ref()
+    }
+
+    /// The authority, i.e. the host and optional port.
+    pub fn authority(&self) -> Option<&Authority> {
+        self.authority.as_ref()
+    }
+}
+
+fn last_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .rfind(|value| !value.is_empty())
+}
+
+/// A `for` or `by` node, `host` and `proto` of an element of the `Forwarded` header.
+#[derive(Debug, Default)]
+pub(crate) struct ForwardedElement<'a> {
+    pub(crate) for_node: Option<&'a str>,
+    pub(crate) host: Option<&'a str>,
+    pub(crate) proto: Option<&'a str>,
+}
+
+impl ForwardedElement<'_> {
+    /// The IP address in the `for` node, if it is one.
+    pub(crate) fn for_addr(&self) -> Option<IpAddr> {
+        parse_node(self.for_node?)
+    }
+}
+
+/// Parse all `Forwarded` headers into their elements, in order.
+///
+/// Elements that can't be parsed are kept, but empty, so they still break a chain of trust.
+pub(crate) fn forwarded_elements(headers: &HeaderMap) -> Vec<ForwardedElement<'_>> {
+    let mut elements = Vec::new();
+
+    for value in headers.get_all(header::FORWARDED) {
+        let Ok(value) = value.to_str() else {
+            elements.push(ForwardedElement::default());
+            continue;
+        };
+
+        for element in split_unquoted(value, ',') {
+            let mut parsed = ForwardedElement::default();
+            for pair in split_unquoted(element, ';') {
+                let Some((name, value)) = pair.split_once('=') else {
+                    continue;
+                };
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value);
+
+                match name.trim() {
+                    name if name.eq_ignore_ascii_case("for") => parsed.for_node = Some(value),
+                    name if name.eq_ignore_ascii_case("host") => parsed.host = Some(value),
+                    name if name.eq_ignore_ascii_case("proto") => parsed.proto = Some(value),
+                    _ => {}
+                }
+            }
+            elements.push(parsed);
+        }
+    }
+
+    elements
+}
+
+/// Split `s` on `separator`, except inside quoted strings.
+fn split_unquoted(s: &str, separator: char) -> impl Iterator<Item = &str> {
+    let mut in_quotes = false;
+    let mut escaped = false;
+    s.split(move |c| {
+        if escaped {
+            escaped = false;
+        } else if in_quotes && c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            in_quotes = !in_quotes;
+        } else if !in_quotes && c == separator {
+            return true;
+        }
+        false
+    })
+    .map(str::trim)
+    .filter(|part| !part.is_empty())
+}
+
+/// Parse a node of the `Forwarded` header, or an entry of `X-Forwarded-For`: an IP address, with
+/// an optional port, IPv6 addresses in brackets when there is a port.
+pub(crate) fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim();
+    if let Ok(addr) = node.parse::<IpAddr>() {
+        return Some(addr);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+    node.split_once(':')?.0.parse().ok()
+}
+
+/// An IP address or a CIDR block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct IpNetwork {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNetwork {
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr.parse::<IpAddr>().ok()?, Some(prefix_len)),
+            None => (s.parse::<IpAddr>().ok()?, None),
+        };
+        let max_len: u8 = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.parse().ok().filter(|len| *len <= max_len)?,
+            None => max_len,
+        };
+
+        // `contains` matches IPv4-mapped addresses as IPv4, so networks written that way must be
+        // IPv4 too.
+        if let IpAddr::V6(v6) = addr {
+            if let (Some(v4), Some(prefix_len)) = (v6.to_ipv4_mapped(), prefix_len.checked_sub(96))
+            {
+                return Some(Self {
+                    addr: IpAddr::V4(v4),
+                    prefix_len,
+                });
+            }
+        }
+
+        Some(Self { addr, prefix_len })
+    }
+
+    pub(crate) fn contains(&self, addr: IpAddr) -> bool {
+        // Match IPv4 clients on dual-stack sockets, e.g. `::ffff:10.0.0.1`.
+        let addr = match addr {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+            addr @ IpAddr::V4(_) => addr,
+        };
+
+        match (self.addr, addr) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                prefix_eq(&network.octets(), &addr.octets(), self.prefix_len)
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                prefix_eq(&network.octets(), &addr.octets(), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+fn prefix_eq(a: &[u8], b: &[u8], prefix_len: u8) -> bool {
+    let full_bytes = usize::from(prefix_len / 8);
+    let rest_bits = prefix_len % 8;
+
+    if a[..full_bytes] != b[..full_bytes] {
+        return false;
+    }
+    if rest_bits == 0 {
+        return true;
+    }
+
+    let mask = !(0xffu8 >> rest_bits);
+    a[full_bytes] & mask == b[full_bytes] & mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(peer: &str, headers: &[(&str, &str)]) -> Request<()> {
+        let mut req = Request::builder().header("host", "backend.internal");
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        let mut req = req.body(()).unwrap();
+        req.extensions_mut()
+            .insert(peer.parse::<SocketAddr>().unwrap());
+        req
+    }
+
+    fn resolve(proxies: &TrustedProxies, req: &Request<()>) -> (Option<String>, Option<String>) {
+        let effective = proxies.effective_authority(req);
+        (
+            effective.scheme().map(|s| s.to_string()),
+            effective.authority().map(|a| a.to_string()),
+        )
+    }
+
+    #[test]
+    fn networks() {
+        let network = IpNetwork::parse("10.0.0.0/8").unwrap();
+        assert!(network.contains("10.255.0.1".parse().unwrap()));
+        assert!(network.contains("::ffff:10.0.0.1".parse().unwrap()));
+        assert!(!network.contains("11.0.0.1".parse().unwrap()));
+
+        let network = IpNetwork::parse("192.168.4.0/22").unwrap();
+        assert!(network.contains("192.168.7.255".parse().unwrap()));
+        assert!(!network.contains("192.168.8.0".parse().unwrap()));
+
+        let network = IpNetwork::parse("fd00::/8").unwrap();
+        assert!(network.contains("fd12::1".parse().unwrap()));
+        assert!(!network.contains("fe80::1".parse().unwrap()));
+
+        assert!(IpNetwork::parse("0.0.0.0/0")
+            .unwrap()
+            .contains("1.2.3.4".parse().unwrap()));
+        assert!(IpNetwork::parse("::1")
+            .unwrap()
+            .contains("::1".parse().unwrap()));
+
+        for invalid in ["", "10.0.0.0/33", "::/129", "example.com", "10.0.0.0/"] {
+            assert_eq!(
+                TrustedProxies::new([invalid]).unwrap_err(),
+                ConfigError::InvalidNetwork {
+                    network: invalid.to_owned()
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn untrusted_peer_is_ignored() {
+        let proxies = TrustedProxies::new(["10.0.0.0/8"]).unwrap();
+        let req = request(
+            "203.0.113.7:1234",
+            &[
+                ("forwarded", "host=evil.example;proto=https"),
+                ("x-forwarded-host", "evil.example"),
+            ],
+        );
+
+        assert_eq!(
+            resolve(&proxies, &req),
+            (None, Some("backend.internal".to_owned()))
+        );
+    }
+
+    #[test]
+    fn forwarded_header() {
+        let proxies = TrustedProxies::new(["10.0.0.0/8"]).unwrap();
+
+        let req = request(
+            "10.0.0.1:1234",
+            &[(
+                "forwarded",
+                r#"for="[2001:db8::1]:4711";host=example.com;proto=https"#,
+            )],
+        );
+        assert_eq!(
+            resolve(&proxies, &req),
+            (Some("https".to_owned()), Some("example.com".to_owned()))
+        );
+
+        // A chain of trusted proxies: the outermost one saw what the client sent.
+        let req = request(
+            "10.0.0.1:1234",
+            &[(
+                "forwarded",
+                "for=203.0.113.7;host=example.com;proto=https, for=10.0.0.2;host=lb.internal;proto=http",
+            )],
+        );
+        assert_eq!(
+            resolve(&proxies, &req),
+            (Some("https".to_owned()), Some("example.com".to_owned()))
+        );
+
+        // Elements added before an untrusted hop are spoofable and ignored.
+        let req = request(
+            "10.0.0.1:1234",
+            &[(
+                "forwarded",
+                "host=evil.example, for=203.0.113.7;host=example.com;proto=https",
+            )],
+        );
+        assert_eq!(
+            resolve(&proxies, &req),
+            (Some("https".to_owned()), Some("example.com".to_owned()))
+        );
+    }
+
+    #[test]
+    fn x_forwarded_headers() {
+        let proxies = TrustedProxies::new(["10.0.0.0/8"]).unwrap();
+        let req = request(
+            "[::ffff:10.0.0.1]:1234",
+            &[
+                ("x-forwarded-host", "evil.example, example.com:8443"),
+                ("x-forwarded-proto", "https"),
+            ],
+        );
+
+        assert_eq!(
+            resolve(&proxies, &req),
+            (
+                Some("https".to_owned()),
+                Some("example.com:8443".to_owned())
+            )
+        );
+    }
+
+    #[test]
+    fn parses_nodes() {
+        for (node, addr) in [
+            ("192.0.2.60", Some("192.0.2.60")),
+            ("192.0.2.60:8080", Some("192.0.2.60")),
+            ("2001:db8::1", Some("2001:db8::1")),
+            ("[2001:db8::1]", Some("2001:db8::1")),
+            ("[2001:db8::1]:4711", Some("2001:db8::1")),
+            ("unknown", None),
+            ("_hidden", None),
+        ] {
+            assert_eq!(
+                parse_node(node),
+                addr.map(|addr| addr.parse().unwrap()),
+                "{}",
+                node
+            );
+        }
+    }
+}
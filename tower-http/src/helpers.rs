@@ -0,0 +1,138 @@
+//! Small helpers shared between middleware.
+
+/// Compares `a` and `b` in time that only depends on their lengths.
+#[cfg(any(feature = "auth", feature = "csrf-token"))]
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Finds the entry whose secret is `given`.
+///
+/// Every entry is compared, so the time taken doesn't reveal which one matched, if any.
+#[cfg(feature = "auth")]
+pub(crate) fn find_in_constant_time<'a, T>(
+    entries: &'a [(T, String)],
+    given: &str,
+) -> Option<&'a T> {
+    entries.iter().fold(None, |found, (entry, expected)| {
+        let matches = constant_time_eq(expected.as_bytes(), given.as_bytes());
+        found.or_else(|| matches.then_some(entry))
+    })
+}
+
+/// Decodes a name or value of `application/x-www-form-urlencoded` data, where `+` is a space.
+#[cfg(any(feature = "auth", feature = "csrf-token"))]
+pub(crate) fn decode_form_component(s: &[u8]) -> Option<String> {
+    let s = s
+        .iter()
+        .map(|&b| if b == b'+' { b' ' } else { b })
+        .collect::<Vec<_>>();
+    percent_encoding::percent_decode(&s)
+        .decode_utf8()
+        .ok()
+        .map(|s| s.into_owned())
+}
+
+/// Quotes `s` as an HTTP quoted string, which is also how structured fields write strings.
+#[cfg(any(feature = "auth", feature = "rate-limit"))]
+pub(crate) fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The IP address of the client from the request extensions.
+///
+/// That is the `ClientIp` inserted by `SetClientIp` if there is one, or else the address of the
+/// peer from a `SocketAddr` or `IpAddr`.
+#[cfg(any(
+    feature = "auth",
+    feature = "concurrency-limit",
+    feature = "ip-filter",
+    feature = "rate-limit"
+))]
+pub(crate) fn client_ip(extensions: &http::Extensions) -> Option<std::net::IpAddr> {
+    use std::net::{IpAddr, SocketAddr};
+
+    #[cfg(feature = "client-ip")]
+    let client_ip = extensions
+        .get::<crate::client_ip::ClientIp>()
+        .map(crate::client_ip::ClientIp::ip);
+    #[cfg(not(feature = "client-ip"))]
+    let client_ip = None;
+
+    client_ip
+        .or_else(|| extensions.get::<SocketAddr>().map(SocketAddr::ip))
+        .or_else(|| extensions.get::<IpAddr>().copied())
+}
+
+/// The response to a rejected request, held by the types defined with `define_rejection!`.
+#[cfg(any(feature = "concurrency-limit", feature = "cors", feature = "ip-filter"))]
+pub(crate) enum Rejection<F: ?Sized> {
+    Status(http::StatusCode),
+    Custom(std::sync::Arc<F>),
+}
+
+#[cfg(any(feature = "concurrency-limit", feature = "cors", feature = "ip-filter"))]
+impl<F: ?Sized> Clone for Rejection<F> {
+    fn clone(&self) -> Self {
+        match self {
+            Rejection::Status(status) => Rejection::Status(*status),
+            Rejection::Custom(f) => Rejection::Custom(f.clone()),
+        }
+    }
+}
+
+#[cfg(any(feature = "concurrency-limit", feature = "cors", feature = "ip-filter"))]
+impl<F: ?Sized> std::fmt::Debug for Rejection<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::Status(status) => f.debug_tuple("Status").field(status).finish(),
+            Rejection::Custom(_) => f.debug_tuple("Custom").finish(),
+        }
+    }
+}
+
+/// A path prefix matched by whole segments, so `/api` matches `/api` and `/api/users` but not
+/// `/apis`.
+#[cfg(any(feature = "cors", feature = "csrf"))]
+#[derive(Clone)]
+pub(crate) struct PathPrefix(
+    /// Stored without a trailing `/`, so `/` is the empty string.
+    String,
+);
+
+#[cfg(any(feature = "cors", feature = "csrf"))]
+impl PathPrefix {
+    /// # Panics
+    ///
+    /// If `prefix` doesn't start with a `/`.
+    pub(crate) fn new(prefix: &str) -> Self {
+        assert!(
+            prefix.starts_with('/'),
+            "path prefix `{}` must start with `/`",
+            prefix
+        );
+
+        Self(prefix.trim_end_matches('/').to_owned())
+    }
+
+    pub(crate) fn matches(&self, path: &str) -> bool {
+        path.strip_prefix(self.0.as_str())
+            .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+    }
+}
+
+#[cfg(any(feature = "cors", feature = "csrf"))]
+impl std::fmt::Debug for PathPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&self.0, f)
+    }
+}
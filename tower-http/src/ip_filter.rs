@@ -0,0 +1,547 @@
+//! Middleware that allows or denies requests by the IP address of the client.
+//!
+//! [`IpFilterRules`] hold CIDR lists of allowed and denied IPv4 and IPv6 networks. Requests from
+//! clients that aren't allowed, or whose address is unknown, are rejected with `403 Forbidden`
+//! by default.
+//!
+//! The rules live behind an [`IpFilterHandle`] shared by all services created from a layer, so
+//! they can be replaced at runtime, e.g. when a configuration file is reloaded.
+//!
+//! The address of the client is taken from, in order:
+//!
+//! 1. The [`ClientIp`] extension inserted by [`SetClientIpLayer`].
+//! 2. A [`SocketAddr`] or [`IpAddr`] extension, which servers usually insert for the peer.
+//!
+//! Use [`IpFilterLayer::trusted_proxies`] to resolve the client behind reverse proxies instead,
+//! or [`IpFilterLayer::client_ip`] to read it from another connection-info extension.
+//!
+//! # Example
+//!
+//! ```
+//! use http::{Request, Response, StatusCode};
+//! use std::convert::Infallible;
+//! use std::net::SocketAddr;
+//! use tower::{Service, ServiceExt, ServiceBuilder, service_fn};
+//! use tower_http::ip_filter::{IpFilterLayer, IpFilterRules};
+//!
+//! async fn handle(_: Request<()>) -> Result<Response<String>, Infallible> {
+//!     Ok(Response::new("admin".to_owned()))
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let layer = IpFilterLayer::new(
+//!     IpFilterRules::new()
+//!         .allow(["10.0.0.0/8", "fd00::/8"])?
+//!         .deny(["10.0.66.0/24"])?,
+//! );
+//! // Keep a handle to replace the rules later.
+//! let rules = layer.handle();
+//!
+//! let mut service = ServiceBuilder::new().layer(layer).service_fn(handle);
+//!
+//! let request = |peer: &str| {
+//!     let mut request = Request::new(());
+//!     request.extensions_mut().insert(peer.parse::<SocketAddr>().unwrap());
+//!     request
+//! };
+//!
+//! let response = service.ready().await?.call(request("10.0.0.1:40000")).await?;
+//! assert_eq!(response.status(), StatusCode::OK);
+//!
+//! let response = service.ready().await?.call(request("10.0.66.1:40000")).await?;
+//! assert_eq!(response.status(), StatusCode::FORBIDDEN);
+//!
+//! rules.set(IpFilterRules::new().allow(["10.0.66.0/24"])?);
+//!
+//! let response = service.ready().await?.call(request("10.0.66.1:40000")).await?;
+//! assert_eq!(response.status(), StatusCode::OK);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`ClientIp`]: crate::client_ip::ClientIp
+//! [`SetClientIpLayer`]: crate::client_ip::SetClientIpLayer
+//! [`SocketAddr`]: std::net::SocketAddr
+
+use http::{Extensions, Request, Response, StatusCode};
+use pin_project_lite::pin_project;
+use std::{
+    fmt,
+    future::Future,
+    net::IpAddr,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::forwarded::{ConfigError, IpNetwork, TrustedProxies};
+use crate::helpers::client_ip;
+
+/// Lists of allowed and denied networks.
+///
+/// A client is allowed if its address isn't in a denied network, and either there are no allowed
+/// networks or its address is in one of them. Denied networks thus take precedence.
+#[derive(Clone, Debug, Default)]
+pub struct IpFilterRules {
+    allow: Vec<IpNetwork>,
+    deny: Vec<IpNetwork>,
+}
+
+impl IpFilterRules {
+    /// Create rules that allow every client.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow clients in the given networks, each an IP address or a CIDR block such as
+    /// `10.0.0.0/8` or `fd00::/8`.
+    ///
+    /// Once a network is allowed, clients outside of allowed networks are denied.
+    pub fn allow<I, S>(mut self, networks: I) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.allow.extend(parse_networks(networks)?);
+        Ok(self)
+    }
+
+    /// Deny clients in the given networks, each an IP address or a CIDR block such as
+    /// `10.0.0.0/8` or `fd00::/8`.
+    pub fn deny<I, S>(mut self, networks: I) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.deny.extend(parse_networks(networks)?);
+        Ok(self)
+    }
+
+    /// Is the client with address `ip` allowed?
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|network| network.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|network| network.contains(ip))
+    }
+}
+
+fn parse_networks<I, S>(networks: I) -> Result<Vec<IpNetwork>, ConfigError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    networks
+        .into_iter()
+        .map(|network| {
+            let network = network.as_ref();
+            IpNetwork::parse(network).ok_or_else(|| ConfigError::InvalidNetwork {
+                network: network.to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// Shared handle to the [`IpFilterRules`] of an [`IpFilterLayer`].
+///
+/// Cloning the handle doesn't clone the rules: rules [set](Self::set) through any clone apply to
+/// all services created from the layer, starting with the next request.
+#[derive(Clone, Debug)]
+pub struct IpFilterHandle(Arc<RwLock<Arc<IpFilterRules>>>);
+
+impl IpFilterHandle {
+    /// Create a new handle holding `rules`.
+    pub fn new(rules: IpFilterRules) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(rules))))
+    }
+
+    /// The current rules.
+    pub fn rules(&self) -> Arc<IpFilterRules> {
+        match self.0.read() {
+            Ok(rules) => rules.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    /// Replace the rules.
+    pub fn set(&self, rules: IpFilterRules) {
+        let rules = Arc::new(rules);
+        match self.0.write() {
+            Ok(mut current) => *current = rules,
+            Err(poisoned) => *poisoned.into_inner() = rules,
+        }
+    }
+}
+
+define_rejection! {
+    /// Holds configuration for the response to rejected requests.
+    ///
+    /// See [`IpFilterLayer::rejection`] for more details.
+    pub struct IpRejection;
+    default = StatusCode::FORBIDDEN;
+    /// Build the response from the address of the rejected client, `None` if it is unknown.
+    custom(ip: Option<IpAddr>);
+}
+
+#[derive(Clone)]
+enum ClientIpSource {
+    Extensions,
+    TrustedProxies(TrustedProxies),
+    Custom(Arc<dyn Fn(&Extensions) -> Option<IpAddr> + Send + Sync + 'static>),
+}
+
+impl ClientIpSource {
+    fn client_ip<B>(&self, req: &Request<B>) -> Option<IpAddr> {
+        match self {
+            ClientIpSource::Extensions => client_ip(req.extensions()),
+            ClientIpSource::TrustedProxies(proxies) => proxies.client_ip(req),
+            ClientIpSource::Custom(f) => f(req.extensions()),
+        }
+    }
+}
+
+impl fmt::Debug for ClientIpSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientIpSource::Extensions => f.debug_tuple("Extensions").finish(),
+            ClientIpSource::TrustedProxies(proxies) => {
+                f.debug_tuple("TrustedProxies").field(proxies).finish()
+            }
+            ClientIpSource::Custom(_) => f.debug_tuple("Custom").finish(),
+        }
+    }
+}
+
+/// Layer that applies the [`IpFilter`] middleware.
+///
+/// See the [module docs](crate::ip_filter) for more details.
+#[derive(Clone, Debug)]
+#[must_use]
+pub struct IpFilterLayer {
+    rules: IpFilterHandle,
+    client_ip: ClientIpSource,
+    rejection: IpRejection,
+}
+
+impl IpFilterLayer {
+    /// Create a new `IpFilterLayer` enforcing `rules`.
+    pub fn new(rules: IpFilterRules) -> Self {
+        Self::with_handle(IpFilterHandle::new(rules))
+    }
+
+    /// Create a new `IpFilterLayer` enforcing the rules held by `handle`.
+    pub fn with_handle(handle: IpFilterHandle) -> Self {
+        Self {
+            rules: handle,
+            client_ip: ClientIpSource::Extensions,
+            rejection: IpRejection::default(),
+        }
+    }
+
+    /// A handle to replace the rules at runtime.
+    pub fn handle(&self) -> IpFilterHandle {
+        self.rules.clone()
+    }
+
+    /// Resolve the client address behind reverse proxies.
+    ///
+    /// See [`TrustedProxies::client_ip`] for details.
+    pub fn trusted_proxies(mut self, proxies: TrustedProxies) -> Self {
+        self.client_ip = ClientIpSource::TrustedProxies(proxies);
+        self
+    }
+
+    /// Read the client address from the request extensions with `f`.
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    /// use tower_http::ip_filter::{IpFilterLayer, IpFilterRules};
+    ///
+    /// #[derive(Clone)]
+    /// struct ConnectInfo(SocketAddr);
+    ///
+    /// let layer = IpFilterLayer::new(IpFilterRules::new().allow(["127.0.0.1", "::1"])?)
+    ///     .client_ip(|extensions| extensions.get::<ConnectInfo>().map(|info| info.0.ip()));
+    /// # Ok::<_, tower_http::forwarded::ConfigError>(())
+    /// ```
+    pub fn client_ip<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Extensions) -> Option<IpAddr> + Send + Sync + 'static,
+    {
+        self.client_ip = ClientIpSource::Custom(Arc::new(f));
+        self
+    }
+
+    /// Set the response to rejected requests.
+    ///
+    /// Defaults to `403 Forbidden` with an empty body.
+    ///
+    /// ```
+    /// use http::StatusCode;
+    /// use tower_http::ip_filter::{IpFilterLayer, IpFilterRules};
+    ///
+    /// // Hide the endpoint from other networks.
+    /// let layer = IpFilterLayer::new(IpFilterRules::new().allow(["10.0.0.0/8"])?)
+    ///     .rejection(StatusCode::NOT_FOUND);
+    /// # Ok::<_, tower_http::forwarded::ConfigError>(())
+    /// ```
+    pub fn rejection<T>(mut self, rejection: T) -> Self
+    where
+        T: Into<IpRejection>,
+    {
+        self.rejection = rejection.into();
+        self
+    }
+}
+
+impl<S> Layer<S> for IpFilterLayer {
+    type Service = IpFilter<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        IpFilter {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Middleware that allows or denies requests by the IP address of the client.
+///
+/// See the [module docs](crate::ip_filter) for more details.
+#[derive(Clone, Debug)]
+#[must_use]
+pub struct IpFilter<S> {
+    inner: S,
+    layer: IpFilterLayer,
+}
+
+impl<S> IpFilter<S> {
+    /// Create a new `IpFilter` enforcing `rules`.
+    pub fn new(inner: S, rules: IpFilterRules) -> Self {
+        Self::layer(rules).layer(inner)
+    }
+
+    define_inner_service_accessors!();
+
+    /// Returns a new [`Layer`] that wraps services with an `IpFilter` middleware.
+    ///
+    /// [`Layer`]: tower_layer::Layer
+    pub fn layer(rules: IpFilterRules) -> IpFilterLayer {
+        IpFilterLayer::new(rules)
+    }
+
+    /// A handle to replace the rules at runtime.
+    pub fn handle(&self) -> IpFilterHandle {
+        self.layer.handle()
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for IpFilter<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    ResBody: Default,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let ip = self.layer.client_ip.client_ip(&req);
+
+        if ip.map_or(false, |ip| self.layer.rules.rules().is_allowed(ip)) {
+            return ResponseFuture {
+                kind: Kind::Future {
+                    future: self.inner.call(req),
+                },
+            };
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(uri = %req.uri().path(), ip = ?ip, "request rejected by IP filter");
+
+        ResponseFuture {
+            kind: Kind::Rejected {
+                response: Some(self.layer.rejection.to_response(ip)),
+            },
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`IpFilter`].
+    pub struct ResponseFuture<F> {
+        #[pin]
+        kind: Kind<F>,
+    }
+}
+
+pin_project! {
+    #[project = KindProj]
+    enum Kind<F> {
+        Future {
+            #[pin]
+            future: F,
+        },
+        Rejected {
+            response: Option<Response<()>>,
+        },
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: Default,
+{
+    type Output = Result<Response<B>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project().kind.project() {
+            KindProj::Future { future } => future.poll(cx),
+            KindProj::Rejected { response } => {
+                let response = response.take().expect("future polled after completion");
+                let (parts, ()) = response.into_parts();
+                Poll::Ready(Ok(Response::from_parts(parts, B::default())))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client_ip::ClientIp, test_helpers::Body};
+    use std::net::SocketAddr;
+    use tower::{service_fn, ServiceBuilder, ServiceExt};
+
+    fn request(peer: &str) -> Request<Body> {
+        let mut req = Request::new(Body::empty());
+        req.extensions_mut()
+            .insert(peer.parse::<SocketAddr>().unwrap());
+        req
+    }
+
+    async fn echo(req: Request<Body>) -> Result<Response<Body>, std::convert::Infallible> {
+        Ok(Response::new(req.into_body()))
+    }
+
+    #[test]
+    fn rules() {
+        let rules = IpFilterRules::new();
+        assert!(rules.is_allowed("203.0.113.7".parse().unwrap()));
+
+        let rules = IpFilterRules::new()
+            .allow(["10.0.0.0/8", "2001:db8::/32"])
+            .unwrap()
+            .deny(["10.1.0.0/16", "2001:db8:bad::/48"])
+            .unwrap();
+        for (ip, allowed) in [
+            ("10.0.0.1", true),
+            ("10.1.0.1", false),
+            ("203.0.113.7", false),
+            ("2001:db8::1", true),
+            ("2001:db8:bad::1", false),
+            ("::ffff:10.0.0.1", true),
+            ("fe80::1", false),
+        ] {
+            assert_eq!(rules.is_allowed(ip.parse().unwrap()), allowed, "{}", ip);
+        }
+
+        let rules = IpFilterRules::new().deny(["203.0.113.0/24"]).unwrap();
+        assert!(!rules.is_allowed("203.0.113.7".parse().unwrap()));
+        assert!(rules.is_allowed("198.51.100.1".parse().unwrap()));
+
+        let rules = IpFilterRules::new()
+            .deny(["::ffff:203.0.113.0/120", "::ffff:198.51.100.1"])
+            .unwrap();
+        for (ip, allowed) in [
+            ("203.0.113.7", false),
+            ("::ffff:203.0.113.7", false),
+            ("198.51.100.1", false),
+            ("198.51.100.2", true),
+        ] {
+            assert_eq!(rules.is_allowed(ip.parse().unwrap()), allowed, "{}", ip);
+        }
+
+        assert_eq!(
+            IpFilterRules::new().allow(["10.0.0.0/40"]).unwrap_err(),
+            ConfigError::InvalidNetwork {
+                network: "10.0.0.0/40".to_owned()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn filters_requests() {
+        let layer = IpFilterLayer::new(IpFilterRules::new().allow(["10.0.0.0/8"]).unwrap());
+        let handle = layer.handle();
+        let svc = ServiceBuilder::new().layer(layer).service_fn(echo);
+
+        let res = svc.clone().oneshot(request("10.0.0.1:1234")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = svc
+            .clone()
+            .oneshot(request("203.0.113.7:1234"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // Unknown clients are rejected.
+        let res = svc
+            .clone()
+            .oneshot(Request::new(Body::empty()))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // The `ClientIp` extension takes precedence over the peer.
+        let mut req = request("203.0.113.7:1234");
+        req.extensions_mut()
+            .insert(ClientIp::new("10.0.0.1".parse().unwrap()));
+        let res = svc.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        handle.set(IpFilterRules::new().deny(["10.0.0.0/8"]).unwrap());
+
+        let res = svc.clone().oneshot(request("10.0.0.1:1234")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = svc.oneshot(request("203.0.113.7:1234")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn trusted_proxies_and_rejection() {
+        let layer = IpFilterLayer::new(IpFilterRules::new().allow(["198.51.100.0/24"]).unwrap())
+            .trusted_proxies(TrustedProxies::new(["10.0.0.0/8"]).unwrap())
+            .rejection(IpRejection::custom(|ip| {
+                let mut res = Response::new(());
+                *res.status_mut() = StatusCode::NOT_FOUND;
+                res.headers_mut()
+                    .insert("x-client-ip", ip.unwrap().to_string().parse().unwrap());
+                res
+            }));
+        let svc = layer.layer(service_fn(echo));
+
+        let mut req = request("10.0.0.1:1234");
+        req.headers_mut()
+            .insert("x-forwarded-for", "198.51.100.1".parse().unwrap());
+        let res = svc.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut req = request("10.0.0.1:1234");
+        req.headers_mut()
+            .insert("x-forwarded-for", "203.0.113.7".parse().unwrap());
+        let res = svc.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.headers()["x-client-ip"], "203.0.113.7");
+    }
+}
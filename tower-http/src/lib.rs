@@ -204,6 +204,8 @@
 #[macro_use]
 pub(crate) mod macros;
 
+mod helpers;
+
 #[cfg(test)]
 mod test_helpers;
 
@@ -293,12 +295,27 @@ pub mod limit;
 #[cfg(feature = "metrics")]
 pub mod metrics;
 
+#[cfg(feature = "client-ip")]
+pub mod client_ip;
+
 #[cfg(feature = "cors")]
 pub mod cors;
 
 #[cfg(feature = "csrf")]
 pub mod csrf;
 
+#[cfg(feature = "forwarded")]
+pub mod forwarded;
+
+#[cfg(feature = "ip-filter")]
+pub mod ip_filter;
+
+#[cfg(feature = "rate-limit")]
+pub mod rate_limit;
+
+#[cfg(feature = "concurrency-limit")]
+pub mod concurrency_limit;
+
 #[cfg(feature = "request-id")]
 pub mod request_id;
 
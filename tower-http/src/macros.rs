@@ -104,6 +104,77 @@ macro_rules! opaque_future {
     }
 }
 
+/// Define the type configuring the response to requests rejected by a middleware.
+///
+/// It responds with a status code, or builds the response with a callback taking the arguments
+/// listed after `custom`. `to_response` is called with those arguments.
+#[allow(unused_macros)]
+macro_rules! define_rejection {
+    (
+        $(#[$m:meta])*
+        pub struct $name:ident;
+        default = $default:expr;
+        $(#[$custom_m:meta])*
+        custom($($arg:ident: $ty:ty),*);
+    ) => {
+        $(#[$m])*
+        #[derive(Clone)]
+        #[must_use]
+        pub struct $name(
+            $crate::helpers::Rejection<
+                dyn Fn($($ty),*) -> http::Response<()> + Send + Sync + 'static,
+            >,
+        );
+
+        impl $name {
+            /// Respond with an empty body and the given status code.
+            pub fn status(status: http::StatusCode) -> Self {
+                Self($crate::helpers::Rejection::Status(status))
+            }
+
+            $(#[$custom_m])*
+            ///
+            /// The body of the response is replaced by the default body of the inner service's
+            /// response body type.
+            pub fn custom<F>(f: F) -> Self
+            where
+                F: Fn($($ty),*) -> http::Response<()> + Send + Sync + 'static,
+            {
+                Self($crate::helpers::Rejection::Custom(std::sync::Arc::new(f)))
+            }
+
+            pub(crate) fn to_response(&self, $($arg: $ty),*) -> http::Response<()> {
+                match &self.0 {
+                    $crate::helpers::Rejection::Status(status) => {
+                        let mut response = http::Response::new(());
+                        *response.status_mut() = *status;
+                        response
+                    }
+                    $crate::helpers::Rejection::Custom(f) => f($($arg),*),
+                }
+            }
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                Self::status($default)
+            }
+        }
+
+        impl From<http::StatusCode> for $name {
+            fn from(status: http::StatusCode) -> Self {
+                Self::status(status)
+            }
+        }
+
+        impl std::fmt::Debug for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                std::fmt::Debug::fmt(&self.0, f)
+            }
+        }
+    };
+}
+
 /// Evaluate `$call` at most once every `$interval` per call site.
 ///
 /// Uses a monotonic clock and atomic timestamp to rate-limit without locks.
@@ -0,0 +1,842 @@
+//! Middleware that limits the rate of requests per client, API key or route.
+//!
+//! Unlike tower's [`RateLimit`], which shares one budget between all requests and waits in
+//! `poll_ready` until it refills, [`RateLimitLayer`] keeps a budget per key and rejects requests
+//! over it right away with `429 Too Many Requests` and a `Retry-After` header. The key is
+//! computed from the request by a closure, and requests without a key aren't limited.
+//!
+//! Budgets are enforced with a token bucket or with the generic cell rate algorithm (GCRA), see
+//! [`Algorithm`]. Responses carry the `RateLimit-Policy` and `RateLimit` headers from the IETF
+//! [RateLimit header fields draft], so well-behaved clients can slow down before being
+//! rejected.
+//!
+//! Keys whose budget has fully recovered are indistinguishable from new keys, so they are
+//! evicted from time to time to keep memory bounded by the number of recently active keys.
+//!
+//! # Example
+//!
+//! ```
+//! use http::{Request, Response, StatusCode, header::RETRY_AFTER};
+//! use http_body_util::Full;
+//! use bytes::Bytes;
+//! use std::net::SocketAddr;
+//! use tower::{Service, ServiceExt, ServiceBuilder, BoxError};
+//! use tower_http::rate_limit::{Quota, RateLimitLayer};
+//!
+//! async fn handle(request: Request<Full<Bytes>>) -> Result<Response<Full<Bytes>>, BoxError> {
+//!     Ok(Response::new(Full::default()))
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), BoxError> {
+//! let mut service = ServiceBuilder::new()
+//!     // 2 requests per minute per client IP.
+//!     .layer(RateLimitLayer::per_ip(Quota::per_minute(2)))
+//!     .service_fn(handle);
+//!
+//! let request = || {
+//!     let mut request = Request::new(Full::default());
+//!     // Usually inserted by the server, or use `SetClientIpLayer`.
+//!     request.extensions_mut().insert("203.0.113.7:4321".parse::<SocketAddr>().unwrap());
+//!     request
+//! };
+//!
+//! let response = service.ready().await?.call(request()).await?;
+//! assert_eq!(StatusCode::OK, response.status());
+//! assert_eq!(response.headers()["ratelimit-policy"], "\"default\";q=2;w=60");
+//! assert_eq!(response.headers()["ratelimit"], "\"default\";r=1;t=30");
+//!
+//! service.ready().await?.call(request()).await?;
+//!
+//! let response = service.ready().await?.call(request()).await?;
+//! assert_eq!(StatusCode::TOO_MANY_REQUESTS, response.status());
+//! assert_eq!(response.headers()[RETRY_AFTER], "30");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Other keys are computed from the request parts:
+//!
+//! ```
+//! use http::request::Parts;
+//! use tower_http::rate_limit::{Algorithm, Quota, RateLimitLayer};
+//!
+//! // 100 requests per hour per API key, in bursts of up to 10.
+//! let layer = RateLimitLayer::new(Quota::per_hour(100).burst(10), |parts: &Parts| {
+//!     parts
+//!         .headers
+//!         .get("x-api-key")
+//!         .map(|key| key.as_bytes().to_vec())
+//! })
+//! .algorithm(Algorithm::Gcra)
+//! .policy_name("api-key");
+//! ```
+//!
+//! [`RateLimit`]: https://docs.rs/tower/latest/tower/limit/rate/struct.RateLimit.html
+//! [RateLimit header fields draft]: https://datatracker.ietf.org/doc/draft-ietf-httpapi-ratelimit-headers/
+
+use crate::helpers::client_ip;
+use crate::helpers::quote;
+use http::{
+    header::{self, HeaderName},
+    request::Parts,
+    HeaderMap, HeaderValue, Request, Response, StatusCode,
+};
+use pin_project_lite::pin_project;
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    hash::Hash,
+    net::IpAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+const RATELIMIT: HeaderName = HeaderName::from_static("ratelimit");
+const RATELIMIT_POLICY: HeaderName = HeaderName::from_static("ratelimit-policy");
+
+/// Sweep for expired keys once the map has grown to at least this many keys.
+const MIN_SWEEP: usize = 1024;
+
+/// The number of requests allowed per time window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quota {
+    limit: u32,
+    window: Duration,
+    burst: u32,
+}
+
+impl Quota {
+    /// Allow `limit` requests per `window`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` or `window` is zero, or if `window / limit` is shorter than a nanosecond.
+    pub fn new(limit: u32, window: Duration) -> Self {
+        assert!(limit > 0, "rate limit must be greater than zero");
+        assert!(
+            !window.is_zero(),
+            "rate limit window must be greater than zero"
+        );
+        assert!(
+            !(window / limit).is_zero(),
+            "rate limit window divided by the limit must be at least a nanosecond"
+        );
+        Self {
+            limit,
+            window,
+            burst: limit,
+        }
+    }
+
+    /// Allow `limit` requests per second.
+    pub fn per_second(limit: u32) -> Self {
+        Self::new(limit, Duration::from_secs(1))
+    }
+
+    /// Allow `limit` requests per minute.
+    pub fn per_minute(limit: u32) -> Self {
+        Self::new(limit, Duration::from_secs(60))
+    }
+
+    /// Allow `limit` requests per hour.
+    pub fn per_hour(limit: u32) -> Self {
+        Self::new(limit, Duration::from_secs(60 * 60))
+    }
+
+    /// Set how many requests may be made at once.
+    ///
+    /// The budget recovers at `limit / window`, up to `burst` requests. Defaults to the limit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `burst` is zero.
+    pub fn burst(mut self, burst: u32) -> Self {
+        assert!(burst > 0, "rate limit burst must be greater than zero");
+        self.burst = burst;
+        self
+    }
+
+    /// The time it takes to recover the budget of one request.
+    fn interval(&self) -> Duration {
+        self.window / self.limit
+    }
+}
+
+/// The algorithm used to enforce a [`Quota`].
+///
+/// Both allow `burst` requests at once and then recover one request per `window / limit`. They
+/// differ in the state they keep per key.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Algorithm {
+    /// A bucket holding up to `burst` tokens, refilled continuously, from which each request
+    /// takes one.
+    ///
+    /// This is the default.
+    #[default]
+    TokenBucket,
+    /// The generic cell rate algorithm, which only keeps the theoretical arrival time of the
+    /// next request.
+    Gcra,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum State {
+    TokenBucket { tokens: f64, last: Instant },
+    Gcra { tat: Instant },
+}
+
+/// The outcome of checking a request against the budget of its key.
+#[derive(Debug, PartialEq)]
+struct Decision {
+    allowed: bool,
+    remaining: u32,
+    /// Until the budget has fully recovered.
+    reset: Duration,
+    /// Until the next request is allowed, if this one isn't.
+    retry_after: Duration,
+}
+
+impl State {
+    fn new(algorithm: Algorithm, quota: &Quota, now: Instant) -> Self {
+        match algorithm {
+            Algorithm::TokenBucket => State::TokenBucket {
+                tokens: f64::from(quota.burst),
+                last: now,
+            },
+            Algorithm::Gcra => State::Gcra { tat: now },
+        }
+    }
+
+    fn check(&mut self, quota: &Quota, now: Instant) -> Decision {
+        let interval = quota.interval();
+        match self {
+            State::TokenBucket { tokens, last } => {
+                let interval = interval.as_secs_f64();
+                let elapsed = now.saturating_duration_since(*last).as_secs_f64();
+                let capacity = f64::from(quota.burst);
+                *tokens = (*tokens + elapsed / interval).min(capacity);
+                *last = now;
+
+                let allowed = *tokens >= 1.0;
+                if allowed {
+                    *tokens -= 1.0;
+                }
+                Decision {
+                    allowed,
+                    remaining: tokens.floor() as u32,
+                    reset: Duration::from_secs_f64((capacity - *tokens) * interval),
+                    retry_after: if allowed {
+                        Duration::ZERO
+                    } else {
+                        Duration::from_secs_f64((1.0 - *tokens) * interval)
+                    },
+                }
+            }
+            State::Gcra { tat } => {
+                let tolerance = interval * quota.burst;
+                let next = (*tat).max(now) + interval;
+                let ahead = next - now;
+
+                if ahead > tolerance {
+                    return Decision {
+                        allowed: false,
+                        remaining: 0,
+                        reset: tat.saturating_duration_since(now),
+                        retry_after: ahead - tolerance,
+                    };
+                }
+                *tat = next;
+                Decision {
+                    allowed: true,
+                    remaining: ((tolerance - ahead).as_nanos() / interval.as_nanos()) as u32,
+                    reset: ahead,
+                    retry_after: Duration::ZERO,
+                }
+            }
+        }
+    }
+
+    /// Whether the budget has fully recovered, so the state can be forgotten.
+    fn is_expired(&self, quota: &Quota, now: Instant) -> bool {
+        match *self {
+            State::TokenBucket { tokens, last } => {
+                let missing = f64::from(quota.burst) - tokens;
+                now.saturating_duration_since(last).as_secs_f64()
+                    >= missing * quota.interval().as_secs_f64()
+            }
+            State::Gcra { tat } => tat <= now,
+        }
+    }
+}
+
+struct States<K> {
+    states: HashMap<K, State>,
+    next_sweep: usize,
+}
+
+impl<K: Hash + Eq> States<K> {
+    fn check(&mut self, key: K, algorithm: Algorithm, quota: &Quota, now: Instant) -> Decision {
+        let decision = self
+            .states
+            .entry(key)
+            .or_insert_with(|| State::new(algorithm, quota, now))
+            .check(quota, now);
+
+        if self.states.len() >= self.next_sweep {
+            self.states.retain(|_, state| !state.is_expired(quota, now));
+            self.next_sweep = (self.states.len() * 2).max(MIN_SWEEP);
+        }
+
+        decision
+    }
+}
+
+type KeyFn<K> = dyn Fn(&Parts) -> Option<K> + Send + Sync + 'static;
+
+/// Layer that applies the [`RateLimit`] middleware.
+///
+/// Services created from the same layer share the budgets, and so do clones of the layer.
+///
+/// See the [module docs](crate::rate_limit) for more details.
+#[must_use]
+pub struct RateLimitLayer<K> {
+    key: Arc<KeyFn<K>>,
+    quota: Quota,
+    algorithm: Algorithm,
+    policy_name: String,
+    headers: bool,
+    states: Arc<Mutex<States<K>>>,
+}
+
+impl RateLimitLayer<IpAddr> {
+    /// Limit requests per client IP address.
+    ///
+    /// The address is taken from the [`ClientIp`] extension inserted by [`SetClientIpLayer`],
+    /// or a [`SocketAddr`] or [`IpAddr`] extension. Requests from an unknown address aren't
+    /// limited.
+    ///
+    /// [`ClientIp`]: crate::client_ip::ClientIp
+    /// [`SetClientIpLayer`]: crate::client_ip::SetClientIpLayer
+    /// [`SocketAddr`]: std::net::SocketAddr
+    pub fn per_ip(quota: Quota) -> Self {
+        Self::new(quota, |parts: &Parts| client_ip(&parts.extensions))
+    }
+}
+
+impl<K> RateLimitLayer<K> {
+    /// Limit requests per key computed by `key`, enforcing `quota` for each.
+    ///
+    /// Requests for which `key` returns `None` aren't limited.
+    pub fn new<F>(quota: Quota, key: F) -> Self
+    where
+        F: Fn(&Parts) -> Option<K> + Send + Sync + 'static,
+    {
+        Self {
+            key: Arc::new(key),
+            quota,
+            algorithm: Algorithm::default(),
+            policy_name: "default".to_owned(),
+            headers: true,
+            states: Arc::new(Mutex::new(States {
+                states: HashMap::new(),
+                next_sweep: MIN_SWEEP,
+            })),
+        }
+    }
+
+    /// Set the [`Algorithm`] used to enforce the quota.
+    ///
+    /// Defaults to [`Algorithm::TokenBucket`].
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Set the name of the policy in the `RateLimit-Policy` and `RateLimit` headers.
+    ///
+    /// Defaults to `default`.
+    pub fn policy_name<N: Into<String>>(mut self, name: N) -> Self {
+        self.policy_name = name.into();
+        self
+    }
+
+    /// Set whether to add the `RateLimit-Policy` and `RateLimit` headers to responses.
+    ///
+    /// Defaults to `true`.
+    pub fn headers(mut self, headers: bool) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    fn check(&self, key: K) -> Decision
+    where
+        K: Hash + Eq,
+    {
+        self.states
+            .lock()
+            .unwrap()
+            .check(key, self.algorithm, &self.quota, Instant::now())
+    }
+
+    fn rate_limit_headers(&self, decision: &Decision) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if !self.headers {
+            return headers;
+        }
+
+        let name = quote(&self.policy_name);
+        let policy = format!(
+            "{};q={};w={}",
+            name,
+            self.quota.limit,
+            ceil_secs(self.quota.window)
+        );
+        let limit = format!(
+            "{};r={};t={}",
+            name,
+            decision.remaining,
+            ceil_secs(decision.reset)
+        );
+        if let (Ok(policy), Ok(limit)) = (
+            HeaderValue::from_str(&policy),
+            HeaderValue::from_str(&limit),
+        ) {
+            headers.insert(RATELIMIT_POLICY, policy);
+            headers.insert(RATELIMIT, limit);
+        }
+        headers
+    }
+}
+
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+impl<K> Clone for RateLimitLayer<K> {
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key.clone(),
+            quota: self.quota,
+            algorithm: self.algorithm,
+            policy_name: self.policy_name.clone(),
+            headers: self.headers,
+            states: self.states.clone(),
+        }
+    }
+}
+
+impl<K> fmt::Debug for RateLimitLayer<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimitLayer")
+            .field("quota", &self.quota)
+            .field("algorithm", &self.algorithm)
+            .field("policy_name", &self.policy_name)
+            .field("headers", &self.headers)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, K> Layer<S> for RateLimitLayer<K> {
+    type Service = RateLimit<S, K>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Middleware that limits the rate of requests per key.
+///
+/// See the [module docs](crate::rate_limit) for more details.
+#[must_use]
+pub struct RateLimit<S, K> {
+    inner: S,
+    layer: RateLimitLayer<K>,
+}
+
+impl<S, K> RateLimit<S, K> {
+    /// Limit requests per key computed by `key`, enforcing `quota` for each.
+    pub fn new<F>(inner: S, quota: Quota, key: F) -> Self
+    where
+        F: Fn(&Parts) -> Option<K> + Send + Sync + 'static,
+    {
+        Self::layer(quota, key).layer(inner)
+    }
+
+    define_inner_service_accessors!();
+
+    /// Returns a new [`Layer`] that wraps services with a `RateLimit` middleware.
+    ///
+    /// [`Layer`]: tower_layer::Layer
+    pub fn layer<F>(quota: Quota, key: F) -> RateLimitLayer<K>
+    where
+        F: Fn(&Parts) -> Option<K> + Send + Sync + 'static,
+    {
+        RateLimitLayer::new(quota, key)
+    }
+}
+
+impl<S, K> Clone for RateLimit<S, K>
+where
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+impl<S, K> fmt::Debug for RateLimit<S, K>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimit")
+            .field("inner", &self.inner)
+            .field("layer", &self.layer)
+            .finish()
+    }
+}
+
+impl<S, K, ReqBody, ResBody> Service<Request<ReqBody>> for RateLimit<S, K>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    K: Hash + Eq,
+    ResBody: Default,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let (parts, body) = req.into_parts();
+        let key = (self.layer.key)(&parts);
+        let req = Request::from_parts(parts, body);
+
+        let Some(key) = key else {
+            return ResponseFuture {
+                kind: Kind::Future {
+                    future: self.inner.call(req),
+                    headers: HeaderMap::new(),
+                },
+            };
+        };
+
+        let decision = self.layer.check(key);
+        let headers = self.layer.rate_limit_headers(&decision);
+        if decision.allowed {
+            return ResponseFuture {
+                kind: Kind::Future {
+                    future: self.inner.call(req),
+                    headers,
+                },
+            };
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(uri = %req.uri().path(), "request rejected by rate limit");
+
+        let mut response = Response::new(());
+        *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+        *response.headers_mut() = headers;
+        response.headers_mut().insert(
+            header::RETRY_AFTER,
+            HeaderValue::from(ceil_secs(decision.retry_after)),
+        );
+        ResponseFuture {
+            kind: Kind::Rejected {
+                response: Some(response),
+            },
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`RateLimit`].
+    pub struct ResponseFuture<F> {
+        #[pin]
+        kind: Kind<F>,
+    }
+}
+
+pin_project! {
+    #[project = KindProj]
+    enum Kind<F> {
+        Future {
+            #[pin]
+            future: F,
+            headers: HeaderMap,
+        },
+        Rejected {
+            response: Option<Response<()>>,
+        },
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: Default,
+{
+    type Output = Result<Response<B>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project().kind.project() {
+            KindProj::Future { future, headers } => {
+                let mut response = ready!(future.poll(cx))?;
+                for (name, value) in headers.drain() {
+                    if let Some(name) = name {
+                        response.headers_mut().insert(name, value);
+                    }
+                }
+                Poll::Ready(Ok(response))
+            }
+            KindProj::Rejected { response } => {
+                let response = response.take().expect("future polled after completion");
+                let (parts, ()) = response.into_parts();
+                Poll::Ready(Ok(Response::from_parts(parts, B::default())))
+            }
+        }
+    }
+}
+
+impl<F> fmt::Debug for ResponseFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseFuture").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::Body;
+    use tower::{BoxError, ServiceBuilder, ServiceExt};
+
+    async fn echo(_: Request<Body>) -> Result<Response<Body>, BoxError> {
+        Ok(Response::new(Body::empty()))
+    }
+
+    fn request(ip: &str) -> Request<Body> {
+        let mut request = Request::new(Body::empty());
+        request
+            .extensions_mut()
+            .insert(ip.parse::<IpAddr>().unwrap());
+        request
+    }
+
+    fn header<'a>(response: &'a Response<Body>, name: &str) -> Option<&'a str> {
+        response
+            .headers()
+            .get(name)
+            .map(|value| value.to_str().unwrap())
+    }
+
+    fn check_all(algorithm: Algorithm, quota: Quota, offsets_ms: &[u64]) -> Vec<Decision> {
+        let start = Instant::now();
+        let mut state = State::new(algorithm, &quota, start);
+        offsets_ms
+            .iter()
+            .map(|offset| state.check(&quota, start + Duration::from_millis(*offset)))
+            .collect()
+    }
+
+    fn allowed(remaining: u32, reset_ms: u64) -> Decision {
+        Decision {
+            allowed: true,
+            remaining,
+            reset: Duration::from_millis(reset_ms),
+            retry_after: Duration::ZERO,
+        }
+    }
+
+    fn rejected(reset_ms: u64, retry_after_ms: u64) -> Decision {
+        Decision {
+            allowed: false,
+            remaining: 0,
+            reset: Duration::from_millis(reset_ms),
+            retry_after: Duration::from_millis(retry_after_ms),
+        }
+    }
+
+    #[test]
+    fn algorithms_agree() {
+        // 2 per second, so one request recovers every 500ms.
+        let quota = Quota::per_second(2);
+        let expected = vec![
+            allowed(1, 500),
+            allowed(0, 1000),
+            rejected(1000, 500),
+            rejected(750, 250),
+            allowed(0, 1000),
+            allowed(1, 500),
+        ];
+        for algorithm in [Algorithm::TokenBucket, Algorithm::Gcra] {
+            assert_eq!(
+                check_all(algorithm, quota, &[0, 0, 0, 250, 500, 1500]),
+                expected,
+                "{:?}",
+                algorithm
+            );
+        }
+    }
+
+    #[test]
+    fn burst() {
+        // 1 per second with bursts of 3.
+        let quota = Quota::per_second(1).burst(3);
+        for algorithm in [Algorithm::TokenBucket, Algorithm::Gcra] {
+            let decisions = check_all(algorithm, quota, &[0, 0, 0, 0, 1000]);
+            let allowed: Vec<_> = decisions.iter().map(|d| d.allowed).collect();
+            assert_eq!(allowed, [true, true, true, false, true], "{:?}", algorithm);
+            assert_eq!(decisions[0].remaining, 2);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "at least a nanosecond")]
+    fn interval_below_a_nanosecond() {
+        Quota::new(2, Duration::from_nanos(1));
+    }
+
+    #[test]
+    fn evicts_expired_keys() {
+        let quota = Quota::per_second(1000);
+        let mut states = States {
+            states: HashMap::new(),
+            next_sweep: MIN_SWEEP,
+        };
+        let start = Instant::now();
+        for i in 0..MIN_SWEEP - 1 {
+            states.check(i, Algorithm::Gcra, &quota, start);
+        }
+        assert_eq!(states.states.len(), MIN_SWEEP - 1);
+
+        // Long after every budget recovered, the next key triggers a sweep.
+        states.check(
+            MIN_SWEEP,
+            Algorithm::Gcra,
+            &quota,
+            start + Duration::from_secs(1),
+        );
+        assert_eq!(states.states.len(), 1);
+        assert_eq!(states.next_sweep, MIN_SWEEP);
+    }
+
+    #[tokio::test]
+    async fn rejects_over_limit() {
+        let mut svc = ServiceBuilder::new()
+            .layer(RateLimitLayer::per_ip(Quota::per_minute(2)))
+            .service_fn(echo);
+
+        let res = svc
+            .ready()
+            .await
+            .unwrap()
+            .call(request("10.0.0.1"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            header(&res, "ratelimit-policy"),
+            Some("\"default\";q=2;w=60")
+        );
+        assert_eq!(header(&res, "ratelimit"), Some("\"default\";r=1;t=30"));
+
+        let res = svc
+            .ready()
+            .await
+            .unwrap()
+            .call(request("10.0.0.1"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(header(&res, "ratelimit"), Some("\"default\";r=0;t=60"));
+
+        let res = svc
+            .ready()
+            .await
+            .unwrap()
+            .call(request("10.0.0.1"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(header(&res, "retry-after"), Some("30"));
+        assert_eq!(header(&res, "ratelimit"), Some("\"default\";r=0;t=60"));
+
+        // Other clients have their own budget.
+        let res = svc
+            .ready()
+            .await
+            .unwrap()
+            .call(request("10.0.0.2"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn custom_key_and_policy() {
+        let svc = ServiceBuilder::new()
+            .layer(
+                RateLimitLayer::new(Quota::per_hour(1), |parts: &Parts| {
+                    parts.uri.path().strip_prefix("/api/").map(str::to_owned)
+                })
+                .algorithm(Algorithm::Gcra)
+                .policy_name("per-route"),
+            )
+            .service_fn(echo);
+
+        let call = |uri: &'static str| {
+            svc.clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        };
+
+        let res = call("/api/a").await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            header(&res, "ratelimit-policy"),
+            Some("\"per-route\";q=1;w=3600")
+        );
+        assert_eq!(
+            call("/api/a").await.unwrap().status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(call("/api/b").await.unwrap().status(), StatusCode::OK);
+
+        // Requests without a key aren't limited.
+        let res = call("/health").await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(header(&res, "ratelimit"), None);
+        assert_eq!(call("/health").await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn without_headers() {
+        let svc = ServiceBuilder::new()
+            .layer(RateLimitLayer::per_ip(Quota::per_second(1)).headers(false))
+            .service_fn(echo);
+
+        let res = svc.clone().oneshot(request("10.0.0.1")).await.unwrap();
+        assert!(res.headers().is_empty());
+
+        let res = svc.oneshot(request("10.0.0.1")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(header(&res, "retry-after"), Some("1"));
+        assert_eq!(header(&res, "ratelimit"), None);
+    }
+}
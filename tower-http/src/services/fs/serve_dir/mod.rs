@@ -2,6 +2,7 @@ use self::future::ResponseFuture;
 use crate::{
     body::UnsyncBoxBody,
     content_encoding::{encodings, SupportedEncodings},
+    forwarded::TrustedProxies,
     set_status::SetStatus,
 };
 use bytes::Bytes;
@@ -56,6 +57,7 @@ const DEFAULT_CAPACITY: usize = 65536;
 pub struct ServeDir<F = DefaultServeDirFallback, B = TokioBackend> {
     base: PathBuf,
     redirect_path_prefix: String,
+    trusted_proxies: Option<TrustedProxies>,
     buf_chunk_size: usize,
     precompressed_variants: Option<PrecompressedVariants>,
     // This is used to specialize implementation for
@@ -78,6 +80,7 @@ impl ServeDir<DefaultServeDirFallback> {
         Self {
             base,
             redirect_path_prefix: String::new(),
+            trusted_proxies: None,
             buf_chunk_size: DEFAULT_CAPACITY,
             precompressed_variants: None,
             variant: ServeVariant::Directory {
@@ -97,6 +100,7 @@ impl ServeDir<DefaultServeDirFallback> {
         Self {
             base: path.as_ref().to_owned(),
             redirect_path_prefix: String::new(),
+            trusted_proxies: None,
             buf_chunk_size: DEFAULT_CAPACITY,
             precompressed_variants: None,
             variant: ServeVariant::SingleFile { mime },
@@ -129,6 +133,7 @@ impl<B: Backend> ServeDir<DefaultServeDirFallback, B> {
             fallback: None,
             call_fallback_on_method_not_allowed: false,
             redirect_path_prefix: String::new(),
+            trusted_proxies: None,
             backend,
         }
     }
@@ -182,6 +187,19 @@ impl<F, B: Backend> ServeDir<F, B> {
         self
     }
 
+    /// Makes trailing slash redirects absolute, pointing at the scheme and host the client used
+    /// as forwarded by trusted reverse proxies.
+    ///
+    /// Forwarding headers are only believed if the request was received from one of the
+    /// `proxies`; see [`TrustedProxies::effective_authority`]. If the scheme or host isn't known,
+    /// the redirect stays relative.
+    ///
+    /// The default is to redirect to a relative URI.
+    pub fn trusted_proxies(mut self, proxies: TrustedProxies) -> Self {
+        self.trusted_proxies = Some(proxies);
+        self
+    }
+
     /// Set a specific read buffer chunk size.
     ///
     /// The default capacity is 64kb.
@@ -279,6 +297,7 @@ impl<F, B: Backend> ServeDir<F, B> {
     pub fn fallback<F2>(self, new_fallback: F2) -> ServeDir<F2, B> {
         ServeDir {
             redirect_path_prefix: self.redirect_path_prefix,
+            trusted_proxies: self.trusted_proxies,
             base: self.base,
             buf_chunk_size: self.buf_chunk_size,
             precompressed_variants: self.precompressed_variants,
@@ -410,6 +429,12 @@ impl<F, B: Backend> ServeDir<F, B> {
             return ResponseFuture::method_not_allowed();
         }
 
+        // Resolved up front, the peer address is in the extensions moved out below.
+        let redirect_authority = self
+            .trusted_proxies
+            .as_ref()
+            .map(|proxies| proxies.effective_authority(&req));
+
         // `ServeDir` doesn't care about the request body but the fallback might. So move out the
         // body and pass it to the fallback, leaving an empty body in its place
         //
@@ -462,6 +487,7 @@ impl<F, B: Backend> ServeDir<F, B> {
         let open_file_future = Box::pin(open_file::open_file(open_file::OpenFileRequest {
             variant: self.variant.clone(),
             redirect_path_prefix,
+            redirect_authority,
             path_to_file,
             req,
             negotiated_encodings,
@@ -4,6 +4,7 @@ use super::{
     ServeVariant,
 };
 use crate::content_encoding::{Encoding, QValue};
+use crate::forwarded::EffectiveAuthority;
 use bytes::Bytes;
 use http::{header, HeaderValue, Method, Request, Uri};
 use http_body_util::Empty;
@@ -54,6 +55,7 @@ pub(super) enum FileRequestExtent {
 pub(super) struct OpenFileRequest<B> {
     pub(super) variant: ServeVariant,
     pub(super) redirect_path_prefix: String,
+    pub(super) redirect_authority: Option<EffectiveAuthority>,
     pub(super) path_to_file: PathBuf,
     pub(super) req: Request<Empty<Bytes>>,
     pub(super) negotiated_encodings: Vec<(Encoding, QValue)>,
@@ -69,6 +71,7 @@ pub(super) async fn open_file<B: Backend>(
     let OpenFileRequest {
         variant,
         redirect_path_prefix,
+        redirect_authority,
         mut path_to_file,
         req,
         negotiated_encodings,
@@ -106,6 +109,7 @@ pub(super) async fn open_file<B: Backend>(
             // modified and proceed to the open file/metadata future.
             if let Some(output) = maybe_redirect_or_append_path(
                 &redirect_path_prefix,
+                redirect_authority,
                 &mut path_to_file,
                 req.uri(),
                 append_index_html_on_directories,
@@ -400,6 +404,7 @@ async fn file_metadata_with_fallback<B: Backend>(
 
 async fn maybe_redirect_or_append_path<B: Backend>(
     redirect_path_prefix: &str,
+    redirect_authority: Option<EffectiveAuthority>,
     path_to_file: &mut PathBuf,
     uri: &Uri,
     append_index_html_on_directories: bool,
@@ -432,7 +437,8 @@ async fn maybe_redirect_or_append_path<B: Backend>(
         path_to_file.push("index.html");
         Ok(None)
     } else {
-        let uri = match append_slash_on_path(uri.clone(), redirect_path_prefix) {
+        let uri = match append_slash_on_path(uri.clone(), redirect_path_prefix, redirect_authority)
+        {
             Ok(uri) => uri,
             Err(err) => return Ok(Some(err)),
         };
@@ -473,14 +479,29 @@ async fn is_dir<B: Backend>(path_to_file: &Path, backend: &B) -> io::Result<Opti
     }
 }
 
-fn append_slash_on_path(uri: Uri, redirect_path_prefix: &str) -> Result<Uri, OpenFileOutput> {
+fn append_slash_on_path(
+    uri: Uri,
+    redirect_path_prefix: &str,
+    redirect_authority: Option<EffectiveAuthority>,
+) -> Result<Uri, OpenFileOutput> {
     let http::uri::Parts {
-        scheme,
-        authority,
+        mut scheme,
+        mut authority,
         path_and_query,
         ..
     } = uri.into_parts();
 
+    // Only redirect to the forwarded location if it is complete, a URI can't have an authority
+    // without a scheme.
+    if let Some(redirect_authority) = redirect_authority {
+        if let (Some(redirect_scheme), Some(redirect_authority)) =
+            (redirect_authority.scheme(), redirect_authority.authority())
+        {
+            scheme = Some(redirect_scheme.clone());
+            authority = Some(redirect_authority.clone());
+        }
+    }
+
     let mut uri_builder = Uri::builder();
 
     if let Some(scheme) = scheme {
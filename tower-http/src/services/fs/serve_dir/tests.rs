@@ -538,6 +538,37 @@ async fn redirect_path_prefix_preserved_through_fallback() {
     assert_eq!(location, "/foo/src/");
 }
 
+#[tokio::test]
+async fn redirect_to_trailing_slash_behind_trusted_proxy() {
+    let proxies = crate::forwarded::TrustedProxies::new(["10.0.0.0/8"]).unwrap();
+    let svc = ServeDir::new(".")
+        .redirect_path_prefix("/foo")
+        .trusted_proxies(proxies);
+
+    let cases = [
+        ("10.0.0.1:1234", "https://example.com/foo/src/?key=value"),
+        // forwarding headers from untrusted peers are ignored
+        ("203.0.113.7:1234", "/foo/src/?key=value"),
+    ];
+
+    for (peer, expected_location) in cases {
+        let mut req = Request::builder()
+            .uri("/src?key=value")
+            .header("host", "backend.internal")
+            .header("forwarded", "for=203.0.113.7;host=example.com;proto=https")
+            .body(Body::empty())
+            .unwrap();
+        req.extensions_mut()
+            .insert(peer.parse::<std::net::SocketAddr>().unwrap());
+        let res = svc.clone().oneshot(req).await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::TEMPORARY_REDIRECT);
+
+        let location = &res.headers()[http::header::LOCATION];
+        assert_eq!(location, expected_location);
+    }
+}
+
 #[tokio::test]
 async fn empty_directory_without_index() {
     let svc = ServeDir::new(".").append_index_html_on_directories(false);
@@ -163,3 +163,65 @@ where
         }
     }
 }
+
+/// Calls `svc` with `request`, returning the status and body of the response.
+#[cfg(feature = "auth")]
+pub(crate) async fn status_and_body<S>(
+    svc: S,
+    request: http::Request<Body>,
+) -> (http::StatusCode, String)
+where
+    S: tower::Service<http::Request<Body>, Response = http::Response<Body>>,
+    S::Error: std::fmt::Debug,
+{
+    use tower::ServiceExt;
+
+    let res = svc.oneshot(request).await.unwrap();
+    let status = res.status();
+    let body = to_bytes(res.into_body()).await.unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+/// A `401 Unauthorized` response with an empty body, as returned by [`status_and_body`].
+#[cfg(feature = "auth")]
+pub(crate) fn unauthorized() -> (http::StatusCode, String) {
+    (http::StatusCode::UNAUTHORIZED, String::new())
+}
+
+/// A `403 Forbidden` response with an empty body, as returned by [`status_and_body`].
+#[cfg(feature = "auth-signed-url")]
+pub(crate) fn forbidden() -> (http::StatusCode, String) {
+    (http::StatusCode::FORBIDDEN, String::new())
+}
+
+/// Calls `svc` with the given `Authorization` header, returning the status and either the body
+/// of an `OK` response or the `WWW-Authenticate` challenge.
+#[cfg(feature = "auth")]
+pub(crate) async fn check_authorization<S>(
+    svc: S,
+    authorization: Option<&str>,
+) -> (http::StatusCode, String)
+where
+    S: tower::Service<http::Request<Body>, Response = http::Response<Body>>,
+    S::Error: std::fmt::Debug,
+{
+    use http::{header, Request, StatusCode};
+    use tower::ServiceExt;
+
+    let mut req = Request::get("/");
+    if let Some(authorization) = authorization {
+        req = req.header(header::AUTHORIZATION, authorization);
+    }
+    let res = svc.oneshot(req.body(Body::empty()).unwrap()).await.unwrap();
+    let status = res.status();
+    if status == StatusCode::OK {
+        let body = to_bytes(res.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    } else {
+        let challenge = res.headers()[header::WWW_AUTHENTICATE]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        (status, challenge)
+    }
+}
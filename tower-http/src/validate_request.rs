@@ -224,7 +224,7 @@ use tower_service::Service;
 /// See the [module docs](crate::validate_request) for an example.
 #[derive(Debug, Clone)]
 pub struct ValidateRequestHeaderLayer<T> {
-    validate: T,
+    pub(crate) validate: T,
 }
 
 impl<ResBody> ValidateRequestHeaderLayer<AcceptHeader<ResBody>> {